@@ -8,7 +8,7 @@ pub struct AnthropicRequest {
   pub messages: Vec<AnthropicMessage>,
   pub max_tokens: u32,
   #[serde(skip_serializing_if = "Option::is_none")]
-  pub system: Option<String>,
+  pub system: Option<Vec<AnthropicContentBlock>>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub temperature: Option<f32>,
   #[serde(skip_serializing_if = "Option::is_none")]
@@ -63,6 +63,16 @@ pub struct AnthropicContentBlock {
   pub thinking: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub signature: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub data: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub cache_control: Option<AnthropicCacheControl>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnthropicCacheControl {
+  #[serde(rename = "type")]
+  pub cache_type: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -79,6 +89,8 @@ pub struct AnthropicTool {
   #[serde(skip_serializing_if = "Option::is_none")]
   pub description: Option<String>,
   pub input_schema: serde_json::Value,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub cache_control: Option<AnthropicCacheControl>,
 }
 
 #[derive(Debug, Serialize)]
@@ -103,6 +115,16 @@ pub struct AnthropicStreamEvent {
   pub delta: Option<AnthropicDelta>,
   #[serde(default)]
   pub usage: Option<AnthropicUsage>,
+  #[serde(default)]
+  pub error: Option<AnthropicError>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnthropicError {
+  #[serde(default, rename = "type")]
+  pub error_type: String,
+  #[serde(default)]
+  pub message: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -116,6 +138,8 @@ pub struct AnthropicDelta {
   #[serde(default)]
   pub thinking: Option<String>,
   #[serde(default)]
+  pub signature: Option<String>,
+  #[serde(default)]
   pub stop_reason: Option<String>,
   #[serde(default)]
   pub stop_sequence: Option<String>,
@@ -0,0 +1,157 @@
+use crate::{
+  config::{BudgetConfig, BudgetPeriod, UsageConfig},
+  usage::{utc_date, UsageLedger},
+  util::now_ms,
+};
+
+#[derive(Debug)]
+pub(crate) struct BudgetExceeded<'a> {
+  pub(crate) budget: &'a BudgetConfig,
+  spent_usd: f64,
+  spent_tokens: i64,
+}
+
+impl BudgetExceeded<'_> {
+  pub(crate) fn message(&self) -> String {
+    let b = self.budget;
+    let scope = if b.model.trim().is_empty() {
+      b.provider_id.trim().to_string()
+    } else {
+      format!("{}:{}", b.provider_id.trim(), b.model.trim())
+    };
+    let period = match b.period {
+      BudgetPeriod::Daily => "今日",
+      BudgetPeriod::Monthly => "本月",
+    };
+    let mut limits = Vec::new();
+    if b.max_usd > 0.0 {
+      limits.push(format!("${:.4} / ${:.2}", self.spent_usd, b.max_usd));
+    }
+    if b.max_tokens > 0 {
+      limits.push(format!("{} / {} tokens", self.spent_tokens, b.max_tokens));
+    }
+    format!("{scope} {period}预算已用尽（{}）", limits.join("，"))
+  }
+}
+
+// 返回第一个已超出的预算（按 usage.budgets 顺序）；不计入仍在进行中的请求
+pub(crate) fn exceeded_budget<'a>(
+  cfg: &'a UsageConfig,
+  ledger: &UsageLedger,
+  provider_id: &str,
+  model: &str,
+) -> Option<BudgetExceeded<'a>> {
+  let today = utc_date(now_ms());
+  cfg
+    .budgets
+    .iter()
+    .filter(|b| b.applies_to(provider_id, model))
+    .find_map(|b| {
+      let since_day = match b.period {
+        BudgetPeriod::Daily => today.clone(),
+        BudgetPeriod::Monthly => format!("{}-01", &today[..7]),
+      };
+      let (mut spent_usd, mut spent_tokens) = (0.0, 0);
+      for (m, totals) in ledger.provider_totals_since(b.provider_id.trim(), &since_day) {
+        if !b.model.trim().is_empty() && m.trim() != b.model.trim() {
+          continue;
+        }
+        spent_tokens += totals.total_tokens();
+        if let Some(price) = cfg.price_for(&m) {
+          spent_usd += totals.cost_with(price);
+        }
+      }
+      let over_usd = b.max_usd > 0.0 && spent_usd >= b.max_usd;
+      let over_tokens = b.max_tokens > 0 && spent_tokens >= b.max_tokens as i64;
+      (over_usd || over_tokens).then_some(BudgetExceeded {
+        budget: b,
+        spent_usd,
+        spent_tokens,
+      })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::usage::UsageRecord;
+
+  #[tokio::test]
+  async fn budgets_survive_restart_via_ledger_file() {
+    let path = std::env::temp_dir().join(format!("usage_ledger_budget_test_{}.jsonl", now_ms()));
+    let cfg: UsageConfig = serde_yaml::from_str(
+      r#"
+prices:
+  "m1": { input: 10 }
+budgets:
+  - { provider_id: p, period: daily, max_usd: 1 }
+  - { provider_id: p, model: m2, period: monthly, max_tokens: 100, on_exceeded: fallback, fallback: "byok:p:m1" }
+"#,
+    )
+    .unwrap();
+    let rec = |model: &str, input| UsageRecord {
+      input_tokens: Some(input),
+      status: "completed".to_string(),
+      ..UsageRecord::new("/chat-stream", "c", "p", model)
+    };
+
+    let ledger = UsageLedger::open(path.clone()).await;
+    ledger.record(rec("m1", 50_000));
+    ledger.record(rec("m2", 99));
+    assert!(exceeded_budget(&cfg, &ledger, "p", "m2").is_none());
+    ledger.record(rec("m1", 50_000));
+    let hit = exceeded_budget(&cfg, &ledger, "p", "m1").unwrap();
+    assert!(
+      hit.message().contains("$1.0000 / $1.00"),
+      "{}",
+      hit.message()
+    );
+
+    // 等后台任务落盘后重新打开，用量从文件重建
+    while ledger.load(0).await.unwrap().len() < 3 {
+      tokio::task::yield_now().await;
+    }
+    let reopened = UsageLedger::open(path.clone()).await;
+    let hit = exceeded_budget(&cfg, &reopened, "p", "m2").unwrap();
+    assert_eq!(hit.budget.max_usd, 1.0);
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[tokio::test]
+  async fn non_chat_calls_count_against_budgets() {
+    use crate::{openai::OpenAIUsage, stats::UpstreamCall};
+    use std::sync::Arc;
+
+    let path =
+      std::env::temp_dir().join(format!("usage_ledger_budget_non_chat_{}.jsonl", now_ms()));
+    let cfg: UsageConfig =
+      serde_yaml::from_str("budgets:\n  - { provider_id: p, period: daily, max_tokens: 1000 }\n")
+        .unwrap();
+    let ledger = Arc::new(UsageLedger::open(path.clone()).await);
+    assert!(exceeded_budget(&cfg, &ledger, "p", "m").is_none());
+
+    // 例如一次生成提交信息：没有经过 chat-stream，也要计入预算
+    let mut call = UpstreamCall::start(
+      Some(ledger.clone()),
+      UsageRecord::new("/generate-commit-message-stream", "", "p", "m"),
+    );
+    call.progress.on_openai_usage(&OpenAIUsage {
+      prompt_tokens: Some(900),
+      completion_tokens: Some(100),
+      ..Default::default()
+    });
+    call.finish();
+    drop(call);
+
+    let hit = exceeded_budget(&cfg, &ledger, "p", "m").unwrap();
+    assert!(
+      hit.message().contains("1000 / 1000 tokens"),
+      "{}",
+      hit.message()
+    );
+    while ledger.load(0).await.unwrap().is_empty() {
+      tokio::task::yield_now().await;
+    }
+    let _ = std::fs::remove_file(&path);
+  }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Debug, Default)]
+pub(crate) struct CircuitBreakerRegistry {
+  by_provider: HashMap<String, Breaker>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BreakerState {
+  #[default]
+  Closed,
+  Open,
+  HalfOpen,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Breaker {
+  state: BreakerState,
+  consecutive_failures: u32,
+  open_until_ms: u64,
+  // 半开期间只放行一个探测请求；探测请求被取消时按 open_seconds 视为超时，允许下一个探测
+  probe_started_at_ms: u64,
+  last_error: Option<String>,
+  last_error_at_ms: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BreakerStatus {
+  provider_id: String,
+  state: BreakerState,
+  consecutive_failures: u32,
+  open_remaining_ms: u64,
+  last_error: Option<String>,
+  last_error_at_ms: u64,
+}
+
+impl BreakerStatus {
+  pub(crate) fn provider_id(&self) -> &str {
+    &self.provider_id
+  }
+
+  pub(crate) fn is_degraded(&self) -> bool {
+    self.state != BreakerState::Closed
+  }
+}
+
+impl CircuitBreakerRegistry {
+  // Err 为熔断剩余时间（ms）
+  pub(crate) fn try_acquire(
+    &mut self,
+    provider_id: &str,
+    cfg: &CircuitBreakerConfig,
+    now: u64,
+  ) -> Result<(), u64> {
+    if !cfg.enabled {
+      return Ok(());
+    }
+    let b = self.by_provider.entry(provider_id.to_string()).or_default();
+    let open_ms = cfg.open_seconds.saturating_mul(1000);
+    match b.state {
+      BreakerState::Closed => Ok(()),
+      BreakerState::Open if now < b.open_until_ms => Err(b.open_until_ms - now),
+      BreakerState::HalfOpen if now < b.probe_started_at_ms.saturating_add(open_ms) => {
+        Err(b.probe_started_at_ms.saturating_add(open_ms) - now)
+      }
+      BreakerState::Open | BreakerState::HalfOpen => {
+        b.state = BreakerState::HalfOpen;
+        b.probe_started_at_ms = now;
+        Ok(())
+      }
+    }
+  }
+
+  pub(crate) fn record_success(&mut self, provider_id: &str) {
+    if let Some(b) = self.by_provider.get_mut(provider_id) {
+      b.state = BreakerState::Closed;
+      b.consecutive_failures = 0;
+    }
+  }
+
+  // 返回 true 表示本次失败让熔断器（重新）打开
+  pub(crate) fn record_failure(
+    &mut self,
+    provider_id: &str,
+    cfg: &CircuitBreakerConfig,
+    error: String,
+    now: u64,
+  ) -> bool {
+    if !cfg.enabled {
+      return false;
+    }
+    let b = self.by_provider.entry(provider_id.to_string()).or_default();
+    b.consecutive_failures = b.consecutive_failures.saturating_add(1);
+    b.last_error = Some(error);
+    b.last_error_at_ms = now;
+    let trip = match b.state {
+      BreakerState::Closed => b.consecutive_failures >= cfg.failure_threshold.max(1),
+      BreakerState::HalfOpen => true,
+      BreakerState::Open => false,
+    };
+    if trip {
+      b.state = BreakerState::Open;
+      b.open_until_ms = now.saturating_add(cfg.open_seconds.saturating_mul(1000));
+    }
+    trip
+  }
+
+  pub(crate) fn status<'a>(
+    &self,
+    provider_ids: impl IntoIterator<Item = &'a str>,
+    now: u64,
+  ) -> Vec<BreakerStatus> {
+    provider_ids
+      .into_iter()
+      .map(|id| {
+        let b = self.by_provider.get(id).cloned().unwrap_or_default();
+        BreakerStatus {
+          provider_id: id.to_string(),
+          state: b.state,
+          consecutive_failures: b.consecutive_failures,
+          open_remaining_ms: match b.state {
+            BreakerState::Open => b.open_until_ms.saturating_sub(now),
+            _ => 0,
+          },
+          last_error: b.last_error,
+          last_error_at_ms: b.last_error_at_ms,
+        }
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn breaker_trips_after_threshold_and_probes_half_open() {
+    let cfg = CircuitBreakerConfig {
+      enabled: true,
+      failure_threshold: 2,
+      open_seconds: 10,
+    };
+    let mut reg = CircuitBreakerRegistry::default();
+
+    assert_eq!(reg.try_acquire("p", &cfg, 0), Ok(()));
+    assert!(!reg.record_failure("p", &cfg, "boom".to_string(), 0));
+    assert_eq!(reg.try_acquire("p", &cfg, 1), Ok(()));
+    assert!(reg.record_failure("p", &cfg, "boom".to_string(), 1_000));
+    assert_eq!(reg.try_acquire("p", &cfg, 2_000), Err(9_000));
+
+    // 冷却结束：只放行一个探测请求
+    assert_eq!(reg.try_acquire("p", &cfg, 11_000), Ok(()));
+    assert!(reg.try_acquire("p", &cfg, 11_001).is_err());
+    assert!(reg.record_failure("p", &cfg, "still down".to_string(), 12_000));
+    assert!(reg.try_acquire("p", &cfg, 13_000).is_err());
+
+    assert_eq!(reg.try_acquire("p", &cfg, 22_000), Ok(()));
+    reg.record_success("p");
+    assert_eq!(reg.try_acquire("p", &cfg, 22_001), Ok(()));
+    let status = reg.status(["p"], 22_001);
+    assert!(!status[0].is_degraded());
+    assert_eq!(status[0].consecutive_failures, 0);
+  }
+
+  #[test]
+  fn abandoned_half_open_probe_does_not_block_forever() {
+    let cfg = CircuitBreakerConfig {
+      enabled: true,
+      failure_threshold: 1,
+      open_seconds: 5,
+    };
+    let mut reg = CircuitBreakerRegistry::default();
+    reg.record_failure("p", &cfg, "boom".to_string(), 0);
+    assert_eq!(reg.try_acquire("p", &cfg, 5_000), Ok(()));
+    assert!(reg.try_acquire("p", &cfg, 9_999).is_err());
+    assert_eq!(reg.try_acquire("p", &cfg, 10_000), Ok(()));
+  }
+
+  #[test]
+  fn disabled_breaker_never_opens() {
+    let cfg = CircuitBreakerConfig {
+      enabled: false,
+      failure_threshold: 1,
+      open_seconds: 5,
+    };
+    let mut reg = CircuitBreakerRegistry::default();
+    assert!(!reg.record_failure("p", &cfg, "boom".to_string(), 0));
+    assert_eq!(reg.try_acquire("p", &cfg, 1), Ok(()));
+  }
+}
@@ -0,0 +1,285 @@
+use std::{
+  collections::{HashMap, VecDeque},
+  sync::{Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+use tracing::{info, warn};
+
+use crate::config::ProviderConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RequestPriority {
+  // 用户正在等待的对话（chat-stream 等）
+  Interactive,
+  // 生成标题 / commit message / history summary 等后台请求
+  Background,
+}
+
+type SlotMap = Arc<Mutex<HashMap<String, ProviderSlots>>>;
+
+// 按 provider 限制同时在途的上游请求数；超出时排队，Interactive 优先，同优先级内 FIFO
+#[derive(Debug, Default)]
+pub(crate) struct ConcurrencyLimiter {
+  slots: SlotMap,
+}
+
+#[derive(Debug, Default)]
+struct ProviderSlots {
+  max_concurrent_requests: usize,
+  in_flight: usize,
+  next_waiter_id: u64,
+  interactive: VecDeque<Waiter>,
+  background: VecDeque<Waiter>,
+  queued_total: u64,
+  queue_timeouts: u64,
+  last_wait_ms: u64,
+  max_wait_ms: u64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+  id: u64,
+  tx: oneshot::Sender<ConcurrencyPermit>,
+}
+
+impl ProviderSlots {
+  fn queue_depth(&self) -> usize {
+    self.interactive.len() + self.background.len()
+  }
+
+  fn has_free_slot(&self) -> bool {
+    self.max_concurrent_requests == 0 || self.in_flight < self.max_concurrent_requests
+  }
+}
+
+// 持有期间占用一个并发名额（流式请求需要一直持有到流结束）；drop 时把名额交给队首请求
+#[derive(Debug)]
+pub(crate) struct ConcurrencyPermit {
+  slots: SlotMap,
+  provider_id: String,
+  armed: bool,
+}
+
+impl Drop for ConcurrencyPermit {
+  fn drop(&mut self) {
+    if self.armed {
+      release(&self.slots, &self.provider_id);
+    }
+  }
+}
+
+fn release(slots: &SlotMap, provider_id: &str) {
+  loop {
+    let waiter = {
+      let Ok(mut map) = slots.lock() else { return };
+      let Some(s) = map.get_mut(provider_id) else {
+        return;
+      };
+      s.in_flight = s.in_flight.saturating_sub(1);
+      if !s.has_free_slot() {
+        return;
+      }
+      let next = s
+        .interactive
+        .pop_front()
+        .or_else(|| s.background.pop_front());
+      let Some(next) = next else { return };
+      s.in_flight += 1;
+      next
+    };
+    let permit = ConcurrencyPermit {
+      slots: slots.clone(),
+      provider_id: provider_id.to_string(),
+      armed: true,
+    };
+    // 等待方已放弃（超时/客户端断开）时 send 失败，名额继续交给下一个
+    match waiter.tx.send(permit) {
+      Ok(()) => return,
+      Err(mut permit) => permit.armed = false,
+    }
+  }
+}
+
+// 排队中的请求被取消时把自己从队列里摘掉
+struct QueuedGuard<'a> {
+  slots: &'a SlotMap,
+  provider_id: &'a str,
+  id: u64,
+}
+
+impl Drop for QueuedGuard<'_> {
+  fn drop(&mut self) {
+    if let Ok(mut map) = self.slots.lock() {
+      if let Some(s) = map.get_mut(self.provider_id) {
+        s.interactive.retain(|w| w.id != self.id);
+        s.background.retain(|w| w.id != self.id);
+      }
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProviderConcurrencyStatus {
+  provider_id: String,
+  max_concurrent_requests: usize,
+  in_flight: usize,
+  queued_interactive: usize,
+  queued_background: usize,
+  queued_total: u64,
+  queue_timeouts: u64,
+  last_wait_ms: u64,
+  max_wait_ms: u64,
+}
+
+impl ConcurrencyLimiter {
+  pub(crate) async fn acquire(
+    &self,
+    provider_id: &str,
+    max_concurrent_requests: usize,
+    queue_timeout: Duration,
+    priority: RequestPriority,
+  ) -> anyhow::Result<ConcurrencyPermit> {
+    let (id, rx, depth) = {
+      let mut map = self
+        .slots
+        .lock()
+        .map_err(|_| anyhow::anyhow!("并发状态锁已损坏"))?;
+      let s = map.entry(provider_id.to_string()).or_default();
+      s.max_concurrent_requests = max_concurrent_requests;
+      if s.has_free_slot() && s.queue_depth() == 0 {
+        s.in_flight += 1;
+        return Ok(ConcurrencyPermit {
+          slots: self.slots.clone(),
+          provider_id: provider_id.to_string(),
+          armed: true,
+        });
+      }
+      let id = s.next_waiter_id;
+      s.next_waiter_id += 1;
+      let (tx, rx) = oneshot::channel();
+      match priority {
+        RequestPriority::Interactive => s.interactive.push_back(Waiter { id, tx }),
+        RequestPriority::Background => s.background.push_back(Waiter { id, tx }),
+      }
+      s.queued_total += 1;
+      (id, rx, s.queue_depth())
+    };
+
+    let _guard = QueuedGuard {
+      slots: &self.slots,
+      provider_id,
+      id,
+    };
+    info!(provider_id=%provider_id, priority=?priority, queue_depth=depth, max_concurrent_requests, "provider 并发已满，请求排队中");
+    let started = Instant::now();
+    let result = tokio::time::timeout(queue_timeout, rx).await;
+    let wait_ms = started.elapsed().as_millis() as u64;
+    let mut map = self
+      .slots
+      .lock()
+      .map_err(|_| anyhow::anyhow!("并发状态锁已损坏"))?;
+    let s = map.entry(provider_id.to_string()).or_default();
+    match result {
+      Ok(Ok(permit)) => {
+        s.last_wait_ms = wait_ms;
+        s.max_wait_ms = s.max_wait_ms.max(wait_ms);
+        info!(provider_id=%provider_id, priority=?priority, wait_ms, queue_depth=s.queue_depth(), "排队结束，开始请求上游");
+        Ok(permit)
+      }
+      Ok(Err(_)) => anyhow::bail!("Provider({provider_id}) 排队被中断"),
+      Err(_) => {
+        s.queue_timeouts += 1;
+        warn!(provider_id=%provider_id, priority=?priority, wait_ms, queue_depth=s.queue_depth(), "排队超时");
+        anyhow::bail!(
+          "Provider({provider_id}) 并发已满（max_concurrent_requests={max_concurrent_requests}），排队 {}s 仍未轮到（queue_timeout_seconds）",
+          queue_timeout.as_secs()
+        )
+      }
+    }
+  }
+
+  pub(crate) fn status(&self, providers: &[ProviderConfig]) -> Vec<ProviderConcurrencyStatus> {
+    let Ok(map) = self.slots.lock() else {
+      return Vec::new();
+    };
+    providers
+      .iter()
+      .map(|p| {
+        let s = map.get(p.id());
+        ProviderConcurrencyStatus {
+          provider_id: p.id().to_string(),
+          max_concurrent_requests: p.concurrency_limit().0,
+          in_flight: s.map_or(0, |s| s.in_flight),
+          queued_interactive: s.map_or(0, |s| s.interactive.len()),
+          queued_background: s.map_or(0, |s| s.background.len()),
+          queued_total: s.map_or(0, |s| s.queued_total),
+          queue_timeouts: s.map_or(0, |s| s.queue_timeouts),
+          last_wait_ms: s.map_or(0, |s| s.last_wait_ms),
+          max_wait_ms: s.map_or(0, |s| s.max_wait_ms),
+        }
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WAIT: Duration = Duration::from_secs(5);
+
+  #[tokio::test]
+  async fn interactive_requests_jump_ahead_of_background() {
+    let limiter = Arc::new(ConcurrencyLimiter::default());
+    let first = limiter
+      .acquire("p", 1, WAIT, RequestPriority::Interactive)
+      .await
+      .unwrap();
+
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let spawn = |name: &'static str, priority| {
+      let (limiter, order) = (limiter.clone(), order.clone());
+      tokio::spawn(async move {
+        let permit = limiter.acquire("p", 1, WAIT, priority).await.unwrap();
+        order.lock().unwrap().push(name);
+        drop(permit);
+      })
+    };
+    let background = spawn("background", RequestPriority::Background);
+    tokio::task::yield_now().await;
+    let interactive = spawn("interactive", RequestPriority::Interactive);
+    while limiter.slots.lock().unwrap()["p"].queue_depth() < 2 {
+      tokio::task::yield_now().await;
+    }
+
+    drop(first);
+    background.await.unwrap();
+    interactive.await.unwrap();
+    assert_eq!(*order.lock().unwrap(), ["interactive", "background"]);
+    assert_eq!(limiter.slots.lock().unwrap()["p"].in_flight, 0);
+  }
+
+  #[tokio::test]
+  async fn queue_timeout_gives_up_and_frees_queue_entry() {
+    let limiter = ConcurrencyLimiter::default();
+    let _held = limiter
+      .acquire("p", 1, WAIT, RequestPriority::Interactive)
+      .await
+      .unwrap();
+    let err = limiter
+      .acquire(
+        "p",
+        1,
+        Duration::from_millis(20),
+        RequestPriority::Background,
+      )
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("queue_timeout_seconds"), "{err}");
+    let map = limiter.slots.lock().unwrap();
+    assert_eq!((map["p"].queue_depth(), map["p"].queue_timeouts), (0, 1));
+  }
+}
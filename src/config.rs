@@ -1,10 +1,20 @@
-use std::{collections::BTreeMap, fs, net::SocketAddr, path::Path};
+use std::{
+  collections::BTreeMap,
+  fs,
+  net::SocketAddr,
+  path::{Path, PathBuf},
+  sync::OnceLock,
+};
 
 use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use tracing_subscriber::{prelude::*, reload, EnvFilter, Registry};
 use url::Url;
 
+use crate::config_override;
 use crate::protocol::de_null_as_default;
+use crate::secrets::SecretRefs;
+use crate::util::normalize_raw_token;
 
 fn default_logging_filter() -> String {
   "info".to_string()
@@ -18,6 +28,10 @@ fn default_thinking_budget_tokens() -> u32 {
   10000
 }
 
+fn default_reasoning_summary() -> String {
+  "auto".to_string()
+}
+
 fn default_max_tokens() -> u32 {
   8192
 }
@@ -26,6 +40,70 @@ fn default_timeout_seconds() -> u64 {
   120
 }
 
+fn default_queue_timeout_seconds() -> u64 {
+  60
+}
+
+fn default_retry_max_attempts() -> u32 {
+  3
+}
+
+fn default_retry_initial_backoff_ms() -> u64 {
+  500
+}
+
+fn default_retry_max_backoff_ms() -> u64 {
+  8_000
+}
+
+fn default_retry_retryable_status_codes() -> Vec<u16> {
+  vec![408, 429, 500, 502, 503, 504, 529]
+}
+
+fn default_retry_on_network_error() -> bool {
+  true
+}
+
+fn default_circuit_breaker_enabled() -> bool {
+  true
+}
+
+fn default_usage_enabled() -> bool {
+  true
+}
+
+fn default_transcripts_dir() -> String {
+  "transcripts".to_string()
+}
+
+fn default_transcripts_max_files() -> usize {
+  200
+}
+
+fn default_transcripts_max_age_hours() -> u64 {
+  72
+}
+
+fn default_transcripts_max_file_bytes() -> usize {
+  8 * 1024 * 1024
+}
+
+fn default_circuit_breaker_failure_threshold() -> u32 {
+  5
+}
+
+fn default_circuit_breaker_open_seconds() -> u64 {
+  30
+}
+
+fn default_key_pool_selection() -> String {
+  "round_robin".to_string()
+}
+
+fn default_key_pool_cooldown_seconds() -> u64 {
+  60
+}
+
 fn default_history_summary_max_tokens() -> u32 {
   1024
 }
@@ -134,12 +212,29 @@ pub struct Config {
   pub history_summary: HistorySummaryConfig,
   #[serde(default)]
   pub logging: LoggingConfig,
+  #[serde(default)]
+  pub retry: RetryConfig,
+  #[serde(default)]
+  pub circuit_breaker: CircuitBreakerConfig,
+  #[serde(default)]
+  pub routing: RoutingConfig,
+  #[serde(default)]
+  pub usage: UsageConfig,
+  #[serde(skip)]
+  pub secret_refs: SecretRefs,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ServerConfig {
   pub host: String,
   pub port: u16,
+  // 轮询 config.yaml 变更的间隔；0 = 不监听
+  #[serde(default = "default_config_watch_interval_seconds")]
+  pub config_watch_interval_seconds: u64,
+}
+
+fn default_config_watch_interval_seconds() -> u64 {
+  2
 }
 
 impl ServerConfig {
@@ -189,9 +284,33 @@ pub struct ByokConfig {
   pub providers: Vec<ProviderConfig>,
   #[serde(default)]
   pub active_provider_id: Option<String>,
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub fallbacks: BTreeMap<String, Vec<String>>,
 }
 
 impl ByokConfig {
+  // 新增、删除或任何字段有变化的 provider id（按 id 对比）
+  pub fn changed_provider_ids(&self, next: &ByokConfig) -> Vec<String> {
+    let by_id = |c: &ByokConfig| -> BTreeMap<String, serde_json::Value> {
+      c.providers
+        .iter()
+        .map(|p| {
+          (
+            p.id().trim().to_string(),
+            serde_json::to_value(p).unwrap_or_default(),
+          )
+        })
+        .collect()
+    };
+    let (old, new) = (by_id(self), by_id(next));
+    let ids: std::collections::BTreeSet<&String> = old
+      .keys()
+      .chain(new.keys())
+      .filter(|id| old.get(*id) != new.get(*id))
+      .collect();
+    ids.into_iter().cloned().collect()
+  }
+
   pub fn validate(&self) -> anyhow::Result<()> {
     if self.providers.is_empty() {
       anyhow::bail!("byok.providers 不能为空");
@@ -222,10 +341,138 @@ impl ByokConfig {
         anyhow::bail!("byok.active_provider_id 未命中 providers: {id}");
       }
     }
+    for (key, targets) in &self.fallbacks {
+      let key_provider_id = match key.trim().strip_prefix("byok:") {
+        Some(_) => split_byok_model_id(key)
+          .map(|(pid, _)| pid)
+          .with_context(|| format!("byok.fallbacks key 格式无效（应为 <providerId> 或 byok:<providerId>:<modelId>）：{key}"))?,
+        None => key.trim(),
+      };
+      if !seen_ids.contains(key_provider_id) {
+        anyhow::bail!("byok.fallbacks key 未命中 providers: {key}");
+      }
+      for target in targets {
+        let (pid, _) = split_byok_model_id(target).with_context(|| {
+          format!(
+            "byok.fallbacks[{key}] 目标格式无效（应为 byok:<providerId>:<modelId>）：{target}"
+          )
+        })?;
+        if !seen_ids.contains(pid) {
+          anyhow::bail!("byok.fallbacks[{key}] 目标 provider 不存在：{target}");
+        }
+      }
+    }
     Ok(())
   }
 }
 
+fn split_byok_model_id(s: &str) -> Option<(&str, &str)> {
+  let (provider_id, model_id) = s.trim().strip_prefix("byok:")?.split_once(':')?;
+  let (provider_id, model_id) = (provider_id.trim(), model_id.trim());
+  (!provider_id.is_empty() && !model_id.is_empty()).then_some((provider_id, model_id))
+}
+
+// api_key 可以是单个字符串，也可以是列表（多个 key 组成 key 池）
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiKeys(Vec<String>);
+
+impl ApiKeys {
+  // 规范化后的非空 key（去重，保持顺序）
+  pub fn keys(&self) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for k in &self.0 {
+      let k = normalize_raw_token(k);
+      if !k.is_empty() && !out.contains(&k) {
+        out.push(k);
+      }
+    }
+    out
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.iter().all(|k| normalize_raw_token(k).is_empty())
+  }
+}
+
+impl From<&str> for ApiKeys {
+  fn from(s: &str) -> Self {
+    Self(vec![s.to_string()])
+  }
+}
+
+impl<'de> Deserialize<'de> for ApiKeys {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+      One(String),
+      Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+      OneOrMany::One(s) => Self(vec![s]),
+      OneOrMany::Many(v) => Self(v),
+    })
+  }
+}
+
+impl Serialize for ApiKeys {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self.0.as_slice() {
+      [one] => serializer.serialize_str(one),
+      many => many.serialize(serializer),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KeyPoolConfig {
+  // round_robin | least_recently_rate_limited
+  #[serde(default = "default_key_pool_selection")]
+  pub selection: String,
+  // key 返回 429/401 后暂停使用的时长
+  #[serde(default = "default_key_pool_cooldown_seconds")]
+  pub cooldown_seconds: u64,
+}
+
+impl Default for KeyPoolConfig {
+  fn default() -> Self {
+    Self {
+      selection: default_key_pool_selection(),
+      cooldown_seconds: default_key_pool_cooldown_seconds(),
+    }
+  }
+}
+
+impl KeyPoolConfig {
+  pub fn validate(&self) -> anyhow::Result<()> {
+    match self.selection.trim() {
+      "round_robin" | "least_recently_rate_limited" => Ok(()),
+      other => anyhow::bail!(
+        "key_pool.selection 仅支持 round_robin / least_recently_rate_limited：{other}"
+      ),
+    }
+  }
+}
+
+// 每个 key 的令牌桶限速（0 = 不限）；输入 token 发送前按估算扣除，流结束后按上游返回的 usage 校正
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RateLimitConfig {
+  #[serde(default)]
+  pub requests_per_minute: u64,
+  #[serde(default)]
+  pub input_tokens_per_minute: u64,
+  #[serde(default)]
+  pub output_tokens_per_minute: u64,
+}
+
+impl RateLimitConfig {
+  pub fn is_enabled(&self) -> bool {
+    self.requests_per_minute > 0
+      || self.input_tokens_per_minute > 0
+      || self.output_tokens_per_minute > 0
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum ProviderConfig {
@@ -237,6 +484,10 @@ pub enum ProviderConfig {
     alias = "openai"
   )]
   OpenAICompatible(OpenAICompatibleProviderConfig),
+  #[serde(rename = "gemini", alias = "google")]
+  Gemini(GeminiProviderConfig),
+  #[serde(rename = "openai_responses", alias = "openai-responses")]
+  OpenAIResponses(OpenAIResponsesProviderConfig),
 }
 
 impl ProviderConfig {
@@ -244,6 +495,45 @@ impl ProviderConfig {
     match self {
       ProviderConfig::Anthropic(p) => p.id.as_str(),
       ProviderConfig::OpenAICompatible(p) => p.id.as_str(),
+      ProviderConfig::Gemini(p) => p.id.as_str(),
+      ProviderConfig::OpenAIResponses(p) => p.id.as_str(),
+    }
+  }
+
+  pub fn api_key(&self) -> &ApiKeys {
+    match self {
+      ProviderConfig::Anthropic(p) => &p.api_key,
+      ProviderConfig::OpenAICompatible(p) => &p.api_key,
+      ProviderConfig::Gemini(p) => &p.api_key,
+      ProviderConfig::OpenAIResponses(p) => &p.api_key,
+    }
+  }
+
+  pub fn key_pool(&self) -> &KeyPoolConfig {
+    match self {
+      ProviderConfig::Anthropic(p) => &p.key_pool,
+      ProviderConfig::OpenAICompatible(p) => &p.key_pool,
+      ProviderConfig::Gemini(p) => &p.key_pool,
+      ProviderConfig::OpenAIResponses(p) => &p.key_pool,
+    }
+  }
+
+  pub fn rate_limit(&self) -> &RateLimitConfig {
+    match self {
+      ProviderConfig::Anthropic(p) => &p.rate_limit,
+      ProviderConfig::OpenAICompatible(p) => &p.rate_limit,
+      ProviderConfig::Gemini(p) => &p.rate_limit,
+      ProviderConfig::OpenAIResponses(p) => &p.rate_limit,
+    }
+  }
+
+  // (max_concurrent_requests, queue_timeout_seconds)
+  pub fn concurrency_limit(&self) -> (usize, u64) {
+    match self {
+      ProviderConfig::Anthropic(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
+      ProviderConfig::OpenAICompatible(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
+      ProviderConfig::Gemini(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
+      ProviderConfig::OpenAIResponses(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
     }
   }
 
@@ -251,6 +541,8 @@ impl ProviderConfig {
     match self {
       ProviderConfig::Anthropic(p) => p.validate(),
       ProviderConfig::OpenAICompatible(p) => p.validate(),
+      ProviderConfig::Gemini(p) => p.validate(),
+      ProviderConfig::OpenAIResponses(p) => p.validate(),
     }
   }
 }
@@ -259,15 +551,32 @@ impl ProviderConfig {
 pub struct AnthropicProviderConfig {
   pub id: String,
   pub base_url: String,
-  pub api_key: String,
+  #[serde(alias = "api_keys")]
+  pub api_key: ApiKeys,
+  #[serde(default)]
+  pub key_pool: KeyPoolConfig,
+  #[serde(default)]
+  pub rate_limit: RateLimitConfig,
   pub default_model: String,
   #[serde(default = "default_max_tokens")]
   pub max_tokens: u32,
   #[serde(default = "default_timeout_seconds")]
   pub timeout_seconds: u64,
+  // 流式请求：等待响应头（每次尝试）与首个数据 / 相邻数据间隔的上限，缺省时沿用 timeout_seconds（流式请求不再受总时长限制）
+  #[serde(default)]
+  pub first_byte_timeout_seconds: Option<u64>,
+  #[serde(default)]
+  pub idle_timeout_seconds: Option<u64>,
+  // 同时发往该 provider 的请求上限（0 = 不限）；超出时排队，等待超过 queue_timeout_seconds 返回错误
+  #[serde(default)]
+  pub max_concurrent_requests: usize,
+  #[serde(default = "default_queue_timeout_seconds")]
+  pub queue_timeout_seconds: u64,
   #[serde(default)]
   pub thinking: ThinkingConfig,
   #[serde(default)]
+  pub prompt_caching: bool,
+  #[serde(default)]
   pub extra_headers: BTreeMap<String, String>,
 }
 
@@ -279,9 +588,13 @@ impl AnthropicProviderConfig {
     if self.base_url.trim().is_empty() {
       anyhow::bail!("byok.providers[type=anthropic].base_url 不能为空");
     }
-    if self.api_key.trim().is_empty() {
+    if self.api_key.is_empty() {
       anyhow::bail!("byok.providers[type=anthropic].api_key 不能为空");
     }
+    self
+      .key_pool
+      .validate()
+      .with_context(|| format!("byok.providers[type=anthropic][id={}]", self.id))?;
     if self.default_model.trim().is_empty() {
       anyhow::bail!("byok.providers[type=anthropic].default_model 不能为空");
     }
@@ -295,13 +608,28 @@ impl AnthropicProviderConfig {
 pub struct OpenAICompatibleProviderConfig {
   pub id: String,
   pub base_url: String,
-  pub api_key: String,
+  #[serde(alias = "api_keys")]
+  pub api_key: ApiKeys,
+  #[serde(default)]
+  pub key_pool: KeyPoolConfig,
+  #[serde(default)]
+  pub rate_limit: RateLimitConfig,
   pub default_model: String,
   #[serde(default = "default_max_tokens")]
   pub max_tokens: u32,
   #[serde(default = "default_timeout_seconds")]
   pub timeout_seconds: u64,
   #[serde(default)]
+  pub first_byte_timeout_seconds: Option<u64>,
+  #[serde(default)]
+  pub idle_timeout_seconds: Option<u64>,
+  #[serde(default)]
+  pub max_concurrent_requests: usize,
+  #[serde(default = "default_queue_timeout_seconds")]
+  pub queue_timeout_seconds: u64,
+  #[serde(default)]
+  pub send_reasoning_content: bool,
+  #[serde(default)]
   pub extra_headers: BTreeMap<String, String>,
 }
 
@@ -313,9 +641,13 @@ impl OpenAICompatibleProviderConfig {
     if self.base_url.trim().is_empty() {
       anyhow::bail!("byok.providers[type=openai_compatible].base_url 不能为空");
     }
-    if self.api_key.trim().is_empty() {
+    if self.api_key.is_empty() {
       anyhow::bail!("byok.providers[type=openai_compatible].api_key 不能为空");
     }
+    self
+      .key_pool
+      .validate()
+      .with_context(|| format!("byok.providers[type=openai_compatible][id={}]", self.id))?;
     if self.default_model.trim().is_empty() {
       anyhow::bail!("byok.providers[type=openai_compatible].default_model 不能为空");
     }
@@ -325,6 +657,132 @@ impl OpenAICompatibleProviderConfig {
   }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct GeminiProviderConfig {
+  pub id: String,
+  pub base_url: String,
+  #[serde(alias = "api_keys")]
+  pub api_key: ApiKeys,
+  #[serde(default)]
+  pub key_pool: KeyPoolConfig,
+  #[serde(default)]
+  pub rate_limit: RateLimitConfig,
+  pub default_model: String,
+  #[serde(default = "default_max_tokens")]
+  pub max_tokens: u32,
+  #[serde(default = "default_timeout_seconds")]
+  pub timeout_seconds: u64,
+  #[serde(default)]
+  pub first_byte_timeout_seconds: Option<u64>,
+  #[serde(default)]
+  pub idle_timeout_seconds: Option<u64>,
+  #[serde(default)]
+  pub max_concurrent_requests: usize,
+  #[serde(default = "default_queue_timeout_seconds")]
+  pub queue_timeout_seconds: u64,
+  #[serde(default)]
+  pub thinking: ThinkingConfig,
+  #[serde(default)]
+  pub extra_headers: BTreeMap<String, String>,
+}
+
+impl GeminiProviderConfig {
+  pub fn validate(&self) -> anyhow::Result<()> {
+    if self.id.trim().is_empty() {
+      anyhow::bail!("byok.providers[type=gemini].id 不能为空");
+    }
+    if self.base_url.trim().is_empty() {
+      anyhow::bail!("byok.providers[type=gemini].base_url 不能为空");
+    }
+    if self.api_key.is_empty() {
+      anyhow::bail!("byok.providers[type=gemini].api_key 不能为空");
+    }
+    self
+      .key_pool
+      .validate()
+      .with_context(|| format!("byok.providers[type=gemini][id={}]", self.id))?;
+    if self.default_model.trim().is_empty() {
+      anyhow::bail!("byok.providers[type=gemini].default_model 不能为空");
+    }
+    let _ =
+      Url::parse(&self.base_url).context("byok.providers[type=gemini].base_url 不是合法 URL")?;
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OpenAIResponsesProviderConfig {
+  pub id: String,
+  pub base_url: String,
+  #[serde(alias = "api_keys")]
+  pub api_key: ApiKeys,
+  #[serde(default)]
+  pub key_pool: KeyPoolConfig,
+  #[serde(default)]
+  pub rate_limit: RateLimitConfig,
+  pub default_model: String,
+  #[serde(default = "default_max_tokens")]
+  pub max_tokens: u32,
+  #[serde(default = "default_timeout_seconds")]
+  pub timeout_seconds: u64,
+  #[serde(default)]
+  pub first_byte_timeout_seconds: Option<u64>,
+  #[serde(default)]
+  pub idle_timeout_seconds: Option<u64>,
+  #[serde(default)]
+  pub max_concurrent_requests: usize,
+  #[serde(default = "default_queue_timeout_seconds")]
+  pub queue_timeout_seconds: u64,
+  #[serde(default)]
+  pub reasoning: ReasoningConfig,
+  #[serde(default)]
+  pub extra_headers: BTreeMap<String, String>,
+}
+
+impl OpenAIResponsesProviderConfig {
+  pub fn validate(&self) -> anyhow::Result<()> {
+    if self.id.trim().is_empty() {
+      anyhow::bail!("byok.providers[type=openai_responses].id 不能为空");
+    }
+    if self.base_url.trim().is_empty() {
+      anyhow::bail!("byok.providers[type=openai_responses].base_url 不能为空");
+    }
+    if self.api_key.is_empty() {
+      anyhow::bail!("byok.providers[type=openai_responses].api_key 不能为空");
+    }
+    self
+      .key_pool
+      .validate()
+      .with_context(|| format!("byok.providers[type=openai_responses][id={}]", self.id))?;
+    if self.default_model.trim().is_empty() {
+      anyhow::bail!("byok.providers[type=openai_responses].default_model 不能为空");
+    }
+    let _ = Url::parse(&self.base_url)
+      .context("byok.providers[type=openai_responses].base_url 不是合法 URL")?;
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ReasoningConfig {
+  #[serde(default = "default_thinking_enabled")]
+  pub enabled: bool,
+  #[serde(default)]
+  pub effort: String,
+  #[serde(default = "default_reasoning_summary")]
+  pub summary: String,
+}
+
+impl Default for ReasoningConfig {
+  fn default() -> Self {
+    Self {
+      enabled: default_thinking_enabled(),
+      effort: String::new(),
+      summary: default_reasoning_summary(),
+    }
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ThinkingConfig {
   #[serde(default = "default_thinking_enabled")]
@@ -343,22 +801,68 @@ impl Default for ThinkingConfig {
 }
 
 impl Config {
+  // 存在 <stem>.override.yaml（管理台保存生成）时叠加在 config.yaml 之上
   pub fn load(path: &Path) -> anyhow::Result<Self> {
-    let bytes = fs::read(path).with_context(|| format!("读取配置失败: {}", path.display()))?;
-    let config: Self =
-      serde_yaml::from_slice(&bytes).context("解析 YAML 配置失败 (config.yaml)")?;
+    let override_path = config_override::override_path(path);
+    let mut config = match config_override::read(&override_path)? {
+      None => Self::load_base(path)?,
+      Some(overlay) => {
+        let mut value = serde_json::to_value(Self::load_base(path)?).context("序列化配置失败")?;
+        config_override::merge(&mut value, overlay);
+        serde_json::from_value(value)
+          .with_context(|| format!("叠加覆盖配置后不合法 ({})", override_path.display()))?
+      }
+    };
+    config.resolve_secrets()?;
     config.validate()?;
     Ok(config)
   }
 
-  pub fn save(&self, path: &Path) -> anyhow::Result<()> {
-    let next = self.clone();
-    next.validate()?;
-    let yaml = serde_yaml::to_string(&next).context("序列化 YAML 配置失败")?;
-    fs::write(path, yaml).with_context(|| format!("写入配置失败: {}", path.display()))?;
+  // 只读 config.yaml 本身：不叠加覆盖配置，也不解析 secret 引用
+  fn load_base(path: &Path) -> anyhow::Result<Self> {
+    let bytes = fs::read(path).with_context(|| format!("读取配置失败: {}", path.display()))?;
+    serde_yaml::from_slice(&bytes).context("解析 YAML 配置失败 (config.yaml)")
+  }
+
+  // config.yaml 保持原样（注释、键顺序都在），与它的差异写入覆盖配置；返回覆盖配置路径
+  pub fn save(&self, path: &Path) -> anyhow::Result<PathBuf> {
+    self.validate()?;
+    let base = serde_json::to_value(Self::load_base(path)?).context("序列化配置失败")?;
+    let override_path = config_override::override_path(path);
+    config_override::write(&override_path, &base, &self.to_unresolved_value()?)?;
+    Ok(override_path)
+  }
+
+  // 刚解析出的配置里若有 ${ENV} / file: / cmd: 引用，替换为实际值并记下原始引用；可能执行外部命令
+  pub fn resolve_secrets(&mut self) -> anyhow::Result<()> {
+    self.resolve_secrets_with(SecretRefs::resolve)
+  }
+
+  // 管理台提交的配置：不执行 cmd: / 不读 file:，只沿用 known 里已解析的同一引用（见 SecretRefs::resolve_known）
+  pub fn resolve_secrets_from_admin(&mut self, known: &SecretRefs) -> anyhow::Result<()> {
+    self.resolve_secrets_with(|value| SecretRefs::resolve_known(value, known))
+  }
+
+  fn resolve_secrets_with(
+    &mut self,
+    resolve: impl FnOnce(&mut serde_json::Value) -> anyhow::Result<SecretRefs>,
+  ) -> anyhow::Result<()> {
+    let mut value = serde_json::to_value(&*self).context("序列化配置失败")?;
+    let refs = resolve(&mut value)?;
+    if !refs.is_empty() {
+      *self = serde_json::from_value(value).context("解析引用后的配置不合法")?;
+    }
+    self.secret_refs = refs;
     Ok(())
   }
 
+  // 对外展示/写回用：secret 字段还原为引用
+  pub fn to_unresolved_value(&self) -> anyhow::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(self).context("序列化配置失败")?;
+    self.secret_refs.restore(&mut value);
+    Ok(value)
+  }
+
   pub fn validate(&self) -> anyhow::Result<()> {
     if self.server.host.trim().is_empty() {
       anyhow::bail!("server.host 不能为空");
@@ -371,6 +875,82 @@ impl Config {
     self.byok.validate()?;
     self.history_summary.validate(&self.byok)?;
     self.logging.validate()?;
+    self.retry.validate()?;
+    self.circuit_breaker.validate()?;
+    self.routing.validate(&self.byok)?;
+    self.usage.validate(&self.byok)?;
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RetryConfig {
+  #[serde(default = "default_retry_max_attempts")]
+  pub max_attempts: u32,
+  #[serde(default = "default_retry_initial_backoff_ms")]
+  pub initial_backoff_ms: u64,
+  #[serde(default = "default_retry_max_backoff_ms")]
+  pub max_backoff_ms: u64,
+  #[serde(default = "default_retry_retryable_status_codes")]
+  pub retryable_status_codes: Vec<u16>,
+  #[serde(default = "default_retry_on_network_error")]
+  pub retry_on_network_error: bool,
+}
+
+impl Default for RetryConfig {
+  fn default() -> Self {
+    Self {
+      max_attempts: default_retry_max_attempts(),
+      initial_backoff_ms: default_retry_initial_backoff_ms(),
+      max_backoff_ms: default_retry_max_backoff_ms(),
+      retryable_status_codes: default_retry_retryable_status_codes(),
+      retry_on_network_error: default_retry_on_network_error(),
+    }
+  }
+}
+
+impl RetryConfig {
+  pub fn validate(&self) -> anyhow::Result<()> {
+    if self.max_attempts == 0 {
+      anyhow::bail!("retry.max_attempts 必须 >= 1（1 表示不重试）");
+    }
+    if self.initial_backoff_ms > self.max_backoff_ms {
+      anyhow::bail!("retry.initial_backoff_ms 不能大于 retry.max_backoff_ms");
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CircuitBreakerConfig {
+  #[serde(default = "default_circuit_breaker_enabled")]
+  pub enabled: bool,
+  // 同一 provider 连续失败（网络错误/5xx）达到该次数后熔断
+  #[serde(default = "default_circuit_breaker_failure_threshold")]
+  pub failure_threshold: u32,
+  // 熔断持续时间；到期后放行一个探测请求（半开），成功则恢复
+  #[serde(default = "default_circuit_breaker_open_seconds")]
+  pub open_seconds: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+  fn default() -> Self {
+    Self {
+      enabled: default_circuit_breaker_enabled(),
+      failure_threshold: default_circuit_breaker_failure_threshold(),
+      open_seconds: default_circuit_breaker_open_seconds(),
+    }
+  }
+}
+
+impl CircuitBreakerConfig {
+  pub fn validate(&self) -> anyhow::Result<()> {
+    if self.failure_threshold == 0 {
+      anyhow::bail!("circuit_breaker.failure_threshold 必须 >= 1");
+    }
+    if self.open_seconds == 0 {
+      anyhow::bail!("circuit_breaker.open_seconds 必须 >= 1");
+    }
     Ok(())
   }
 }
@@ -584,12 +1164,233 @@ impl AbridgedHistoryParams {
   }
 }
 
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct RoutingConfig {
+  // 按顺序匹配，命中第一条即停止；x-byok-mode / x-byok-model header 优先于规则
+  #[serde(default)]
+  pub rules: Vec<RoutingRule>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RoutingRule {
+  // 精确匹配；以 * 结尾表示前缀匹配（单独的 * 匹配所有路径）
+  pub path: String,
+  // 请求体 mode（如 AGENT / CHAT），忽略大小写；为空表示不限
+  #[serde(default, deserialize_with = "de_null_as_default")]
+  pub mode: String,
+  #[serde(default, deserialize_with = "de_null_as_default")]
+  pub message_source: String,
+  #[serde(default)]
+  pub min_body_bytes: Option<usize>,
+  #[serde(default)]
+  pub max_body_bytes: Option<usize>,
+  // byok | byok:<providerId>:<modelId> | official | disabled
+  pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteTarget {
+  // byok:<providerId>:<modelId> 原样保留，交给请求处理时的 model 解析
+  Byok(Option<String>),
+  Official,
+  Disabled,
+}
+
+impl RoutingRule {
+  pub fn route_target(&self) -> Option<RouteTarget> {
+    match self.target.trim() {
+      "byok" => Some(RouteTarget::Byok(None)),
+      "official" => Some(RouteTarget::Official),
+      "disabled" => Some(RouteTarget::Disabled),
+      other => split_byok_model_id(other).map(|_| RouteTarget::Byok(Some(other.to_string()))),
+    }
+  }
+}
+
+impl RoutingConfig {
+  pub fn validate(&self, byok: &ByokConfig) -> anyhow::Result<()> {
+    for (i, rule) in self.rules.iter().enumerate() {
+      if rule.path.trim().is_empty() {
+        anyhow::bail!("routing.rules[{i}].path 不能为空");
+      }
+      match rule.route_target() {
+        None => anyhow::bail!(
+          "routing.rules[{i}].target 无效（应为 byok / byok:<providerId>:<modelId> / official / disabled）：{}",
+          rule.target
+        ),
+        Some(RouteTarget::Byok(Some(target))) => {
+          let pid = split_byok_model_id(&target).map(|(pid, _)| pid).unwrap_or_default();
+          if !byok.providers.iter().any(|p| p.id().trim() == pid) {
+            anyhow::bail!("routing.rules[{i}].target provider 不存在：{}", rule.target);
+          }
+        }
+        Some(_) => {}
+      }
+      if let (Some(min), Some(max)) = (rule.min_body_bytes, rule.max_body_bytes) {
+        if min > max {
+          anyhow::bail!("routing.rules[{i}].min_body_bytes 不能大于 max_body_bytes");
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UsageConfig {
+  // 每次上游调用追加一行到 config.yaml 同目录的 usage_ledger.jsonl
+  #[serde(default = "default_usage_enabled")]
+  pub enabled: bool,
+  // key 为模型 id，精确匹配优先；以 * 结尾表示前缀匹配（取最长前缀）
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub prices: BTreeMap<String, ModelPrice>,
+  // 按 provider / model 的日、月预算（UTC 自然日/自然月），用量来自 usage ledger
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub budgets: Vec<BudgetConfig>,
+}
+
+impl Default for UsageConfig {
+  fn default() -> Self {
+    Self {
+      enabled: default_usage_enabled(),
+      prices: BTreeMap::new(),
+      budgets: Vec::new(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct BudgetConfig {
+  pub provider_id: String,
+  // 为空表示整个 provider（所有模型合计）
+  #[serde(default, deserialize_with = "de_null_as_default")]
+  pub model: String,
+  pub period: BudgetPeriod,
+  // 0 = 不限；USD 按 usage.prices 计算，input/output/cache token 合计
+  #[serde(default)]
+  pub max_usd: f64,
+  #[serde(default)]
+  pub max_tokens: u64,
+  #[serde(default)]
+  pub on_exceeded: BudgetAction,
+  // on_exceeded=fallback 时改用的模型：byok:<providerId>:<modelId>
+  #[serde(default, deserialize_with = "de_null_as_default")]
+  pub fallback: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetPeriod {
+  Daily,
+  Monthly,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetAction {
+  #[default]
+  Block,
+  Fallback,
+}
+
+impl BudgetConfig {
+  pub fn applies_to(&self, provider_id: &str, model: &str) -> bool {
+    self.provider_id.trim() == provider_id.trim()
+      && (self.model.trim().is_empty() || self.model.trim() == model.trim())
+  }
+
+  pub fn fallback_target(&self) -> Option<(&str, &str)> {
+    split_byok_model_id(&self.fallback)
+  }
+}
+
+// 单位：USD / 1M tokens
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ModelPrice {
+  #[serde(default)]
+  pub input: f64,
+  #[serde(default)]
+  pub output: f64,
+  #[serde(default)]
+  pub cache_read: f64,
+  #[serde(default)]
+  pub cache_creation: f64,
+}
+
+impl ModelPrice {
+  pub fn cost_usd(&self, input: i64, output: i64, cache_read: i64, cache_creation: i64) -> f64 {
+    let usd = |tokens: i64, per_million: f64| tokens.max(0) as f64 * per_million / 1_000_000.0;
+    usd(input, self.input)
+      + usd(output, self.output)
+      + usd(cache_read, self.cache_read)
+      + usd(cache_creation, self.cache_creation)
+  }
+}
+
+impl UsageConfig {
+  pub fn price_for(&self, model: &str) -> Option<&ModelPrice> {
+    let model = model.trim();
+    if let Some(p) = self.prices.get(model) {
+      return Some(p);
+    }
+    self
+      .prices
+      .iter()
+      .filter_map(|(k, p)| Some((k.strip_suffix('*')?, p)))
+      .filter(|(prefix, _)| model.starts_with(prefix))
+      .max_by_key(|(prefix, _)| prefix.len())
+      .map(|(_, p)| p)
+  }
+
+  pub fn validate(&self, byok: &ByokConfig) -> anyhow::Result<()> {
+    let provider_exists = |pid: &str| byok.providers.iter().any(|p| p.id().trim() == pid.trim());
+    for (i, b) in self.budgets.iter().enumerate() {
+      if !self.enabled {
+        anyhow::bail!("usage.budgets 依赖 usage ledger：请保持 usage.enabled=true");
+      }
+      if !provider_exists(&b.provider_id) {
+        anyhow::bail!("usage.budgets[{i}].provider_id 不存在：{}", b.provider_id);
+      }
+      if !b.max_usd.is_finite() || b.max_usd < 0.0 {
+        anyhow::bail!("usage.budgets[{i}].max_usd 必须 >= 0");
+      }
+      if b.max_usd == 0.0 && b.max_tokens == 0 {
+        anyhow::bail!("usage.budgets[{i}] 需要设置 max_usd 或 max_tokens");
+      }
+      if b.on_exceeded == BudgetAction::Fallback {
+        match b.fallback_target() {
+          None => anyhow::bail!(
+            "usage.budgets[{i}].fallback 无效（on_exceeded=fallback 时必填，格式 byok:<providerId>:<modelId>）：{}",
+            b.fallback
+          ),
+          Some((pid, _)) if !provider_exists(pid) => {
+            anyhow::bail!("usage.budgets[{i}].fallback provider 不存在：{}", b.fallback)
+          }
+          Some(_) => {}
+        }
+      }
+    }
+    for (model, p) in &self.prices {
+      if model.trim().is_empty() {
+        anyhow::bail!("usage.prices 的模型名不能为空");
+      }
+      let all = [p.input, p.output, p.cache_read, p.cache_creation];
+      if all.iter().any(|v| !v.is_finite() || *v < 0.0) {
+        anyhow::bail!("usage.prices.{model} 的价格必须是 >= 0 的数字");
+      }
+    }
+    Ok(())
+  }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LoggingConfig {
   #[serde(default = "default_logging_filter")]
   pub filter: String,
   #[serde(default)]
   pub dump_chat_stream_body: bool,
+  #[serde(default)]
+  pub transcripts: TranscriptConfig,
 }
 
 impl Default for LoggingConfig {
@@ -597,10 +1398,62 @@ impl Default for LoggingConfig {
     Self {
       filter: default_logging_filter(),
       dump_chat_stream_body: false,
+      transcripts: TranscriptConfig::default(),
+    }
+  }
+}
+
+// chat-stream 完整记录（原始请求 / 转换后的上游请求 / 上游 SSE 原文 / 输出的 NDJSON），每个请求一个文件
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TranscriptConfig {
+  #[serde(default)]
+  pub enabled: bool,
+  // 相对路径以 config.yaml 所在目录为基准
+  #[serde(default = "default_transcripts_dir")]
+  pub dir: String,
+  // 保留最近 N 个文件 / N 小时内的文件（0 = 不限）
+  #[serde(default = "default_transcripts_max_files")]
+  pub max_files: usize,
+  #[serde(default = "default_transcripts_max_age_hours")]
+  pub max_age_hours: u64,
+  // 单个文件超过该大小后不再追加 SSE / NDJSON 行（标记 truncated）
+  #[serde(default = "default_transcripts_max_file_bytes")]
+  pub max_file_bytes: usize,
+  // 过滤条件：为空表示不限；多个条件同时满足才记录
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub providers: Vec<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub models: Vec<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub conversation_ids: Vec<String>,
+}
+
+impl Default for TranscriptConfig {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      dir: default_transcripts_dir(),
+      max_files: default_transcripts_max_files(),
+      max_age_hours: default_transcripts_max_age_hours(),
+      max_file_bytes: default_transcripts_max_file_bytes(),
+      providers: Vec::new(),
+      models: Vec::new(),
+      conversation_ids: Vec::new(),
     }
   }
 }
 
+impl TranscriptConfig {
+  pub fn matches(&self, provider_id: &str, model: &str, conversation_id: &str) -> bool {
+    let allowed =
+      |list: &[String], v: &str| list.is_empty() || list.iter().any(|x| x.trim() == v.trim());
+    self.enabled
+      && allowed(&self.providers, provider_id)
+      && allowed(&self.models, model)
+      && allowed(&self.conversation_ids, conversation_id)
+  }
+}
+
 impl LoggingConfig {
   pub fn validate(&self) -> anyhow::Result<()> {
     if self.filter.trim().is_empty() {
@@ -608,17 +1461,48 @@ impl LoggingConfig {
     }
     tracing_subscriber::EnvFilter::try_new(self.filter.trim())
       .context("logging.filter 不是合法 tracing filter (EnvFilter 语法)")?;
+    if self.transcripts.enabled && self.transcripts.dir.trim().is_empty() {
+      anyhow::bail!("logging.transcripts.dir 不能为空");
+    }
     Ok(())
   }
 }
 
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
 pub fn init_tracing(logging: &LoggingConfig) -> anyhow::Result<()> {
-  let filter = tracing_subscriber::EnvFilter::try_new(logging.filter.trim())
+  let filter = EnvFilter::try_new(logging.filter.trim())
     .context("logging.filter 不是合法 tracing filter (EnvFilter 语法)")?;
+  let (filter, handle) = reload::Layer::new(filter);
+  tracing_subscriber::registry()
+    .with(filter)
+    .with(
+      tracing_subscriber::fmt::layer()
+        .with_target(false)
+        .compact(),
+    )
+    .init();
+  let _ = LOG_FILTER.set(handle);
+  Ok(())
+}
+
+// 子命令（replay、doctor）：stdout 留给报告，warn 及以上的日志写到 stderr
+pub fn init_cli_tracing() -> anyhow::Result<()> {
   tracing_subscriber::fmt()
-    .with_env_filter(filter)
+    .with_env_filter(EnvFilter::try_new("warn")?)
+    .with_writer(std::io::stderr)
     .with_target(false)
     .compact()
     .init();
   Ok(())
 }
+
+// 运行时替换 logging.filter；未经 init_tracing 初始化（replay、测试）时什么也不做
+pub fn reload_log_filter(filter: &str) -> anyhow::Result<()> {
+  let Some(handle) = LOG_FILTER.get() else {
+    return Ok(());
+  };
+  let filter = EnvFilter::try_new(filter.trim())
+    .context("logging.filter 不是合法 tracing filter (EnvFilter 语法)")?;
+  handle.reload(filter).context("更新 logging.filter 失败")
+}
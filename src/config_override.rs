@@ -0,0 +1,186 @@
+use std::{
+  fs,
+  io::ErrorKind,
+  path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde_json::{Map, Value};
+
+// 管理台「保存到文件」不重写 config.yaml（会丢注释与键顺序），而是把与 config.yaml 的差异写到同目录的
+// <stem>.override.yaml，加载时叠加在上面：对象逐键合并，数组与标量整体替换，null 表示删除该键（回到默认值）
+const HEADER: &str = "# 由管理台「保存到文件」生成，加载时叠加在同名 .yaml 之上（对象逐键合并，数组整体替换，null = 删除该键）。\n# 删除本文件即回到原始配置；直接编辑原始配置时，这里列出的字段仍以本文件为准。\n";
+
+pub fn override_path(path: &Path) -> PathBuf {
+  let stem = path
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or("config");
+  path.with_file_name(format!("{stem}.override.yaml"))
+}
+
+pub fn read(path: &Path) -> anyhow::Result<Option<Value>> {
+  let bytes = match fs::read(path) {
+    Ok(b) => b,
+    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+    Err(err) => return Err(err).with_context(|| format!("读取覆盖配置失败: {}", path.display())),
+  };
+  let value: Option<Value> = serde_yaml::from_slice(&bytes)
+    .with_context(|| format!("解析 YAML 覆盖配置失败 ({})", path.display()))?;
+  Ok(value.filter(|v| !v.is_null()))
+}
+
+// 与 base 相同时删除覆盖文件
+pub fn write(path: &Path, base: &Value, current: &Value) -> anyhow::Result<()> {
+  let Some(diff) = diff(base, current) else {
+    return match fs::remove_file(path) {
+      Err(err) if err.kind() != ErrorKind::NotFound => {
+        Err(err).with_context(|| format!("删除覆盖配置失败: {}", path.display()))
+      }
+      _ => Ok(()),
+    };
+  };
+  let yaml = serde_yaml::to_string(&diff).context("序列化 YAML 覆盖配置失败")?;
+  fs::write(path, format!("{HEADER}{yaml}"))
+    .with_context(|| format!("写入覆盖配置失败: {}", path.display()))
+}
+
+pub fn merge(base: &mut Value, overlay: Value) {
+  match (base, overlay) {
+    (Value::Object(base), Value::Object(overlay)) => {
+      for (k, v) in overlay {
+        if v.is_null() {
+          base.remove(&k);
+        } else if let Some(slot) = base.get_mut(&k) {
+          merge(slot, v);
+        } else {
+          base.insert(k, v);
+        }
+      }
+    }
+    (slot, v) => *slot = v,
+  }
+}
+
+// 满足 merge(base, diff(base, current)) == current（current 里的 null 与缺失等价）
+fn diff(base: &Value, current: &Value) -> Option<Value> {
+  if base == current {
+    return None;
+  }
+  let (Value::Object(base), Value::Object(current)) = (base, current) else {
+    return Some(current.clone());
+  };
+  let mut out = Map::new();
+  for (k, v) in current {
+    match base.get(k) {
+      Some(b) => {
+        if let Some(d) = diff(b, v) {
+          out.insert(k.clone(), d);
+        }
+      }
+      None if v.is_null() => {}
+      None => {
+        out.insert(k.clone(), v.clone());
+      }
+    }
+  }
+  for k in base.keys() {
+    if !current.contains_key(k) {
+      out.insert(k.clone(), Value::Null);
+    }
+  }
+  Some(Value::Object(out))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn diff_round_trips_through_merge() {
+    assert_eq!(
+      override_path(Path::new("/etc/byok/config.yaml")),
+      Path::new("/etc/byok/config.override.yaml")
+    );
+
+    let base = json!({
+      "server": { "host": "127.0.0.1", "port": 8317 },
+      "byok": { "providers": [{ "id": "a" }, { "id": "b" }], "active_provider_id": "a" },
+      "usage": { "prices": { "claude-*": { "input": 3 }, "gpt-*": { "input": 1 } } },
+    });
+    let current = json!({
+      "server": { "host": "127.0.0.1", "port": 9000 },
+      "byok": { "providers": [{ "id": "a" }], "active_provider_id": null },
+      "usage": { "prices": { "claude-*": { "input": 3 } }, "budget": { "daily_usd": 5 } },
+    });
+    let d = diff(&base, &current).unwrap();
+    assert_eq!(
+      d,
+      json!({
+        "server": { "port": 9000 },
+        "byok": { "providers": [{ "id": "a" }], "active_provider_id": null },
+        "usage": { "prices": { "gpt-*": null }, "budget": { "daily_usd": 5 } },
+      })
+    );
+    let mut merged = base.clone();
+    merge(&mut merged, d);
+    let mut expected = current.clone();
+    expected["byok"]
+      .as_object_mut()
+      .unwrap()
+      .remove("active_provider_id");
+    assert_eq!(merged, expected);
+    assert_eq!(diff(&base, &base), None);
+
+    let dir =
+      std::env::temp_dir().join(format!("byok_config_override_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = override_path(&dir.join("config.yaml"));
+    write(&path, &base, &current).unwrap();
+    assert!(fs::read_to_string(&path).unwrap().starts_with("# "));
+    assert_eq!(read(&path).unwrap(), diff(&base, &current));
+    write(&path, &base, &base).unwrap();
+    assert!(!path.exists());
+    assert_eq!(read(&path).unwrap(), None);
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn save_leaves_config_yaml_untouched() {
+    use crate::config::Config;
+
+    let dir = std::env::temp_dir().join(format!("byok_config_save_test_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.yaml");
+    let original = r#"# 顶部注释
+server: { host: "127.0.0.1", port: 8317 } # 行尾注释
+proxy: { auth_token: "${BYOK_SAVE_TEST_TOKEN:-proxy-token}" }
+official: { base_url: "https://example.com/", api_token: "t" }
+byok:
+  providers:
+    - { type: anthropic, id: a, base_url: "https://a/v1", api_key: "k", default_model: m }
+"#;
+    fs::write(&path, original).unwrap();
+
+    let mut cfg = Config::load(&path).unwrap();
+    cfg.server.port = 9000;
+    let saved = cfg.save(&path).unwrap();
+    assert_eq!(saved, dir.join("config.override.yaml"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    // 未改动的字段（包括 secret 引用）不进覆盖配置
+    assert_eq!(
+      read(&saved).unwrap(),
+      Some(json!({ "server": { "port": 9000 } }))
+    );
+
+    let reloaded = Config::load(&path).unwrap();
+    assert_eq!(reloaded.server.port, 9000);
+    assert_eq!(reloaded.proxy.auth_token, "proxy-token");
+
+    cfg.server.port = 8317;
+    cfg.save(&path).unwrap();
+    assert!(!saved.exists());
+    let _ = fs::remove_dir_all(&dir);
+  }
+}
@@ -0,0 +1,150 @@
+use std::{path::Path, time::Duration};
+
+use tracing::{debug, error, info};
+
+use crate::{config::Config, config_override, AppState};
+
+// config.yaml 与其覆盖配置（不存在时为 None）的内容
+type Snapshot = (Vec<u8>, Option<Vec<u8>>);
+
+async fn snapshot(path: &Path) -> Option<Snapshot> {
+  let base = tokio::fs::read(path).await.ok()?;
+  let overlay = tokio::fs::read(config_override::override_path(path))
+    .await
+    .ok();
+  Some((base, overlay))
+}
+
+pub(crate) fn spawn(state: AppState) {
+  tokio::spawn(async move {
+    let mut last = snapshot(&state.config_path).await;
+    loop {
+      let interval = state.cfg.read().await.server.config_watch_interval_seconds;
+      // 0 = 关闭监听；仍然定期醒来，以便热更新把它重新打开
+      let poll = if interval == 0 { 2 } else { interval };
+      tokio::time::sleep(Duration::from_secs(poll)).await;
+      if interval > 0 {
+        reload_if_changed(&state, &mut last).await;
+      }
+    }
+  });
+}
+
+// 按文件内容判断是否变更（比 mtime 可靠，编辑器先删后写、时间精度不足都不受影响）
+async fn reload_if_changed(state: &AppState, last: &mut Option<Snapshot>) {
+  let Some(current) = snapshot(&state.config_path).await else {
+    // 编辑器保存时文件可能短暂不存在；保持 last 不变，下一轮再看
+    return;
+  };
+  if last.as_ref() == Some(&current) {
+    return;
+  }
+  *last = Some(current);
+
+  // cmd: 引用会执行外部命令，放到阻塞线程池
+  let path = state.config_path.clone();
+  let next = match tokio::task::spawn_blocking(move || Config::load(&path)).await {
+    Ok(Ok(v)) => v,
+    Ok(Err(err)) => {
+      error!(error=%format!("{err:#}"), path=%state.config_path.display(), "config.yaml 已变更但加载失败，继续使用之前的配置");
+      return;
+    }
+    Err(err) => {
+      error!(error=%err, "config.yaml 重新加载任务失败");
+      return;
+    }
+  };
+
+  let current = state.cfg.read().await.clone();
+  // 管理台保存到文件也会触发一次变更，内容等价时不必替换
+  if serde_json::to_value(&current).ok() == serde_json::to_value(&next).ok()
+    && current.secret_refs == next.secret_refs
+  {
+    debug!("config.yaml 已变更，但解析后的配置与当前一致");
+    return;
+  }
+  match state.apply_config(next).await {
+    Ok(changed_providers) => {
+      info!(changed_providers=?changed_providers, "config.yaml 已变更，配置已热更新")
+    }
+    Err(err) => {
+      error!(error=%format!("{err:#}"), "config.yaml 已变更但无法应用，继续使用之前的配置")
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ModelCacheEntry;
+
+  fn write_config(path: &std::path::Path, base_url: &str, auth_token: &str) {
+    std::fs::write(
+      path,
+      format!(
+        r#"
+server: {{ host: "127.0.0.1", port: 8317 }}
+proxy: {{ auth_token: "{auth_token}" }}
+official: {{ base_url: "https://example.com/", api_token: "t" }}
+byok:
+  providers:
+    - {{ type: anthropic, id: a, base_url: "{base_url}", api_key: "k", default_model: m }}
+    - {{ type: anthropic, id: b, base_url: "https://b/v1", api_key: "k", default_model: m }}
+"#
+      ),
+    )
+    .unwrap();
+  }
+
+  #[tokio::test]
+  async fn reloads_valid_edits_and_keeps_config_on_invalid_ones() {
+    let dir = std::env::temp_dir().join(format!("byok_config_watch_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.yaml");
+    write_config(&path, "https://a/v1", "token-1");
+    let cfg = Config::load(&path).unwrap();
+    let state = AppState::new(path.clone(), cfg, reqwest::Client::new()).await;
+    {
+      let mut cache = state.models_cache.write().await;
+      for id in ["a", "b"] {
+        cache.providers.insert(
+          id.to_string(),
+          ModelCacheEntry {
+            kind: "anthropic".to_string(),
+            base_url: String::new(),
+            updated_at_ms: 0,
+            models: vec!["m".to_string()],
+          },
+        );
+      }
+    }
+    let mut last = snapshot(&path).await;
+
+    std::fs::write(&path, "server: [not valid").unwrap();
+    reload_if_changed(&state, &mut last).await;
+    assert_eq!(state.cfg.read().await.proxy.auth_token, "token-1");
+
+    write_config(&path, "https://a2/v1", "token-2");
+    reload_if_changed(&state, &mut last).await;
+    assert_eq!(state.cfg.read().await.proxy.auth_token, "token-2");
+    let cached: Vec<String> = state
+      .models_cache
+      .read()
+      .await
+      .providers
+      .keys()
+      .cloned()
+      .collect();
+    assert_eq!(cached, ["b"]);
+
+    // 覆盖配置的变更同样触发热更新
+    std::fs::write(
+      config_override::override_path(&path),
+      "proxy: { auth_token: token-3 }\n",
+    )
+    .unwrap();
+    reload_if_changed(&state, &mut last).await;
+    assert_eq!(state.cfg.read().await.proxy.auth_token, "token-3");
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+}
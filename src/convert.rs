@@ -5,14 +5,27 @@ use serde_json::Value;
 
 use crate::{
   anthropic::{
-    AnthropicContentBlock, AnthropicImageSource, AnthropicMessage, AnthropicRequest,
-    AnthropicThinking, AnthropicTool, AnthropicToolChoice,
+    AnthropicCacheControl, AnthropicContentBlock, AnthropicImageSource, AnthropicMessage,
+    AnthropicRequest, AnthropicThinking, AnthropicTool, AnthropicToolChoice,
+  },
+  config::{
+    AnthropicProviderConfig, GeminiProviderConfig, OpenAICompatibleProviderConfig,
+    OpenAIResponsesProviderConfig,
+  },
+  gemini::{
+    GeminiContent, GeminiFunctionCall, GeminiFunctionCallingConfig, GeminiFunctionDeclaration,
+    GeminiFunctionResponse, GeminiGenerationConfig, GeminiInlineData, GeminiPart, GeminiRequest,
+    GeminiThinkingConfig, GeminiTool, GeminiToolConfig,
   },
-  config::{AnthropicProviderConfig, OpenAICompatibleProviderConfig},
   openai::{
     OpenAIChatCompletionRequest, OpenAIChatMessage, OpenAIFunctionCall, OpenAIStreamOptions,
     OpenAITool, OpenAIToolCall,
   },
+  openai_responses::{
+    OpenAIResponsesInputItem, OpenAIResponsesOutputItem, OpenAIResponsesReasoning,
+    OpenAIResponsesRequest, OpenAIResponsesResponse, OpenAIResponsesStreamEvent,
+    OpenAIResponsesSummaryText, OpenAIResponsesTool,
+  },
   protocol::{
     AugmentChatHistory, AugmentRequest, NodeIn, NodeOut, TextNode, ThinkingNode, TokenUsageNode,
     ToolDefinition, ToolResultContentNode, ToolUse, REQUEST_NODE_CHANGE_PERSONALITY,
@@ -21,8 +34,10 @@ use crate::{
     REQUEST_NODE_IMAGE_ID, REQUEST_NODE_TEXT, REQUEST_NODE_TOOL_RESULT,
     RESPONSE_NODE_MAIN_TEXT_FINISHED, RESPONSE_NODE_RAW_RESPONSE, RESPONSE_NODE_THINKING,
     RESPONSE_NODE_TOKEN_USAGE, RESPONSE_NODE_TOOL_USE, RESPONSE_NODE_TOOL_USE_START,
-    STOP_REASON_END_TURN, STOP_REASON_MAX_TOKENS, STOP_REASON_RECITATION, STOP_REASON_SAFETY,
-    STOP_REASON_TOOL_USE_REQUESTED, TOOL_RESULT_CONTENT_NODE_IMAGE, TOOL_RESULT_CONTENT_NODE_TEXT,
+    STOP_REASON_END_TURN, STOP_REASON_MALFORMED_FUNCTION_CALL, STOP_REASON_MAX_TOKENS,
+    STOP_REASON_RECITATION, STOP_REASON_SAFETY, STOP_REASON_TOOL_USE_REQUESTED,
+    THINKING_PROVIDER_ANTHROPIC, THINKING_PROVIDER_GEMINI, THINKING_PROVIDER_OPENAI_RESPONSES,
+    TOOL_RESULT_CONTENT_NODE_IMAGE, TOOL_RESULT_CONTENT_NODE_TEXT,
   },
 };
 
@@ -84,8 +99,10 @@ pub fn convert_augment_to_anthropic(
       augment.chat_history.as_slice(),
       index,
       history,
+      provider.thinking.enabled,
     )?;
   }
+  let history_message_count = messages.len();
 
   let virtual_nodes = build_virtual_context_text_nodes(augment);
   let current_nodes = augment
@@ -104,7 +121,7 @@ pub fn convert_augment_to_anthropic(
     }
   }
 
-  let tools = convert_tools(&augment.tool_definitions)?;
+  let mut tools = convert_tools(&augment.tool_definitions)?;
   let tool_choice = (!tools.is_empty()).then(|| AnthropicToolChoice {
     choice_type: "auto".to_string(),
     name: None,
@@ -115,11 +132,24 @@ pub fn convert_augment_to_anthropic(
     budget_tokens: provider.thinking.budget_tokens,
   });
 
+  let mut system_blocks: Vec<AnthropicContentBlock> = Vec::new();
+  if !system.is_empty() {
+    system_blocks.push(text_block(&system));
+  }
+
+  if provider.prompt_caching {
+    apply_anthropic_cache_breakpoints(
+      &mut tools,
+      &mut system_blocks,
+      &mut messages[..history_message_count],
+    );
+  }
+
   Ok(AnthropicRequest {
     model,
     messages,
     max_tokens: provider.max_tokens,
-    system: (!system.is_empty()).then_some(system),
+    system: (!system_blocks.is_empty()).then_some(system_blocks),
     temperature: None,
     top_p: None,
     top_k: None,
@@ -131,6 +161,36 @@ pub fn convert_augment_to_anthropic(
   })
 }
 
+// 缓存前缀顺序为 tools → system → messages；这里最多放 3 个断点（上游上限 4 个）：
+// 最后一个 tool、system、以及 chat_history 转换出的最后一条消息（当前轮用户输入每次都变，不缓存）。
+fn apply_anthropic_cache_breakpoints(
+  tools: &mut [AnthropicTool],
+  system: &mut [AnthropicContentBlock],
+  history: &mut [AnthropicMessage],
+) {
+  if let Some(tool) = tools.last_mut() {
+    tool.cache_control = Some(ephemeral_cache_control());
+  }
+  if let Some(block) = system.last_mut() {
+    block.cache_control = Some(ephemeral_cache_control());
+  }
+  // thinking/redacted_thinking 不能直接打断点
+  if let Some(block) = history
+    .iter_mut()
+    .rev()
+    .flat_map(|m| m.content.iter_mut().rev())
+    .find(|b| b.block_type != "thinking" && b.block_type != "redacted_thinking")
+  {
+    block.cache_control = Some(ephemeral_cache_control());
+  }
+}
+
+fn ephemeral_cache_control() -> AnthropicCacheControl {
+  AnthropicCacheControl {
+    cache_type: "ephemeral".to_string(),
+  }
+}
+
 #[derive(Debug, Clone)]
 enum OpenAISegment {
   Text(String),
@@ -436,6 +496,7 @@ fn build_openai_tool_messages_from_request_nodes<'a>(
       content: Some(Value::String(content)),
       tool_calls: None,
       tool_call_id: Some(tool_use_id.to_string()),
+      reasoning_content: None,
     });
   }
   out
@@ -464,11 +525,25 @@ fn convert_openai_tools(defs: &[ToolDefinition]) -> anyhow::Result<Vec<OpenAIToo
   Ok(tools)
 }
 
+fn extract_reasoning_from_output_nodes<'a>(
+  nodes: impl Iterator<Item = &'a NodeIn>,
+) -> Option<String> {
+  let reasoning = nodes
+    .filter(|n| n.node_type == RESPONSE_NODE_THINKING)
+    .filter_map(|n| n.thinking.as_ref())
+    .map(|t| t.summary.as_str())
+    .filter(|s| !s.trim().is_empty())
+    .collect::<Vec<_>>()
+    .join("\n");
+  (!reasoning.is_empty()).then_some(reasoning)
+}
+
 fn push_history_messages_openai(
   out: &mut Vec<OpenAIChatMessage>,
   all: &[AugmentChatHistory],
   index: usize,
   history: &AugmentChatHistory,
+  include_reasoning: bool,
 ) -> anyhow::Result<()> {
   let req_nodes = history
     .request_nodes
@@ -482,6 +557,7 @@ fn push_history_messages_openai(
       content: Some(content),
       tool_calls: None,
       tool_call_id: None,
+      reasoning_content: None,
     });
   }
 
@@ -498,12 +574,18 @@ fn push_history_messages_openai(
   let has_tool_calls = !tool_calls.is_empty();
   let content =
     (!assistant_text.trim().is_empty()).then(|| Value::String(assistant_text.trim().to_string()));
+  let reasoning_content = if include_reasoning {
+    extract_reasoning_from_output_nodes(out_nodes.clone())
+  } else {
+    None
+  };
   if content.is_some() || !tool_calls.is_empty() {
     out.push(OpenAIChatMessage {
       role: "assistant".to_string(),
       content,
       tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
       tool_call_id: None,
+      reasoning_content,
     });
   }
 
@@ -536,6 +618,7 @@ pub fn convert_augment_to_openai_compatible(
       content: Some(Value::String(system)),
       tool_calls: None,
       tool_call_id: None,
+      reasoning_content: None,
     });
   }
 
@@ -545,6 +628,7 @@ pub fn convert_augment_to_openai_compatible(
       augment.chat_history.as_slice(),
       index,
       history,
+      provider.send_reasoning_content,
     )?;
   }
 
@@ -569,6 +653,7 @@ pub fn convert_augment_to_openai_compatible(
       content: Some(content),
       tool_calls: None,
       tool_call_id: None,
+      reasoning_content: None,
     });
   }
 
@@ -590,132 +675,208 @@ pub fn convert_augment_to_openai_compatible(
   })
 }
 
-fn build_system_prompt(augment: &AugmentRequest) -> String {
-  let mut parts: Vec<String> = Vec::new();
-  if !augment.user_guidelines.trim().is_empty() {
-    parts.push(augment.user_guidelines.trim().to_string());
-  }
-  if !augment.workspace_guidelines.trim().is_empty() {
-    parts.push(augment.workspace_guidelines.trim().to_string());
+fn gemini_parts_from_segments(segments: Vec<OpenAISegment>) -> Vec<GeminiPart> {
+  let mut parts: Vec<GeminiPart> = Vec::new();
+  for s in segments {
+    match s {
+      OpenAISegment::Text(t) => {
+        let t = t.trim();
+        if t.is_empty() {
+          continue;
+        }
+        parts.push(GeminiPart {
+          text: Some(t.to_string()),
+          ..Default::default()
+        });
+      }
+      OpenAISegment::Image { media_type, data } => {
+        let data = data.trim();
+        if data.is_empty() {
+          continue;
+        }
+        parts.push(GeminiPart {
+          inline_data: Some(GeminiInlineData {
+            mime_type: media_type,
+            data: data.to_string(),
+          }),
+          ..Default::default()
+        });
+      }
+    }
   }
-  let rules_text = match &augment.rules {
-    Value::String(s) => s.trim().to_string(),
-    Value::Array(arr) => arr
-      .iter()
-      .filter_map(|x| x.as_str())
-      .map(str::trim)
-      .filter(|s| !s.is_empty())
-      .collect::<Vec<_>>()
-      .join("\n"),
-    _ => String::new(),
-  };
-  if !rules_text.trim().is_empty() {
-    parts.push(rules_text);
+  parts
+}
+
+fn push_gemini_content(out: &mut Vec<GeminiContent>, role: &str, parts: Vec<GeminiPart>) {
+  if parts.is_empty() {
+    return;
   }
-  if !augment.agent_memories.trim().is_empty() {
-    parts.push(augment.agent_memories.trim().to_string());
+  if let Some(last) = out.last_mut() {
+    if last.role == role {
+      last.parts.extend(parts);
+      return;
+    }
   }
-  if augment.mode.trim() == "AGENT" {
-    parts.push(
-			"You are an AI coding assistant with access to tools. Use tools when needed to complete tasks."
-				.to_string(),
-			);
+  out.push(GeminiContent {
+    role: role.to_string(),
+    parts,
+  });
+}
+
+fn build_gemini_model_parts<'a>(
+  text: &str,
+  nodes: impl Iterator<Item = &'a NodeIn> + Clone,
+) -> Vec<GeminiPart> {
+  let signature = nodes
+    .clone()
+    .filter(|n| n.node_type == RESPONSE_NODE_THINKING)
+    .filter_map(|n| n.thinking.as_ref())
+    .filter(|t| t.written_by(THINKING_PROVIDER_GEMINI))
+    .map(|t| t.signature.trim())
+    .find(|s| !s.is_empty())
+    .map(str::to_string);
+
+  let mut parts: Vec<GeminiPart> = Vec::new();
+  if !text.trim().is_empty() {
+    parts.push(GeminiPart {
+      text: Some(text.trim().to_string()),
+      ..Default::default()
+    });
   }
-  let ctx = augment.context.as_ref();
-  let lang = augment.lang.trim();
-  let lang = if lang.is_empty() {
-    ctx.map(|c| c.lang.trim()).unwrap_or("")
-  } else {
-    lang
-  };
-  if !lang.is_empty() {
-    parts.push(format!("The user is working with {} code.", lang));
+  for call in build_openai_tool_calls_from_output_nodes(nodes) {
+    let args: Value = serde_json::from_str(&call.function.arguments)
+      .unwrap_or_else(|_| Value::Object(serde_json::Map::new()));
+    parts.push(GeminiPart {
+      function_call: Some(GeminiFunctionCall {
+        id: Some(call.id),
+        name: call.function.name,
+        args: Some(args),
+      }),
+      ..Default::default()
+    });
   }
-  let path = augment.path.trim();
-  let path = if path.is_empty() {
-    ctx.map(|c| c.path.trim()).unwrap_or("")
-  } else {
-    path
-  };
-  if !path.is_empty() {
-    parts.push(format!("Current file path: {}", path));
+
+  // Gemini 要求把 thoughtSignature 原样放回：有 functionCall 时放在第一个 functionCall part 上，否则放在第一个 part 上。
+  if let Some(signature) = signature {
+    let idx = parts
+      .iter()
+      .position(|p| p.function_call.is_some())
+      .unwrap_or(0);
+    if let Some(part) = parts.get_mut(idx) {
+      part.thought_signature = Some(signature);
+    }
   }
-  parts.join("\n\n")
+  parts
 }
 
-fn build_virtual_context_text_nodes(augment: &AugmentRequest) -> Vec<NodeIn> {
-  let ctx = augment.context.as_ref();
-  let message = augment.message.trim();
-
-  let prefix = if !augment.prefix.trim().is_empty() {
-    augment.prefix.trim()
-  } else {
-    ctx.map(|c| c.prefix.trim()).unwrap_or("")
-  };
-  let selected_code = if !augment.selected_code.trim().is_empty() {
-    augment.selected_code.trim()
-  } else {
-    ctx.map(|c| c.selected_code.trim()).unwrap_or("")
-  };
-  let suffix = if !augment.suffix.trim().is_empty() {
-    augment.suffix.trim()
-  } else {
-    ctx.map(|c| c.suffix.trim()).unwrap_or("")
-  };
-
-  let code = format!("{prefix}{selected_code}{suffix}")
-    .trim()
-    .to_string();
-  let diff = if !augment.diff.trim().is_empty() {
-    augment.diff.trim()
-  } else {
-    ctx.map(|c| c.diff.trim()).unwrap_or("")
-  }
-  .trim()
-  .to_string();
+fn build_gemini_tool_names_by_id<'a>(
+  nodes: impl Iterator<Item = &'a NodeIn>,
+) -> HashMap<String, String> {
+  build_openai_tool_calls_from_output_nodes(nodes)
+    .into_iter()
+    .map(|c| (c.id, c.function.name))
+    .collect()
+}
 
-  let mut next_id: i32 = 1_000_000;
-  let mut out: Vec<NodeIn> = Vec::new();
-  let mut push_text = |out: &mut Vec<NodeIn>, s: String| {
-    let trimmed = s.trim();
-    if trimmed.is_empty() || is_user_placeholder_message(trimmed) {
-      return;
+fn build_gemini_function_responses<'a>(
+  nodes: impl Iterator<Item = &'a NodeIn>,
+  names_by_id: &HashMap<String, String>,
+) -> Vec<GeminiPart> {
+  let mut parts: Vec<GeminiPart> = Vec::new();
+  for node in nodes {
+    if node.node_type != REQUEST_NODE_TOOL_RESULT {
+      continue;
     }
-    next_id += 1;
-    out.push(NodeIn {
-      id: next_id,
-      node_type: REQUEST_NODE_TEXT,
-      content: String::new(),
-      text_node: Some(TextNode {
-        content: trimmed.to_string(),
+    let Some(tool) = &node.tool_result_node else {
+      continue;
+    };
+    let tool_use_id = tool.tool_use_id.trim();
+    let Some(name) = names_by_id.get(tool_use_id) else {
+      continue;
+    };
+    let text = build_openai_tool_result_text(tool.content.as_str(), &tool.content_nodes);
+    let response = if tool.is_error {
+      serde_json::json!({ "error": text })
+    } else {
+      serde_json::json!({ "output": text })
+    };
+    parts.push(GeminiPart {
+      function_response: Some(GeminiFunctionResponse {
+        id: Some(tool_use_id.to_string()),
+        name: name.clone(),
+        response,
       }),
-      tool_result_node: None,
-      image_node: None,
-      image_id_node: None,
-      ide_state_node: None,
-      edit_events_node: None,
-      checkpoint_ref_node: None,
-      change_personality_node: None,
-      file_node: None,
-      file_id_node: None,
-      history_summary_node: None,
-      tool_use: None,
-      thinking: None,
+      ..Default::default()
     });
-  };
-
-  if !code.is_empty() && code != message {
-    push_text(&mut out, code.clone());
   }
-  if !diff.is_empty() && diff != message && diff != code {
-    push_text(&mut out, diff);
+  parts
+}
+
+const GEMINI_UNSUPPORTED_SCHEMA_KEYS: &[&str] = &[
+  "$schema",
+  "$id",
+  "$ref",
+  "$defs",
+  "definitions",
+  "additionalProperties",
+  "patternProperties",
+  "examples",
+];
+
+fn sanitize_gemini_schema(v: &mut Value) {
+  match v {
+    Value::Object(map) => {
+      for key in GEMINI_UNSUPPORTED_SCHEMA_KEYS {
+        map.remove(*key);
+      }
+      for (key, child) in map.iter_mut() {
+        if key == "properties" {
+          if let Value::Object(props) = child {
+            for prop in props.values_mut() {
+              sanitize_gemini_schema(prop);
+            }
+            continue;
+          }
+        }
+        sanitize_gemini_schema(child);
+      }
+    }
+    Value::Array(items) => {
+      for item in items {
+        sanitize_gemini_schema(item);
+      }
+    }
+    _ => {}
   }
+}
 
-  out
+fn convert_gemini_tools(defs: &[ToolDefinition]) -> anyhow::Result<Vec<GeminiFunctionDeclaration>> {
+  let mut decls: Vec<GeminiFunctionDeclaration> = Vec::with_capacity(defs.len());
+  for def in defs {
+    let mut schema = if let Some(v) = &def.input_schema {
+      v.clone()
+    } else if !def.input_schema_json.trim().is_empty() {
+      serde_json::from_str(&def.input_schema_json)
+        .with_context(|| format!("解析 tool input_schema_json 失败: {}", def.name))?
+    } else {
+      serde_json::json!({"type":"object","properties":{}})
+    };
+    sanitize_gemini_schema(&mut schema);
+    let has_properties = schema
+      .get("properties")
+      .and_then(|p| p.as_object())
+      .is_some_and(|p| !p.is_empty());
+    decls.push(GeminiFunctionDeclaration {
+      name: def.name.clone(),
+      description: (!def.description.trim().is_empty()).then_some(def.description.clone()),
+      parameters: has_properties.then_some(schema),
+    });
+  }
+  Ok(decls)
 }
 
-fn push_history_messages(
-  out: &mut Vec<AnthropicMessage>,
+fn push_history_messages_gemini(
+  out: &mut Vec<GeminiContent>,
   all: &[AugmentChatHistory],
   index: usize,
   history: &AugmentChatHistory,
@@ -725,13 +886,8 @@ fn push_history_messages(
     .iter()
     .chain(&history.structured_request_nodes)
     .chain(&history.nodes);
-  let user_content = build_user_content_blocks(&history.request_message, req_nodes, false)?;
-  if !user_content.is_empty() {
-    out.push(AnthropicMessage {
-      role: "user".to_string(),
-      content: user_content,
-    });
-  }
+  let req_segments = build_openai_user_segments(&history.request_message, req_nodes)?;
+  push_gemini_content(out, "user", gemini_parts_from_segments(req_segments));
 
   let out_nodes = history
     .response_nodes
@@ -742,859 +898,2003 @@ fn push_history_messages(
   } else {
     history.response_text.clone()
   };
-  let assistant_content = build_assistant_content_blocks(&assistant_text, out_nodes)?;
-  let has_tool_use = assistant_content.iter().any(|b| b.block_type == "tool_use");
-  if !assistant_content.is_empty() {
-    out.push(AnthropicMessage {
-      role: "assistant".to_string(),
-      content: assistant_content,
-    });
-  }
+  let model_parts = build_gemini_model_parts(&assistant_text, out_nodes.clone());
+  let has_function_calls = model_parts.iter().any(|p| p.function_call.is_some());
+  push_gemini_content(out, "model", model_parts);
 
   if let Some(next) = all.get(index + 1) {
-    if has_tool_use {
+    if has_function_calls {
+      let names_by_id = build_gemini_tool_names_by_id(out_nodes);
       let next_req_nodes = next
         .request_nodes
         .iter()
         .chain(&next.structured_request_nodes)
         .chain(&next.nodes);
-      let tool_results = build_tool_results(next_req_nodes)?;
-      if !tool_results.is_empty() {
-        out.push(AnthropicMessage {
-          role: "user".to_string(),
-          content: tool_results,
-        });
-      }
+      push_gemini_content(
+        out,
+        "user",
+        build_gemini_function_responses(next_req_nodes, &names_by_id),
+      );
     }
   }
-
   Ok(())
 }
 
-fn build_user_content_blocks<'a>(
-  message: &str,
-  nodes: impl Iterator<Item = &'a NodeIn>,
-  include_tool_results: bool,
-) -> anyhow::Result<Vec<AnthropicContentBlock>> {
-  let mut blocks: Vec<AnthropicContentBlock> = Vec::new();
-  let mut last_text: Option<String> = None;
-  let message_trimmed = message.trim();
-  let skip_message_text = (!message_trimmed.is_empty()
-    && !is_user_placeholder_message(message_trimmed))
-  .then_some(message_trimmed);
-  push_text_block(&mut blocks, &mut last_text, message);
+pub fn convert_augment_to_gemini(
+  provider: &GeminiProviderConfig,
+  augment: &AugmentRequest,
+) -> anyhow::Result<GeminiRequest> {
+  let mut contents: Vec<GeminiContent> = Vec::new();
 
-  for node in nodes {
-    match node.node_type {
-      REQUEST_NODE_TEXT => {
-        if let Some(t) = &node.text_node {
-          if skip_message_text == Some(t.content.trim()) {
-            continue;
-          }
-          push_text_block(&mut blocks, &mut last_text, &t.content);
-        }
-      }
-      REQUEST_NODE_TOOL_RESULT => {
-        if include_tool_results {
-          if let Some(tool) = &node.tool_result_node {
-            let tool_use_id = tool.tool_use_id.trim();
-            if tool_use_id.is_empty() {
-              continue;
-            }
-            let content =
-              build_anthropic_tool_result_content(tool.content.as_str(), &tool.content_nodes);
-            blocks.push(AnthropicContentBlock {
-              block_type: "tool_result".to_string(),
-              text: None,
-              source: None,
-              id: None,
-              name: None,
-              input: None,
-              tool_use_id: Some(tool_use_id.to_string()),
-              content: Some(content),
-              is_error: Some(tool.is_error),
-              thinking: None,
-              signature: None,
-            });
-            last_text = None;
-          }
-        }
-      }
-      REQUEST_NODE_IMAGE => {
-        if let Some(img) = &node.image_node {
-          blocks.push(image_block_from_format_and_data(
-            img.format,
-            &img.image_data,
-          ));
-          last_text = None;
-        }
-      }
-      REQUEST_NODE_IMAGE_ID => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.image_id_node.as_ref(),
-        format_image_id_for_prompt,
-      ),
-      REQUEST_NODE_IDE_STATE => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.ide_state_node.as_ref(),
-        format_ide_state_for_prompt,
-      ),
-      REQUEST_NODE_EDIT_EVENTS => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.edit_events_node.as_ref(),
-        format_edit_events_for_prompt,
-      ),
-      REQUEST_NODE_CHECKPOINT_REF => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.checkpoint_ref_node.as_ref(),
-        format_checkpoint_ref_for_prompt,
-      ),
-      REQUEST_NODE_CHANGE_PERSONALITY => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.change_personality_node.as_ref(),
-        format_change_personality_for_prompt,
-      ),
-      REQUEST_NODE_FILE => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.file_node.as_ref(),
-        format_file_node_for_prompt,
-      ),
-      REQUEST_NODE_FILE_ID => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.file_id_node.as_ref(),
-        format_file_id_for_prompt,
-      ),
-      REQUEST_NODE_HISTORY_SUMMARY => push_text_block_from_value(
-        &mut blocks,
-        &mut last_text,
-        node.history_summary_node.as_ref(),
-        format_history_summary_for_prompt,
-      ),
-      _ => {}
-    }
+  for (index, history) in augment.chat_history.iter().enumerate() {
+    push_history_messages_gemini(
+      &mut contents,
+      augment.chat_history.as_slice(),
+      index,
+      history,
+    )?;
   }
 
-  Ok(blocks)
-}
+  let mut current_nodes: Vec<&NodeIn> = augment
+    .nodes
+    .iter()
+    .chain(&augment.structured_request_nodes)
+    .chain(&augment.request_nodes)
+    .collect();
+  let virtual_nodes = build_virtual_context_text_nodes(augment);
+  current_nodes.extend(virtual_nodes.iter());
 
-fn extract_assistant_text_from_output_nodes<'a>(nodes: impl Iterator<Item = &'a NodeIn>) -> String {
-  let mut finished: Option<String> = None;
-  let mut raw = String::new();
-  for n in nodes {
-    if n.node_type == RESPONSE_NODE_MAIN_TEXT_FINISHED && !n.content.trim().is_empty() {
-      finished = Some(n.content.clone());
-    } else if n.node_type == RESPONSE_NODE_RAW_RESPONSE && !n.content.is_empty() {
-      raw.push_str(&n.content);
-    }
+  if let Some(last) = augment.chat_history.last() {
+    let names_by_id = build_gemini_tool_names_by_id(
+      last
+        .response_nodes
+        .iter()
+        .chain(&last.structured_output_nodes),
+    );
+    push_gemini_content(
+      &mut contents,
+      "user",
+      build_gemini_function_responses(current_nodes.iter().copied(), &names_by_id),
+    );
   }
-  finished.unwrap_or(raw).trim().to_string()
-}
+  current_nodes.retain(|n| n.node_type != REQUEST_NODE_TOOL_RESULT);
 
-fn build_assistant_content_blocks<'a>(
-  text: &str,
-  nodes: impl Iterator<Item = &'a NodeIn>,
-) -> anyhow::Result<Vec<AnthropicContentBlock>> {
-  let mut blocks: Vec<AnthropicContentBlock> = Vec::new();
-  if !text.is_empty() {
-    blocks.push(text_block(text));
-  }
+  let req_segments = build_openai_user_segments(&augment.message, current_nodes.iter().copied())?;
+  push_gemini_content(
+    &mut contents,
+    "user",
+    gemini_parts_from_segments(req_segments),
+  );
 
-  let mut tool_use_nodes: Vec<&NodeIn> = Vec::new();
-  let mut tool_use_start_nodes: Vec<&NodeIn> = Vec::new();
-  for node in nodes {
-    if node.node_type == RESPONSE_NODE_TOOL_USE {
-      tool_use_nodes.push(node);
-    } else if node.node_type == RESPONSE_NODE_TOOL_USE_START {
-      tool_use_start_nodes.push(node);
-    }
-  }
+  let system = build_system_prompt(augment);
+  let system_instruction = (!system.trim().is_empty()).then(|| GeminiContent {
+    role: String::new(),
+    parts: vec![GeminiPart {
+      text: Some(system),
+      ..Default::default()
+    }],
+  });
 
-  let chosen = if tool_use_nodes.is_empty() {
-    tool_use_start_nodes
-  } else {
-    tool_use_nodes
-  };
+  let decls = convert_gemini_tools(&augment.tool_definitions)?;
+  let tool_config = (!decls.is_empty()).then(|| GeminiToolConfig {
+    function_calling_config: GeminiFunctionCallingConfig {
+      mode: "AUTO".to_string(),
+    },
+  });
 
-  let mut seen_tool_use_ids: HashSet<String> = HashSet::new();
-  for node in chosen {
-    let Some(tool_use) = &node.tool_use else {
-      continue;
-    };
-    if tool_use.tool_use_id.trim().is_empty() || tool_use.tool_name.trim().is_empty() {
-      continue;
-    }
-    if !seen_tool_use_ids.insert(tool_use.tool_use_id.clone()) {
-      continue;
-    }
+  let thinking_config = provider.thinking.enabled.then_some(GeminiThinkingConfig {
+    include_thoughts: true,
+    thinking_budget: Some(provider.thinking.budget_tokens),
+  });
 
-    let input: serde_json::Value = if tool_use.input_json.trim().is_empty() {
-      serde_json::Value::Object(serde_json::Map::new())
-    } else {
-      serde_json::from_str(&tool_use.input_json)
-        .unwrap_or_else(|_| serde_json::Value::Object(serde_json::Map::new()))
-    };
+  Ok(GeminiRequest {
+    contents,
+    system_instruction,
+    tools: (!decls.is_empty()).then(|| {
+      vec![GeminiTool {
+        function_declarations: decls,
+      }]
+    }),
+    tool_config,
+    generation_config: Some(GeminiGenerationConfig {
+      max_output_tokens: Some(provider.max_tokens),
+      temperature: None,
+      top_p: None,
+      thinking_config,
+    }),
+  })
+}
 
-    blocks.push(AnthropicContentBlock {
-      block_type: "tool_use".to_string(),
-      text: None,
-      source: None,
-      id: Some(tool_use.tool_use_id.clone()),
-      name: Some(tool_use.tool_name.clone()),
-      input: Some(input),
-      tool_use_id: None,
-      content: None,
-      is_error: None,
-      thinking: None,
-      signature: None,
-    });
+fn build_responses_user_content(segments: Vec<OpenAISegment>) -> Option<Value> {
+  let mut out: Vec<Value> = Vec::new();
+  for s in segments {
+    match s {
+      OpenAISegment::Text(t) => {
+        let t = t.trim();
+        if !t.is_empty() {
+          out.push(serde_json::json!({ "type": "input_text", "text": t }));
+        }
+      }
+      OpenAISegment::Image { media_type, data } => {
+        let data = data.trim();
+        if !data.is_empty() {
+          out.push(serde_json::json!({
+            "type": "input_image",
+            "image_url": format!("data:{media_type};base64,{data}")
+          }));
+        }
+      }
+    }
   }
-
-  Ok(blocks)
+  (!out.is_empty()).then_some(Value::Array(out))
 }
 
-fn build_tool_results<'a>(
+fn build_responses_reasoning_items<'a>(
   nodes: impl Iterator<Item = &'a NodeIn>,
-) -> anyhow::Result<Vec<AnthropicContentBlock>> {
-  let mut blocks: Vec<AnthropicContentBlock> = Vec::new();
+) -> Vec<OpenAIResponsesInputItem> {
+  let mut out: Vec<OpenAIResponsesInputItem> = Vec::new();
   for node in nodes {
-    if node.node_type != REQUEST_NODE_TOOL_RESULT {
+    if node.node_type != RESPONSE_NODE_THINKING {
       continue;
     }
-    let Some(tool) = &node.tool_result_node else {
+    let Some(thinking) = &node.thinking else {
       continue;
     };
-    if tool.tool_use_id.trim().is_empty() {
+    let encrypted = thinking.encrypted_content.trim();
+    if encrypted.is_empty() || !thinking.written_by(THINKING_PROVIDER_OPENAI_RESPONSES) {
       continue;
     }
-    blocks.push(AnthropicContentBlock {
-      block_type: "tool_result".to_string(),
-      text: None,
-      source: None,
-      id: None,
-      name: None,
-      input: None,
-      tool_use_id: Some(tool.tool_use_id.clone()),
-      content: Some(build_anthropic_tool_result_content(
-        tool.content.as_str(),
-        &tool.content_nodes,
-      )),
-      is_error: Some(tool.is_error),
-      thinking: None,
-      signature: None,
+    let summary = if thinking.summary.trim().is_empty() {
+      Vec::new()
+    } else {
+      vec![OpenAIResponsesSummaryText {
+        summary_type: "summary_text".to_string(),
+        text: thinking.summary.clone(),
+      }]
+    };
+    out.push(OpenAIResponsesInputItem::Reasoning {
+      summary,
+      encrypted_content: Some(encrypted.to_string()),
     });
   }
-  Ok(blocks)
+  out
 }
 
-fn text_block(text: &str) -> AnthropicContentBlock {
-  AnthropicContentBlock {
-    block_type: "text".to_string(),
-    text: Some(text.to_string()),
-    source: None,
-    id: None,
-    name: None,
-    input: None,
-    tool_use_id: None,
-    content: None,
-    is_error: None,
-    thinking: None,
-    signature: None,
-  }
+fn build_responses_function_call_outputs<'a>(
+  nodes: impl Iterator<Item = &'a NodeIn>,
+) -> Vec<OpenAIResponsesInputItem> {
+  build_openai_tool_messages_from_request_nodes(nodes)
+    .into_iter()
+    .filter_map(|m| {
+      let call_id = m.tool_call_id?;
+      let output = match m.content {
+        Some(Value::String(s)) => s,
+        _ => String::new(),
+      };
+      Some(OpenAIResponsesInputItem::FunctionCallOutput { call_id, output })
+    })
+    .collect()
 }
 
-fn convert_tools(defs: &[ToolDefinition]) -> anyhow::Result<Vec<AnthropicTool>> {
-  let mut tools = Vec::with_capacity(defs.len());
-  for def in defs {
-    let input_schema = if let Some(v) = &def.input_schema {
-      v.clone()
-    } else if !def.input_schema_json.trim().is_empty() {
-      serde_json::from_str(&def.input_schema_json)
-        .with_context(|| format!("解析 tool input_schema_json 失败: {}", def.name))?
-    } else {
-      serde_json::json!({"type":"object","properties":{}})
-    };
-
-    tools.push(AnthropicTool {
-      name: def.name.clone(),
-      description: (!def.description.trim().is_empty()).then_some(def.description.clone()),
-      input_schema,
+fn push_history_messages_responses(
+  out: &mut Vec<OpenAIResponsesInputItem>,
+  all: &[AugmentChatHistory],
+  index: usize,
+  history: &AugmentChatHistory,
+) -> anyhow::Result<()> {
+  let req_nodes = history
+    .request_nodes
+    .iter()
+    .chain(&history.structured_request_nodes)
+    .chain(&history.nodes);
+  let req_segments = build_openai_user_segments(&history.request_message, req_nodes)?;
+  if let Some(content) = build_responses_user_content(req_segments) {
+    out.push(OpenAIResponsesInputItem::Message {
+      role: "user".to_string(),
+      content,
     });
   }
-  Ok(tools)
-}
 
-pub fn map_anthropic_stop_reason_to_augment(reason: &str) -> i32 {
-  match reason {
-    "end_turn" => STOP_REASON_END_TURN,
-    "max_tokens" => STOP_REASON_MAX_TOKENS,
-    "tool_use" => STOP_REASON_TOOL_USE_REQUESTED,
-    "stop_sequence" => STOP_REASON_END_TURN,
-    "safety" => STOP_REASON_SAFETY,
-    "recitation" => STOP_REASON_RECITATION,
-    _ => STOP_REASON_END_TURN,
+  let out_nodes = history
+    .response_nodes
+    .iter()
+    .chain(&history.structured_output_nodes);
+  out.extend(build_responses_reasoning_items(out_nodes.clone()));
+  let assistant_text = if history.response_text.trim().is_empty() {
+    extract_assistant_text_from_output_nodes(out_nodes.clone())
+  } else {
+    history.response_text.clone()
+  };
+  if !assistant_text.trim().is_empty() {
+    out.push(OpenAIResponsesInputItem::Message {
+      role: "assistant".to_string(),
+      content: Value::String(assistant_text.trim().to_string()),
+    });
   }
-}
-
-pub fn map_openai_finish_reason_to_augment(reason: &str) -> i32 {
-  match reason {
-    "stop" => STOP_REASON_END_TURN,
-    "length" => STOP_REASON_MAX_TOKENS,
-    "tool_calls" => STOP_REASON_TOOL_USE_REQUESTED,
-    "function_call" => STOP_REASON_TOOL_USE_REQUESTED,
-    "content_filter" => STOP_REASON_SAFETY,
-    _ => STOP_REASON_END_TURN,
+  let tool_calls = build_openai_tool_calls_from_output_nodes(out_nodes);
+  let has_tool_calls = !tool_calls.is_empty();
+  for call in tool_calls {
+    out.push(OpenAIResponsesInputItem::FunctionCall {
+      call_id: call.id,
+      name: call.function.name,
+      arguments: call.function.arguments,
+    });
   }
-}
 
-fn map_image_format_to_media_type(format: i32) -> &'static str {
-  match format {
-    2 => "image/jpeg",
-    3 => "image/gif",
-    4 => "image/webp",
-    _ => "image/png",
+  if let Some(next) = all.get(index + 1) {
+    if has_tool_calls {
+      let next_req_nodes = next
+        .request_nodes
+        .iter()
+        .chain(&next.structured_request_nodes)
+        .chain(&next.nodes);
+      out.extend(build_responses_function_call_outputs(next_req_nodes));
+    }
   }
+  Ok(())
 }
 
-fn image_block_from_format_and_data(format: i32, data: &str) -> AnthropicContentBlock {
-  AnthropicContentBlock {
-    block_type: "image".to_string(),
-    text: None,
-    source: Some(AnthropicImageSource {
-      source_type: "base64".to_string(),
-      media_type: map_image_format_to_media_type(format).to_string(),
-      data: data.to_string(),
-    }),
-    id: None,
-    name: None,
-    input: None,
-    tool_use_id: None,
-    content: None,
-    is_error: None,
-    thinking: None,
-    signature: None,
-  }
-}
+pub fn convert_augment_to_openai_responses(
+  provider: &OpenAIResponsesProviderConfig,
+  augment: &AugmentRequest,
+  model: String,
+) -> anyhow::Result<OpenAIResponsesRequest> {
+  let mut input: Vec<OpenAIResponsesInputItem> = Vec::new();
 
-fn push_text_block_from_value(
-  blocks: &mut Vec<AnthropicContentBlock>,
-  last_text: &mut Option<String>,
-  value: Option<&Value>,
-  fmt: fn(&Value) -> String,
-) {
-  let Some(v) = value else { return };
-  let text = fmt(v);
-  if text.trim().is_empty() {
-    return;
+  for (index, history) in augment.chat_history.iter().enumerate() {
+    push_history_messages_responses(&mut input, augment.chat_history.as_slice(), index, history)?;
   }
-  push_text_block(blocks, last_text, &text);
-}
 
-fn build_anthropic_tool_result_content(
-  fallback_text: &str,
-  nodes: &[ToolResultContentNode],
-) -> Value {
-  let mut content_blocks: Vec<Value> = Vec::new();
-  let mut last_text = String::new();
+  let mut current_nodes: Vec<&NodeIn> = augment
+    .nodes
+    .iter()
+    .chain(&augment.structured_request_nodes)
+    .chain(&augment.request_nodes)
+    .collect();
+  let virtual_nodes = build_virtual_context_text_nodes(augment);
+  current_nodes.extend(virtual_nodes.iter());
 
-  for n in nodes {
-    match n.node_type {
-      TOOL_RESULT_CONTENT_NODE_TEXT => {
-        let text = n.text_content.trim();
-        if text.is_empty() || is_user_placeholder_message(text) {
-          continue;
-        }
-        if !last_text.is_empty() && last_text == text {
-          continue;
-        }
-        content_blocks.push(serde_json::json!({ "type": "text", "text": text }));
-        last_text = text.to_string();
-      }
-      TOOL_RESULT_CONTENT_NODE_IMAGE => {
-        let Some(img) = &n.image_content else {
-          continue;
-        };
-        let data = img.image_data.trim();
-        if data.is_empty() {
-          continue;
-        }
-        content_blocks.push(serde_json::json!({
-          "type": "image",
-          "source": { "type": "base64", "media_type": map_image_format_to_media_type(img.format), "data": data }
-        }));
-        last_text.clear();
-      }
-      _ => {}
-    }
-  }
+  input.extend(build_responses_function_call_outputs(
+    current_nodes.iter().copied(),
+  ));
+  current_nodes.retain(|n| n.node_type != REQUEST_NODE_TOOL_RESULT);
 
-  if !content_blocks.is_empty() {
-    Value::Array(content_blocks)
-  } else {
-    Value::String(fallback_text.to_string())
+  let req_segments = build_openai_user_segments(&augment.message, current_nodes.iter().copied())?;
+  if let Some(content) = build_responses_user_content(req_segments) {
+    input.push(OpenAIResponsesInputItem::Message {
+      role: "user".to_string(),
+      content,
+    });
   }
+
+  let tools: Vec<OpenAIResponsesTool> = convert_openai_tools(&augment.tool_definitions)?
+    .into_iter()
+    .map(|t| OpenAIResponsesTool {
+      tool_type: "function".to_string(),
+      name: t.function.name,
+      description: t.function.description,
+      parameters: t.function.parameters,
+    })
+    .collect();
+  let tool_choice = (!tools.is_empty()).then(|| Value::String("auto".to_string()));
+
+  let system = build_system_prompt(augment);
+  let reasoning_cfg = &provider.reasoning;
+  let reasoning = reasoning_cfg.enabled.then(|| OpenAIResponsesReasoning {
+    effort: (!reasoning_cfg.effort.trim().is_empty())
+      .then(|| reasoning_cfg.effort.trim().to_string()),
+    summary: (!reasoning_cfg.summary.trim().is_empty())
+      .then(|| reasoning_cfg.summary.trim().to_string()),
+  });
+  // store=false 时只有带上 encrypted_content 才能在下一轮把 reasoning item 原样回放。
+  let include = reasoning_cfg
+    .enabled
+    .then(|| vec!["reasoning.encrypted_content".to_string()]);
+
+  Ok(OpenAIResponsesRequest {
+    model,
+    input,
+    instructions: (!system.trim().is_empty()).then_some(system),
+    stream: true,
+    store: Some(false),
+    max_output_tokens: Some(provider.max_tokens),
+    tools: (!tools.is_empty()).then_some(tools),
+    tool_choice,
+    reasoning,
+    include,
+  })
 }
 
-fn normalize_string(v: Option<&Value>) -> String {
-  match v {
-    Some(Value::String(s)) => s.trim().to_string(),
+fn build_system_prompt(augment: &AugmentRequest) -> String {
+  let mut parts: Vec<String> = Vec::new();
+  if !augment.user_guidelines.trim().is_empty() {
+    parts.push(augment.user_guidelines.trim().to_string());
+  }
+  if !augment.workspace_guidelines.trim().is_empty() {
+    parts.push(augment.workspace_guidelines.trim().to_string());
+  }
+  let rules_text = match &augment.rules {
+    Value::String(s) => s.trim().to_string(),
+    Value::Array(arr) => arr
+      .iter()
+      .filter_map(|x| x.as_str())
+      .map(str::trim)
+      .filter(|s| !s.is_empty())
+      .collect::<Vec<_>>()
+      .join("\n"),
     _ => String::new(),
+  };
+  if !rules_text.trim().is_empty() {
+    parts.push(rules_text);
   }
-}
-
-fn truncate_inline_text(v: Option<&Value>, max_chars: usize) -> String {
-  let s = normalize_string(v);
-  if s.is_empty() {
-    return s;
+  if !augment.agent_memories.trim().is_empty() {
+    parts.push(augment.agent_memories.trim().to_string());
   }
-  let mut out = String::new();
-  for (i, ch) in s.chars().enumerate() {
-    if i >= max_chars {
-      out.push('…');
-      break;
-    }
-    out.push(ch);
+  if augment.mode.trim() == "AGENT" {
+    parts.push(
+			"You are an AI coding assistant with access to tools. Use tools when needed to complete tasks."
+				.to_string(),
+			);
   }
-  out
-}
-
-fn persona_type_to_label(v: Option<&Value>) -> &'static str {
-  let n = v.and_then(|x| x.as_i64()).unwrap_or(0);
-  match n {
-    1 => "PROTOTYPER",
-    2 => "BRAINSTORM",
-    3 => "REVIEWER",
-    _ => "DEFAULT",
+  let ctx = augment.context.as_ref();
+  let lang = augment.lang.trim();
+  let lang = if lang.is_empty() {
+    ctx.map(|c| c.lang.trim()).unwrap_or("")
+  } else {
+    lang
+  };
+  if !lang.is_empty() {
+    parts.push(format!("The user is working with {} code.", lang));
+  }
+  let path = augment.path.trim();
+  let path = if path.is_empty() {
+    ctx.map(|c| c.path.trim()).unwrap_or("")
+  } else {
+    path
+  };
+  if !path.is_empty() {
+    parts.push(format!("Current file path: {}", path));
   }
+  parts.join("\n\n")
 }
 
-fn format_ide_state_for_prompt(v: &Value) -> String {
-  let Some(ide) = v.as_object() else {
-    return String::new();
+fn build_virtual_context_text_nodes(augment: &AugmentRequest) -> Vec<NodeIn> {
+  let ctx = augment.context.as_ref();
+  let message = augment.message.trim();
+
+  let prefix = if !augment.prefix.trim().is_empty() {
+    augment.prefix.trim()
+  } else {
+    ctx.map(|c| c.prefix.trim()).unwrap_or("")
+  };
+  let selected_code = if !augment.selected_code.trim().is_empty() {
+    augment.selected_code.trim()
+  } else {
+    ctx.map(|c| c.selected_code.trim()).unwrap_or("")
+  };
+  let suffix = if !augment.suffix.trim().is_empty() {
+    augment.suffix.trim()
+  } else {
+    ctx.map(|c| c.suffix.trim()).unwrap_or("")
   };
-  let mut lines: Vec<String> = vec!["[IDE_STATE]".to_string()];
 
-  let unchanged = ide
-    .get("workspace_folders_unchanged")
-    .or_else(|| ide.get("workspaceFoldersUnchanged"));
-  if let Some(b) = unchanged.and_then(|x| x.as_bool()) {
-    lines.push(format!("workspace_folders_unchanged={b}"));
+  let code = format!("{prefix}{selected_code}{suffix}")
+    .trim()
+    .to_string();
+  let diff = if !augment.diff.trim().is_empty() {
+    augment.diff.trim()
+  } else {
+    ctx.map(|c| c.diff.trim()).unwrap_or("")
   }
+  .trim()
+  .to_string();
 
-  let folders = ide
-    .get("workspace_folders")
-    .or_else(|| ide.get("workspaceFolders"))
-    .and_then(|x| x.as_array())
-    .cloned()
-    .unwrap_or_default();
-  if !folders.is_empty() {
-    lines.push("workspace_folders:".to_string());
-    for f in folders.iter().take(8) {
-      let Some(r) = f.as_object() else { continue };
-      let repo_root = truncate_inline_text(
-        r.get("repository_root").or_else(|| r.get("repositoryRoot")),
-        200,
-      );
-      let folder_root =
-        truncate_inline_text(r.get("folder_root").or_else(|| r.get("folderRoot")), 200);
-      if repo_root.is_empty() && folder_root.is_empty() {
-        continue;
+  let mut next_id: i32 = 1_000_000;
+  let mut out: Vec<NodeIn> = Vec::new();
+  let mut push_text = |out: &mut Vec<NodeIn>, s: String| {
+    let trimmed = s.trim();
+    if trimmed.is_empty() || is_user_placeholder_message(trimmed) {
+      return;
+    }
+    next_id += 1;
+    out.push(NodeIn {
+      id: next_id,
+      node_type: REQUEST_NODE_TEXT,
+      content: String::new(),
+      text_node: Some(TextNode {
+        content: trimmed.to_string(),
+      }),
+      tool_result_node: None,
+      image_node: None,
+      image_id_node: None,
+      ide_state_node: None,
+      edit_events_node: None,
+      checkpoint_ref_node: None,
+      change_personality_node: None,
+      file_node: None,
+      file_id_node: None,
+      history_summary_node: None,
+      tool_use: None,
+      thinking: None,
+    });
+  };
+
+  if !code.is_empty() && code != message {
+    push_text(&mut out, code.clone());
+  }
+  if !diff.is_empty() && diff != message && diff != code {
+    push_text(&mut out, diff);
+  }
+
+  out
+}
+
+fn push_history_messages(
+  out: &mut Vec<AnthropicMessage>,
+  all: &[AugmentChatHistory],
+  index: usize,
+  history: &AugmentChatHistory,
+  include_thinking: bool,
+) -> anyhow::Result<()> {
+  let req_nodes = history
+    .request_nodes
+    .iter()
+    .chain(&history.structured_request_nodes)
+    .chain(&history.nodes);
+  let user_content = build_user_content_blocks(&history.request_message, req_nodes, false)?;
+  if !user_content.is_empty() {
+    out.push(AnthropicMessage {
+      role: "user".to_string(),
+      content: user_content,
+    });
+  }
+
+  let out_nodes = history
+    .response_nodes
+    .iter()
+    .chain(&history.structured_output_nodes);
+  let assistant_text = if history.response_text.trim().is_empty() {
+    extract_assistant_text_from_output_nodes(out_nodes.clone())
+  } else {
+    history.response_text.clone()
+  };
+  let assistant_content =
+    build_assistant_content_blocks(&assistant_text, out_nodes, include_thinking)?;
+  let has_tool_use = assistant_content.iter().any(|b| b.block_type == "tool_use");
+  if !assistant_content.is_empty() {
+    out.push(AnthropicMessage {
+      role: "assistant".to_string(),
+      content: assistant_content,
+    });
+  }
+
+  if let Some(next) = all.get(index + 1) {
+    if has_tool_use {
+      let next_req_nodes = next
+        .request_nodes
+        .iter()
+        .chain(&next.structured_request_nodes)
+        .chain(&next.nodes);
+      let tool_results = build_tool_results(next_req_nodes)?;
+      if !tool_results.is_empty() {
+        out.push(AnthropicMessage {
+          role: "user".to_string(),
+          content: tool_results,
+        });
       }
-      lines.push(format!(
-        "- repository_root={} folder_root={}",
-        if repo_root.is_empty() {
-          "(unknown)"
-        } else {
-          repo_root.as_str()
-        },
-        if folder_root.is_empty() {
-          "(unknown)"
-        } else {
-          folder_root.as_str()
+    }
+  }
+
+  Ok(())
+}
+
+fn build_user_content_blocks<'a>(
+  message: &str,
+  nodes: impl Iterator<Item = &'a NodeIn>,
+  include_tool_results: bool,
+) -> anyhow::Result<Vec<AnthropicContentBlock>> {
+  let mut blocks: Vec<AnthropicContentBlock> = Vec::new();
+  let mut last_text: Option<String> = None;
+  let message_trimmed = message.trim();
+  let skip_message_text = (!message_trimmed.is_empty()
+    && !is_user_placeholder_message(message_trimmed))
+  .then_some(message_trimmed);
+  push_text_block(&mut blocks, &mut last_text, message);
+
+  for node in nodes {
+    match node.node_type {
+      REQUEST_NODE_TEXT => {
+        if let Some(t) = &node.text_node {
+          if skip_message_text == Some(t.content.trim()) {
+            continue;
+          }
+          push_text_block(&mut blocks, &mut last_text, &t.content);
         }
-      ));
+      }
+      REQUEST_NODE_TOOL_RESULT => {
+        if include_tool_results {
+          if let Some(tool) = &node.tool_result_node {
+            let tool_use_id = tool.tool_use_id.trim();
+            if tool_use_id.is_empty() {
+              continue;
+            }
+            let content =
+              build_anthropic_tool_result_content(tool.content.as_str(), &tool.content_nodes);
+            blocks.push(AnthropicContentBlock {
+              block_type: "tool_result".to_string(),
+              text: None,
+              source: None,
+              id: None,
+              name: None,
+              input: None,
+              tool_use_id: Some(tool_use_id.to_string()),
+              content: Some(content),
+              is_error: Some(tool.is_error),
+              thinking: None,
+              signature: None,
+              data: None,
+              cache_control: None,
+            });
+            last_text = None;
+          }
+        }
+      }
+      REQUEST_NODE_IMAGE => {
+        if let Some(img) = &node.image_node {
+          blocks.push(image_block_from_format_and_data(
+            img.format,
+            &img.image_data,
+          ));
+          last_text = None;
+        }
+      }
+      REQUEST_NODE_IMAGE_ID => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.image_id_node.as_ref(),
+        format_image_id_for_prompt,
+      ),
+      REQUEST_NODE_IDE_STATE => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.ide_state_node.as_ref(),
+        format_ide_state_for_prompt,
+      ),
+      REQUEST_NODE_EDIT_EVENTS => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.edit_events_node.as_ref(),
+        format_edit_events_for_prompt,
+      ),
+      REQUEST_NODE_CHECKPOINT_REF => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.checkpoint_ref_node.as_ref(),
+        format_checkpoint_ref_for_prompt,
+      ),
+      REQUEST_NODE_CHANGE_PERSONALITY => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.change_personality_node.as_ref(),
+        format_change_personality_for_prompt,
+      ),
+      REQUEST_NODE_FILE => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.file_node.as_ref(),
+        format_file_node_for_prompt,
+      ),
+      REQUEST_NODE_FILE_ID => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.file_id_node.as_ref(),
+        format_file_id_for_prompt,
+      ),
+      REQUEST_NODE_HISTORY_SUMMARY => push_text_block_from_value(
+        &mut blocks,
+        &mut last_text,
+        node.history_summary_node.as_ref(),
+        format_history_summary_for_prompt,
+      ),
+      _ => {}
+    }
+  }
+
+  Ok(blocks)
+}
+
+fn extract_assistant_text_from_output_nodes<'a>(nodes: impl Iterator<Item = &'a NodeIn>) -> String {
+  let mut finished: Option<String> = None;
+  let mut raw = String::new();
+  for n in nodes {
+    if n.node_type == RESPONSE_NODE_MAIN_TEXT_FINISHED && !n.content.trim().is_empty() {
+      finished = Some(n.content.clone());
+    } else if n.node_type == RESPONSE_NODE_RAW_RESPONSE && !n.content.is_empty() {
+      raw.push_str(&n.content);
+    }
+  }
+  finished.unwrap_or(raw).trim().to_string()
+}
+
+fn build_assistant_content_blocks<'a>(
+  text: &str,
+  nodes: impl Iterator<Item = &'a NodeIn>,
+  include_thinking: bool,
+) -> anyhow::Result<Vec<AnthropicContentBlock>> {
+  let mut blocks: Vec<AnthropicContentBlock> = Vec::new();
+  if !text.is_empty() {
+    blocks.push(text_block(text));
+  }
+
+  let mut thinking_blocks: Vec<AnthropicContentBlock> = Vec::new();
+  let mut tool_use_nodes: Vec<&NodeIn> = Vec::new();
+  let mut tool_use_start_nodes: Vec<&NodeIn> = Vec::new();
+  for node in nodes {
+    if node.node_type == RESPONSE_NODE_TOOL_USE {
+      tool_use_nodes.push(node);
+    } else if node.node_type == RESPONSE_NODE_TOOL_USE_START {
+      tool_use_start_nodes.push(node);
+    } else if node.node_type == RESPONSE_NODE_THINKING && include_thinking {
+      thinking_blocks.extend(node.thinking.as_ref().and_then(thinking_block));
+    }
+  }
+
+  let chosen = if tool_use_nodes.is_empty() {
+    tool_use_start_nodes
+  } else {
+    tool_use_nodes
+  };
+
+  let mut seen_tool_use_ids: HashSet<String> = HashSet::new();
+  for node in chosen {
+    let Some(tool_use) = &node.tool_use else {
+      continue;
+    };
+    if tool_use.tool_use_id.trim().is_empty() || tool_use.tool_name.trim().is_empty() {
+      continue;
+    }
+    if !seen_tool_use_ids.insert(tool_use.tool_use_id.clone()) {
+      continue;
+    }
+
+    let input: serde_json::Value = if tool_use.input_json.trim().is_empty() {
+      serde_json::Value::Object(serde_json::Map::new())
+    } else {
+      serde_json::from_str(&tool_use.input_json)
+        .unwrap_or_else(|_| serde_json::Value::Object(serde_json::Map::new()))
+    };
+
+    blocks.push(AnthropicContentBlock {
+      block_type: "tool_use".to_string(),
+      text: None,
+      source: None,
+      id: Some(tool_use.tool_use_id.clone()),
+      name: Some(tool_use.tool_name.clone()),
+      input: Some(input),
+      tool_use_id: None,
+      content: None,
+      is_error: None,
+      thinking: None,
+      signature: None,
+      data: None,
+      cache_control: None,
+    });
+  }
+
+  // Anthropic 要求 thinking/redacted_thinking 原样出现在该轮 assistant 内容最前面；
+  // 只剩 thinking 的 assistant 消息没有意义，直接丢弃。
+  if !blocks.is_empty() && !thinking_blocks.is_empty() {
+    thinking_blocks.append(&mut blocks);
+    blocks = thinking_blocks;
+  }
+
+  Ok(blocks)
+}
+
+fn thinking_block(thinking: &ThinkingNode) -> Option<AnthropicContentBlock> {
+  if !thinking.written_by(THINKING_PROVIDER_ANTHROPIC) {
+    return None;
+  }
+  let (block_type, text, signature, data) = if !thinking.signature.is_empty() {
+    (
+      "thinking",
+      Some(thinking.summary.clone()),
+      Some(thinking.signature.clone()),
+      None,
+    )
+  } else if !thinking.encrypted_content.is_empty() {
+    (
+      "redacted_thinking",
+      None,
+      None,
+      Some(thinking.encrypted_content.clone()),
+    )
+  } else {
+    // 没有 signature 的 thinking 会被上游拒绝
+    return None;
+  };
+  Some(AnthropicContentBlock {
+    block_type: block_type.to_string(),
+    text: None,
+    source: None,
+    id: None,
+    name: None,
+    input: None,
+    tool_use_id: None,
+    content: None,
+    is_error: None,
+    thinking: text,
+    signature,
+    data,
+    cache_control: None,
+  })
+}
+
+fn build_tool_results<'a>(
+  nodes: impl Iterator<Item = &'a NodeIn>,
+) -> anyhow::Result<Vec<AnthropicContentBlock>> {
+  let mut blocks: Vec<AnthropicContentBlock> = Vec::new();
+  for node in nodes {
+    if node.node_type != REQUEST_NODE_TOOL_RESULT {
+      continue;
+    }
+    let Some(tool) = &node.tool_result_node else {
+      continue;
+    };
+    if tool.tool_use_id.trim().is_empty() {
+      continue;
+    }
+    blocks.push(AnthropicContentBlock {
+      block_type: "tool_result".to_string(),
+      text: None,
+      source: None,
+      id: None,
+      name: None,
+      input: None,
+      tool_use_id: Some(tool.tool_use_id.clone()),
+      content: Some(build_anthropic_tool_result_content(
+        tool.content.as_str(),
+        &tool.content_nodes,
+      )),
+      is_error: Some(tool.is_error),
+      thinking: None,
+      signature: None,
+      data: None,
+      cache_control: None,
+    });
+  }
+  Ok(blocks)
+}
+
+fn text_block(text: &str) -> AnthropicContentBlock {
+  AnthropicContentBlock {
+    block_type: "text".to_string(),
+    text: Some(text.to_string()),
+    source: None,
+    id: None,
+    name: None,
+    input: None,
+    tool_use_id: None,
+    content: None,
+    is_error: None,
+    thinking: None,
+    signature: None,
+    data: None,
+    cache_control: None,
+  }
+}
+
+fn convert_tools(defs: &[ToolDefinition]) -> anyhow::Result<Vec<AnthropicTool>> {
+  let mut tools = Vec::with_capacity(defs.len());
+  for def in defs {
+    let input_schema = if let Some(v) = &def.input_schema {
+      v.clone()
+    } else if !def.input_schema_json.trim().is_empty() {
+      serde_json::from_str(&def.input_schema_json)
+        .with_context(|| format!("解析 tool input_schema_json 失败: {}", def.name))?
+    } else {
+      serde_json::json!({"type":"object","properties":{}})
+    };
+
+    tools.push(AnthropicTool {
+      name: def.name.clone(),
+      description: (!def.description.trim().is_empty()).then_some(def.description.clone()),
+      input_schema,
+      cache_control: None,
+    });
+  }
+  Ok(tools)
+}
+
+pub fn map_anthropic_stop_reason_to_augment(reason: &str) -> i32 {
+  match reason {
+    "end_turn" => STOP_REASON_END_TURN,
+    "max_tokens" => STOP_REASON_MAX_TOKENS,
+    "tool_use" => STOP_REASON_TOOL_USE_REQUESTED,
+    "stop_sequence" => STOP_REASON_END_TURN,
+    "safety" => STOP_REASON_SAFETY,
+    "recitation" => STOP_REASON_RECITATION,
+    _ => STOP_REASON_END_TURN,
+  }
+}
+
+pub fn map_openai_finish_reason_to_augment(reason: &str) -> i32 {
+  match reason {
+    "stop" => STOP_REASON_END_TURN,
+    "length" => STOP_REASON_MAX_TOKENS,
+    "tool_calls" => STOP_REASON_TOOL_USE_REQUESTED,
+    "function_call" => STOP_REASON_TOOL_USE_REQUESTED,
+    "content_filter" => STOP_REASON_SAFETY,
+    _ => STOP_REASON_END_TURN,
+  }
+}
+
+pub fn map_gemini_finish_reason_to_augment(reason: &str) -> i32 {
+  match reason {
+    "STOP" => STOP_REASON_END_TURN,
+    "MAX_TOKENS" => STOP_REASON_MAX_TOKENS,
+    "SAFETY" | "PROHIBITED_CONTENT" | "BLOCKLIST" | "SPII" | "IMAGE_SAFETY" => STOP_REASON_SAFETY,
+    "RECITATION" => STOP_REASON_RECITATION,
+    "MALFORMED_FUNCTION_CALL" | "UNEXPECTED_TOOL_CALL" => STOP_REASON_MALFORMED_FUNCTION_CALL,
+    _ => STOP_REASON_END_TURN,
+  }
+}
+
+fn map_image_format_to_media_type(format: i32) -> &'static str {
+  match format {
+    2 => "image/jpeg",
+    3 => "image/gif",
+    4 => "image/webp",
+    _ => "image/png",
+  }
+}
+
+fn image_block_from_format_and_data(format: i32, data: &str) -> AnthropicContentBlock {
+  AnthropicContentBlock {
+    block_type: "image".to_string(),
+    text: None,
+    source: Some(AnthropicImageSource {
+      source_type: "base64".to_string(),
+      media_type: map_image_format_to_media_type(format).to_string(),
+      data: data.to_string(),
+    }),
+    id: None,
+    name: None,
+    input: None,
+    tool_use_id: None,
+    content: None,
+    is_error: None,
+    thinking: None,
+    signature: None,
+    data: None,
+    cache_control: None,
+  }
+}
+
+fn push_text_block_from_value(
+  blocks: &mut Vec<AnthropicContentBlock>,
+  last_text: &mut Option<String>,
+  value: Option<&Value>,
+  fmt: fn(&Value) -> String,
+) {
+  let Some(v) = value else { return };
+  let text = fmt(v);
+  if text.trim().is_empty() {
+    return;
+  }
+  push_text_block(blocks, last_text, &text);
+}
+
+fn build_anthropic_tool_result_content(
+  fallback_text: &str,
+  nodes: &[ToolResultContentNode],
+) -> Value {
+  let mut content_blocks: Vec<Value> = Vec::new();
+  let mut last_text = String::new();
+
+  for n in nodes {
+    match n.node_type {
+      TOOL_RESULT_CONTENT_NODE_TEXT => {
+        let text = n.text_content.trim();
+        if text.is_empty() || is_user_placeholder_message(text) {
+          continue;
+        }
+        if !last_text.is_empty() && last_text == text {
+          continue;
+        }
+        content_blocks.push(serde_json::json!({ "type": "text", "text": text }));
+        last_text = text.to_string();
+      }
+      TOOL_RESULT_CONTENT_NODE_IMAGE => {
+        let Some(img) = &n.image_content else {
+          continue;
+        };
+        let data = img.image_data.trim();
+        if data.is_empty() {
+          continue;
+        }
+        content_blocks.push(serde_json::json!({
+          "type": "image",
+          "source": { "type": "base64", "media_type": map_image_format_to_media_type(img.format), "data": data }
+        }));
+        last_text.clear();
+      }
+      _ => {}
+    }
+  }
+
+  if !content_blocks.is_empty() {
+    Value::Array(content_blocks)
+  } else {
+    Value::String(fallback_text.to_string())
+  }
+}
+
+fn normalize_string(v: Option<&Value>) -> String {
+  match v {
+    Some(Value::String(s)) => s.trim().to_string(),
+    _ => String::new(),
+  }
+}
+
+fn truncate_inline_text(v: Option<&Value>, max_chars: usize) -> String {
+  let s = normalize_string(v);
+  if s.is_empty() {
+    return s;
+  }
+  let mut out = String::new();
+  for (i, ch) in s.chars().enumerate() {
+    if i >= max_chars {
+      out.push('…');
+      break;
+    }
+    out.push(ch);
+  }
+  out
+}
+
+fn persona_type_to_label(v: Option<&Value>) -> &'static str {
+  let n = v.and_then(|x| x.as_i64()).unwrap_or(0);
+  match n {
+    1 => "PROTOTYPER",
+    2 => "BRAINSTORM",
+    3 => "REVIEWER",
+    _ => "DEFAULT",
+  }
+}
+
+fn format_ide_state_for_prompt(v: &Value) -> String {
+  let Some(ide) = v.as_object() else {
+    return String::new();
+  };
+  let mut lines: Vec<String> = vec!["[IDE_STATE]".to_string()];
+
+  let unchanged = ide
+    .get("workspace_folders_unchanged")
+    .or_else(|| ide.get("workspaceFoldersUnchanged"));
+  if let Some(b) = unchanged.and_then(|x| x.as_bool()) {
+    lines.push(format!("workspace_folders_unchanged={b}"));
+  }
+
+  let folders = ide
+    .get("workspace_folders")
+    .or_else(|| ide.get("workspaceFolders"))
+    .and_then(|x| x.as_array())
+    .cloned()
+    .unwrap_or_default();
+  if !folders.is_empty() {
+    lines.push("workspace_folders:".to_string());
+    for f in folders.iter().take(8) {
+      let Some(r) = f.as_object() else { continue };
+      let repo_root = truncate_inline_text(
+        r.get("repository_root").or_else(|| r.get("repositoryRoot")),
+        200,
+      );
+      let folder_root =
+        truncate_inline_text(r.get("folder_root").or_else(|| r.get("folderRoot")), 200);
+      if repo_root.is_empty() && folder_root.is_empty() {
+        continue;
+      }
+      lines.push(format!(
+        "- repository_root={} folder_root={}",
+        if repo_root.is_empty() {
+          "(unknown)"
+        } else {
+          repo_root.as_str()
+        },
+        if folder_root.is_empty() {
+          "(unknown)"
+        } else {
+          folder_root.as_str()
+        }
+      ));
+    }
+  }
+
+  if let Some(term) = ide
+    .get("current_terminal")
+    .or_else(|| ide.get("currentTerminal"))
+    .and_then(|x| x.as_object())
+  {
+    let tid = term
+      .get("terminal_id")
+      .or_else(|| term.get("terminalId"))
+      .and_then(|x| x.as_i64());
+    let cwd = truncate_inline_text(
+      term
+        .get("current_working_directory")
+        .or_else(|| term.get("currentWorkingDirectory")),
+      200,
+    );
+    if tid.is_some() || !cwd.is_empty() {
+      lines.push(format!(
+        "current_terminal: id={} cwd={}",
+        tid
+          .map(|n| n.to_string())
+          .unwrap_or_else(|| "?".to_string()),
+        if cwd.is_empty() {
+          "(unknown)"
+        } else {
+          cwd.as_str()
+        }
+      ));
+    }
+  }
+
+  if lines.len() == 1 {
+    return String::new();
+  }
+  lines.push("[/IDE_STATE]".to_string());
+  lines.join("\n").trim().to_string()
+}
+
+fn format_edit_events_for_prompt(v: &Value) -> String {
+  let Some(node) = v.as_object() else {
+    return String::new();
+  };
+  let mut lines: Vec<String> = vec!["[EDIT_EVENTS]".to_string()];
+
+  if let Some(src) = node.get("source") {
+    if !src.is_null() {
+      lines.push(format!("source={}", truncate_inline_text(Some(src), 200)));
+    }
+  }
+
+  let events = node
+    .get("edit_events")
+    .or_else(|| node.get("editEvents"))
+    .and_then(|x| x.as_array())
+    .cloned()
+    .unwrap_or_default();
+  for ev in events.iter().take(6) {
+    let Some(r) = ev.as_object() else { continue };
+    let path = truncate_inline_text(r.get("path"), 200);
+    let before_blob = truncate_inline_text(
+      r.get("before_blob_name")
+        .or_else(|| r.get("beforeBlobName")),
+      120,
+    );
+    let after_blob = truncate_inline_text(
+      r.get("after_blob_name").or_else(|| r.get("afterBlobName")),
+      120,
+    );
+    let edits = r
+      .get("edits")
+      .and_then(|x| x.as_array())
+      .cloned()
+      .unwrap_or_default();
+
+    lines.push(format!(
+      "- file: {} edits={}{}{}",
+      if path.is_empty() {
+        "(unknown)"
+      } else {
+        path.as_str()
+      },
+      edits.len(),
+      if before_blob.is_empty() {
+        "".to_string()
+      } else {
+        format!(" before={before_blob}")
+      },
+      if after_blob.is_empty() {
+        "".to_string()
+      } else {
+        format!(" after={after_blob}")
+      }
+    ));
+
+    for ed in edits.iter().take(6) {
+      let Some(e) = ed.as_object() else { continue };
+      let after_start = e
+        .get("after_line_start")
+        .or_else(|| e.get("afterLineStart"))
+        .and_then(|x| x.as_i64());
+      let before_start = e
+        .get("before_line_start")
+        .or_else(|| e.get("beforeLineStart"))
+        .and_then(|x| x.as_i64());
+      let before_text =
+        truncate_inline_text(e.get("before_text").or_else(|| e.get("beforeText")), 200);
+      let after_text =
+        truncate_inline_text(e.get("after_text").or_else(|| e.get("afterText")), 200);
+      lines.push(format!(
+        "  - edit: after_line_start={} before_line_start={} before=\"{}\" after=\"{}\"",
+        after_start
+          .map(|n| n.to_string())
+          .unwrap_or_else(|| "?".to_string()),
+        before_start
+          .map(|n| n.to_string())
+          .unwrap_or_else(|| "?".to_string()),
+        before_text,
+        after_text
+      ));
+    }
+  }
+
+  if lines.len() == 1 {
+    return String::new();
+  }
+  lines.push("[/EDIT_EVENTS]".to_string());
+  lines.join("\n").trim().to_string()
+}
+
+fn format_checkpoint_ref_for_prompt(v: &Value) -> String {
+  let Some(r) = v.as_object() else {
+    return String::new();
+  };
+  let mut lines: Vec<String> = vec!["[CHECKPOINT_REF]".to_string()];
+
+  let request_id = truncate_inline_text(r.get("request_id").or_else(|| r.get("requestId")), 120);
+  if !request_id.is_empty() {
+    lines.push(format!("request_id={request_id}"));
+  }
+
+  let from = r
+    .get("from_timestamp")
+    .or_else(|| r.get("fromTimestamp"))
+    .and_then(|x| x.as_i64());
+  let to = r
+    .get("to_timestamp")
+    .or_else(|| r.get("toTimestamp"))
+    .and_then(|x| x.as_i64());
+  if from.is_some() || to.is_some() {
+    lines.push(format!(
+      "from_timestamp={} to_timestamp={}",
+      from
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| "?".to_string()),
+      to.map(|n| n.to_string()).unwrap_or_else(|| "?".to_string())
+    ));
+  }
+
+  if let Some(src) = r.get("source") {
+    if !src.is_null() {
+      lines.push(format!("source={}", truncate_inline_text(Some(src), 200)));
+    }
+  }
+
+  if lines.len() == 1 {
+    return String::new();
+  }
+  lines.push("[/CHECKPOINT_REF]".to_string());
+  lines.join("\n").trim().to_string()
+}
+
+fn format_change_personality_for_prompt(v: &Value) -> String {
+  let Some(p) = v.as_object() else {
+    return String::new();
+  };
+  let t = persona_type_to_label(
+    p.get("personality_type")
+      .or_else(|| p.get("personalityType")),
+  );
+  let custom = truncate_inline_text(
+    p.get("custom_instructions")
+      .or_else(|| p.get("customInstructions")),
+    1000,
+  );
+  let mut lines: Vec<String> = vec![
+    "[CHANGE_PERSONALITY]".to_string(),
+    format!("personality_type={t}"),
+  ];
+  if !custom.is_empty() {
+    lines.push(format!("custom_instructions={custom}"));
+  }
+  lines.push("[/CHANGE_PERSONALITY]".to_string());
+  lines.join("\n").trim().to_string()
+}
+
+fn format_image_id_for_prompt(v: &Value) -> String {
+  let Some(img) = v.as_object() else {
+    return String::new();
+  };
+  let id = truncate_inline_text(img.get("image_id").or_else(|| img.get("imageId")), 200);
+  if id.is_empty() {
+    return String::new();
+  }
+  let fmt = img.get("format").and_then(|x| x.as_i64());
+  format!(
+    "[IMAGE_ID] image_id={} format={}",
+    id,
+    fmt
+      .map(|n| n.to_string())
+      .unwrap_or_else(|| "?".to_string())
+  )
+}
+
+fn format_file_id_for_prompt(v: &Value) -> String {
+  let Some(f) = v.as_object() else {
+    return String::new();
+  };
+  let id = truncate_inline_text(f.get("file_id").or_else(|| f.get("fileId")), 200);
+  let name = truncate_inline_text(f.get("file_name").or_else(|| f.get("fileName")), 200);
+  if id.is_empty() && name.is_empty() {
+    return String::new();
+  }
+  format!(
+    "[FILE_ID] file_name={} file_id={}",
+    if name.is_empty() {
+      "(unknown)"
+    } else {
+      name.as_str()
+    },
+    if id.is_empty() {
+      "(unknown)"
+    } else {
+      id.as_str()
+    }
+  )
+}
+
+fn format_file_node_for_prompt(v: &Value) -> String {
+  let Some(f) = v.as_object() else {
+    return String::new();
+  };
+  let raw = normalize_string(f.get("file_data").or_else(|| f.get("fileData")));
+  let format = normalize_string(f.get("format"));
+  let format = if format.is_empty() {
+    "application/octet-stream".to_string()
+  } else {
+    format
+  };
+  if raw.is_empty() {
+    return format!("[FILE] format={format} (empty)");
+  }
+
+  let b64 = raw
+    .strip_prefix("data:")
+    .and_then(|rest| rest.split_once(";base64,").map(|(_, b)| b))
+    .unwrap_or(raw.as_str());
+  let approx_bytes = ((b64.len() * 3) / 4) as i64;
+
+  let is_text_like = format.starts_with("text/")
+    || matches!(
+      format.as_str(),
+      "application/json"
+        | "application/xml"
+        | "application/yaml"
+        | "application/x-yaml"
+        | "application/markdown"
+    );
+  if !is_text_like {
+    return format!("[FILE] format={format} bytes≈{approx_bytes} (content omitted)");
+  }
+
+  let decoded = match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, b64) {
+    Ok(v) => v,
+    Err(_) => return format!("[FILE] format={format} bytes≈{approx_bytes} (decode failed)"),
+  };
+  let decoded = match String::from_utf8(decoded) {
+    Ok(v) => v,
+    Err(_) => return format!("[FILE] format={format} bytes≈{approx_bytes} (decode failed)"),
+  };
+  let max = 20_000usize;
+  let content = if decoded.chars().count() > max {
+    decoded.chars().take(max).collect::<String>() + "\n\n[Content truncated due to length...]"
+  } else {
+    decoded
+  };
+  format!("[FILE] format={format} bytes≈{approx_bytes}\n\n{content}")
+    .trim()
+    .to_string()
+}
+
+fn format_history_summary_for_prompt(v: &Value) -> String {
+  if let Some(rendered) = crate::history_summary::render_history_summary_node_value(v, &[]) {
+    return rendered;
+  }
+  let Some(h) = v.as_object() else {
+    return String::new();
+  };
+  let summary_text =
+    truncate_inline_text(h.get("summary_text").or_else(|| h.get("summaryText")), 3000);
+  let req_id = truncate_inline_text(
+    h.get("summarization_request_id")
+      .or_else(|| h.get("summarizationRequestId")),
+    120,
+  );
+  let dropped = h
+    .get("history_beginning_dropped_num_exchanges")
+    .or_else(|| h.get("historyBeginningDroppedNumExchanges"))
+    .and_then(|x| x.as_i64());
+  let abridged = truncate_inline_text(
+    h.get("history_middle_abridged_text")
+      .or_else(|| h.get("historyMiddleAbridgedText")),
+    2000,
+  );
+  let end_len = h
+    .get("history_end")
+    .or_else(|| h.get("historyEnd"))
+    .and_then(|x| x.as_array())
+    .map(|a| a.len());
+  let tmpl = truncate_inline_text(
+    h.get("message_template")
+      .or_else(|| h.get("messageTemplate")),
+    400,
+  );
+
+  let mut lines: Vec<String> = vec!["[HISTORY_SUMMARY]".to_string()];
+  if !req_id.is_empty() {
+    lines.push(format!("summarization_request_id={req_id}"));
+  }
+  if let Some(n) = dropped {
+    lines.push(format!("history_beginning_dropped_num_exchanges={n}"));
+  }
+  if !tmpl.is_empty() {
+    lines.push(format!("message_template={tmpl}"));
+  }
+  if !summary_text.is_empty() {
+    lines.push(format!("summary_text={summary_text}"));
+  }
+  if !abridged.is_empty() {
+    lines.push(format!("history_middle_abridged_text={abridged}"));
+  }
+  if let Some(n) = end_len {
+    if n > 0 {
+      lines.push(format!("history_end_exchanges={n}"));
+    }
+  }
+  if lines.len() == 1 {
+    return String::new();
+  }
+  lines.push("[/HISTORY_SUMMARY]".to_string());
+  lines.join("\n").trim().to_string()
+}
+
+#[derive(Debug, Default)]
+pub struct AnthropicStreamState {
+  pub node_id: i32,
+  pub full_text: String,
+  pub saw_tool_use: bool,
+  pub stop_reason: Option<i32>,
+  pub tool_meta_by_name: HashMap<String, (String, String)>,
+  pub current_tool_use_id: Option<String>,
+  pub current_tool_name: Option<String>,
+  pub current_mcp_server_name: String,
+  pub current_mcp_tool_name: String,
+  pub tool_input_buffer: String,
+  pub in_thinking_block: bool,
+  pub thinking_buffer: String,
+  pub thinking_signature: String,
+  pub usage_input_tokens: Option<i64>,
+  pub usage_output_tokens: Option<i64>,
+  pub usage_cache_read_input_tokens: Option<i64>,
+  pub usage_cache_creation_input_tokens: Option<i64>,
+  pub stop_reason_seen: bool,
+}
+
+impl AnthropicStreamState {
+  pub fn on_text_delta(&mut self, delta: &str) -> crate::protocol::AugmentStreamChunk {
+    self.full_text.push_str(delta);
+    self.node_id += 1;
+    crate::protocol::AugmentStreamChunk {
+      text: delta.to_string(),
+      nodes: vec![NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_RAW_RESPONSE,
+        content: delta.to_string(),
+        tool_use: None,
+        thinking: None,
+        token_usage: None,
+      }],
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      stop_reason: None,
+    }
+  }
+
+  pub fn on_usage(&mut self, usage: &crate::anthropic::AnthropicUsage) {
+    if let Some(v) = usage.input_tokens {
+      self.usage_input_tokens = Some(v);
+    }
+    if let Some(v) = usage.output_tokens {
+      self.usage_output_tokens = Some(v);
+    }
+    if let Some(v) = usage.cache_read_input_tokens {
+      self.usage_cache_read_input_tokens = Some(v);
+    }
+    if let Some(v) = usage.cache_creation_input_tokens {
+      self.usage_cache_creation_input_tokens = Some(v);
+    }
+  }
+
+  pub fn on_thinking_block_start(&mut self) {
+    self.in_thinking_block = true;
+    self.thinking_buffer.clear();
+    self.thinking_signature.clear();
+  }
+
+  pub fn on_thinking_delta(&mut self, delta: &str) {
+    self.thinking_buffer.push_str(delta);
+  }
+
+  pub fn on_signature_delta(&mut self, delta: &str) {
+    self.thinking_signature.push_str(delta);
+  }
+
+  pub fn on_thinking_block_stop(&mut self) -> Option<crate::protocol::AugmentStreamChunk> {
+    if !self.in_thinking_block {
+      return None;
+    }
+    self.in_thinking_block = false;
+    if self.thinking_buffer.is_empty() && self.thinking_signature.is_empty() {
+      return None;
+    }
+    let thinking = ThinkingNode {
+      summary: std::mem::take(&mut self.thinking_buffer),
+      signature: std::mem::take(&mut self.thinking_signature),
+      encrypted_content: String::new(),
+      provider: THINKING_PROVIDER_ANTHROPIC.to_string(),
+    };
+    Some(self.thinking_chunk(thinking))
+  }
+
+  // redacted_thinking 只有加密的 data，原样放进 encrypted_content，回放时还原
+  pub fn on_redacted_thinking_block(
+    &mut self,
+    data: &str,
+  ) -> Option<crate::protocol::AugmentStreamChunk> {
+    if data.is_empty() {
+      return None;
+    }
+    Some(self.thinking_chunk(ThinkingNode {
+      summary: String::new(),
+      signature: String::new(),
+      encrypted_content: data.to_string(),
+      provider: THINKING_PROVIDER_ANTHROPIC.to_string(),
+    }))
+  }
+
+  fn thinking_chunk(&mut self, thinking: ThinkingNode) -> crate::protocol::AugmentStreamChunk {
+    self.node_id += 1;
+    let node = NodeOut {
+      id: self.node_id,
+      node_type: RESPONSE_NODE_THINKING,
+      content: "".to_string(),
+      tool_use: None,
+      thinking: Some(thinking),
+      token_usage: None,
+    };
+    crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      nodes: vec![node],
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      stop_reason: None,
     }
   }
 
-  if let Some(term) = ide
-    .get("current_terminal")
-    .or_else(|| ide.get("currentTerminal"))
-    .and_then(|x| x.as_object())
-  {
-    let tid = term
-      .get("terminal_id")
-      .or_else(|| term.get("terminalId"))
-      .and_then(|x| x.as_i64());
-    let cwd = truncate_inline_text(
-      term
-        .get("current_working_directory")
-        .or_else(|| term.get("currentWorkingDirectory")),
-      200,
-    );
-    if tid.is_some() || !cwd.is_empty() {
-      lines.push(format!(
-        "current_terminal: id={} cwd={}",
-        tid
-          .map(|n| n.to_string())
-          .unwrap_or_else(|| "?".to_string()),
-        if cwd.is_empty() {
-          "(unknown)"
-        } else {
-          cwd.as_str()
-        }
-      ));
+  pub fn on_tool_use_block_start(&mut self, tool_use_id: &str, tool_name: &str) {
+    let tool_name = tool_name.trim().to_string();
+    self.current_tool_use_id = Some(tool_use_id.trim().to_string());
+    self.current_tool_name = Some(tool_name.clone());
+    self.tool_input_buffer.clear();
+    if let Some((mcp_server_name, mcp_tool_name)) = self.tool_meta_by_name.get(&tool_name) {
+      self.current_mcp_server_name = mcp_server_name.clone();
+      self.current_mcp_tool_name = mcp_tool_name.clone();
+    } else {
+      self.current_mcp_server_name.clear();
+      self.current_mcp_tool_name.clear();
     }
   }
 
-  if lines.len() == 1 {
-    return String::new();
+  pub fn on_tool_input_json_delta(&mut self, partial: &str) {
+    self.tool_input_buffer.push_str(partial);
   }
-  lines.push("[/IDE_STATE]".to_string());
-  lines.join("\n").trim().to_string()
-}
 
-fn format_edit_events_for_prompt(v: &Value) -> String {
-  let Some(node) = v.as_object() else {
-    return String::new();
-  };
-  let mut lines: Vec<String> = vec!["[EDIT_EVENTS]".to_string()];
+  pub fn on_tool_use_block_stop(&mut self) -> Vec<crate::protocol::AugmentStreamChunk> {
+    let (Some(mut id), Some(name)) = (
+      self.current_tool_use_id.take(),
+      self.current_tool_name.take(),
+    ) else {
+      return Vec::new();
+    };
 
-  if let Some(src) = node.get("source") {
-    if !src.is_null() {
-      lines.push(format!("source={}", truncate_inline_text(Some(src), 200)));
+    if id.trim().is_empty() {
+      id = format!("tool-{}", self.node_id + 1);
     }
+    let input_json = {
+      let v = std::mem::take(&mut self.tool_input_buffer);
+      let trimmed = v.trim();
+      if trimmed.is_empty() {
+        "{}".to_string()
+      } else {
+        trimmed.to_string()
+      }
+    };
+
+    self.saw_tool_use = true;
+    let mcp_server_name = std::mem::take(&mut self.current_mcp_server_name);
+    let mcp_tool_name = std::mem::take(&mut self.current_mcp_tool_name);
+    let tool_use = ToolUse {
+      tool_use_id: id.clone(),
+      tool_name: name,
+      input_json,
+      mcp_server_name,
+      mcp_tool_name,
+    };
+
+    let mk_chunk = |node: NodeOut| crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      nodes: vec![node],
+      stop_reason: None,
+    };
+
+    self.node_id += 1;
+    let start = mk_chunk(NodeOut {
+      id: self.node_id,
+      node_type: RESPONSE_NODE_TOOL_USE_START,
+      content: "".to_string(),
+      tool_use: Some(tool_use.clone()),
+      thinking: None,
+      token_usage: None,
+    });
+
+    self.node_id += 1;
+    let done = mk_chunk(NodeOut {
+      id: self.node_id,
+      node_type: RESPONSE_NODE_TOOL_USE,
+      content: "".to_string(),
+      tool_use: Some(tool_use),
+      thinking: None,
+      token_usage: None,
+    });
+
+    vec![start, done]
   }
 
-  let events = node
-    .get("edit_events")
-    .or_else(|| node.get("editEvents"))
-    .and_then(|x| x.as_array())
-    .cloned()
-    .unwrap_or_default();
-  for ev in events.iter().take(6) {
-    let Some(r) = ev.as_object() else { continue };
-    let path = truncate_inline_text(r.get("path"), 200);
-    let before_blob = truncate_inline_text(
-      r.get("before_blob_name")
-        .or_else(|| r.get("beforeBlobName")),
-      120,
-    );
-    let after_blob = truncate_inline_text(
-      r.get("after_blob_name").or_else(|| r.get("afterBlobName")),
-      120,
-    );
-    let edits = r
-      .get("edits")
-      .and_then(|x| x.as_array())
-      .cloned()
-      .unwrap_or_default();
+  pub fn on_stop_reason(&mut self, anthropic_stop_reason: &str) {
+    self.stop_reason_seen = true;
+    self.stop_reason = Some(map_anthropic_stop_reason_to_augment(anthropic_stop_reason));
+  }
 
-    lines.push(format!(
-      "- file: {} edits={}{}{}",
-      if path.is_empty() {
-        "(unknown)"
-      } else {
-        path.as_str()
-      },
-      edits.len(),
-      if before_blob.is_empty() {
-        "".to_string()
-      } else {
-        format!(" before={before_blob}")
-      },
-      if after_blob.is_empty() {
-        "".to_string()
-      } else {
-        format!(" after={after_blob}")
-      }
-    ));
+  pub fn finalize(&mut self) -> Vec<crate::protocol::AugmentStreamChunk> {
+    let mut chunks: Vec<crate::protocol::AugmentStreamChunk> = Vec::new();
 
-    for ed in edits.iter().take(6) {
-      let Some(e) = ed.as_object() else { continue };
-      let after_start = e
-        .get("after_line_start")
-        .or_else(|| e.get("afterLineStart"))
-        .and_then(|x| x.as_i64());
-      let before_start = e
-        .get("before_line_start")
-        .or_else(|| e.get("beforeLineStart"))
-        .and_then(|x| x.as_i64());
-      let before_text =
-        truncate_inline_text(e.get("before_text").or_else(|| e.get("beforeText")), 200);
-      let after_text =
-        truncate_inline_text(e.get("after_text").or_else(|| e.get("afterText")), 200);
-      lines.push(format!(
-        "  - edit: after_line_start={} before_line_start={} before=\"{}\" after=\"{}\"",
-        after_start
-          .map(|n| n.to_string())
-          .unwrap_or_else(|| "?".to_string()),
-        before_start
-          .map(|n| n.to_string())
-          .unwrap_or_else(|| "?".to_string()),
-        before_text,
-        after_text
-      ));
+    if let Some(thinking) = self.on_thinking_block_stop() {
+      chunks.push(thinking);
     }
-  }
 
-  if lines.len() == 1 {
-    return String::new();
+    if self.usage_input_tokens.is_some()
+      || self.usage_output_tokens.is_some()
+      || self.usage_cache_read_input_tokens.is_some()
+      || self.usage_cache_creation_input_tokens.is_some()
+    {
+      self.node_id += 1;
+      chunks.push(crate::protocol::AugmentStreamChunk {
+        text: "".to_string(),
+        unknown_blob_names: Vec::new(),
+        checkpoint_not_found: false,
+        workspace_file_chunks: Vec::new(),
+        nodes: vec![NodeOut {
+          id: self.node_id,
+          node_type: RESPONSE_NODE_TOKEN_USAGE,
+          content: "".to_string(),
+          tool_use: None,
+          thinking: None,
+          token_usage: Some(TokenUsageNode {
+            input_tokens: self.usage_input_tokens,
+            output_tokens: self.usage_output_tokens,
+            cache_read_input_tokens: self.usage_cache_read_input_tokens,
+            cache_creation_input_tokens: self.usage_cache_creation_input_tokens,
+          }),
+        }],
+        stop_reason: None,
+      });
+    }
+
+    let mut final_nodes: Vec<NodeOut> = Vec::new();
+    if !self.full_text.is_empty() {
+      self.node_id += 1;
+      final_nodes.push(NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_MAIN_TEXT_FINISHED,
+        content: self.full_text.clone(),
+        tool_use: None,
+        thinking: None,
+        token_usage: None,
+      });
+    }
+
+    let stop_reason = self.stop_reason.unwrap_or_else(|| {
+      if self.saw_tool_use {
+        STOP_REASON_TOOL_USE_REQUESTED
+      } else {
+        STOP_REASON_END_TURN
+      }
+    });
+    chunks.push(crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      nodes: final_nodes,
+      stop_reason: Some(stop_reason),
+    });
+
+    chunks
   }
-  lines.push("[/EDIT_EVENTS]".to_string());
-  lines.join("\n").trim().to_string()
 }
 
-fn format_checkpoint_ref_for_prompt(v: &Value) -> String {
-  let Some(r) = v.as_object() else {
-    return String::new();
-  };
-  let mut lines: Vec<String> = vec!["[CHECKPOINT_REF]".to_string()];
+#[derive(Debug, Default)]
+pub struct OpenAIStreamState {
+  pub node_id: i32,
+  pub full_text: String,
+  pub saw_tool_use: bool,
+  pub stop_reason: Option<i32>,
+  pub tool_meta_by_name: HashMap<String, (String, String)>,
+  pub tool_calls: HashMap<usize, OpenAIToolCallBuffer>,
+  pub thinking_buffer: String,
+  pub usage_input_tokens: Option<i64>,
+  pub usage_output_tokens: Option<i64>,
+  pub stop_reason_seen: bool,
+}
 
-  let request_id = truncate_inline_text(r.get("request_id").or_else(|| r.get("requestId")), 120);
-  if !request_id.is_empty() {
-    lines.push(format!("request_id={request_id}"));
-  }
+#[derive(Debug, Default)]
+pub struct OpenAIToolCallBuffer {
+  pub id: String,
+  pub name: String,
+  pub arguments: String,
+  pub mcp_server_name: String,
+  pub mcp_tool_name: String,
+  pub started: bool,
+}
 
-  let from = r
-    .get("from_timestamp")
-    .or_else(|| r.get("fromTimestamp"))
-    .and_then(|x| x.as_i64());
-  let to = r
-    .get("to_timestamp")
-    .or_else(|| r.get("toTimestamp"))
-    .and_then(|x| x.as_i64());
-  if from.is_some() || to.is_some() {
-    lines.push(format!(
-      "from_timestamp={} to_timestamp={}",
-      from
-        .map(|n| n.to_string())
-        .unwrap_or_else(|| "?".to_string()),
-      to.map(|n| n.to_string()).unwrap_or_else(|| "?".to_string())
-    ));
+impl OpenAIStreamState {
+  pub fn on_text_delta(&mut self, delta: &str) -> crate::protocol::AugmentStreamChunk {
+    self.full_text.push_str(delta);
+    self.node_id += 1;
+    crate::protocol::AugmentStreamChunk {
+      text: delta.to_string(),
+      nodes: vec![NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_RAW_RESPONSE,
+        content: delta.to_string(),
+        tool_use: None,
+        thinking: None,
+        token_usage: None,
+      }],
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      stop_reason: None,
+    }
   }
 
-  if let Some(src) = r.get("source") {
-    if !src.is_null() {
-      lines.push(format!("source={}", truncate_inline_text(Some(src), 200)));
+  pub fn on_usage(&mut self, prompt_tokens: Option<i64>, completion_tokens: Option<i64>) {
+    if let Some(v) = prompt_tokens {
+      self.usage_input_tokens = Some(v);
+    }
+    if let Some(v) = completion_tokens {
+      self.usage_output_tokens = Some(v);
     }
   }
 
-  if lines.len() == 1 {
-    return String::new();
+  pub fn on_reasoning_delta(&mut self, delta: &str) {
+    self.thinking_buffer.push_str(delta);
   }
-  lines.push("[/CHECKPOINT_REF]".to_string());
-  lines.join("\n").trim().to_string()
-}
 
-fn format_change_personality_for_prompt(v: &Value) -> String {
-  let Some(p) = v.as_object() else {
-    return String::new();
-  };
-  let t = persona_type_to_label(
-    p.get("personality_type")
-      .or_else(|| p.get("personalityType")),
-  );
-  let custom = truncate_inline_text(
-    p.get("custom_instructions")
-      .or_else(|| p.get("customInstructions")),
-    1000,
-  );
-  let mut lines: Vec<String> = vec![
-    "[CHANGE_PERSONALITY]".to_string(),
-    format!("personality_type={t}"),
-  ];
-  if !custom.is_empty() {
-    lines.push(format!("custom_instructions={custom}"));
+  // reasoning_content 没有显式的结束事件：遇到正文/工具调用或流结束时一次性发出
+  pub fn flush_thinking(&mut self) -> Option<crate::protocol::AugmentStreamChunk> {
+    if self.thinking_buffer.is_empty() {
+      return None;
+    }
+    self.node_id += 1;
+    Some(crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      nodes: vec![NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_THINKING,
+        content: "".to_string(),
+        tool_use: None,
+        thinking: Some(ThinkingNode {
+          summary: std::mem::take(&mut self.thinking_buffer),
+          signature: String::new(),
+          encrypted_content: String::new(),
+          provider: String::new(),
+        }),
+        token_usage: None,
+      }],
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      stop_reason: None,
+    })
   }
-  lines.push("[/CHANGE_PERSONALITY]".to_string());
-  lines.join("\n").trim().to_string()
-}
 
-fn format_image_id_for_prompt(v: &Value) -> String {
-  let Some(img) = v.as_object() else {
-    return String::new();
-  };
-  let id = truncate_inline_text(img.get("image_id").or_else(|| img.get("imageId")), 200);
-  if id.is_empty() {
-    return String::new();
+  pub fn on_tool_call_delta(
+    &mut self,
+    index: usize,
+    id: Option<&str>,
+    name: Option<&str>,
+    arguments: Option<&str>,
+  ) -> Option<crate::protocol::AugmentStreamChunk> {
+    let entry = self
+      .tool_calls
+      .entry(index)
+      .or_insert_with(OpenAIToolCallBuffer::default);
+
+    if entry.id.trim().is_empty() {
+      if let Some(id) = id.map(str::trim).filter(|s| !s.is_empty()) {
+        entry.id = id.to_string();
+      }
+    }
+
+    if entry.name.trim().is_empty() {
+      if let Some(name) = name.map(str::trim).filter(|s| !s.is_empty()) {
+        entry.name = name.to_string();
+        if let Some((mcp_server_name, mcp_tool_name)) = self.tool_meta_by_name.get(name) {
+          entry.mcp_server_name = mcp_server_name.clone();
+          entry.mcp_tool_name = mcp_tool_name.clone();
+        }
+      }
+    }
+
+    if let Some(args) = arguments {
+      entry.arguments.push_str(args);
+    }
+
+    let name = entry.name.trim();
+    if name.is_empty() || entry.started {
+      return None;
+    }
+    if entry.id.trim().is_empty() {
+      entry.id = format!("tool-{}", index + 1);
+    }
+
+    entry.started = true;
+    self.saw_tool_use = true;
+    self.node_id += 1;
+    Some(crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      nodes: vec![NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_TOOL_USE_START,
+        content: "".to_string(),
+        tool_use: Some(ToolUse {
+          tool_use_id: entry.id.trim().to_string(),
+          tool_name: name.to_string(),
+          input_json: "{}".to_string(),
+          mcp_server_name: entry.mcp_server_name.clone(),
+          mcp_tool_name: entry.mcp_tool_name.clone(),
+        }),
+        thinking: None,
+        token_usage: None,
+      }],
+      stop_reason: None,
+    })
   }
-  let fmt = img.get("format").and_then(|x| x.as_i64());
-  format!(
-    "[IMAGE_ID] image_id={} format={}",
-    id,
-    fmt
-      .map(|n| n.to_string())
-      .unwrap_or_else(|| "?".to_string())
-  )
-}
 
-fn format_file_id_for_prompt(v: &Value) -> String {
-  let Some(f) = v.as_object() else {
-    return String::new();
-  };
-  let id = truncate_inline_text(f.get("file_id").or_else(|| f.get("fileId")), 200);
-  let name = truncate_inline_text(f.get("file_name").or_else(|| f.get("fileName")), 200);
-  if id.is_empty() && name.is_empty() {
-    return String::new();
+  pub fn on_finish_reason(&mut self, finish_reason: &str) {
+    self.stop_reason_seen = true;
+    self.stop_reason = Some(map_openai_finish_reason_to_augment(finish_reason));
   }
-  format!(
-    "[FILE_ID] file_name={} file_id={}",
-    if name.is_empty() {
-      "(unknown)"
-    } else {
-      name.as_str()
-    },
-    if id.is_empty() {
-      "(unknown)"
-    } else {
-      id.as_str()
-    }
-  )
-}
 
-fn format_file_node_for_prompt(v: &Value) -> String {
-  let Some(f) = v.as_object() else {
-    return String::new();
-  };
-  let raw = normalize_string(f.get("file_data").or_else(|| f.get("fileData")));
-  let format = normalize_string(f.get("format"));
-  let format = if format.is_empty() {
-    "application/octet-stream".to_string()
-  } else {
-    format
-  };
-  if raw.is_empty() {
-    return format!("[FILE] format={format} (empty)");
-  }
+  pub fn finalize(&mut self) -> Vec<crate::protocol::AugmentStreamChunk> {
+    let mut chunks: Vec<crate::protocol::AugmentStreamChunk> = Vec::new();
+    chunks.extend(self.flush_thinking());
+
+    let mut indices: Vec<usize> = self.tool_calls.keys().copied().collect();
+    indices.sort();
+    for idx in indices {
+      let Some(call) = self.tool_calls.get(&idx) else {
+        continue;
+      };
+      let name = call.name.trim();
+      let started = call.started;
+      if name.is_empty() {
+        continue;
+      }
+      let mut id = call.id.trim().to_string();
+      if id.is_empty() {
+        id = format!("tool-{}", self.node_id + 1);
+      }
+      let input_json = {
+        let v = call.arguments.trim();
+        if v.is_empty() {
+          "{}".to_string()
+        } else {
+          v.to_string()
+        }
+      };
+
+      self.saw_tool_use = true;
+      let tool_use = ToolUse {
+        tool_use_id: id.clone(),
+        tool_name: name.to_string(),
+        input_json,
+        mcp_server_name: call.mcp_server_name.clone(),
+        mcp_tool_name: call.mcp_tool_name.clone(),
+      };
 
-  let b64 = raw
-    .strip_prefix("data:")
-    .and_then(|rest| rest.split_once(";base64,").map(|(_, b)| b))
-    .unwrap_or(raw.as_str());
-  let approx_bytes = ((b64.len() * 3) / 4) as i64;
+      let mk_chunk = |node: NodeOut| crate::protocol::AugmentStreamChunk {
+        text: "".to_string(),
+        unknown_blob_names: Vec::new(),
+        checkpoint_not_found: false,
+        workspace_file_chunks: Vec::new(),
+        nodes: vec![node],
+        stop_reason: None,
+      };
 
-  let is_text_like = format.starts_with("text/")
-    || matches!(
-      format.as_str(),
-      "application/json"
-        | "application/xml"
-        | "application/yaml"
-        | "application/x-yaml"
-        | "application/markdown"
-    );
-  if !is_text_like {
-    return format!("[FILE] format={format} bytes≈{approx_bytes} (content omitted)");
-  }
+      if !started {
+        self.node_id += 1;
+        chunks.push(mk_chunk(NodeOut {
+          id: self.node_id,
+          node_type: RESPONSE_NODE_TOOL_USE_START,
+          content: "".to_string(),
+          tool_use: Some(tool_use.clone()),
+          thinking: None,
+          token_usage: None,
+        }));
+      }
 
-  let decoded = match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, b64) {
-    Ok(v) => v,
-    Err(_) => return format!("[FILE] format={format} bytes≈{approx_bytes} (decode failed)"),
-  };
-  let decoded = match String::from_utf8(decoded) {
-    Ok(v) => v,
-    Err(_) => return format!("[FILE] format={format} bytes≈{approx_bytes} (decode failed)"),
-  };
-  let max = 20_000usize;
-  let content = if decoded.chars().count() > max {
-    decoded.chars().take(max).collect::<String>() + "\n\n[Content truncated due to length...]"
-  } else {
-    decoded
-  };
-  format!("[FILE] format={format} bytes≈{approx_bytes}\n\n{content}")
-    .trim()
-    .to_string()
-}
+      self.node_id += 1;
+      chunks.push(mk_chunk(NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_TOOL_USE,
+        content: "".to_string(),
+        tool_use: Some(tool_use),
+        thinking: None,
+        token_usage: None,
+      }));
+    }
 
-fn format_history_summary_for_prompt(v: &Value) -> String {
-  if let Some(rendered) = crate::history_summary::render_history_summary_node_value(v, &[]) {
-    return rendered;
-  }
-  let Some(h) = v.as_object() else {
-    return String::new();
-  };
-  let summary_text =
-    truncate_inline_text(h.get("summary_text").or_else(|| h.get("summaryText")), 3000);
-  let req_id = truncate_inline_text(
-    h.get("summarization_request_id")
-      .or_else(|| h.get("summarizationRequestId")),
-    120,
-  );
-  let dropped = h
-    .get("history_beginning_dropped_num_exchanges")
-    .or_else(|| h.get("historyBeginningDroppedNumExchanges"))
-    .and_then(|x| x.as_i64());
-  let abridged = truncate_inline_text(
-    h.get("history_middle_abridged_text")
-      .or_else(|| h.get("historyMiddleAbridgedText")),
-    2000,
-  );
-  let end_len = h
-    .get("history_end")
-    .or_else(|| h.get("historyEnd"))
-    .and_then(|x| x.as_array())
-    .map(|a| a.len());
-  let tmpl = truncate_inline_text(
-    h.get("message_template")
-      .or_else(|| h.get("messageTemplate")),
-    400,
-  );
+    if self.usage_input_tokens.is_some() || self.usage_output_tokens.is_some() {
+      self.node_id += 1;
+      chunks.push(crate::protocol::AugmentStreamChunk {
+        text: "".to_string(),
+        unknown_blob_names: Vec::new(),
+        checkpoint_not_found: false,
+        workspace_file_chunks: Vec::new(),
+        nodes: vec![NodeOut {
+          id: self.node_id,
+          node_type: RESPONSE_NODE_TOKEN_USAGE,
+          content: "".to_string(),
+          tool_use: None,
+          thinking: None,
+          token_usage: Some(TokenUsageNode {
+            input_tokens: self.usage_input_tokens,
+            output_tokens: self.usage_output_tokens,
+            cache_read_input_tokens: None,
+            cache_creation_input_tokens: None,
+          }),
+        }],
+        stop_reason: None,
+      });
+    }
 
-  let mut lines: Vec<String> = vec!["[HISTORY_SUMMARY]".to_string()];
-  if !req_id.is_empty() {
-    lines.push(format!("summarization_request_id={req_id}"));
-  }
-  if let Some(n) = dropped {
-    lines.push(format!("history_beginning_dropped_num_exchanges={n}"));
-  }
-  if !tmpl.is_empty() {
-    lines.push(format!("message_template={tmpl}"));
-  }
-  if !summary_text.is_empty() {
-    lines.push(format!("summary_text={summary_text}"));
-  }
-  if !abridged.is_empty() {
-    lines.push(format!("history_middle_abridged_text={abridged}"));
-  }
-  if let Some(n) = end_len {
-    if n > 0 {
-      lines.push(format!("history_end_exchanges={n}"));
+    let mut final_nodes: Vec<NodeOut> = Vec::new();
+    if !self.full_text.is_empty() {
+      self.node_id += 1;
+      final_nodes.push(NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_MAIN_TEXT_FINISHED,
+        content: self.full_text.clone(),
+        tool_use: None,
+        thinking: None,
+        token_usage: None,
+      });
     }
+
+    let stop_reason = self.stop_reason.unwrap_or_else(|| {
+      if self.saw_tool_use {
+        STOP_REASON_TOOL_USE_REQUESTED
+      } else {
+        STOP_REASON_END_TURN
+      }
+    });
+    chunks.push(crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      nodes: final_nodes,
+      stop_reason: Some(stop_reason),
+    });
+    chunks
   }
-  if lines.len() == 1 {
-    return String::new();
-  }
-  lines.push("[/HISTORY_SUMMARY]".to_string());
-  lines.join("\n").trim().to_string()
 }
 
 #[derive(Debug, Default)]
-pub struct AnthropicStreamState {
+pub struct GeminiStreamState {
   pub node_id: i32,
   pub full_text: String,
   pub saw_tool_use: bool,
   pub stop_reason: Option<i32>,
   pub tool_meta_by_name: HashMap<String, (String, String)>,
-  pub current_tool_use_id: Option<String>,
-  pub current_tool_name: Option<String>,
-  pub current_mcp_server_name: String,
-  pub current_mcp_tool_name: String,
-  pub tool_input_buffer: String,
-  pub in_thinking_block: bool,
+  pub tool_call_count: usize,
   pub thinking_buffer: String,
+  pub thought_signature: String,
+  pub thought_signature_emitted: bool,
   pub usage_input_tokens: Option<i64>,
   pub usage_output_tokens: Option<i64>,
   pub usage_cache_read_input_tokens: Option<i64>,
-  pub usage_cache_creation_input_tokens: Option<i64>,
   pub stop_reason_seen: bool,
 }
 
-impl AnthropicStreamState {
+impl GeminiStreamState {
+  pub fn on_chunk(
+    &mut self,
+    chunk: &crate::gemini::GeminiStreamChunk,
+  ) -> Vec<crate::protocol::AugmentStreamChunk> {
+    let mut out: Vec<crate::protocol::AugmentStreamChunk> = Vec::new();
+    if let Some(u) = chunk.usage_metadata.as_ref() {
+      self.on_usage(u);
+    }
+    if let Some(candidate) = chunk.candidates.first() {
+      if let Some(content) = candidate.content.as_ref() {
+        for part in &content.parts {
+          out.extend(self.on_part(part));
+        }
+      }
+      if let Some(r) = candidate.finish_reason.as_deref() {
+        self.on_finish_reason(r);
+      }
+    }
+    out
+  }
+
+  pub fn on_part(&mut self, part: &GeminiPart) -> Vec<crate::protocol::AugmentStreamChunk> {
+    if let Some(sig) = part
+      .thought_signature
+      .as_deref()
+      .map(str::trim)
+      .filter(|s| !s.is_empty())
+    {
+      if self.thought_signature.is_empty() {
+        self.thought_signature = sig.to_string();
+      }
+    }
+
+    if part.thought == Some(true) {
+      if let Some(t) = part.text.as_deref() {
+        self.thinking_buffer.push_str(t);
+      }
+      return Vec::new();
+    }
+
+    let mut out: Vec<crate::protocol::AugmentStreamChunk> = Vec::new();
+    if let Some(call) = part.function_call.as_ref() {
+      out.extend(self.flush_thinking());
+      out.extend(self.on_function_call(call));
+      return out;
+    }
+    if let Some(t) = part.text.as_deref().filter(|t| !t.is_empty()) {
+      out.extend(self.flush_thinking());
+      out.push(self.on_text_delta(t));
+    }
+    out
+  }
+
   pub fn on_text_delta(&mut self, delta: &str) -> crate::protocol::AugmentStreamChunk {
     self.full_text.push_str(delta);
     self.node_id += 1;
@@ -1611,56 +2911,36 @@ impl AnthropicStreamState {
       unknown_blob_names: Vec::new(),
       checkpoint_not_found: false,
       workspace_file_chunks: Vec::new(),
-      stop_reason: None,
-    }
-  }
-
-  pub fn on_usage(&mut self, usage: &crate::anthropic::AnthropicUsage) {
-    if let Some(v) = usage.input_tokens {
-      self.usage_input_tokens = Some(v);
-    }
-    if let Some(v) = usage.output_tokens {
-      self.usage_output_tokens = Some(v);
-    }
-    if let Some(v) = usage.cache_read_input_tokens {
-      self.usage_cache_read_input_tokens = Some(v);
-    }
-    if let Some(v) = usage.cache_creation_input_tokens {
-      self.usage_cache_creation_input_tokens = Some(v);
-    }
-  }
-
-  pub fn on_thinking_block_start(&mut self) {
-    self.in_thinking_block = true;
-    self.thinking_buffer.clear();
-  }
-
-  pub fn on_thinking_delta(&mut self, delta: &str) {
-    self.thinking_buffer.push_str(delta);
-  }
-
-  pub fn on_thinking_block_stop(&mut self) -> Option<crate::protocol::AugmentStreamChunk> {
-    if !self.in_thinking_block {
-      return None;
+      stop_reason: None,
     }
-    self.in_thinking_block = false;
+  }
+
+  fn flush_thinking(&mut self) -> Option<crate::protocol::AugmentStreamChunk> {
     if self.thinking_buffer.is_empty() {
       return None;
     }
-    self.node_id += 1;
-    let node = NodeOut {
-      id: self.node_id,
-      node_type: RESPONSE_NODE_THINKING,
-      content: "".to_string(),
-      tool_use: None,
-      thinking: Some(ThinkingNode {
-        summary: std::mem::take(&mut self.thinking_buffer),
-      }),
-      token_usage: None,
+    let signature = if self.thought_signature_emitted {
+      String::new()
+    } else {
+      self.thought_signature_emitted = !self.thought_signature.is_empty();
+      self.thought_signature.clone()
     };
+    self.node_id += 1;
     Some(crate::protocol::AugmentStreamChunk {
       text: "".to_string(),
-      nodes: vec![node],
+      nodes: vec![NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_THINKING,
+        content: "".to_string(),
+        tool_use: None,
+        thinking: Some(ThinkingNode {
+          summary: std::mem::take(&mut self.thinking_buffer),
+          signature,
+          encrypted_content: String::new(),
+          provider: THINKING_PROVIDER_GEMINI.to_string(),
+        }),
+        token_usage: None,
+      }],
       unknown_blob_names: Vec::new(),
       checkpoint_not_found: false,
       workspace_file_chunks: Vec::new(),
@@ -1668,51 +2948,36 @@ impl AnthropicStreamState {
     })
   }
 
-  pub fn on_tool_use_block_start(&mut self, tool_use_id: &str, tool_name: &str) {
-    let tool_name = tool_name.trim().to_string();
-    self.current_tool_use_id = Some(tool_use_id.trim().to_string());
-    self.current_tool_name = Some(tool_name.clone());
-    self.tool_input_buffer.clear();
-    if let Some((mcp_server_name, mcp_tool_name)) = self.tool_meta_by_name.get(&tool_name) {
-      self.current_mcp_server_name = mcp_server_name.clone();
-      self.current_mcp_tool_name = mcp_tool_name.clone();
-    } else {
-      self.current_mcp_server_name.clear();
-      self.current_mcp_tool_name.clear();
-    }
-  }
-
-  pub fn on_tool_input_json_delta(&mut self, partial: &str) {
-    self.tool_input_buffer.push_str(partial);
-  }
-
-  pub fn on_tool_use_block_stop(&mut self) -> Vec<crate::protocol::AugmentStreamChunk> {
-    let (Some(mut id), Some(name)) = (
-      self.current_tool_use_id.take(),
-      self.current_tool_name.take(),
-    ) else {
+  pub fn on_function_call(
+    &mut self,
+    call: &GeminiFunctionCall,
+  ) -> Vec<crate::protocol::AugmentStreamChunk> {
+    let name = call.name.trim();
+    if name.is_empty() {
       return Vec::new();
-    };
-
-    if id.trim().is_empty() {
-      id = format!("tool-{}", self.node_id + 1);
     }
-    let input_json = {
-      let v = std::mem::take(&mut self.tool_input_buffer);
-      let trimmed = v.trim();
-      if trimmed.is_empty() {
-        "{}".to_string()
-      } else {
-        trimmed.to_string()
-      }
+    self.tool_call_count += 1;
+    let id = call
+      .id
+      .as_deref()
+      .map(str::trim)
+      .filter(|s| !s.is_empty())
+      .map(str::to_string)
+      .unwrap_or_else(|| format!("tool-{}", self.tool_call_count));
+    let input_json = match call.args.as_ref() {
+      Some(v) if !v.is_null() => serde_json::to_string(v).unwrap_or_else(|_| "{}".to_string()),
+      _ => "{}".to_string(),
     };
+    let (mcp_server_name, mcp_tool_name) = self
+      .tool_meta_by_name
+      .get(name)
+      .cloned()
+      .unwrap_or_default();
 
     self.saw_tool_use = true;
-    let mcp_server_name = std::mem::take(&mut self.current_mcp_server_name);
-    let mcp_tool_name = std::mem::take(&mut self.current_mcp_tool_name);
     let tool_use = ToolUse {
-      tool_use_id: id.clone(),
-      tool_name: name,
+      tool_use_id: id,
+      tool_name: name.to_string(),
       input_json,
       mcp_server_name,
       mcp_tool_name,
@@ -1750,22 +3015,59 @@ impl AnthropicStreamState {
     vec![start, done]
   }
 
-  pub fn on_stop_reason(&mut self, anthropic_stop_reason: &str) {
+  pub fn on_usage(&mut self, usage: &crate::gemini::GeminiUsageMetadata) {
+    if let Some(v) = usage.prompt_token_count {
+      self.usage_input_tokens = Some(v);
+    }
+    if usage.candidates_token_count.is_some() || usage.thoughts_token_count.is_some() {
+      self.usage_output_tokens =
+        Some(usage.candidates_token_count.unwrap_or(0) + usage.thoughts_token_count.unwrap_or(0));
+    }
+    if let Some(v) = usage.cached_content_token_count {
+      self.usage_cache_read_input_tokens = Some(v);
+    }
+  }
+
+  pub fn on_finish_reason(&mut self, finish_reason: &str) {
     self.stop_reason_seen = true;
-    self.stop_reason = Some(map_anthropic_stop_reason_to_augment(anthropic_stop_reason));
+    self.stop_reason = Some(map_gemini_finish_reason_to_augment(finish_reason));
   }
 
   pub fn finalize(&mut self) -> Vec<crate::protocol::AugmentStreamChunk> {
     let mut chunks: Vec<crate::protocol::AugmentStreamChunk> = Vec::new();
 
-    if let Some(thinking) = self.on_thinking_block_stop() {
+    if let Some(thinking) = self.flush_thinking() {
       chunks.push(thinking);
     }
+    // thoughtSignature 可能出现在最后一个（空）text part 上，此时单独补一个只带 signature 的 thinking 节点。
+    if !self.thought_signature.is_empty() && !self.thought_signature_emitted {
+      self.thought_signature_emitted = true;
+      self.node_id += 1;
+      chunks.push(crate::protocol::AugmentStreamChunk {
+        text: "".to_string(),
+        unknown_blob_names: Vec::new(),
+        checkpoint_not_found: false,
+        workspace_file_chunks: Vec::new(),
+        nodes: vec![NodeOut {
+          id: self.node_id,
+          node_type: RESPONSE_NODE_THINKING,
+          content: "".to_string(),
+          tool_use: None,
+          thinking: Some(ThinkingNode {
+            summary: String::new(),
+            signature: self.thought_signature.clone(),
+            encrypted_content: String::new(),
+            provider: THINKING_PROVIDER_GEMINI.to_string(),
+          }),
+          token_usage: None,
+        }],
+        stop_reason: None,
+      });
+    }
 
     if self.usage_input_tokens.is_some()
       || self.usage_output_tokens.is_some()
       || self.usage_cache_read_input_tokens.is_some()
-      || self.usage_cache_creation_input_tokens.is_some()
     {
       self.node_id += 1;
       chunks.push(crate::protocol::AugmentStreamChunk {
@@ -1783,7 +3085,7 @@ impl AnthropicStreamState {
             input_tokens: self.usage_input_tokens,
             output_tokens: self.usage_output_tokens,
             cache_read_input_tokens: self.usage_cache_read_input_tokens,
-            cache_creation_input_tokens: self.usage_cache_creation_input_tokens,
+            cache_creation_input_tokens: None,
           }),
         }],
         stop_reason: None,
@@ -1803,13 +3105,12 @@ impl AnthropicStreamState {
       });
     }
 
-    let stop_reason = self.stop_reason.unwrap_or_else(|| {
-      if self.saw_tool_use {
-        STOP_REASON_TOOL_USE_REQUESTED
-      } else {
-        STOP_REASON_END_TURN
-      }
-    });
+    // Gemini 在返回 functionCall 时 finishReason 仍是 STOP。
+    let stop_reason = match self.stop_reason {
+      Some(STOP_REASON_END_TURN) | None if self.saw_tool_use => STOP_REASON_TOOL_USE_REQUESTED,
+      Some(v) => v,
+      None => STOP_REASON_END_TURN,
+    };
     chunks.push(crate::protocol::AugmentStreamChunk {
       text: "".to_string(),
       unknown_blob_names: Vec::new(),
@@ -1818,35 +3119,102 @@ impl AnthropicStreamState {
       nodes: final_nodes,
       stop_reason: Some(stop_reason),
     });
-
     chunks
   }
 }
 
 #[derive(Debug, Default)]
-pub struct OpenAIStreamState {
+pub struct OpenAIResponsesStreamState {
   pub node_id: i32,
   pub full_text: String,
   pub saw_tool_use: bool,
   pub stop_reason: Option<i32>,
   pub tool_meta_by_name: HashMap<String, (String, String)>,
-  pub tool_calls: HashMap<usize, OpenAIToolCallBuffer>,
+  pub function_calls: HashMap<String, OpenAIToolCallBuffer>,
+  pub function_call_order: Vec<String>,
+  // 已登记的 function_call 数，用于给缺少 call_id 的调用生成不重复的 tool-N
+  pub function_call_seq: usize,
+  pub reasoning_buffer: String,
   pub usage_input_tokens: Option<i64>,
   pub usage_output_tokens: Option<i64>,
+  pub usage_cache_read_input_tokens: Option<i64>,
   pub stop_reason_seen: bool,
+  pub error_message: Option<String>,
 }
 
-#[derive(Debug, Default)]
-pub struct OpenAIToolCallBuffer {
-  pub id: String,
-  pub name: String,
-  pub arguments: String,
-  pub mcp_server_name: String,
-  pub mcp_tool_name: String,
-  pub started: bool,
-}
+impl OpenAIResponsesStreamState {
+  pub fn on_event(
+    &mut self,
+    event: OpenAIResponsesStreamEvent,
+  ) -> Vec<crate::protocol::AugmentStreamChunk> {
+    match event.event_type.as_str() {
+      "response.output_text.delta" => match event.delta.as_deref() {
+        Some(delta) if !delta.is_empty() => vec![self.on_text_delta(delta)],
+        _ => Vec::new(),
+      },
+      "response.reasoning_summary_part.added" => {
+        if !self.reasoning_buffer.is_empty() {
+          self.reasoning_buffer.push_str("\n\n");
+        }
+        Vec::new()
+      }
+      "response.reasoning_summary_text.delta" => {
+        if let Some(delta) = event.delta.as_deref() {
+          self.reasoning_buffer.push_str(delta);
+        }
+        Vec::new()
+      }
+      "response.output_item.added" => match event.item {
+        Some(item) if item.item_type == "function_call" => {
+          self.on_function_call_added(&item).into_iter().collect()
+        }
+        _ => Vec::new(),
+      },
+      "response.function_call_arguments.delta" => {
+        if let (Some(item_id), Some(delta)) = (event.item_id.as_deref(), event.delta.as_deref()) {
+          if let Some(call) = self.function_calls.get_mut(item_id) {
+            call.arguments.push_str(delta);
+          }
+        }
+        Vec::new()
+      }
+      "response.output_item.done" => match event.item {
+        Some(item) if item.item_type == "reasoning" => {
+          self.on_reasoning_item_done(&item).into_iter().collect()
+        }
+        Some(item) if item.item_type == "function_call" => self.on_function_call_done(&item),
+        _ => Vec::new(),
+      },
+      "response.completed" | "response.incomplete" => {
+        if let Some(resp) = event.response.as_ref() {
+          self.on_response_finished(resp);
+        }
+        Vec::new()
+      }
+      "response.failed" => {
+        let msg = event
+          .response
+          .as_ref()
+          .and_then(|r| r.error.as_ref())
+          .map(|e| e.message.clone())
+          .filter(|s| !s.trim().is_empty())
+          .unwrap_or_else(|| "response.failed".to_string());
+        self.error_message = Some(msg);
+        Vec::new()
+      }
+      "error" => {
+        self.error_message = Some(
+          event
+            .message
+            .filter(|s| !s.trim().is_empty())
+            .unwrap_or_else(|| "error".to_string()),
+        );
+        Vec::new()
+      }
+      _ => Vec::new(),
+    }
+  }
 
-impl OpenAIStreamState {
   pub fn on_text_delta(&mut self, delta: &str) -> crate::protocol::AugmentStreamChunk {
     self.full_text.push_str(delta);
     self.node_id += 1;
@@ -1867,56 +3235,97 @@ impl OpenAIStreamState {
     }
   }
 
-  pub fn on_usage(&mut self, prompt_tokens: Option<i64>, completion_tokens: Option<i64>) {
-    if let Some(v) = prompt_tokens {
-      self.usage_input_tokens = Some(v);
-    }
-    if let Some(v) = completion_tokens {
-      self.usage_output_tokens = Some(v);
-    }
-  }
-
-  pub fn on_tool_call_delta(
+  pub fn on_reasoning_item_done(
     &mut self,
-    index: usize,
-    id: Option<&str>,
-    name: Option<&str>,
-    arguments: Option<&str>,
+    item: &OpenAIResponsesOutputItem,
   ) -> Option<crate::protocol::AugmentStreamChunk> {
-    let entry = self
-      .tool_calls
-      .entry(index)
-      .or_insert_with(OpenAIToolCallBuffer::default);
-
-    if entry.id.trim().is_empty() {
-      if let Some(id) = id.map(str::trim).filter(|s| !s.is_empty()) {
-        entry.id = id.to_string();
-      }
+    let mut summary = std::mem::take(&mut self.reasoning_buffer);
+    if summary.trim().is_empty() {
+      summary = item
+        .summary
+        .iter()
+        .map(|s| s.text.trim())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
     }
-
-    if entry.name.trim().is_empty() {
-      if let Some(name) = name.map(str::trim).filter(|s| !s.is_empty()) {
-        entry.name = name.to_string();
-        if let Some((mcp_server_name, mcp_tool_name)) = self.tool_meta_by_name.get(name) {
-          entry.mcp_server_name = mcp_server_name.clone();
-          entry.mcp_tool_name = mcp_tool_name.clone();
-        }
-      }
+    let encrypted_content = item.encrypted_content.clone().unwrap_or_default();
+    if summary.trim().is_empty() && encrypted_content.trim().is_empty() {
+      return None;
     }
+    self.node_id += 1;
+    Some(crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      nodes: vec![NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_THINKING,
+        content: "".to_string(),
+        tool_use: None,
+        thinking: Some(ThinkingNode {
+          summary,
+          signature: String::new(),
+          encrypted_content,
+          provider: THINKING_PROVIDER_OPENAI_RESPONSES.to_string(),
+        }),
+        token_usage: None,
+      }],
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      stop_reason: None,
+    })
+  }
 
-    if let Some(args) = arguments {
-      entry.arguments.push_str(args);
+  fn register_function_call(&mut self, item: &OpenAIResponsesOutputItem) -> String {
+    let item_id = item
+      .id
+      .clone()
+      .or_else(|| item.call_id.clone())
+      .unwrap_or_default();
+    let name = item.name.as_deref().unwrap_or("").trim().to_string();
+    self.function_call_seq += 1;
+    let mut call_id = item.call_id.as_deref().unwrap_or("").trim().to_string();
+    if call_id.is_empty() {
+      call_id = format!("tool-{}", self.function_call_seq);
     }
+    let (mcp_server_name, mcp_tool_name) = self
+      .tool_meta_by_name
+      .get(&name)
+      .cloned()
+      .unwrap_or_default();
+    self.function_call_order.push(item_id.clone());
+    self.function_calls.insert(
+      item_id.clone(),
+      OpenAIToolCallBuffer {
+        id: call_id,
+        name,
+        arguments: item.arguments.clone().unwrap_or_default(),
+        mcp_server_name,
+        mcp_tool_name,
+        started: false,
+      },
+    );
+    item_id
+  }
 
-    let name = entry.name.trim();
-    if name.is_empty() || entry.started {
+  fn on_function_call_added(
+    &mut self,
+    item: &OpenAIResponsesOutputItem,
+  ) -> Option<crate::protocol::AugmentStreamChunk> {
+    let item_id = self.register_function_call(item);
+    let call = self.function_calls.get_mut(&item_id)?;
+    if call.name.is_empty() {
       return None;
     }
-    if entry.id.trim().is_empty() {
-      entry.id = format!("tool-{}", index + 1);
-    }
+    call.started = true;
+    let tool_use = ToolUse {
+      tool_use_id: call.id.clone(),
+      tool_name: call.name.clone(),
+      input_json: "{}".to_string(),
+      mcp_server_name: call.mcp_server_name.clone(),
+      mcp_tool_name: call.mcp_tool_name.clone(),
+    };
 
-    entry.started = true;
     self.saw_tool_use = true;
     self.node_id += 1;
     Some(crate::protocol::AugmentStreamChunk {
@@ -1928,94 +3337,140 @@ impl OpenAIStreamState {
         id: self.node_id,
         node_type: RESPONSE_NODE_TOOL_USE_START,
         content: "".to_string(),
-        tool_use: Some(ToolUse {
-          tool_use_id: entry.id.trim().to_string(),
-          tool_name: name.to_string(),
-          input_json: "{}".to_string(),
-          mcp_server_name: entry.mcp_server_name.clone(),
-          mcp_tool_name: entry.mcp_tool_name.clone(),
-        }),
+        tool_use: Some(tool_use),
+        thinking: None,
+        token_usage: None,
+      }],
+      stop_reason: None,
+    })
+  }
+
+  fn on_function_call_done(
+    &mut self,
+    item: &OpenAIResponsesOutputItem,
+  ) -> Vec<crate::protocol::AugmentStreamChunk> {
+    let mut item_id = item
+      .id
+      .clone()
+      .or_else(|| item.call_id.clone())
+      .unwrap_or_default();
+    if !self.function_calls.contains_key(&item_id) {
+      item_id = self.register_function_call(item);
+    }
+    let Some(mut call) = self.function_calls.remove(&item_id) else {
+      return Vec::new();
+    };
+    self.function_call_order.retain(|id| id != &item_id);
+    if let Some(args) = item.arguments.as_deref().filter(|s| !s.trim().is_empty()) {
+      call.arguments = args.to_string();
+    }
+    self.emit_function_call(call)
+  }
+
+  fn emit_function_call(
+    &mut self,
+    call: OpenAIToolCallBuffer,
+  ) -> Vec<crate::protocol::AugmentStreamChunk> {
+    let name = call.name.trim();
+    if name.is_empty() {
+      return Vec::new();
+    }
+    let input_json = if call.arguments.trim().is_empty() {
+      "{}".to_string()
+    } else {
+      call.arguments.trim().to_string()
+    };
+
+    self.saw_tool_use = true;
+    let tool_use = ToolUse {
+      tool_use_id: call.id.clone(),
+      tool_name: name.to_string(),
+      input_json,
+      mcp_server_name: call.mcp_server_name.clone(),
+      mcp_tool_name: call.mcp_tool_name.clone(),
+    };
+
+    let mk_chunk = |node: NodeOut| crate::protocol::AugmentStreamChunk {
+      text: "".to_string(),
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      nodes: vec![node],
+      stop_reason: None,
+    };
+
+    let mut chunks: Vec<crate::protocol::AugmentStreamChunk> = Vec::new();
+    if !call.started {
+      self.node_id += 1;
+      chunks.push(mk_chunk(NodeOut {
+        id: self.node_id,
+        node_type: RESPONSE_NODE_TOOL_USE_START,
+        content: "".to_string(),
+        tool_use: Some(tool_use.clone()),
         thinking: None,
         token_usage: None,
-      }],
-      stop_reason: None,
-    })
+      }));
+    }
+
+    self.node_id += 1;
+    chunks.push(mk_chunk(NodeOut {
+      id: self.node_id,
+      node_type: RESPONSE_NODE_TOOL_USE,
+      content: "".to_string(),
+      tool_use: Some(tool_use),
+      thinking: None,
+      token_usage: None,
+    }));
+    chunks
   }
 
-  pub fn on_finish_reason(&mut self, finish_reason: &str) {
+  pub fn on_response_finished(&mut self, resp: &OpenAIResponsesResponse) {
+    if let Some(u) = resp.usage.as_ref() {
+      if let Some(v) = u.input_tokens {
+        self.usage_input_tokens = Some(v);
+      }
+      if let Some(v) = u.output_tokens {
+        self.usage_output_tokens = Some(v);
+      }
+      if let Some(v) = u
+        .input_tokens_details
+        .as_ref()
+        .and_then(|d| d.cached_tokens)
+      {
+        self.usage_cache_read_input_tokens = Some(v);
+      }
+    }
     self.stop_reason_seen = true;
-    self.stop_reason = Some(map_openai_finish_reason_to_augment(finish_reason));
+    let incomplete_reason = resp
+      .incomplete_details
+      .as_ref()
+      .and_then(|d| d.reason.as_deref());
+    self.stop_reason = Some(match incomplete_reason {
+      Some("max_output_tokens") => STOP_REASON_MAX_TOKENS,
+      Some("content_filter") => STOP_REASON_SAFETY,
+      _ if self.saw_tool_use => STOP_REASON_TOOL_USE_REQUESTED,
+      _ => STOP_REASON_END_TURN,
+    });
   }
 
   pub fn finalize(&mut self) -> Vec<crate::protocol::AugmentStreamChunk> {
     let mut chunks: Vec<crate::protocol::AugmentStreamChunk> = Vec::new();
 
-    let mut indices: Vec<usize> = self.tool_calls.keys().copied().collect();
-    indices.sort();
-    for idx in indices {
-      let Some(call) = self.tool_calls.get(&idx) else {
-        continue;
-      };
-      let name = call.name.trim();
-      let started = call.started;
-      if name.is_empty() {
-        continue;
-      }
-      let mut id = call.id.trim().to_string();
-      if id.is_empty() {
-        id = format!("tool-{}", self.node_id + 1);
-      }
-      let input_json = {
-        let v = call.arguments.trim();
-        if v.is_empty() {
-          "{}".to_string()
-        } else {
-          v.to_string()
-        }
-      };
-
-      self.saw_tool_use = true;
-      let tool_use = ToolUse {
-        tool_use_id: id.clone(),
-        tool_name: name.to_string(),
-        input_json,
-        mcp_server_name: call.mcp_server_name.clone(),
-        mcp_tool_name: call.mcp_tool_name.clone(),
-      };
-
-      let mk_chunk = |node: NodeOut| crate::protocol::AugmentStreamChunk {
-        text: "".to_string(),
-        unknown_blob_names: Vec::new(),
-        checkpoint_not_found: false,
-        workspace_file_chunks: Vec::new(),
-        nodes: vec![node],
-        stop_reason: None,
-      };
+    if !self.reasoning_buffer.trim().is_empty() {
+      let item = OpenAIResponsesOutputItem::default();
+      chunks.extend(self.on_reasoning_item_done(&item));
+    }
 
-      if !started {
-        self.node_id += 1;
-        chunks.push(mk_chunk(NodeOut {
-          id: self.node_id,
-          node_type: RESPONSE_NODE_TOOL_USE_START,
-          content: "".to_string(),
-          tool_use: Some(tool_use.clone()),
-          thinking: None,
-          token_usage: None,
-        }));
+    for item_id in std::mem::take(&mut self.function_call_order) {
+      if let Some(call) = self.function_calls.remove(&item_id) {
+        chunks.extend(self.emit_function_call(call));
       }
-
-      self.node_id += 1;
-      chunks.push(mk_chunk(NodeOut {
-        id: self.node_id,
-        node_type: RESPONSE_NODE_TOOL_USE,
-        content: "".to_string(),
-        tool_use: Some(tool_use),
-        thinking: None,
-        token_usage: None,
-      }));
     }
 
-    if self.usage_input_tokens.is_some() || self.usage_output_tokens.is_some() {
+    if self.usage_input_tokens.is_some()
+      || self.usage_output_tokens.is_some()
+      || self.usage_cache_read_input_tokens.is_some()
+    {
       self.node_id += 1;
       chunks.push(crate::protocol::AugmentStreamChunk {
         text: "".to_string(),
@@ -2031,7 +3486,7 @@ impl OpenAIStreamState {
           token_usage: Some(TokenUsageNode {
             input_tokens: self.usage_input_tokens,
             output_tokens: self.usage_output_tokens,
-            cache_read_input_tokens: None,
+            cache_read_input_tokens: self.usage_cache_read_input_tokens,
             cache_creation_input_tokens: None,
           }),
         }],
@@ -2052,12 +3507,10 @@ impl OpenAIStreamState {
       });
     }
 
-    let stop_reason = self.stop_reason.unwrap_or_else(|| {
-      if self.saw_tool_use {
-        STOP_REASON_TOOL_USE_REQUESTED
-      } else {
-        STOP_REASON_END_TURN
-      }
+    let stop_reason = self.stop_reason.unwrap_or(if self.saw_tool_use {
+      STOP_REASON_TOOL_USE_REQUESTED
+    } else {
+      STOP_REASON_END_TURN
     });
     chunks.push(crate::protocol::AugmentStreamChunk {
       text: "".to_string(),
@@ -2074,7 +3527,10 @@ impl OpenAIStreamState {
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::config::{AnthropicProviderConfig, OpenAICompatibleProviderConfig, ThinkingConfig};
+  use crate::config::{
+    AnthropicProviderConfig, GeminiProviderConfig, OpenAICompatibleProviderConfig,
+    OpenAIResponsesProviderConfig, ThinkingConfig,
+  };
   use crate::protocol::{
     AugmentChatHistory, AugmentContext, AugmentRequest, NodeIn, TextNode, ToolDefinition,
     ToolResultContentNode, ToolResultNode, ToolUse, REQUEST_NODE_TEXT, REQUEST_NODE_TOOL_RESULT,
@@ -2185,7 +3641,13 @@ mod tests {
     let provider = AnthropicProviderConfig {
       id: "p1".to_string(),
       base_url: "https://api.anthropic.com/v1".to_string(),
-      api_key: "sk-ant-dummy".to_string(),
+      api_key: "sk-ant-dummy".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
       default_model: "claude-sonnet-4-20250514".to_string(),
       max_tokens: 8192,
       timeout_seconds: 120,
@@ -2193,6 +3655,7 @@ mod tests {
         enabled: false,
         budget_tokens: 0,
       },
+      prompt_caching: false,
       extra_headers: BTreeMap::new(),
     };
 
@@ -2251,7 +3714,13 @@ mod tests {
     let cfg = AnthropicProviderConfig {
       id: "p1".to_string(),
       base_url: "https://api.anthropic.com/v1".to_string(),
-      api_key: "sk-ant-dummy".to_string(),
+      api_key: "sk-ant-dummy".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
       default_model: "claude-sonnet-4-20250514".to_string(),
       max_tokens: 8192,
       timeout_seconds: 120,
@@ -2259,6 +3728,7 @@ mod tests {
         enabled: true,
         budget_tokens: 10000,
       },
+      prompt_caching: false,
       extra_headers: BTreeMap::new(),
     };
 
@@ -2309,7 +3779,12 @@ mod tests {
     assert_eq!(out.tool_choice.as_ref().unwrap().choice_type, "auto");
     assert_eq!(out.thinking.as_ref().unwrap().thinking_type, "enabled");
     assert_eq!(out.thinking.as_ref().unwrap().budget_tokens, 10000);
-    let system = out.system.as_deref().unwrap_or("");
+    let system = out
+      .system
+      .as_ref()
+      .and_then(|blocks| blocks.first())
+      .and_then(|b| b.text.as_deref())
+      .unwrap_or("");
     assert_eq!(system.contains("PREFIX_CODE_"), false);
     assert_eq!(system.contains("G"), true);
     assert_eq!(system.contains("rust"), true);
@@ -2342,6 +3817,7 @@ mod tests {
     s.on_thinking_block_start();
     s.on_thinking_delta("t1");
     s.on_thinking_delta("t2");
+    s.on_signature_delta("sig-1");
     let thinking = s.on_thinking_block_stop().unwrap();
     assert_eq!(thinking.text, "");
     assert_eq!(thinking.nodes.len(), 1);
@@ -2350,6 +3826,25 @@ mod tests {
       thinking.nodes[0].thinking.as_ref().unwrap().summary,
       "t1t2".to_string()
     );
+    assert_eq!(
+      thinking.nodes[0].thinking.as_ref().unwrap().signature,
+      "sig-1".to_string()
+    );
+    assert_eq!(
+      thinking.nodes[0].thinking.as_ref().unwrap().provider,
+      THINKING_PROVIDER_ANTHROPIC
+    );
+
+    let redacted = s.on_redacted_thinking_block("opaque").unwrap();
+    assert_eq!(redacted.nodes[0].node_type, RESPONSE_NODE_THINKING);
+    assert_eq!(
+      redacted.nodes[0]
+        .thinking
+        .as_ref()
+        .unwrap()
+        .encrypted_content,
+      "opaque".to_string()
+    );
 
     s.on_tool_use_block_start("u1", "tool_a");
     s.on_tool_input_json_delta("{\"x\":");
@@ -2385,30 +3880,235 @@ mod tests {
   }
 
   #[test]
-  fn anthropic_history_does_not_emit_orphan_tool_results() {
+  fn anthropic_history_does_not_emit_orphan_tool_results() {
+    let provider = AnthropicProviderConfig {
+      id: "p1".to_string(),
+      base_url: "https://api.anthropic.com/v1".to_string(),
+      api_key: "sk-ant-dummy".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
+      default_model: "claude-sonnet-4-20250514".to_string(),
+      max_tokens: 8192,
+      timeout_seconds: 120,
+      thinking: ThinkingConfig {
+        enabled: false,
+        budget_tokens: 0,
+      },
+      prompt_caching: false,
+      extra_headers: BTreeMap::new(),
+    };
+
+    let history = vec![
+      AugmentChatHistory {
+        response_text: String::new(),
+        request_message: "[PREVIOUS_SUMMARY]\nS\n[/PREVIOUS_SUMMARY]".to_string(),
+        request_id: "r0".to_string(),
+        request_nodes: Vec::new(),
+        structured_request_nodes: Vec::new(),
+        nodes: Vec::new(),
+        response_nodes: Vec::new(),
+        structured_output_nodes: Vec::new(),
+      },
+      AugmentChatHistory {
+        response_text: "done".to_string(),
+        request_message: "-".to_string(),
+        request_id: "r1".to_string(),
+        request_nodes: vec![make_tool_result_node(1, "tool-1")],
+        structured_request_nodes: Vec::new(),
+        nodes: Vec::new(),
+        response_nodes: Vec::new(),
+        structured_output_nodes: Vec::new(),
+      },
+    ];
+
+    let augment = AugmentRequest {
+      model: None,
+      chat_history: history,
+      message: "hi".to_string(),
+      message_source: String::new(),
+      agent_memories: String::new(),
+      mode: "CHAT".to_string(),
+      prefix: String::new(),
+      selected_code: String::new(),
+      disable_selected_code_details: false,
+      suffix: String::new(),
+      diff: String::new(),
+      lang: String::new(),
+      path: String::new(),
+      blobs: None,
+      external_source_ids: Vec::new(),
+      user_guided_blobs: Vec::new(),
+      disable_auto_external_sources: false,
+      disable_retrieval: false,
+      canvas_id: String::new(),
+      user_guidelines: String::new(),
+      workspace_guidelines: String::new(),
+      rules: Value::Null,
+      tool_definitions: Vec::new(),
+      nodes: Vec::new(),
+      structured_request_nodes: Vec::new(),
+      request_nodes: Vec::new(),
+      conversation_id: None,
+      context: None,
+    };
+
+    let out = convert_augment_to_anthropic(&provider, &augment, "m".to_string()).unwrap();
+
+    let has_tool_result = out
+      .messages
+      .iter()
+      .flat_map(|m| m.content.iter())
+      .any(|b| b.block_type == "tool_result");
+    assert_eq!(has_tool_result, false);
+  }
+
+  #[test]
+  fn anthropic_history_emits_tool_results_after_tool_use() {
+    let provider = AnthropicProviderConfig {
+      id: "p1".to_string(),
+      base_url: "https://api.anthropic.com/v1".to_string(),
+      api_key: "sk-ant-dummy".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
+      default_model: "claude-sonnet-4-20250514".to_string(),
+      max_tokens: 8192,
+      timeout_seconds: 120,
+      thinking: ThinkingConfig {
+        enabled: false,
+        budget_tokens: 0,
+      },
+      prompt_caching: false,
+      extra_headers: BTreeMap::new(),
+    };
+
+    let history = vec![
+      AugmentChatHistory {
+        response_text: String::new(),
+        request_message: "please run a tool".to_string(),
+        request_id: "r0".to_string(),
+        request_nodes: Vec::new(),
+        structured_request_nodes: Vec::new(),
+        nodes: Vec::new(),
+        response_nodes: vec![make_tool_use_node(1, "tool-1")],
+        structured_output_nodes: Vec::new(),
+      },
+      AugmentChatHistory {
+        response_text: "done".to_string(),
+        request_message: "-".to_string(),
+        request_id: "r1".to_string(),
+        request_nodes: vec![make_tool_result_node(1, "tool-1")],
+        structured_request_nodes: Vec::new(),
+        nodes: Vec::new(),
+        response_nodes: Vec::new(),
+        structured_output_nodes: Vec::new(),
+      },
+    ];
+
+    let augment = AugmentRequest {
+      model: None,
+      chat_history: history,
+      message: "hi".to_string(),
+      message_source: String::new(),
+      agent_memories: String::new(),
+      mode: "CHAT".to_string(),
+      prefix: String::new(),
+      selected_code: String::new(),
+      disable_selected_code_details: false,
+      suffix: String::new(),
+      diff: String::new(),
+      lang: String::new(),
+      path: String::new(),
+      blobs: None,
+      external_source_ids: Vec::new(),
+      user_guided_blobs: Vec::new(),
+      disable_auto_external_sources: false,
+      disable_retrieval: false,
+      canvas_id: String::new(),
+      user_guidelines: String::new(),
+      workspace_guidelines: String::new(),
+      rules: Value::Null,
+      tool_definitions: Vec::new(),
+      nodes: Vec::new(),
+      structured_request_nodes: Vec::new(),
+      request_nodes: Vec::new(),
+      conversation_id: None,
+      context: None,
+    };
+
+    let out = convert_augment_to_anthropic(&provider, &augment, "m".to_string()).unwrap();
+
+    let tool_result_blocks: Vec<&AnthropicContentBlock> = out
+      .messages
+      .iter()
+      .flat_map(|m| m.content.iter())
+      .filter(|b| b.block_type == "tool_result")
+      .collect();
+    assert_eq!(tool_result_blocks.len(), 1);
+    assert_eq!(tool_result_blocks[0].tool_use_id.as_deref(), Some("tool-1"));
+  }
+
+  #[test]
+  fn anthropic_history_replays_signed_thinking_before_tool_use() {
     let provider = AnthropicProviderConfig {
       id: "p1".to_string(),
       base_url: "https://api.anthropic.com/v1".to_string(),
-      api_key: "sk-ant-dummy".to_string(),
+      api_key: "sk-ant-dummy".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
       default_model: "claude-sonnet-4-20250514".to_string(),
       max_tokens: 8192,
       timeout_seconds: 120,
       thinking: ThinkingConfig {
-        enabled: false,
-        budget_tokens: 0,
+        enabled: true,
+        budget_tokens: 1024,
       },
+      prompt_caching: false,
       extra_headers: BTreeMap::new(),
     };
 
+    let mut signed = empty_node(1, RESPONSE_NODE_THINKING);
+    signed.thinking = Some(ThinkingNode {
+      summary: "let me look".to_string(),
+      signature: "sig-1".to_string(),
+      encrypted_content: String::new(),
+      provider: THINKING_PROVIDER_ANTHROPIC.to_string(),
+    });
+    let mut redacted = empty_node(2, RESPONSE_NODE_THINKING);
+    redacted.thinking = Some(ThinkingNode {
+      summary: String::new(),
+      signature: String::new(),
+      encrypted_content: "opaque".to_string(),
+      provider: THINKING_PROVIDER_ANTHROPIC.to_string(),
+    });
+    let mut unsigned = empty_node(3, RESPONSE_NODE_THINKING);
+    unsigned.thinking = Some(ThinkingNode {
+      summary: "no signature".to_string(),
+      signature: String::new(),
+      encrypted_content: String::new(),
+      provider: THINKING_PROVIDER_ANTHROPIC.to_string(),
+    });
+
     let history = vec![
       AugmentChatHistory {
         response_text: String::new(),
-        request_message: "[PREVIOUS_SUMMARY]\nS\n[/PREVIOUS_SUMMARY]".to_string(),
+        request_message: "please run a tool".to_string(),
         request_id: "r0".to_string(),
         request_nodes: Vec::new(),
         structured_request_nodes: Vec::new(),
         nodes: Vec::new(),
-        response_nodes: Vec::new(),
+        response_nodes: vec![signed, redacted, unsigned, make_tool_use_node(4, "tool-1")],
         structured_output_nodes: Vec::new(),
       },
       AugmentChatHistory {
@@ -2429,7 +4129,7 @@ mod tests {
       message: "hi".to_string(),
       message_source: String::new(),
       agent_memories: String::new(),
-      mode: "CHAT".to_string(),
+      mode: "AGENT".to_string(),
       prefix: String::new(),
       selected_code: String::new(),
       disable_selected_code_details: false,
@@ -2455,21 +4155,40 @@ mod tests {
     };
 
     let out = convert_augment_to_anthropic(&provider, &augment, "m".to_string()).unwrap();
-
-    let has_tool_result = out
-      .messages
+    let assistant = out.messages.iter().find(|m| m.role == "assistant").unwrap();
+    let types: Vec<&str> = assistant
+      .content
       .iter()
-      .flat_map(|m| m.content.iter())
-      .any(|b| b.block_type == "tool_result");
-    assert_eq!(has_tool_result, false);
+      .map(|b| b.block_type.as_str())
+      .collect();
+    assert_eq!(types, vec!["thinking", "redacted_thinking", "tool_use"]);
+    assert_eq!(
+      assistant.content[0].thinking.as_deref(),
+      Some("let me look")
+    );
+    assert_eq!(assistant.content[0].signature.as_deref(), Some("sig-1"));
+    assert_eq!(assistant.content[1].data.as_deref(), Some("opaque"));
+
+    let mut disabled = provider;
+    disabled.thinking.enabled = false;
+    let out = convert_augment_to_anthropic(&disabled, &augment, "m".to_string()).unwrap();
+    let assistant = out.messages.iter().find(|m| m.role == "assistant").unwrap();
+    assert_eq!(assistant.content.len(), 1);
+    assert_eq!(assistant.content[0].block_type, "tool_use");
   }
 
   #[test]
-  fn anthropic_history_emits_tool_results_after_tool_use() {
+  fn anthropic_prompt_caching_places_breakpoints() {
     let provider = AnthropicProviderConfig {
       id: "p1".to_string(),
       base_url: "https://api.anthropic.com/v1".to_string(),
-      api_key: "sk-ant-dummy".to_string(),
+      api_key: "sk-ant-dummy".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
       default_model: "claude-sonnet-4-20250514".to_string(),
       max_tokens: 8192,
       timeout_seconds: 120,
@@ -2477,9 +4196,20 @@ mod tests {
         enabled: false,
         budget_tokens: 0,
       },
+      prompt_caching: true,
       extra_headers: BTreeMap::new(),
     };
 
+    let tool = |name: &str| ToolDefinition {
+      name: name.to_string(),
+      description: String::new(),
+      input_schema: None,
+      input_schema_json: String::new(),
+      tool_safety: None,
+      mcp_server_name: String::new(),
+      mcp_tool_name: String::new(),
+    };
+
     let history = vec![
       AugmentChatHistory {
         response_text: String::new(),
@@ -2509,7 +4239,7 @@ mod tests {
       message: "hi".to_string(),
       message_source: String::new(),
       agent_memories: String::new(),
-      mode: "CHAT".to_string(),
+      mode: "AGENT".to_string(),
       prefix: String::new(),
       selected_code: String::new(),
       disable_selected_code_details: false,
@@ -2523,10 +4253,10 @@ mod tests {
       disable_auto_external_sources: false,
       disable_retrieval: false,
       canvas_id: String::new(),
-      user_guidelines: String::new(),
+      user_guidelines: "G".to_string(),
       workspace_guidelines: String::new(),
       rules: Value::Null,
-      tool_definitions: Vec::new(),
+      tool_definitions: vec![tool("t1"), tool("t2")],
       nodes: Vec::new(),
       structured_request_nodes: Vec::new(),
       request_nodes: Vec::new(),
@@ -2535,15 +4265,38 @@ mod tests {
     };
 
     let out = convert_augment_to_anthropic(&provider, &augment, "m".to_string()).unwrap();
+    let tools = out.tools.as_ref().unwrap();
+    assert_eq!(tools[0].cache_control.is_none(), true);
+    assert_eq!(tools[1].cache_control.is_some(), true);
+    assert_eq!(
+      out.system.as_ref().unwrap()[0].cache_control.is_some(),
+      true
+    );
 
-    let tool_result_blocks: Vec<&AnthropicContentBlock> = out
+    let cached: Vec<&AnthropicContentBlock> = out
       .messages
       .iter()
       .flat_map(|m| m.content.iter())
-      .filter(|b| b.block_type == "tool_result")
+      .filter(|b| b.cache_control.is_some())
       .collect();
-    assert_eq!(tool_result_blocks.len(), 1);
-    assert_eq!(tool_result_blocks[0].tool_use_id.as_deref(), Some("tool-1"));
+    assert_eq!(cached.len(), 1);
+    assert_eq!(cached[0].block_type, "text");
+    assert_eq!(cached[0].text.as_deref(), Some("done"));
+    let last = out.messages.last().unwrap();
+    assert_eq!(last.content.iter().all(|b| b.cache_control.is_none()), true);
+
+    let mut disabled = provider;
+    disabled.prompt_caching = false;
+    let out = convert_augment_to_anthropic(&disabled, &augment, "m".to_string()).unwrap();
+    assert_eq!(out.tools.as_ref().unwrap()[1].cache_control.is_none(), true);
+    assert_eq!(
+      out
+        .messages
+        .iter()
+        .flat_map(|m| m.content.iter())
+        .all(|b| b.cache_control.is_none()),
+      true
+    );
   }
 
   #[test]
@@ -2551,10 +4304,17 @@ mod tests {
     let provider = OpenAICompatibleProviderConfig {
       id: "o1".to_string(),
       base_url: "https://api.openai.com/v1".to_string(),
-      api_key: "sk-test".to_string(),
+      api_key: "sk-test".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
       default_model: "gpt-4o-mini".to_string(),
       max_tokens: 1234,
       timeout_seconds: 120,
+      send_reasoning_content: false,
       extra_headers: BTreeMap::new(),
     };
 
@@ -2623,10 +4383,17 @@ mod tests {
     let provider = OpenAICompatibleProviderConfig {
       id: "o1".to_string(),
       base_url: "https://api.openai.com/v1".to_string(),
-      api_key: "sk-test".to_string(),
+      api_key: "sk-test".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
       default_model: "gpt-4o-mini".to_string(),
       max_tokens: 1234,
       timeout_seconds: 120,
+      send_reasoning_content: false,
       extra_headers: BTreeMap::new(),
     };
 
@@ -2694,10 +4461,17 @@ mod tests {
     let provider = OpenAICompatibleProviderConfig {
       id: "o1".to_string(),
       base_url: "https://api.openai.com/v1".to_string(),
-      api_key: "sk-test".to_string(),
+      api_key: "sk-test".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
       default_model: "gpt-4o-mini".to_string(),
       max_tokens: 1234,
       timeout_seconds: 120,
+      send_reasoning_content: false,
       extra_headers: BTreeMap::new(),
     };
 
@@ -2863,4 +4637,513 @@ mod tests {
     );
     assert_eq!(chunks[1].stop_reason, Some(STOP_REASON_TOOL_USE_REQUESTED));
   }
+
+  #[test]
+  fn openai_reasoning_content_becomes_thinking_and_replays_when_enabled() {
+    let mut state = OpenAIStreamState::default();
+    state.on_reasoning_delta("r1");
+    state.on_reasoning_delta("r2");
+    let thinking = state.flush_thinking().unwrap();
+    assert_eq!(thinking.nodes[0].node_type, RESPONSE_NODE_THINKING);
+    assert_eq!(
+      thinking.nodes[0].thinking.as_ref().unwrap().summary,
+      "r1r2".to_string()
+    );
+    assert_eq!(state.flush_thinking().is_none(), true);
+    state.on_reasoning_delta("tail");
+    let chunks = state.finalize();
+    assert_eq!(chunks[0].nodes[0].node_type, RESPONSE_NODE_THINKING);
+
+    let provider = OpenAICompatibleProviderConfig {
+      id: "o1".to_string(),
+      base_url: "https://api.deepseek.com/v1".to_string(),
+      api_key: "sk-test".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
+      default_model: "deepseek-reasoner".to_string(),
+      max_tokens: 1234,
+      timeout_seconds: 120,
+      send_reasoning_content: true,
+      extra_headers: BTreeMap::new(),
+    };
+    let mut reasoning = empty_node(1, RESPONSE_NODE_THINKING);
+    reasoning.thinking = Some(ThinkingNode {
+      summary: "think first".to_string(),
+      signature: String::new(),
+      encrypted_content: String::new(),
+      provider: String::new(),
+    });
+    let augment = AugmentRequest {
+      model: None,
+      chat_history: vec![AugmentChatHistory {
+        response_text: String::new(),
+        request_message: "please run a tool".to_string(),
+        request_id: "r0".to_string(),
+        request_nodes: Vec::new(),
+        structured_request_nodes: Vec::new(),
+        nodes: Vec::new(),
+        response_nodes: vec![reasoning, make_tool_use_node(2, "tool-1")],
+        structured_output_nodes: Vec::new(),
+      }],
+      message: "hi".to_string(),
+      message_source: String::new(),
+      agent_memories: String::new(),
+      mode: "AGENT".to_string(),
+      prefix: String::new(),
+      selected_code: String::new(),
+      disable_selected_code_details: false,
+      suffix: String::new(),
+      diff: String::new(),
+      lang: String::new(),
+      path: String::new(),
+      blobs: None,
+      external_source_ids: Vec::new(),
+      user_guided_blobs: Vec::new(),
+      disable_auto_external_sources: false,
+      disable_retrieval: false,
+      canvas_id: String::new(),
+      user_guidelines: String::new(),
+      workspace_guidelines: String::new(),
+      rules: Value::Null,
+      tool_definitions: Vec::new(),
+      nodes: Vec::new(),
+      structured_request_nodes: Vec::new(),
+      request_nodes: Vec::new(),
+      conversation_id: None,
+      context: None,
+    };
+
+    let out = convert_augment_to_openai_compatible(&provider, &augment, "m".to_string()).unwrap();
+    let assistant = out.messages.iter().find(|m| m.role == "assistant").unwrap();
+    assert_eq!(assistant.reasoning_content.as_deref(), Some("think first"));
+
+    let mut disabled = provider;
+    disabled.send_reasoning_content = false;
+    let out = convert_augment_to_openai_compatible(&disabled, &augment, "m".to_string()).unwrap();
+    let assistant = out.messages.iter().find(|m| m.role == "assistant").unwrap();
+    assert_eq!(assistant.reasoning_content, None);
+  }
+
+  #[test]
+  fn gemini_history_replays_function_calls_and_thought_signature() {
+    let provider = GeminiProviderConfig {
+      id: "g1".to_string(),
+      base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
+      api_key: "test".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
+      default_model: "gemini-2.5-pro".to_string(),
+      max_tokens: 2048,
+      timeout_seconds: 120,
+      thinking: ThinkingConfig {
+        enabled: true,
+        budget_tokens: 512,
+      },
+      extra_headers: BTreeMap::new(),
+    };
+
+    let mut thinking = empty_node(2, RESPONSE_NODE_THINKING);
+    thinking.thinking = Some(ThinkingNode {
+      summary: "plan".to_string(),
+      signature: "sig-1".to_string(),
+      encrypted_content: String::new(),
+      provider: THINKING_PROVIDER_GEMINI.to_string(),
+    });
+    let history = vec![AugmentChatHistory {
+      response_text: String::new(),
+      request_message: "please run a tool".to_string(),
+      request_id: "r0".to_string(),
+      request_nodes: Vec::new(),
+      structured_request_nodes: Vec::new(),
+      nodes: Vec::new(),
+      response_nodes: vec![thinking, make_tool_use_node(1, "call-1")],
+      structured_output_nodes: Vec::new(),
+    }];
+
+    let augment = AugmentRequest {
+      model: None,
+      chat_history: history,
+      message: "-".to_string(),
+      message_source: String::new(),
+      agent_memories: String::new(),
+      mode: "AGENT".to_string(),
+      prefix: String::new(),
+      selected_code: String::new(),
+      disable_selected_code_details: false,
+      suffix: String::new(),
+      diff: String::new(),
+      lang: String::new(),
+      path: String::new(),
+      blobs: None,
+      external_source_ids: Vec::new(),
+      user_guided_blobs: Vec::new(),
+      disable_auto_external_sources: false,
+      disable_retrieval: false,
+      canvas_id: String::new(),
+      user_guidelines: "be brief".to_string(),
+      workspace_guidelines: String::new(),
+      rules: Value::Null,
+      tool_definitions: vec![ToolDefinition {
+        name: "view".to_string(),
+        description: "view file".to_string(),
+        input_schema: None,
+        input_schema_json: "{\"$schema\":\"x\",\"type\":\"object\",\"additionalProperties\":false,\"properties\":{\"examples\":{\"type\":\"string\"}}}".to_string(),
+        tool_safety: None,
+        mcp_server_name: String::new(),
+        mcp_tool_name: String::new(),
+      }],
+      nodes: vec![make_tool_result_node(1, "call-1")],
+      structured_request_nodes: Vec::new(),
+      request_nodes: Vec::new(),
+      conversation_id: None,
+      context: None,
+    };
+
+    let out = convert_augment_to_gemini(&provider, &augment).unwrap();
+    let roles: Vec<&str> = out.contents.iter().map(|c| c.role.as_str()).collect();
+    assert_eq!(roles, vec!["user", "model", "user"]);
+
+    let call_part = &out.contents[1].parts[0];
+    let call = call_part.function_call.as_ref().unwrap();
+    assert_eq!(call.id.as_deref(), Some("call-1"));
+    assert_eq!(call.name, "view");
+    assert_eq!(call_part.thought_signature.as_deref(), Some("sig-1"));
+
+    let resp = out.contents[2].parts[0].function_response.as_ref().unwrap();
+    assert_eq!(resp.id.as_deref(), Some("call-1"));
+    assert_eq!(resp.name, "view");
+    assert_eq!(resp.response, serde_json::json!({ "output": "OK" }));
+
+    let params = out.tools.as_ref().unwrap()[0].function_declarations[0]
+      .parameters
+      .clone()
+      .unwrap();
+    assert_eq!(
+      params,
+      serde_json::json!({"type":"object","properties":{"examples":{"type":"string"}}})
+    );
+    let system = out.system_instruction.unwrap().parts[0]
+      .text
+      .clone()
+      .unwrap();
+    assert_eq!(system.starts_with("be brief"), true);
+    let gen = out.generation_config.unwrap();
+    assert_eq!(gen.max_output_tokens, Some(2048));
+    assert_eq!(gen.thinking_config.unwrap().thinking_budget, Some(512));
+  }
+
+  #[test]
+  fn gemini_stream_state_handles_thoughts_function_calls_and_usage() {
+    let mut state = GeminiStreamState::default();
+    state
+      .tool_meta_by_name
+      .insert("view".to_string(), ("mcp".to_string(), "tool".to_string()));
+
+    let chunk: crate::gemini::GeminiStreamChunk = serde_json::from_str(
+      r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"thinking...","thought":true}]}}]}"#,
+    )
+    .unwrap();
+    assert_eq!(state.on_chunk(&chunk).len(), 0);
+
+    let chunk: crate::gemini::GeminiStreamChunk = serde_json::from_str(
+      r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"Hi"},{"functionCall":{"name":"view","args":{"path":"a"}},"thoughtSignature":"sig"}]},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":10,"candidatesTokenCount":3,"thoughtsTokenCount":2}}"#,
+    )
+    .unwrap();
+    let out = state.on_chunk(&chunk);
+    let types: Vec<i32> = out.iter().map(|c| c.nodes[0].node_type).collect();
+    assert_eq!(
+      types,
+      vec![
+        RESPONSE_NODE_THINKING,
+        RESPONSE_NODE_RAW_RESPONSE,
+        RESPONSE_NODE_TOOL_USE_START,
+        RESPONSE_NODE_TOOL_USE
+      ]
+    );
+    let tool_use = out[3].nodes[0].tool_use.as_ref().unwrap();
+    assert_eq!(tool_use.tool_use_id, "tool-1");
+    assert_eq!(tool_use.input_json, "{\"path\":\"a\"}");
+    assert_eq!(tool_use.mcp_server_name, "mcp");
+
+    let fin = state.finalize();
+    let thinking = out[0].nodes[0].thinking.as_ref().unwrap();
+    assert_eq!(thinking.summary, "thinking...");
+    assert_eq!(thinking.signature, "");
+    // signature 晚于 thinking 到达，finalize 时单独补发。
+    assert_eq!(fin[0].nodes[0].thinking.as_ref().unwrap().signature, "sig");
+    let usage = fin[1].nodes[0].token_usage.as_ref().unwrap();
+    assert_eq!(usage.input_tokens, Some(10));
+    assert_eq!(usage.output_tokens, Some(5));
+    let last = fin.last().unwrap();
+    assert_eq!(last.stop_reason, Some(STOP_REASON_TOOL_USE_REQUESTED));
+    assert_eq!(last.nodes[0].node_type, RESPONSE_NODE_MAIN_TEXT_FINISHED);
+  }
+
+  #[test]
+  fn openai_responses_history_replays_reasoning_and_function_calls() {
+    let provider = OpenAIResponsesProviderConfig {
+      id: "r1".to_string(),
+      base_url: "https://api.openai.com/v1".to_string(),
+      api_key: "sk-test".into(),
+      key_pool: Default::default(),
+      rate_limit: Default::default(),
+      first_byte_timeout_seconds: None,
+      idle_timeout_seconds: None,
+      max_concurrent_requests: 0,
+      queue_timeout_seconds: 60,
+      default_model: "o4-mini".to_string(),
+      max_tokens: 4096,
+      timeout_seconds: 120,
+      reasoning: crate::config::ReasoningConfig {
+        enabled: true,
+        effort: "high".to_string(),
+        summary: "auto".to_string(),
+      },
+      extra_headers: BTreeMap::new(),
+    };
+
+    let mut thinking = empty_node(2, RESPONSE_NODE_THINKING);
+    thinking.thinking = Some(ThinkingNode {
+      summary: "plan".to_string(),
+      signature: String::new(),
+      encrypted_content: "enc-1".to_string(),
+      provider: THINKING_PROVIDER_OPENAI_RESPONSES.to_string(),
+    });
+    let history = vec![AugmentChatHistory {
+      response_text: String::new(),
+      request_message: "please run a tool".to_string(),
+      request_id: "r0".to_string(),
+      request_nodes: Vec::new(),
+      structured_request_nodes: Vec::new(),
+      nodes: Vec::new(),
+      response_nodes: vec![thinking, make_tool_use_node(1, "call_1")],
+      structured_output_nodes: Vec::new(),
+    }];
+
+    let augment = AugmentRequest {
+      model: None,
+      chat_history: history,
+      message: "-".to_string(),
+      message_source: String::new(),
+      agent_memories: String::new(),
+      mode: "AGENT".to_string(),
+      prefix: String::new(),
+      selected_code: String::new(),
+      disable_selected_code_details: false,
+      suffix: String::new(),
+      diff: String::new(),
+      lang: String::new(),
+      path: String::new(),
+      blobs: None,
+      external_source_ids: Vec::new(),
+      user_guided_blobs: Vec::new(),
+      disable_auto_external_sources: false,
+      disable_retrieval: false,
+      canvas_id: String::new(),
+      user_guidelines: String::new(),
+      workspace_guidelines: String::new(),
+      rules: Value::Null,
+      tool_definitions: Vec::new(),
+      nodes: vec![make_tool_result_node(1, "call_1")],
+      structured_request_nodes: Vec::new(),
+      request_nodes: Vec::new(),
+      conversation_id: None,
+      context: None,
+    };
+
+    let out =
+      convert_augment_to_openai_responses(&provider, &augment, "o4-mini".to_string()).unwrap();
+    let v = serde_json::to_value(&out).unwrap();
+    let types: Vec<&str> = v["input"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|i| i["type"].as_str().unwrap())
+      .collect();
+    assert_eq!(
+      types,
+      vec![
+        "message",
+        "reasoning",
+        "function_call",
+        "function_call_output"
+      ]
+    );
+    assert_eq!(v["input"][1]["encrypted_content"], "enc-1");
+    assert_eq!(v["input"][1]["summary"][0]["text"], "plan");
+    assert_eq!(v["input"][2]["call_id"], "call_1");
+    assert_eq!(v["input"][3]["call_id"], "call_1");
+    assert_eq!(v["input"][3]["output"], "OK");
+    assert_eq!(v["store"], false);
+    assert_eq!(v["include"][0], "reasoning.encrypted_content");
+    assert_eq!(v["reasoning"]["effort"], "high");
+  }
+
+  #[test]
+  fn openai_responses_stream_state_handles_reasoning_and_function_calls() {
+    let mut state = OpenAIResponsesStreamState::default();
+    let ev = |s: &str| -> OpenAIResponsesStreamEvent { serde_json::from_str(s).unwrap() };
+
+    assert_eq!(
+      state
+        .on_event(ev(
+          r#"{"type":"response.reasoning_summary_text.delta","item_id":"rs_1","delta":"think"}"#
+        ))
+        .len(),
+      0
+    );
+    let out = state.on_event(ev(
+      r#"{"type":"response.output_item.done","item":{"type":"reasoning","id":"rs_1","summary":[],"encrypted_content":"enc"}}"#,
+    ));
+    let thinking = out[0].nodes[0].thinking.as_ref().unwrap();
+    assert_eq!(thinking.summary, "think");
+    assert_eq!(thinking.encrypted_content, "enc");
+    assert_eq!(thinking.provider, THINKING_PROVIDER_OPENAI_RESPONSES);
+
+    let out = state.on_event(ev(r#"{"type":"response.output_text.delta","delta":"Hi"}"#));
+    assert_eq!(out[0].nodes[0].node_type, RESPONSE_NODE_RAW_RESPONSE);
+
+    let out = state.on_event(ev(
+      r#"{"type":"response.output_item.added","item":{"type":"function_call","id":"fc_1","call_id":"call_1","name":"view","arguments":""}}"#,
+    ));
+    assert_eq!(out[0].nodes[0].node_type, RESPONSE_NODE_TOOL_USE_START);
+    state.on_event(ev(
+      r#"{"type":"response.function_call_arguments.delta","item_id":"fc_1","delta":"{\"path\":"}"#,
+    ));
+    state.on_event(ev(
+      r#"{"type":"response.function_call_arguments.delta","item_id":"fc_1","delta":"\"a\"}"}"#,
+    ));
+    let out = state.on_event(ev(
+      r#"{"type":"response.output_item.done","item":{"type":"function_call","id":"fc_1","call_id":"call_1","name":"view"}}"#,
+    ));
+    assert_eq!(out.len(), 1);
+    let tool_use = out[0].nodes[0].tool_use.as_ref().unwrap();
+    assert_eq!(out[0].nodes[0].node_type, RESPONSE_NODE_TOOL_USE);
+    assert_eq!(tool_use.tool_use_id, "call_1");
+    assert_eq!(tool_use.input_json, "{\"path\":\"a\"}");
+
+    // 没有 call_id 的调用：前一个 done 之后再来的调用不能复用同一个 tool-N
+    let mut ids = Vec::new();
+    for fc in ["fc_2", "fc_3"] {
+      state.on_event(ev(&format!(
+        r#"{{"type":"response.output_item.added","item":{{"type":"function_call","id":"{fc}","name":"view","arguments":""}}}}"#
+      )));
+      let out = state.on_event(ev(&format!(
+        r#"{{"type":"response.output_item.done","item":{{"type":"function_call","id":"{fc}","name":"view","arguments":"{{}}"}}}}"#
+      )));
+      ids.push(
+        out[0].nodes[0]
+          .tool_use
+          .as_ref()
+          .unwrap()
+          .tool_use_id
+          .clone(),
+      );
+    }
+    assert_ne!(ids[0], ids[1]);
+
+    state.on_event(ev(
+      r#"{"type":"response.completed","response":{"id":"resp_1","status":"completed","usage":{"input_tokens":12,"output_tokens":7,"input_tokens_details":{"cached_tokens":4}}}}"#,
+    ));
+    let fin = state.finalize();
+    let usage = fin[0].nodes[0].token_usage.as_ref().unwrap();
+    assert_eq!(usage.input_tokens, Some(12));
+    assert_eq!(usage.cache_read_input_tokens, Some(4));
+    assert_eq!(
+      fin.last().unwrap().stop_reason,
+      Some(STOP_REASON_TOOL_USE_REQUESTED)
+    );
+  }
+
+  #[test]
+  fn thinking_blobs_replay_only_to_the_provider_that_wrote_them() {
+    let thinking = |signature: &str, encrypted_content: &str, provider: &str| {
+      let mut n = empty_node(0, RESPONSE_NODE_THINKING);
+      n.thinking = Some(ThinkingNode {
+        summary: "plan".to_string(),
+        signature: signature.to_string(),
+        encrypted_content: encrypted_content.to_string(),
+        provider: provider.to_string(),
+      });
+      n
+    };
+    // 同一段对话先后经过 Anthropic、Gemini、Responses，另有一个升级前没有 provider 标记的节点
+    let mut augment: AugmentRequest =
+      serde_json::from_value(serde_json::json!({ "message": "-", "mode": "AGENT" })).unwrap();
+    augment.chat_history = vec![AugmentChatHistory {
+      response_text: String::new(),
+      request_message: "please run a tool".to_string(),
+      request_id: "r0".to_string(),
+      request_nodes: Vec::new(),
+      structured_request_nodes: Vec::new(),
+      nodes: Vec::new(),
+      response_nodes: vec![
+        thinking("sig-ant", "", THINKING_PROVIDER_ANTHROPIC),
+        thinking("", "redacted-ant", THINKING_PROVIDER_ANTHROPIC),
+        thinking("sig-gem", "", THINKING_PROVIDER_GEMINI),
+        thinking("", "enc-resp", THINKING_PROVIDER_OPENAI_RESPONSES),
+        thinking("sig-legacy", "enc-legacy", ""),
+        make_tool_use_node(1, "call-1"),
+      ],
+      structured_output_nodes: Vec::new(),
+    }];
+    augment.nodes = vec![make_tool_result_node(1, "call-1")];
+    let provider = |kind: &str| {
+      serde_json::json!({
+        "id": kind, "base_url": "https://x/v1", "api_key": "k", "default_model": "m",
+        "thinking": { "enabled": true, "budget_tokens": 1024 },
+        "reasoning": { "enabled": true, "effort": "high" },
+      })
+    };
+
+    let anthropic: AnthropicProviderConfig = serde_json::from_value(provider("a")).unwrap();
+    let out = convert_augment_to_anthropic(&anthropic, &augment, "m".to_string()).unwrap();
+    let assistant = out.messages.iter().find(|m| m.role == "assistant").unwrap();
+    let blocks: Vec<(&str, Option<&str>)> = assistant
+      .content
+      .iter()
+      .map(|b| {
+        let blob = b.signature.as_deref().or(b.data.as_deref());
+        (b.block_type.as_str(), blob)
+      })
+      .collect();
+    assert_eq!(
+      blocks,
+      vec![
+        ("thinking", Some("sig-ant")),
+        ("redacted_thinking", Some("redacted-ant")),
+        ("tool_use", None)
+      ]
+    );
+
+    let gemini: GeminiProviderConfig = serde_json::from_value(provider("g")).unwrap();
+    let out = convert_augment_to_gemini(&gemini, &augment).unwrap();
+    let signatures: Vec<&str> = out
+      .contents
+      .iter()
+      .flat_map(|c| &c.parts)
+      .filter_map(|p| p.thought_signature.as_deref())
+      .collect();
+    assert_eq!(signatures, vec!["sig-gem"]);
+
+    let responses: OpenAIResponsesProviderConfig = serde_json::from_value(provider("r")).unwrap();
+    let out = convert_augment_to_openai_responses(&responses, &augment, "m".to_string()).unwrap();
+    let v = serde_json::to_value(&out).unwrap();
+    let encrypted: Vec<&str> = v["input"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .filter(|i| i["type"] == "reasoning")
+      .map(|i| i["encrypted_content"].as_str().unwrap())
+      .collect();
+    assert_eq!(encrypted, vec!["enc-resp"]);
+  }
 }
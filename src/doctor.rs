@@ -0,0 +1,617 @@
+use std::{
+  collections::{BTreeMap, BTreeSet},
+  fmt::Write as _,
+  future::Future,
+  path::PathBuf,
+  time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::{
+  config::{self, Config, ProviderConfig},
+  config_override, fetch_provider_models, get_provider_by_id, provider_complete_text,
+  upstream_model_name,
+  util::{join_url, normalize_raw_token},
+  AppState,
+};
+
+// 0 = 全部通过（可有警告）；1 = 有探测失败；2 = config.yaml 无法加载/校验
+const EXIT_FAILED: i32 = 1;
+const EXIT_CONFIG: i32 = 2;
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct DoctorArgs {
+  #[arg(long, help = "跳过 1-token 补全探测（不消耗 token）")]
+  skip_completion: bool,
+  #[arg(long, help = "跳过官方 get-models 探测")]
+  skip_official: bool,
+  #[arg(
+    long,
+    default_value_t = 30,
+    value_name = "SECONDS",
+    help = "单项探测超时（秒）"
+  )]
+  timeout: u64,
+  #[arg(long, help = "警告也按失败处理（退出码 1）")]
+  strict: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+  Pass,
+  Warn,
+  Fail,
+  Skip,
+}
+
+#[derive(Debug)]
+struct Check {
+  component: String,
+  status: Status,
+  latency: Option<Duration>,
+  detail: String,
+  hint: Option<String>,
+}
+
+impl Check {
+  fn new(component: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+    Self {
+      component: component.into(),
+      status,
+      latency: None,
+      detail: detail.into(),
+      hint: None,
+    }
+  }
+
+  fn latency(mut self, latency: Duration) -> Self {
+    self.latency = Some(latency);
+    self
+  }
+
+  fn hint(mut self, hint: Option<String>) -> Self {
+    self.hint = hint;
+    self
+  }
+}
+
+pub(crate) async fn run(config_path: PathBuf, args: DoctorArgs) -> anyhow::Result<i32> {
+  config::init_cli_tracing()?;
+  let limit = Duration::from_secs(args.timeout.max(1));
+  let mut checks = Vec::new();
+
+  // cmd: 引用会执行外部命令，放到阻塞线程池
+  let started = Instant::now();
+  let path = config_path.clone();
+  let loaded = tokio::task::spawn_blocking(move || Config::load(&path))
+    .await
+    .context("加载配置任务失败")?;
+  let cfg = match loaded {
+    Ok(cfg) => {
+      let mut detail = format!(
+        "{} 已加载并通过校验（{} 个 provider）",
+        config_path.display(),
+        cfg.byok.providers.len()
+      );
+      // 覆盖配置里的字段优先于 config.yaml，直接改 config.yaml 不生效时多半是它
+      let override_path = config_override::override_path(&config_path);
+      if override_path.exists() {
+        let _ = write!(detail, "，已叠加 {}", override_path.display());
+      }
+      checks.push(Check::new("config", Status::Pass, detail).latency(started.elapsed()));
+      cfg
+    }
+    Err(err) => {
+      let text = format!("{err:#}");
+      let hint = config_hint(&text);
+      checks.push(
+        Check::new("config", Status::Fail, text)
+          .latency(started.elapsed())
+          .hint(hint),
+      );
+      print!("{}", render_report(&checks));
+      return Ok(EXIT_CONFIG);
+    }
+  };
+
+  let state = AppState::new(
+    config_path,
+    probe_config(&cfg),
+    reqwest::Client::builder().build()?,
+  )
+  .await;
+  let probe_cfg = state.cfg.read().await.clone();
+
+  // provider 之间并发探测，报告仍按配置顺序输出
+  let probes = cfg.byok.providers.iter().map(|p| {
+    let (state, probe_cfg, args) = (&state, &probe_cfg, &args);
+    async move { probe_provider(state, probe_cfg, p.id(), args, limit).await }
+  });
+  let mut models_by_provider = BTreeMap::new();
+  for (id, provider_checks, models) in futures::future::join_all(probes).await {
+    checks.extend(provider_checks);
+    if let Some(models) = models {
+      models_by_provider.insert(id, models);
+    }
+  }
+
+  if args.skip_official {
+    checks.push(Check::new(
+      "official get-models",
+      Status::Skip,
+      "--skip-official",
+    ));
+  } else {
+    checks.push(probe_official(&state, &cfg, limit).await);
+  }
+  checks.push(check_history_summary(&cfg, &models_by_provider));
+  checks.push(check_context_window_overrides(&cfg, &models_by_provider));
+
+  print!("{}", render_report(&checks));
+  Ok(exit_code(&checks, args.strict))
+}
+
+// 探测只需证明 key/模型可用：不重试（失败原因直接报出来），输出压到最小并关闭 thinking/reasoning
+fn probe_config(cfg: &Config) -> Config {
+  let mut cfg = cfg.clone();
+  cfg.retry.max_attempts = 1;
+  // 探测请求不写 usage 账本
+  cfg.usage.enabled = false;
+  for p in &mut cfg.byok.providers {
+    match p {
+      ProviderConfig::Anthropic(p) => {
+        p.max_tokens = 1;
+        p.thinking.enabled = false;
+      }
+      ProviderConfig::OpenAICompatible(p) => p.max_tokens = 1,
+      ProviderConfig::Gemini(p) => {
+        p.max_tokens = 1;
+        p.thinking.enabled = false;
+      }
+      // Responses API 要求 max_output_tokens >= 16
+      ProviderConfig::OpenAIResponses(p) => {
+        p.max_tokens = 16;
+        p.reasoning.enabled = false;
+      }
+    }
+  }
+  cfg
+}
+
+async fn timed<T>(
+  limit: Duration,
+  fut: impl Future<Output = anyhow::Result<T>>,
+) -> (Duration, anyhow::Result<T>) {
+  let started = Instant::now();
+  let res = match tokio::time::timeout(limit, fut).await {
+    Ok(res) => res,
+    Err(_) => Err(anyhow::anyhow!("探测超时（{}s）", limit.as_secs())),
+  };
+  (started.elapsed(), res)
+}
+
+async fn probe_provider(
+  state: &AppState,
+  cfg: &Config,
+  id: &str,
+  args: &DoctorArgs,
+  limit: Duration,
+) -> (String, Vec<Check>, Option<Vec<String>>) {
+  let mut checks = Vec::new();
+  let provider = match get_provider_by_id(cfg, id) {
+    Ok(p) => p,
+    Err(err) => {
+      checks.push(Check::new(
+        format!("provider {id}"),
+        Status::Fail,
+        format!("{err:#}"),
+      ));
+      return (id.to_string(), checks, None);
+    }
+  };
+  let default_model = upstream_model_name(provider, provider.default_model());
+
+  let (latency, res) = timed(limit, fetch_provider_models(state, provider)).await;
+  let models = match res {
+    Ok(models) => {
+      let listed = models
+        .iter()
+        .any(|m| upstream_model_name(provider, m) == default_model);
+      let check = if listed {
+        Check::new(
+          format!("provider {id} /models"),
+          Status::Pass,
+          format!("返回 {} 个模型", models.len()),
+        )
+      } else {
+        Check::new(
+          format!("provider {id} /models"),
+          Status::Warn,
+          format!(
+            "返回 {} 个模型，但不包含 default_model={default_model}",
+            models.len()
+          ),
+        )
+        .hint(Some(
+          "确认 default_model 拼写；部分网关的 /models 不列出全部模型，补全探测通过即可忽略"
+            .to_string(),
+        ))
+      };
+      checks.push(check.latency(latency));
+      Some(models)
+    }
+    Err(err) => {
+      let text = format!("{err:#}");
+      let hint = error_hint(&text, &format!("byok.providers[{id}]"));
+      checks.push(
+        Check::new(format!("provider {id} /models"), Status::Fail, text)
+          .latency(latency)
+          .hint(hint),
+      );
+      None
+    }
+  };
+
+  let component = format!("provider {id} completion");
+  if args.skip_completion {
+    checks.push(Check::new(component, Status::Skip, "--skip-completion"));
+  } else {
+    let (latency, res) = timed(
+      limit,
+      provider_complete_text(state, "doctor", provider, &default_model, "", "ping"),
+    )
+    .await;
+    let check = match res {
+      Ok(text) => Check::new(
+        component,
+        Status::Pass,
+        format!("model={default_model} 回复 {text:?}"),
+      ),
+      Err(err) => {
+        let text = format!("{err:#}");
+        let hint = error_hint(&text, &format!("byok.providers[{id}]"));
+        Check::new(
+          component,
+          Status::Fail,
+          format!("model={default_model}: {text}"),
+        )
+        .hint(hint)
+      }
+    };
+    checks.push(check.latency(latency));
+  }
+  (id.to_string(), checks, models)
+}
+
+async fn probe_official(state: &AppState, cfg: &Config, limit: Duration) -> Check {
+  const COMPONENT: &str = "official get-models";
+  let token = normalize_raw_token(&cfg.official.api_token);
+  let (latency, res) = timed(limit, async {
+    let url = join_url(&cfg.official.base_url, "get-models").context("official.base_url 无效")?;
+    let resp = state
+      .http
+      .post(url)
+      .header("content-type", "application/json")
+      .header("accept", "application/json")
+      .header("authorization", format!("Bearer {token}"))
+      .body("{}")
+      .send()
+      .await
+      .context("请求官方 get-models 失败")?;
+    let status = resp.status();
+    let text = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+      anyhow::bail!("官方 get-models 返回错误: {status} {text}");
+    }
+    let json: serde_json::Value =
+      serde_json::from_str(&text).context("官方 get-models 响应不是 JSON")?;
+    Ok(json)
+  })
+  .await;
+  match res {
+    Ok(json) => {
+      let detail = match json.get("models").and_then(|v| v.as_array()) {
+        Some(models) => format!("返回 {} 个模型", models.len()),
+        None => "已连通".to_string(),
+      };
+      Check::new(COMPONENT, Status::Pass, detail).latency(latency)
+    }
+    Err(err) => {
+      let text = format!("{err:#}");
+      let hint = match http_status(&text) {
+        Some(401 | 403) => Some(
+          "official.api_token 无效或已过期：从 Augment 插件重新获取 token".to_string(),
+        ),
+        Some(404) => Some(
+          "确认 official.base_url 是 Augment 租户地址（形如 https://<tenant>.api.augmentcode.com/）"
+            .to_string(),
+        ),
+        _ => error_hint(&text, "official"),
+      };
+      Check::new(COMPONENT, Status::Fail, text)
+        .latency(latency)
+        .hint(hint)
+    }
+  }
+}
+
+fn check_history_summary(
+  cfg: &Config,
+  models_by_provider: &BTreeMap<String, Vec<String>>,
+) -> Check {
+  const COMPONENT: &str = "history_summary";
+  let hs = &cfg.history_summary;
+  if !hs.enabled {
+    return Check::new(COMPONENT, Status::Skip, "history_summary.enabled=false");
+  }
+  let summary_provider = hs.provider_id.trim();
+  if summary_provider.is_empty() {
+    let model = match hs.model.trim() {
+      "" => "当次 chat 的模型".to_string(),
+      m => format!("model={m}"),
+    };
+    return Check::new(
+      COMPONENT,
+      Status::Pass,
+      format!("provider_id 为空，使用当次 chat 的 provider，{model}"),
+    );
+  }
+  let provider = match get_provider_by_id(cfg, summary_provider) {
+    Ok(p) => p,
+    Err(err) => {
+      return Check::new(COMPONENT, Status::Fail, format!("{err:#}")).hint(Some(format!(
+        "history_summary.provider_id 须为 byok.providers[].id 之一：{}",
+        cfg
+          .byok
+          .providers
+          .iter()
+          .map(ProviderConfig::id)
+          .collect::<Vec<_>>()
+          .join(", ")
+      )))
+    }
+  };
+  let model = match hs.model.trim() {
+    "" => provider.default_model().trim().to_string(),
+    m => m.to_string(),
+  };
+  let resolved = format!("provider={summary_provider} model={model}");
+  match models_by_provider.get(summary_provider) {
+    None => Check::new(
+      COMPONENT,
+      Status::Warn,
+      format!("{resolved}（该 provider 的 /models 探测失败，未能核对模型名）"),
+    ),
+    Some(models)
+      if !models
+        .iter()
+        .any(|m| upstream_model_name(provider, m) == upstream_model_name(provider, &model)) =>
+    {
+      Check::new(
+        COMPONENT,
+        Status::Warn,
+        format!("{resolved}，但该模型不在 /models 列表中"),
+      )
+      .hint(Some(
+        "确认 history_summary.model 拼写；留空则使用该 provider 的 default_model".to_string(),
+      ))
+    }
+    Some(_) => Check::new(COMPONENT, Status::Pass, resolved),
+  }
+}
+
+fn check_context_window_overrides(
+  cfg: &Config,
+  models_by_provider: &BTreeMap<String, Vec<String>>,
+) -> Check {
+  const COMPONENT: &str = "context_window_tokens_overrides";
+  let overrides = &cfg.history_summary.context_window_tokens_overrides;
+  if overrides.is_empty() {
+    return Check::new(COMPONENT, Status::Skip, "未配置");
+  }
+  // 配置里写死的模型名也算：/models 不列出的模型照样可以被请求
+  let mut known: BTreeSet<&str> = models_by_provider
+    .values()
+    .flatten()
+    .map(String::as_str)
+    .collect();
+  for p in &cfg.byok.providers {
+    if let Ok(p) = get_provider_by_id(cfg, p.id()) {
+      known.insert(p.default_model());
+    }
+  }
+  known.insert(cfg.history_summary.model.as_str());
+  let unmatched = unmatched_override_keys(overrides, &known);
+  if unmatched.is_empty() {
+    return Check::new(
+      COMPONENT,
+      Status::Pass,
+      format!("{} 条均命中已知模型名", overrides.len()),
+    );
+  }
+  Check::new(
+    COMPONENT,
+    Status::Warn,
+    format!("未命中任何已知模型名：{}", unmatched.join(", ")),
+  )
+  .hint(Some(
+    "key 按子串匹配请求的模型名（如 claude-sonnet-4 匹配 claude-sonnet-4-20250514）；确认拼写或删除不再使用的条目"
+      .to_string(),
+  ))
+}
+
+// 与 history_summary_auto 的匹配规则一致：模型名包含 key 即命中
+fn unmatched_override_keys<'a>(
+  overrides: &'a BTreeMap<String, u32>,
+  known: &BTreeSet<&str>,
+) -> Vec<&'a str> {
+  overrides
+    .keys()
+    .map(|k| k.trim())
+    .filter(|k| !k.is_empty() && !known.iter().any(|m| m.contains(k)))
+    .collect()
+}
+
+// 从 "... 返回错误: 401 Unauthorized ..." 中取 HTTP 状态码
+fn http_status(text: &str) -> Option<u16> {
+  let (_, rest) = text.split_once("返回错误: ")?;
+  rest.get(..3)?.parse().ok()
+}
+
+fn error_hint(text: &str, section: &str) -> Option<String> {
+  let hint = match http_status(text) {
+    Some(401 | 403) => format!("检查 {section} 的 api_key / key_pool 是否有效、是否有该模型权限"),
+    Some(404) => format!(
+      "检查 {section} 的 base_url 是否带正确的 API 前缀（如 https://api.openai.com/v1）以及模型名"
+    ),
+    Some(429) => "上游限流或额度不足：稍后重试或检查账户余额".to_string(),
+    Some(400) => format!("上游拒绝了请求：检查 {section} 的模型名与 extra_headers"),
+    Some(s) if s >= 500 => "上游服务端错误：稍后重试".to_string(),
+    _ if text.contains("api_key 为空") => {
+      format!("填写 {section} 的 api_key（支持 ${{ENV}} / file: / cmd: 引用）")
+    }
+    _ if text.contains("探测超时") || text.contains("timed out") => {
+      "请求超时：检查网络与代理，或用 --timeout 调大".to_string()
+    }
+    _ if text.contains("error sending request") || text.contains("dns") => {
+      format!("无法连接上游：检查 {section} 的 base_url 主机名、网络与代理（HTTPS_PROXY）")
+    }
+    _ if text.contains("熔断") => "该 provider 处于熔断状态：稍后重试".to_string(),
+    _ => return None,
+  };
+  Some(hint)
+}
+
+fn config_hint(text: &str) -> Option<String> {
+  let hint = if text.contains("读取配置失败") {
+    "确认 --config 路径；可从 config.example.yaml 复制一份开始"
+  } else if text.contains("解析 YAML") {
+    "YAML 语法或字段类型错误：对照 config.example.yaml 检查缩进与字段名"
+  } else if text.contains("解析配置引用失败") {
+    "检查 ${ENV} 环境变量是否已设置、file: 路径是否可读、cmd: 命令能否在当前 shell 执行"
+  } else {
+    "按错误信息修改 config.yaml 中对应字段"
+  };
+  Some(hint.to_string())
+}
+
+fn one_line(text: &str, max_chars: usize) -> String {
+  let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+  if flat.chars().count() <= max_chars {
+    return flat;
+  }
+  let mut out: String = flat.chars().take(max_chars).collect();
+  out.push('…');
+  out
+}
+
+fn render_report(checks: &[Check]) -> String {
+  let width = checks
+    .iter()
+    .map(|c| c.component.chars().count())
+    .max()
+    .unwrap_or(0);
+  let mut out = String::new();
+  let mut counts = [0usize; 4];
+  for c in checks {
+    let (mark, i) = match c.status {
+      Status::Pass => ("✅ PASS", 0),
+      Status::Warn => ("⚠️ WARN", 1),
+      Status::Fail => ("❌ FAIL", 2),
+      Status::Skip => ("⏭️ SKIP", 3),
+    };
+    counts[i] += 1;
+    let latency = c
+      .latency
+      .map(|d| format!("{}ms", d.as_millis()))
+      .unwrap_or_default();
+    let _ = writeln!(
+      out,
+      "{mark}  {:<width$}  {latency:>7}  {}",
+      c.component,
+      one_line(&c.detail, 240)
+    );
+    if let Some(hint) = &c.hint {
+      let _ = writeln!(out, "         ↳ {hint}");
+    }
+  }
+  let _ = writeln!(
+    out,
+    "\n共 {} 项：{} 通过，{} 警告，{} 失败，{} 跳过",
+    checks.len(),
+    counts[0],
+    counts[1],
+    counts[2],
+    counts[3]
+  );
+  out
+}
+
+fn exit_code(checks: &[Check], strict: bool) -> i32 {
+  let failed = checks.iter().any(|c| match c.status {
+    Status::Fail => true,
+    Status::Warn => strict,
+    Status::Pass | Status::Skip => false,
+  });
+  if failed {
+    EXIT_FAILED
+  } else {
+    0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn matches_overrides_by_substring_and_maps_failures_to_hints() {
+    let overrides: BTreeMap<String, u32> = [
+      ("claude-sonnet-4".to_string(), 200_000),
+      ("gpt-5".to_string(), 400_000),
+      ("gemnii-2.5".to_string(), 1_000_000),
+    ]
+    .into_iter()
+    .collect();
+    let known: BTreeSet<&str> = ["claude-sonnet-4-20250514", "gpt-5-mini"]
+      .into_iter()
+      .collect();
+    assert_eq!(unmatched_override_keys(&overrides, &known), ["gemnii-2.5"]);
+
+    assert_eq!(
+      http_status("请求 Anthropic /models 失败: Anthropic /models 返回错误: 401 Unauthorized {}"),
+      Some(401)
+    );
+    let hint = error_hint(
+      "OpenAI /models 返回错误: 404 Not Found",
+      "byok.providers[a]",
+    )
+    .unwrap();
+    assert!(hint.contains("base_url"), "{hint}");
+    assert!(error_hint("something else", "official").is_none());
+
+    let mut checks = vec![
+      Check::new("config", Status::Pass, "ok"),
+      Check::new("overrides", Status::Warn, "gemnii-2.5"),
+    ];
+    assert_eq!(exit_code(&checks, false), 0);
+    assert_eq!(exit_code(&checks, true), EXIT_FAILED);
+    checks.push(
+      Check::new("provider a /models", Status::Fail, "line1\nline2")
+        .latency(Duration::from_millis(12))
+        .hint(Some("检查 api_key".to_string())),
+    );
+    assert_eq!(exit_code(&checks, false), EXIT_FAILED);
+    let report = render_report(&checks);
+    assert!(
+      report.contains("❌ FAIL  provider a /models     12ms  line1 line2\n"),
+      "{report}"
+    );
+    assert!(report.contains("↳ 检查 api_key"), "{report}");
+    assert!(
+      report.ends_with("共 3 项：1 通过，1 警告，1 失败，0 跳过\n"),
+      "{report}"
+    );
+  }
+}
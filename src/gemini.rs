@@ -0,0 +1,170 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiRequest {
+  pub contents: Vec<GeminiContent>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub system_instruction: Option<GeminiContent>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tools: Option<Vec<GeminiTool>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tool_config: Option<GeminiToolConfig>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiContent {
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  pub role: String,
+  #[serde(default)]
+  pub parts: Vec<GeminiPart>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiPart {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub text: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub thought: Option<bool>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub thought_signature: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub inline_data: Option<GeminiInlineData>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub function_call: Option<GeminiFunctionCall>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub function_response: Option<GeminiFunctionResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiInlineData {
+  pub mime_type: String,
+  pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiFunctionCall {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+  #[serde(default)]
+  pub name: String,
+  #[serde(default)]
+  pub args: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeminiFunctionResponse {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+  pub name: String,
+  pub response: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiTool {
+  pub function_declarations: Vec<GeminiFunctionDeclaration>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeminiFunctionDeclaration {
+  pub name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiToolConfig {
+  pub function_calling_config: GeminiFunctionCallingConfig,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GeminiFunctionCallingConfig {
+  pub mode: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiGenerationConfig {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_output_tokens: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub temperature: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub top_p: Option<f32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub thinking_config: Option<GeminiThinkingConfig>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiThinkingConfig {
+  pub include_thoughts: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub thinking_budget: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiStreamChunk {
+  #[serde(default)]
+  pub candidates: Vec<GeminiCandidate>,
+  #[serde(default)]
+  pub usage_metadata: Option<GeminiUsageMetadata>,
+  #[serde(default)]
+  pub model_version: Option<String>,
+  #[serde(default)]
+  pub response_id: Option<String>,
+}
+
+impl GeminiStreamChunk {
+  pub fn text(&self) -> String {
+    let mut out = String::new();
+    if let Some(content) = self.candidates.first().and_then(|c| c.content.as_ref()) {
+      for part in &content.parts {
+        if part.thought == Some(true) {
+          continue;
+        }
+        if let Some(t) = part.text.as_deref() {
+          out.push_str(t);
+        }
+      }
+    }
+    out
+  }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiCandidate {
+  #[serde(default)]
+  pub content: Option<GeminiContent>,
+  #[serde(default)]
+  pub finish_reason: Option<String>,
+  #[serde(default)]
+  pub index: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiUsageMetadata {
+  #[serde(default)]
+  pub prompt_token_count: Option<i64>,
+  #[serde(default)]
+  pub candidates_token_count: Option<i64>,
+  #[serde(default)]
+  pub thoughts_token_count: Option<i64>,
+  #[serde(default)]
+  pub cached_content_token_count: Option<i64>,
+  #[serde(default)]
+  pub total_token_count: Option<i64>,
+}
@@ -7,23 +7,35 @@ use reqwest::header::HeaderValue;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use crate::anthropic::{AnthropicRequest, AnthropicResponse};
+use crate::budget::exceeded_budget;
+use crate::concurrency::RequestPriority;
 use crate::config::{
-  AbridgedHistoryParams, AnthropicProviderConfig, Config, OpenAICompatibleProviderConfig,
+  AbridgedHistoryParams, AnthropicProviderConfig, Config, GeminiProviderConfig,
+  HistorySummaryConfig, OpenAICompatibleProviderConfig, OpenAIResponsesProviderConfig,
   ProviderConfig,
 };
-use crate::convert::{convert_augment_to_anthropic, convert_augment_to_openai_compatible};
+use crate::convert::{
+  convert_augment_to_anthropic, convert_augment_to_gemini, convert_augment_to_openai_compatible,
+  convert_augment_to_openai_responses,
+};
+use crate::gemini::{GeminiRequest, GeminiStreamChunk};
 use crate::history_summary::compact_chat_history;
-use crate::openai::OpenAIChatCompletionRequest;
+use crate::metrics;
+use crate::openai::{OpenAIChatCompletionRequest, OpenAIUsage};
+use crate::openai_responses::{OpenAIResponsesRequest, OpenAIResponsesResponse};
 use crate::protocol::{
   has_history_summary_node, AugmentChatHistory, AugmentRequest, NodeIn, REQUEST_NODE_FILE,
   REQUEST_NODE_FILE_ID, REQUEST_NODE_HISTORY_SUMMARY, REQUEST_NODE_IMAGE, REQUEST_NODE_IMAGE_ID,
   REQUEST_NODE_TOOL_RESULT, RESPONSE_NODE_MAIN_TEXT_FINISHED, RESPONSE_NODE_RAW_RESPONSE,
   RESPONSE_NODE_TOOL_USE, RESPONSE_NODE_TOOL_USE_START,
 };
-use crate::util::{join_url, normalize_raw_token, now_ms};
+use crate::stats::UpstreamCall;
+use crate::upstream::{send_upstream, UpstreamClient};
+use crate::usage::UsageRecord;
+use crate::util::{join_url, now_ms};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct HistorySummaryCache {
@@ -158,7 +170,7 @@ impl HistorySummaryCache {
   }
 }
 
-fn approx_token_count_from_byte_len(len: usize) -> u32 {
+pub(crate) fn approx_token_count_from_byte_len(len: usize) -> u32 {
   const BYTES_PER_TOKEN: usize = 4;
   let tokens = len
     .saturating_add(BYTES_PER_TOKEN.saturating_sub(1))
@@ -402,6 +414,18 @@ fn estimate_request_extra_size_chars(augment: &AugmentRequest) -> usize {
   prefix.len() + selected_code.len() + suffix.len() + diff.len()
 }
 
+fn estimate_request_size_chars(augment: &AugmentRequest) -> usize {
+  estimate_history_size_chars(&augment.chat_history)
+    .saturating_add(augment.message.len())
+    .saturating_add(estimate_request_extra_size_chars(augment))
+}
+
+pub(crate) fn estimate_request_input_tokens(augment: &AugmentRequest) -> u64 {
+  u64::from(approx_token_count_from_byte_len(
+    estimate_request_size_chars(augment),
+  ))
+}
+
 #[derive(Debug, Clone)]
 struct HistorySplit {
   head: Vec<AugmentChatHistory>,
@@ -891,6 +915,8 @@ fn build_abridged_history_text(
 enum SummaryProviderRef<'a> {
   Anthropic(&'a AnthropicProviderConfig),
   OpenAICompatible(&'a OpenAICompatibleProviderConfig),
+  Gemini(&'a GeminiProviderConfig),
+  OpenAIResponses(&'a OpenAIResponsesProviderConfig),
 }
 
 fn get_byok_provider_by_id<'a>(
@@ -909,6 +935,10 @@ fn get_byok_provider_by_id<'a>(
       ProviderConfig::OpenAICompatible(p) if p.id.trim() == pid => {
         return Ok(SummaryProviderRef::OpenAICompatible(p))
       }
+      ProviderConfig::Gemini(p) if p.id.trim() == pid => return Ok(SummaryProviderRef::Gemini(p)),
+      ProviderConfig::OpenAIResponses(p) if p.id.trim() == pid => {
+        return Ok(SummaryProviderRef::OpenAIResponses(p))
+      }
       _ => {}
     }
   }
@@ -951,14 +981,15 @@ fn extract_anthropic_text(resp: &AnthropicResponse) -> String {
 }
 
 async fn run_summary_model_once(
-  http: &reqwest::Client,
+  client: UpstreamClient<'_>,
   provider: SummaryProviderRef<'_>,
+  hs: &HistorySummaryConfig,
+  conversation_id: &str,
   prompt: &str,
   chat_history: Vec<AugmentChatHistory>,
-  max_tokens: u32,
-  timeout_seconds: u64,
   model: String,
 ) -> anyhow::Result<(String, String)> {
+  let (max_tokens, timeout_seconds) = (hs.max_tokens, hs.timeout_seconds);
   let augment = AugmentRequest {
     model: None,
     chat_history,
@@ -989,12 +1020,44 @@ async fn run_summary_model_once(
     conversation_id: None,
     context: None,
   };
+  let UpstreamClient {
+    http,
+    health: upstream,
+    policy,
+    usage,
+  } = client;
+  let policy = &policy
+    .clone()
+    .with_estimated_input_tokens(estimate_request_input_tokens(&augment));
+
+  let (provider_id, max_concurrent, queue_timeout_seconds) = match provider {
+    SummaryProviderRef::Anthropic(p) => (&p.id, p.max_concurrent_requests, p.queue_timeout_seconds),
+    SummaryProviderRef::OpenAICompatible(p) => {
+      (&p.id, p.max_concurrent_requests, p.queue_timeout_seconds)
+    }
+    SummaryProviderRef::Gemini(p) => (&p.id, p.max_concurrent_requests, p.queue_timeout_seconds),
+    SummaryProviderRef::OpenAIResponses(p) => {
+      (&p.id, p.max_concurrent_requests, p.queue_timeout_seconds)
+    }
+  };
+  let _permit = upstream
+    .concurrency
+    .acquire(
+      provider_id,
+      max_concurrent,
+      Duration::from_secs(queue_timeout_seconds),
+      RequestPriority::Background,
+    )
+    .await?;
+  let mut call = UpstreamCall::start(
+    usage.cloned(),
+    UsageRecord::new("history_summary", conversation_id, provider_id, &model),
+  );
 
   match provider {
     SummaryProviderRef::Anthropic(p) => {
       let url = join_url(&p.base_url, "messages").context("anthropic base_url 无效")?;
-      let api_key = normalize_raw_token(&p.api_key);
-      if api_key.is_empty() {
+      if p.api_key.is_empty() {
         anyhow::bail!("history_summary provider({}) api_key 为空", p.id);
       }
 
@@ -1010,7 +1073,6 @@ async fn run_summary_model_once(
         .header("content-type", "application/json")
         .header("accept", "application/json")
         .header("anthropic-version", "2023-06-01")
-        .header("x-api-key", api_key)
         .timeout(Duration::from_secs(timeout_seconds))
         .json(&req);
 
@@ -1020,19 +1082,31 @@ async fn run_summary_model_once(
         }
       }
 
-      let resp = r.send().await.context("上游请求失败")?;
+      let resp = send_upstream(
+        upstream,
+        policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        r,
+        |r, k| r.header("x-api-key", k),
+      )
+      .await
+      .context("上游请求失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
       if !resp.status().is_success() {
         let status = resp.status();
         let body = resp.text().await.unwrap_or_default();
         anyhow::bail!("上游返回错误: {status} {body}");
       }
       let body: AnthropicResponse = resp.json().await.context("解析 Anthropic 响应失败")?;
+      call.progress.on_anthropic_usage(&body.usage);
+      call.finish();
       Ok((body.id.clone(), extract_anthropic_text(&body)))
     }
     SummaryProviderRef::OpenAICompatible(p) => {
       let url = join_url(&p.base_url, "chat/completions").context("openai base_url 无效")?;
-      let api_key = normalize_raw_token(&p.api_key);
-      if api_key.is_empty() {
+      if p.api_key.is_empty() {
         anyhow::bail!("history_summary provider({}) api_key 为空", p.id);
       }
 
@@ -1048,7 +1122,6 @@ async fn run_summary_model_once(
         .post(url)
         .header("content-type", "application/json")
         .header("accept", "application/json")
-        .header("authorization", format!("Bearer {api_key}"))
         .timeout(Duration::from_secs(timeout_seconds))
         .json(&req);
 
@@ -1058,21 +1131,141 @@ async fn run_summary_model_once(
         }
       }
 
-      let resp = r.send().await.context("上游请求失败")?;
+      let resp = send_upstream(
+        upstream,
+        policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        r,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
+      .context("上游请求失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
       if !resp.status().is_success() {
         let status = resp.status();
         let body = resp.text().await.unwrap_or_default();
         anyhow::bail!("上游返回错误: {status} {body}");
       }
       let body: Value = resp.json().await.context("解析 OpenAI 响应失败")?;
+      if let Ok(usage) = serde_json::from_value::<OpenAIUsage>(body["usage"].clone()) {
+        call.progress.on_openai_usage(&usage);
+      }
       let id = body
         .get("id")
         .and_then(|x| x.as_str())
         .unwrap_or("")
         .to_string();
       let text = extract_openai_choice_text(&body);
+      call.finish();
       Ok((id, text))
     }
+    SummaryProviderRef::Gemini(p) => {
+      let model = model.trim().trim_start_matches("models/").to_string();
+      let url = join_url(&p.base_url, &format!("models/{model}:generateContent"))
+        .context("gemini base_url 无效")?;
+      if p.api_key.is_empty() {
+        anyhow::bail!("history_summary provider({}) api_key 为空", p.id);
+      }
+
+      let mut req: GeminiRequest = convert_augment_to_gemini(p, &augment)?;
+      req.tools = None;
+      req.tool_config = None;
+      if let Some(gen) = req.generation_config.as_mut() {
+        gen.max_output_tokens = Some(max_tokens);
+        gen.thinking_config = None;
+      }
+
+      let mut r = http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "application/json")
+        .timeout(Duration::from_secs(timeout_seconds))
+        .json(&req);
+
+      for (k, v) in &p.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          r = r.header(k, value);
+        }
+      }
+
+      let resp = send_upstream(
+        upstream,
+        policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        r,
+        |r, k| r.header("x-goog-api-key", k),
+      )
+      .await
+      .context("上游请求失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
+      if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("上游返回错误: {status} {body}");
+      }
+      let body: GeminiStreamChunk = resp.json().await.context("解析 Gemini 响应失败")?;
+      if let Some(usage) = &body.usage_metadata {
+        call.progress.on_gemini_usage(usage);
+      }
+      let id = body.response_id.clone().unwrap_or_default();
+      call.finish();
+      Ok((id, body.text()))
+    }
+    SummaryProviderRef::OpenAIResponses(p) => {
+      let url = join_url(&p.base_url, "responses").context("openai base_url 无效")?;
+      if p.api_key.is_empty() {
+        anyhow::bail!("history_summary provider({}) api_key 为空", p.id);
+      }
+
+      let mut req: OpenAIResponsesRequest =
+        convert_augment_to_openai_responses(p, &augment, model)?;
+      req.stream = false;
+      req.max_output_tokens = Some(max_tokens);
+      req.tools = None;
+      req.tool_choice = None;
+      req.include = None;
+
+      let mut r = http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "application/json")
+        .timeout(Duration::from_secs(timeout_seconds))
+        .json(&req);
+
+      for (k, v) in &p.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          r = r.header(k, value);
+        }
+      }
+
+      let resp = send_upstream(
+        upstream,
+        policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        r,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
+      .context("上游请求失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
+      if !resp.status().is_success() {
+        let status = resp.status();
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("上游返回错误: {status} {body}");
+      }
+      let body: OpenAIResponsesResponse = resp.json().await.context("解析 OpenAI 响应失败")?;
+      if let Some(usage) = &body.usage {
+        call.progress.on_openai_responses_usage(usage);
+      }
+      call.finish();
+      Ok((body.id.clone(), body.output_text()))
+    }
   }
 }
 
@@ -1128,7 +1321,7 @@ fn take_tool_result_nodes(nodes: &mut Vec<NodeIn>) -> Vec<NodeIn> {
 }
 
 pub async fn maybe_summarize_and_compact(
-  http: &reqwest::Client,
+  client: UpstreamClient<'_>,
   cfg: &Config,
   cache: &RwLock<HistorySummaryCache>,
   cache_path: &Path,
@@ -1160,9 +1353,7 @@ pub async fn maybe_summarize_and_compact(
   }
 
   let total_chars = estimate_history_size_chars(&augment.chat_history);
-  let total_with_extra = total_chars
-    .saturating_add(augment.message.len())
-    .saturating_add(estimate_request_extra_size_chars(augment));
+  let total_with_extra = estimate_request_size_chars(augment);
 
   let strategy = hs.trigger_strategy.trim().to_ascii_lowercase();
   let cw_tokens_raw = resolve_context_window_tokens(hs, chat_model);
@@ -1303,6 +1494,7 @@ pub async fn maybe_summarize_and_compact(
       (threshold_chars, target_tail_budget_chars)
     }
   };
+  metrics::record_history_summary_trigger();
 
   let split = split_history_for_summary(
     &augment.chat_history,
@@ -1360,6 +1552,7 @@ pub async fn maybe_summarize_and_compact(
   ) {
     Some((s, id)) => {
       debug!(conversation_id=%conv_id, boundary_request_id=%boundary_request_id, "history_summary 命中缓存");
+      metrics::record_history_summary_cache_hit();
       (s, id)
     }
     None => {
@@ -1442,9 +1635,11 @@ pub async fn maybe_summarize_and_compact(
         return Ok(false);
       }
 
-      let default_model = match provider {
-        SummaryProviderRef::Anthropic(p) => p.default_model.as_str(),
-        SummaryProviderRef::OpenAICompatible(p) => p.default_model.as_str(),
+      let (summary_provider_id, default_model) = match provider {
+        SummaryProviderRef::Anthropic(p) => (p.id.as_str(), p.default_model.as_str()),
+        SummaryProviderRef::OpenAICompatible(p) => (p.id.as_str(), p.default_model.as_str()),
+        SummaryProviderRef::Gemini(p) => (p.id.as_str(), p.default_model.as_str()),
+        SummaryProviderRef::OpenAIResponses(p) => (p.id.as_str(), p.default_model.as_str()),
       };
       let model = if !hs.model.trim().is_empty() {
         hs.model.trim().to_string()
@@ -1454,17 +1649,28 @@ pub async fn maybe_summarize_and_compact(
         default_model.trim().to_string()
       };
 
-      let (req_id, text) = run_summary_model_once(
-        http,
+      // 摘要模型预算用尽时不压缩历史，本轮照常发送完整历史
+      let over_budget = client
+        .usage
+        .and_then(|ledger| exceeded_budget(&cfg.usage, ledger, summary_provider_id, &model));
+      if let Some(hit) = over_budget {
+        warn!(conversation_id=%conv_id, provider_id=%summary_provider_id, model=%model, "history_summary 跳过：{}", hit.message());
+        return Ok(false);
+      }
+
+      let summary_started = std::time::Instant::now();
+      let summarized = run_summary_model_once(
+        client,
         provider,
+        hs,
+        conv_id,
         prompt.as_str(),
         input_history,
-        hs.max_tokens,
-        hs.timeout_seconds,
         model,
       )
-      .await
-      .context("history_summary 摘要模型调用失败")?;
+      .await;
+      metrics::record_history_summary_duration(summary_started.elapsed(), summarized.is_ok());
+      let (req_id, text) = summarized.context("history_summary 摘要模型调用失败")?;
 
       let req_id = if req_id.trim().is_empty() {
         format!("proxy_history_summary_{}", now)
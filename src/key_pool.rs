@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+  config::{ApiKeys, KeyPoolConfig, ProviderConfig},
+  rate_limit::wait_for_capacity,
+  retry::send_with_retry,
+  upstream::{UpstreamHealth, UpstreamPolicy},
+  util::now_ms,
+};
+
+#[derive(Debug, Default)]
+pub(crate) struct KeyPoolRegistry {
+  by_provider: HashMap<String, ProviderKeyPool>,
+}
+
+#[derive(Debug, Default)]
+struct ProviderKeyPool {
+  next_index: usize,
+  by_key: HashMap<String, KeyHealth>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct KeyHealth {
+  cooldown_until_ms: u64,
+  last_used_at_ms: u64,
+  last_rate_limited_at_ms: u64,
+  last_error: Option<String>,
+  last_error_at_ms: u64,
+  success_count: u64,
+  failure_count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProviderKeyPoolStatus {
+  provider_id: String,
+  selection: String,
+  cooldown_seconds: u64,
+  keys: Vec<KeyStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct KeyStatus {
+  key: String,
+  healthy: bool,
+  cooldown_remaining_ms: u64,
+  last_used_at_ms: u64,
+  last_rate_limited_at_ms: u64,
+  last_error: Option<String>,
+  last_error_at_ms: u64,
+  success_count: u64,
+  failure_count: u64,
+}
+
+fn puts_key_on_cooldown(status: StatusCode) -> bool {
+  status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::UNAUTHORIZED
+}
+
+impl KeyPoolRegistry {
+  // 优先选不在冷却期且本次请求还没试过的 key；全部冷却时（且还没试过任何 key）退而选最早解除冷却的
+  fn pick(
+    &mut self,
+    provider_id: &str,
+    keys: &[String],
+    pool_cfg: &KeyPoolConfig,
+    tried: &HashSet<String>,
+    now: u64,
+  ) -> Option<String> {
+    let pool = self.by_provider.entry(provider_id.to_string()).or_default();
+    pool.by_key.retain(|k, _| keys.contains(k));
+
+    let health = |k: &String| pool.by_key.get(k).cloned().unwrap_or_default();
+    let available: Vec<usize> = (0..keys.len())
+      .filter(|&i| !tried.contains(&keys[i]) && health(&keys[i]).cooldown_until_ms <= now)
+      .collect();
+
+    let picked = if available.is_empty() {
+      if !tried.is_empty() {
+        return None;
+      }
+      (0..keys.len()).min_by_key(|&i| health(&keys[i]).cooldown_until_ms)?
+    } else if pool_cfg.selection.trim() == "least_recently_rate_limited" {
+      available.iter().copied().min_by_key(|&i| {
+        let h = health(&keys[i]);
+        (h.last_rate_limited_at_ms, h.last_used_at_ms)
+      })?
+    } else {
+      let start = pool.next_index % keys.len();
+      available
+        .iter()
+        .copied()
+        .min_by_key(|&i| (i + keys.len() - start) % keys.len())?
+    };
+
+    pool.next_index = picked + 1;
+    let key = keys[picked].clone();
+    pool.by_key.entry(key.clone()).or_default().last_used_at_ms = now;
+    Some(key)
+  }
+
+  fn record(
+    &mut self,
+    provider_id: &str,
+    key: &str,
+    pool_cfg: &KeyPoolConfig,
+    result: &anyhow::Result<Response>,
+    now: u64,
+  ) {
+    let h = self
+      .by_provider
+      .entry(provider_id.to_string())
+      .or_default()
+      .by_key
+      .entry(key.to_string())
+      .or_default();
+    let error = match result {
+      Ok(resp) if resp.status().is_success() => {
+        h.success_count += 1;
+        return;
+      }
+      Ok(resp) => {
+        let status = resp.status();
+        if puts_key_on_cooldown(status) {
+          h.cooldown_until_ms = now + pool_cfg.cooldown_seconds.saturating_mul(1000);
+          h.last_rate_limited_at_ms = now;
+        }
+        format!("上游返回 {status}")
+      }
+      Err(err) => format!("上游请求失败: {err}"),
+    };
+    h.failure_count += 1;
+    h.last_error = Some(error);
+    h.last_error_at_ms = now;
+  }
+
+  pub(crate) fn status(
+    &self,
+    providers: &[ProviderConfig],
+    now: u64,
+  ) -> Vec<ProviderKeyPoolStatus> {
+    providers
+      .iter()
+      .map(|p| {
+        let pool = self.by_provider.get(p.id());
+        let keys = p
+          .api_key()
+          .keys()
+          .iter()
+          .map(|k| {
+            let h = pool
+              .and_then(|pool| pool.by_key.get(k))
+              .cloned()
+              .unwrap_or_default();
+            KeyStatus {
+              key: mask_key(k),
+              healthy: h.cooldown_until_ms <= now,
+              cooldown_remaining_ms: h.cooldown_until_ms.saturating_sub(now),
+              last_used_at_ms: h.last_used_at_ms,
+              last_rate_limited_at_ms: h.last_rate_limited_at_ms,
+              last_error: h.last_error,
+              last_error_at_ms: h.last_error_at_ms,
+              success_count: h.success_count,
+              failure_count: h.failure_count,
+            }
+          })
+          .collect();
+        ProviderKeyPoolStatus {
+          provider_id: p.id().to_string(),
+          selection: p.key_pool().selection.clone(),
+          cooldown_seconds: p.key_pool().cooldown_seconds,
+          keys,
+        }
+      })
+      .collect()
+  }
+}
+
+pub(crate) fn mask_key(key: &str) -> String {
+  let chars: Vec<char> = key.chars().collect();
+  if chars.len() <= 12 {
+    return "****".to_string();
+  }
+  let head: String = chars[..4].iter().collect();
+  let tail: String = chars[chars.len() - 4..].iter().collect();
+  format!("{head}…{tail}")
+}
+
+// req 不带鉴权头，由 auth 按选中的 key 补上；key 返回 429/401 时进入冷却并换下一个 key 重发。
+// provider 配了 rate_limit 时，发送前先等选中 key 的令牌桶放行，并把 RateLimitTicket 附在响应上
+pub(crate) async fn send_with_key_pool(
+  health: &UpstreamHealth,
+  policy: &UpstreamPolicy,
+  provider_id: &str,
+  api_key: &ApiKeys,
+  pool_cfg: &KeyPoolConfig,
+  req: RequestBuilder,
+  auth: impl Fn(RequestBuilder, &str) -> RequestBuilder,
+) -> anyhow::Result<Response> {
+  let pools = &health.key_pools;
+  let retry = &policy.retry;
+  let rate_limit = policy.rate_limit(provider_id);
+  let keys = api_key.keys();
+  // 多 key 时 429 交给换 key 处理，不在同一个 key 上等待
+  let retry = if keys.len() > 1 {
+    let mut r = retry.clone();
+    r.retryable_status_codes
+      .retain(|&c| !puts_key_on_cooldown(StatusCode::from_u16(c).unwrap_or(StatusCode::OK)));
+    r
+  } else {
+    retry.clone()
+  };
+
+  let mut tried: HashSet<String> = HashSet::new();
+  let mut current = req;
+  loop {
+    let key = pools
+      .write()
+      .await
+      .pick(provider_id, &keys, pool_cfg, &tried, now_ms());
+    let Some(key) = key else {
+      // 调用方已检查 api_key 非空，这里不会走到；兜底按原请求发送
+      return send_with_retry(&retry, policy.header_timeout, current).await;
+    };
+    tried.insert(key.clone());
+    let next = if tried.len() < keys.len() {
+      current.try_clone()
+    } else {
+      None
+    };
+
+    let ticket = match rate_limit {
+      Some(cfg) => Some(
+        wait_for_capacity(
+          &health.rate_limits,
+          provider_id,
+          &key,
+          cfg,
+          policy.estimated_input_tokens,
+        )
+        .await,
+      ),
+      None => None,
+    };
+    let mut result = send_with_retry(&retry, policy.header_timeout, auth(current, &key)).await;
+    if let (Ok(resp), Some(ticket)) = (&mut result, ticket) {
+      resp.extensions_mut().insert(ticket);
+    }
+    pools
+      .write()
+      .await
+      .record(provider_id, &key, pool_cfg, &result, now_ms());
+
+    match (&result, next) {
+      (Ok(resp), Some(next)) if puts_key_on_cooldown(resp.status()) => {
+        warn!(provider_id=%provider_id, key=%mask_key(&key), status=%resp.status(), "key 被限流/鉴权失败，进入冷却并切换下一个 key");
+        current = next;
+      }
+      _ => return result,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pool_cfg(selection: &str) -> KeyPoolConfig {
+    KeyPoolConfig {
+      selection: selection.to_string(),
+      cooldown_seconds: 60,
+    }
+  }
+
+  #[test]
+  fn round_robin_skips_keys_on_cooldown() {
+    let keys: Vec<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+    let cfg = pool_cfg("round_robin");
+    let none = HashSet::new();
+    let mut reg = KeyPoolRegistry::default();
+
+    let picks: Vec<String> = (0..4)
+      .map(|_| reg.pick("p", &keys, &cfg, &none, 1_000).unwrap())
+      .collect();
+    assert_eq!(picks, ["a", "b", "c", "a"]);
+
+    reg
+      .by_provider
+      .get_mut("p")
+      .unwrap()
+      .by_key
+      .get_mut("b")
+      .unwrap()
+      .cooldown_until_ms = 61_000;
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 2_000).unwrap(), "c");
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 2_000).unwrap(), "a");
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 2_000).unwrap(), "c");
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 61_000).unwrap(), "a");
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 61_000).unwrap(), "b");
+
+    let tried: HashSet<String> = keys.iter().cloned().collect();
+    assert_eq!(reg.pick("p", &keys, &cfg, &tried, 61_000), None);
+  }
+
+  #[test]
+  fn least_recently_rate_limited_prefers_clean_keys_and_falls_back_to_earliest_cooldown() {
+    let keys: Vec<String> = ["a", "b"].iter().map(|s| s.to_string()).collect();
+    let cfg = pool_cfg("least_recently_rate_limited");
+    let none = HashSet::new();
+    let mut reg = KeyPoolRegistry::default();
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 1_000).unwrap(), "a");
+
+    {
+      let pool = reg.by_provider.get_mut("p").unwrap();
+      let a = pool.by_key.entry("a".to_string()).or_default();
+      a.last_rate_limited_at_ms = 500;
+      let b = pool.by_key.entry("b".to_string()).or_default();
+      b.last_rate_limited_at_ms = 900;
+    }
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 1_000).unwrap(), "a");
+
+    {
+      let pool = reg.by_provider.get_mut("p").unwrap();
+      pool.by_key.get_mut("a").unwrap().cooldown_until_ms = 90_000;
+      pool.by_key.get_mut("b").unwrap().cooldown_until_ms = 70_000;
+    }
+    assert_eq!(reg.pick("p", &keys, &cfg, &none, 2_000).unwrap(), "b");
+  }
+
+  #[test]
+  fn mask_key_hides_middle() {
+    assert_eq!(mask_key("sk-ant-0123456789abcd"), "sk-a…abcd");
+    assert_eq!(mask_key("short"), "****");
+  }
+}
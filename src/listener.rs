@@ -0,0 +1,125 @@
+use std::{net::SocketAddr, sync::OnceLock};
+
+use anyhow::Context;
+use axum::Router;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info};
+
+type RebindRequest = (SocketAddr, oneshot::Sender<anyhow::Result<()>>);
+
+// 运行时切换监听地址的入口；serve 启动前（replay、测试）rebind 为 no-op
+#[derive(Debug, Default)]
+pub(crate) struct ListenerControl {
+  tx: OnceLock<mpsc::UnboundedSender<RebindRequest>>,
+}
+
+impl ListenerControl {
+  // 新地址绑定成功才返回 Ok；失败时仍在旧地址上提供服务
+  pub(crate) async fn rebind(&self, addr: SocketAddr) -> anyhow::Result<()> {
+    let Some(tx) = self.tx.get() else {
+      return Ok(());
+    };
+    let (done_tx, done_rx) = oneshot::channel();
+    tx.send((addr, done_tx))
+      .map_err(|_| anyhow::anyhow!("监听任务已退出"))?;
+    done_rx.await.context("监听任务已退出")?
+  }
+}
+
+struct Running {
+  addr: SocketAddr,
+  shutdown: oneshot::Sender<()>,
+}
+
+async fn start(app: Router, addr: SocketAddr) -> anyhow::Result<Running> {
+  let listener = tokio::net::TcpListener::bind(addr).await.with_context(|| {
+    format!("监听 {addr} 失败（端口可能被占用；可修改 config.yaml 的 server.port）")
+  })?;
+  let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+  tokio::spawn(async move {
+    // 收到 shutdown 后停止 accept；已建立的连接（包括进行中的 chat-stream）照常跑完
+    let served = axum::serve(listener, app)
+      .with_graceful_shutdown(async move {
+        let _ = shutdown_rx.await;
+      })
+      .await;
+    match served {
+      Ok(()) => info!(%addr, "旧监听地址上的连接已全部结束"),
+      Err(err) => error!(%addr, error=%err, "HTTP 服务异常退出"),
+    }
+  });
+  Ok(Running { addr, shutdown })
+}
+
+// 先绑定新地址、再让旧 listener 停止 accept，切换期间不会出现无人监听的窗口
+pub(crate) async fn serve(
+  control: &ListenerControl,
+  app: Router,
+  addr: SocketAddr,
+) -> anyhow::Result<()> {
+  let (tx, mut rx) = mpsc::unbounded_channel::<RebindRequest>();
+  if control.tx.set(tx).is_err() {
+    anyhow::bail!("HTTP 服务已经在运行");
+  }
+  let mut current = start(app.clone(), addr).await?;
+  info!(%addr, "Augment-BYOK-Proxy 启动");
+  while let Some((addr, done)) = rx.recv().await {
+    if addr == current.addr {
+      let _ = done.send(Ok(()));
+      continue;
+    }
+    match start(app.clone(), addr).await {
+      Ok(next) => {
+        let old = std::mem::replace(&mut current, next);
+        let _ = old.shutdown.send(());
+        info!(from=%old.addr, to=%addr, "监听地址已切换（旧地址上进行中的请求会继续完成）");
+        let _ = done.send(Ok(()));
+      }
+      Err(err) => {
+        let _ = done.send(Err(err));
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::routing::get;
+
+  #[tokio::test]
+  async fn rebinds_to_a_new_port_and_stops_accepting_on_the_old_one() {
+    let free_addr = || {
+      let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+      l.local_addr().unwrap()
+    };
+    let (a, b) = (free_addr(), free_addr());
+    let control = std::sync::Arc::new(ListenerControl::default());
+    assert!(control.rebind(a).await.is_ok(), "serve 前为 no-op");
+
+    let app = Router::new().route("/", get(|| async { "ok" }));
+    let serving = control.clone();
+    tokio::spawn(async move { serve(&serving, app, a).await });
+    while tokio::net::TcpStream::connect(a).await.is_err() {
+      tokio::task::yield_now().await;
+    }
+
+    // 新地址被占用时保持旧地址
+    let busy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    assert!(control.rebind(busy.local_addr().unwrap()).await.is_err());
+    assert!(tokio::net::TcpStream::connect(a).await.is_ok());
+
+    control.rebind(b).await.unwrap();
+    assert!(tokio::net::TcpStream::connect(b).await.is_ok());
+    let mut refused = false;
+    for _ in 0..100 {
+      if tokio::net::TcpStream::connect(a).await.is_err() {
+        refused = true;
+        break;
+      }
+      tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(refused, "旧地址应停止 accept");
+  }
+}
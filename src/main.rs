@@ -1,11 +1,33 @@
 mod anthropic;
+mod budget;
+mod circuit_breaker;
+mod concurrency;
 mod config;
+mod config_override;
+mod config_watch;
 mod convert;
+mod doctor;
+mod gemini;
 mod history_summary;
 mod history_summary_auto;
+mod key_pool;
+mod listener;
+mod metrics;
 mod official_injection;
 mod openai;
+mod openai_responses;
 mod protocol;
+mod rate_limit;
+mod replay;
+mod request_id;
+mod retry;
+mod routing;
+mod secrets;
+mod stats;
+mod stream_timeout;
+mod transcript;
+mod upstream;
+mod usage;
 mod util;
 
 use std::{collections::HashMap, convert::Infallible, path::PathBuf, sync::Arc, time::Duration};
@@ -28,17 +50,36 @@ use tokio_util::io::StreamReader;
 use tracing::{debug, error, info, warn};
 
 use crate::{
-  anthropic::AnthropicStreamEvent,
-  config::{AnthropicProviderConfig, Config, OpenAICompatibleProviderConfig, ProviderConfig},
+  anthropic::{AnthropicStreamEvent, AnthropicUsage},
+  budget::exceeded_budget,
+  circuit_breaker::BreakerStatus,
+  concurrency::RequestPriority,
+  config::{
+    AnthropicProviderConfig, ApiKeys, BudgetAction, Config, GeminiProviderConfig, KeyPoolConfig,
+    OpenAICompatibleProviderConfig, OpenAIResponsesProviderConfig, ProviderConfig, RetryConfig,
+    RouteTarget, RoutingRule,
+  },
   convert::{
-    clean_model, convert_augment_to_anthropic, convert_augment_to_openai_compatible,
-    AnthropicStreamState, OpenAIStreamState,
+    clean_model, convert_augment_to_anthropic, convert_augment_to_gemini,
+    convert_augment_to_openai_compatible, convert_augment_to_openai_responses,
+    AnthropicStreamState, GeminiStreamState, OpenAIResponsesStreamState, OpenAIStreamState,
   },
+  gemini::GeminiStreamChunk,
   history_summary::compact_chat_history,
-  history_summary_auto::{maybe_summarize_and_compact, HistorySummaryCache},
+  history_summary_auto::{
+    approx_token_count_from_byte_len, estimate_request_input_tokens, maybe_summarize_and_compact,
+    HistorySummaryCache,
+  },
+  listener::ListenerControl,
   official_injection::{maybe_inject_official_context, ContextCanvasCache},
-  openai::OpenAIChatCompletionChunk,
+  openai::{OpenAIChatCompletionChunk, OpenAIUsage},
+  openai_responses::{OpenAIResponsesResponse, OpenAIResponsesStreamEvent},
   protocol::{error_response, probe_response, AugmentRequest, AugmentStreamChunk},
+  stats::{ChatStreamTurn, ProxyStats, TurnProgress, UpstreamCall},
+  stream_timeout::StreamTimeouts,
+  transcript::Transcript,
+  upstream::{send_upstream, UpstreamClient, UpstreamHealth, UpstreamPolicy},
+  usage::{summarize, UsageGroupBy, UsageLedger, UsageRecord},
   util::{join_url, normalize_raw_token, now_ms},
 };
 
@@ -46,6 +87,8 @@ use crate::{
 enum ProviderRef<'a> {
   Anthropic(&'a AnthropicProviderConfig),
   OpenAICompatible(&'a OpenAICompatibleProviderConfig),
+  Gemini(&'a GeminiProviderConfig),
+  OpenAIResponses(&'a OpenAIResponsesProviderConfig),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -61,6 +104,8 @@ impl<'a> ProviderRef<'a> {
     match self {
       ProviderRef::Anthropic(p) => p.id.as_str(),
       ProviderRef::OpenAICompatible(p) => p.id.as_str(),
+      ProviderRef::Gemini(p) => p.id.as_str(),
+      ProviderRef::OpenAIResponses(p) => p.id.as_str(),
     }
   }
 
@@ -68,21 +113,86 @@ impl<'a> ProviderRef<'a> {
     match self {
       ProviderRef::Anthropic(p) => p.base_url.as_str(),
       ProviderRef::OpenAICompatible(p) => p.base_url.as_str(),
+      ProviderRef::Gemini(p) => p.base_url.as_str(),
+      ProviderRef::OpenAIResponses(p) => p.base_url.as_str(),
     }
   }
 
+  // (max_concurrent_requests, queue_timeout)
+  fn concurrency_limit(&self) -> (usize, Duration) {
+    let (max, queue_timeout_seconds) = match self {
+      ProviderRef::Anthropic(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
+      ProviderRef::OpenAICompatible(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
+      ProviderRef::Gemini(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
+      ProviderRef::OpenAIResponses(p) => (p.max_concurrent_requests, p.queue_timeout_seconds),
+    };
+    (max, Duration::from_secs(queue_timeout_seconds))
+  }
+
+  fn stream_timeouts(&self) -> StreamTimeouts {
+    let (total, first_byte, idle) = match self {
+      ProviderRef::Anthropic(p) => (
+        p.timeout_seconds,
+        p.first_byte_timeout_seconds,
+        p.idle_timeout_seconds,
+      ),
+      ProviderRef::OpenAICompatible(p) => (
+        p.timeout_seconds,
+        p.first_byte_timeout_seconds,
+        p.idle_timeout_seconds,
+      ),
+      ProviderRef::Gemini(p) => (
+        p.timeout_seconds,
+        p.first_byte_timeout_seconds,
+        p.idle_timeout_seconds,
+      ),
+      ProviderRef::OpenAIResponses(p) => (
+        p.timeout_seconds,
+        p.first_byte_timeout_seconds,
+        p.idle_timeout_seconds,
+      ),
+    };
+    StreamTimeouts::start(
+      Duration::from_secs(first_byte.unwrap_or(total)),
+      Duration::from_secs(idle.unwrap_or(total)),
+    )
+  }
+
   fn default_model(&self) -> &'a str {
     match self {
       ProviderRef::Anthropic(p) => p.default_model.as_str(),
       ProviderRef::OpenAICompatible(p) => p.default_model.as_str(),
+      ProviderRef::Gemini(p) => p.default_model.as_str(),
+      ProviderRef::OpenAIResponses(p) => p.default_model.as_str(),
     }
   }
 }
 
 #[derive(Debug, Parser)]
 struct Args {
-  #[arg(short, long, default_value = "config.yaml", value_name = "PATH")]
+  #[arg(
+    short,
+    long,
+    global = true,
+    default_value = "config.yaml",
+    value_name = "PATH"
+  )]
   config: PathBuf,
+  #[command(subcommand)]
+  command: Option<Command>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+  #[command(
+    about = "重放录制的 chat-stream 请求体：只做转换（--dry-run）或发往多个 byok 目标并对比结果"
+  )]
+  Replay(replay::ReplayArgs),
+  #[command(
+    visible_alias = "check-config",
+    about = "校验 config.yaml 并逐项探测 provider /models、1-token 补全、官方 get-models 与 history_summary 配置；有失败时退出码非 0"
+  )]
+  Doctor(doctor::DoctorArgs),
 }
 
 #[derive(Clone)]
@@ -94,6 +204,98 @@ struct AppState {
   context_canvas_cache: Arc<RwLock<ContextCanvasCache>>,
   history_summary_cache: Arc<RwLock<HistorySummaryCache>>,
   history_summary_cache_path: PathBuf,
+  upstream: Arc<UpstreamHealth>,
+  stats: Arc<ProxyStats>,
+  usage: Arc<UsageLedger>,
+  listener: Arc<ListenerControl>,
+}
+
+impl AppState {
+  async fn new(config_path: PathBuf, cfg: Config, http: reqwest::Client) -> Self {
+    let history_summary_cache_path = config_path.with_file_name("history_summary_cache.json");
+    let history_summary_cache =
+      match HistorySummaryCache::load_from_file(&history_summary_cache_path).await {
+        Ok(v) => v,
+        Err(err) => {
+          warn!(
+            error=%err,
+            cache_path=%history_summary_cache_path.display(),
+            "history_summary cache 读取失败（将使用空缓存）"
+          );
+          HistorySummaryCache::default()
+        }
+      };
+    let usage = UsageLedger::open(config_path.with_file_name("usage_ledger.jsonl")).await;
+    Self {
+      config_path,
+      cfg: Arc::new(RwLock::new(cfg)),
+      http,
+      models_cache: Arc::new(RwLock::new(ModelCache::default())),
+      context_canvas_cache: Arc::new(RwLock::new(ContextCanvasCache::default())),
+      history_summary_cache: Arc::new(RwLock::new(history_summary_cache)),
+      history_summary_cache_path,
+      upstream: Arc::new(UpstreamHealth::default()),
+      stats: Arc::new(ProxyStats::default()),
+      usage: Arc::new(usage),
+      listener: Arc::new(ListenerControl::default()),
+    }
+  }
+
+  // 应用新配置：监听地址变化时先切换 listener（失败则整体不生效），再更新日志 filter 并替换配置
+  async fn apply_config(&self, next: Config) -> anyhow::Result<Vec<String>> {
+    let (addr, filter) = {
+      let cfg = self.cfg.read().await;
+      (
+        cfg.server.socket_addr()?,
+        cfg.logging.filter.trim().to_string(),
+      )
+    };
+    let next_addr = next.server.socket_addr()?;
+    if next_addr != addr {
+      self.listener.rebind(next_addr).await?;
+    }
+    if next.logging.filter.trim() != filter {
+      config::reload_log_filter(&next.logging.filter)?;
+      info!(filter=%next.logging.filter.trim(), "logging.filter 已更新");
+    }
+    Ok(self.replace_config(next).await)
+  }
+
+  // 替换运行时配置；有变化的 provider 丢弃其模型列表缓存。返回有变化的 provider id
+  async fn replace_config(&self, next: Config) -> Vec<String> {
+    let mut cfg = self.cfg.write().await;
+    let changed = cfg.byok.changed_provider_ids(&next.byok);
+    *cfg = next;
+    drop(cfg);
+    if !changed.is_empty() {
+      self
+        .models_cache
+        .write()
+        .await
+        .providers
+        .retain(|id, _| !changed.contains(id));
+    }
+    changed
+  }
+
+  async fn retry_policy(&self) -> RetryConfig {
+    self.cfg.read().await.retry.clone()
+  }
+
+  async fn upstream_policy(&self) -> UpstreamPolicy {
+    UpstreamPolicy::from_config(&*self.cfg.read().await)
+  }
+
+  // usage.enabled=false 时不写账本
+  async fn usage_ledger(&self) -> Option<Arc<UsageLedger>> {
+    self
+      .cfg
+      .read()
+      .await
+      .usage
+      .enabled
+      .then(|| self.usage.clone())
+  }
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -107,6 +309,15 @@ struct AdminDeleteHistorySummaryCacheReq {
   conversation_id: String,
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct AdminUsageQuery {
+  #[serde(default)]
+  group_by: Option<UsageGroupBy>,
+  // 最近 N 天（默认 30；0 = 全部）
+  #[serde(default)]
+  days: Option<u64>,
+}
+
 #[derive(Debug, Default)]
 struct ModelCache {
   providers: HashMap<String, ModelCacheEntry>,
@@ -120,40 +331,32 @@ struct ModelCacheEntry {
   models: Vec<String>,
 }
 
-const ADMIN_HTML: &str = r#"<!doctype html><html lang="zh-CN"><head><meta charset="utf-8"/><meta name="viewport" content="width=device-width,initial-scale=1"/><title>Augment-BYOK-Proxy Admin</title><style>body{font-family:ui-sans-serif,system-ui,-apple-system,"Segoe UI",Roboto,"Helvetica Neue",Arial,"Noto Sans","PingFang SC","Hiragino Sans GB","Microsoft YaHei";margin:24px;max-width:980px}textarea{width:100%;min-height:420px;font-family:ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,"Liberation Mono","Courier New",monospace;font-size:12px}button{margin-right:8px;padding:8px 12px}small{color:#666}pre{background:#111;color:#eee;padding:12px;white-space:pre-wrap}</style></head><body><h1>Augment-BYOK-Proxy Admin</h1><p><small>说明：此页面直接编辑运行时配置（JSON）。<b>不支持</b>通过此接口热更新 <code>server.host/server.port</code> / <code>logging.filter</code>（需要重启进程）。</small></p><div style="margin:12px 0"><button id="reload">刷新</button><button id="apply">应用(热更新)</button><button id="save">保存到文件</button></div><textarea id="cfg" spellcheck="false"></textarea><h2>状态</h2><pre id="status">ready</pre><script>const $=s=>document.querySelector(s);const setStatus=(v)=>{$('#status').textContent=typeof v==='string'?v:JSON.stringify(v,null,2)};async function load(){setStatus('loading...');const r=await fetch('/admin/api/config');const t=await r.text();if(!r.ok){setStatus({ok:false,status:r.status,body:t});return}try{$('#cfg').value=JSON.stringify(JSON.parse(t),null,2);setStatus({ok:true})}catch(e){$('#cfg').value=t;setStatus({ok:false,error:'config JSON parse failed',detail:String(e)})}}async function apply(){let obj;try{obj=JSON.parse($('#cfg').value)}catch(e){setStatus({ok:false,error:'invalid JSON',detail:String(e)});return}setStatus('applying...');const r=await fetch('/admin/api/config',{method:'PUT',headers:{'content-type':'application/json'},body:JSON.stringify(obj)});const j=await r.json().catch(async()=>({ok:false,body:await r.text()}));setStatus(j);if(r.ok)await load()}async function save(){setStatus('saving...');const r=await fetch('/admin/api/config/save',{method:'POST'});const j=await r.json().catch(async()=>({ok:false,body:await r.text()}));setStatus(j)}$('#reload').addEventListener('click',load);$('#apply').addEventListener('click',apply);$('#save').addEventListener('click',save);load();</script></body></html>"#;
+const ADMIN_HTML: &str = r#"<!doctype html><html lang="zh-CN"><head><meta charset="utf-8"/><meta name="viewport" content="width=device-width,initial-scale=1"/><title>Augment-BYOK-Proxy Admin</title><style>body{font-family:ui-sans-serif,system-ui,-apple-system,"Segoe UI",Roboto,"Helvetica Neue",Arial,"Noto Sans","PingFang SC","Hiragino Sans GB","Microsoft YaHei";margin:24px;max-width:980px}textarea{width:100%;min-height:420px;font-family:ui-monospace,SFMono-Regular,Menlo,Monaco,Consolas,"Liberation Mono","Courier New",monospace;font-size:12px}button{margin-right:8px;padding:8px 12px}small{color:#666}pre{background:#111;color:#eee;padding:12px;white-space:pre-wrap}</style></head><body><h1>Augment-BYOK-Proxy Admin</h1><p><small>说明：此页面直接编辑运行时配置（JSON）。修改 <code>server.host/server.port</code> 会先绑定新地址再关闭旧地址（进行中的请求继续完成），<code>logging.filter</code> 立即生效。「保存到文件」只把与 config.yaml 的差异写入 config.override.yaml，config.yaml 及其注释保持不变。</small></p><div style="margin:12px 0"><button id="reload">刷新</button><button id="apply">应用(热更新)</button><button id="save">保存到文件</button></div><textarea id="cfg" spellcheck="false"></textarea><h2>状态</h2><pre id="status">ready</pre><script>const $=s=>document.querySelector(s);const setStatus=(v)=>{$('#status').textContent=typeof v==='string'?v:JSON.stringify(v,null,2)};async function load(){setStatus('loading...');const r=await fetch('/admin/api/config');const t=await r.text();if(!r.ok){setStatus({ok:false,status:r.status,body:t});return}try{$('#cfg').value=JSON.stringify(JSON.parse(t),null,2);setStatus({ok:true})}catch(e){$('#cfg').value=t;setStatus({ok:false,error:'config JSON parse failed',detail:String(e)})}}async function apply(){let obj;try{obj=JSON.parse($('#cfg').value)}catch(e){setStatus({ok:false,error:'invalid JSON',detail:String(e)});return}setStatus('applying...');const r=await fetch('/admin/api/config',{method:'PUT',headers:{'content-type':'application/json'},body:JSON.stringify(obj)});const j=await r.json().catch(async()=>({ok:false,body:await r.text()}));setStatus(j);if(r.ok)await load()}async function save(){setStatus('saving...');const r=await fetch('/admin/api/config/save',{method:'POST'});const j=await r.json().catch(async()=>({ok:false,body:await r.text()}));setStatus(j)}$('#reload').addEventListener('click',load);$('#apply').addEventListener('click',apply);$('#save').addEventListener('click',save);load();</script></body></html>"#;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
   let args = Args::parse();
+  match args.command {
+    // 加载失败也要出报告，由 doctor 自己加载配置
+    Some(Command::Doctor(doctor_args)) => {
+      let code = doctor::run(args.config, doctor_args).await?;
+      std::process::exit(code);
+    }
+    Some(Command::Replay(replay_args)) => {
+      let cfg = Config::load(&args.config)?;
+      return replay::run(args.config, cfg, replay_args).await;
+    }
+    None => {}
+  }
   let cfg = Config::load(&args.config)?;
   config::init_tracing(&cfg.logging)?;
 
   let addr = cfg.server.socket_addr()?;
   let http = reqwest::Client::builder().build()?;
 
-  let history_summary_cache_path = args.config.with_file_name("history_summary_cache.json");
-  let history_summary_cache =
-    match HistorySummaryCache::load_from_file(&history_summary_cache_path).await {
-      Ok(v) => v,
-      Err(err) => {
-        warn!(
-          error=%err,
-          cache_path=%history_summary_cache_path.display(),
-          "history_summary cache 读取失败（将使用空缓存）"
-        );
-        HistorySummaryCache::default()
-      }
-    };
-
-  let state = AppState {
-    config_path: args.config,
-    cfg: Arc::new(RwLock::new(cfg)),
-    http,
-    models_cache: Arc::new(RwLock::new(ModelCache::default())),
-    context_canvas_cache: Arc::new(RwLock::new(ContextCanvasCache::default())),
-    history_summary_cache: Arc::new(RwLock::new(history_summary_cache)),
-    history_summary_cache_path,
-  };
+  let state = AppState::new(args.config, cfg, http).await;
+  config_watch::spawn(state.clone());
+  let listener = state.listener.clone();
 
   let app = Router::new()
     .route("/health", get(health))
@@ -188,20 +391,46 @@ async fn main() -> anyhow::Result<()> {
       "/admin/api/history-summary-cache/clear",
       post(admin_clear_history_summary_cache),
     )
+    .route("/admin/api/key-pools", get(admin_get_key_pools))
+    .route(
+      "/admin/api/circuit-breakers",
+      get(admin_get_circuit_breakers),
+    )
+    .route("/admin/api/stats", get(admin_get_stats))
+    .route("/admin/api/usage", get(admin_get_usage))
+    .route("/metrics", get(prometheus_metrics))
     .fallback(proxy_fallback)
     .with_state(state)
+    .layer(axum::middleware::from_fn(metrics::track_requests))
+    .layer(axum::middleware::from_fn(request_id::assign_request_id))
     .layer(axum::extract::DefaultBodyLimit::max(16 * 1024 * 1024));
 
-  let listener = tokio::net::TcpListener::bind(addr).await.with_context(|| {
-    format!("监听 {addr} 失败（端口可能被占用；可修改 config.yaml 的 server.port）")
-  })?;
-  info!(%addr, "Augment-BYOK-Proxy 启动");
-  axum::serve(listener, app).await?;
-  Ok(())
+  listener::serve(&listener, app, addr).await
+}
+
+async fn health(State(state): State<AppState>) -> impl IntoResponse {
+  let breakers = circuit_breaker_status(&state).await;
+  let degraded: Vec<&str> = breakers
+    .iter()
+    .filter(|b| b.is_degraded())
+    .map(|b| b.provider_id())
+    .collect();
+  axum::Json(serde_json::json!({
+    "status": "ok",
+    "service": "augment-byok-proxy",
+    "degraded_providers": degraded,
+    "circuit_breakers": breakers,
+  }))
 }
 
-async fn health() -> impl IntoResponse {
-  axum::Json(serde_json::json!({ "status": "ok", "service": "augment-byok-proxy" }))
+async fn circuit_breaker_status(state: &AppState) -> Vec<BreakerStatus> {
+  let cfg = state.cfg.read().await.clone();
+  state
+    .upstream
+    .breakers
+    .read()
+    .await
+    .status(cfg.byok.providers.iter().map(|p| p.id()), now_ms())
 }
 
 async fn admin_index() -> impl IntoResponse {
@@ -210,37 +439,110 @@ async fn admin_index() -> impl IntoResponse {
 
 async fn admin_get_config(State(state): State<AppState>) -> impl IntoResponse {
   let cfg = state.cfg.read().await.clone();
-  axum::Json(cfg)
+  match cfg.to_unresolved_value() {
+    Ok(v) => (StatusCode::OK, axum::Json(v)),
+    Err(err) => (
+      StatusCode::INTERNAL_SERVER_ERROR,
+      axum::Json(serde_json::json!({ "ok": false, "error": format!("{err:#}") })),
+    ),
+  }
+}
+
+async fn admin_get_key_pools(State(state): State<AppState>) -> impl IntoResponse {
+  let cfg = state.cfg.read().await.clone();
+  let providers = state
+    .upstream
+    .key_pools
+    .read()
+    .await
+    .status(&cfg.byok.providers, now_ms());
+  axum::Json(serde_json::json!({ "providers": providers }))
+}
+
+async fn admin_get_circuit_breakers(State(state): State<AppState>) -> impl IntoResponse {
+  axum::Json(serde_json::json!({ "providers": circuit_breaker_status(&state).await }))
+}
+
+async fn admin_get_stats(State(state): State<AppState>) -> impl IntoResponse {
+  let cfg = state.cfg.read().await.clone();
+  let mut stats = serde_json::to_value(state.stats.snapshot()).unwrap_or_default();
+  if let Some(obj) = stats.as_object_mut() {
+    obj.insert(
+      "concurrency".to_string(),
+      serde_json::json!(state.upstream.concurrency.status(&cfg.byok.providers)),
+    );
+  }
+  axum::Json(stats)
+}
+
+async fn admin_get_usage(
+  State(state): State<AppState>,
+  Query(query): Query<AdminUsageQuery>,
+) -> impl IntoResponse {
+  let group_by = query.group_by.unwrap_or(UsageGroupBy::Day);
+  let days = query.days.unwrap_or(30);
+  let since_ms = match days {
+    0 => 0,
+    n => now_ms().saturating_sub(n.saturating_mul(86_400_000)),
+  };
+  let records = match state.usage.load(since_ms).await {
+    Ok(v) => v,
+    Err(err) => {
+      return (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        axum::Json(
+          serde_json::json!({ "ok": false, "error": format!("读取 usage ledger 失败: {err}") }),
+        ),
+      );
+    }
+  };
+  let cfg = state.cfg.read().await.clone();
+  let (groups, total) = summarize(&records, &cfg.usage, group_by);
+  (
+    StatusCode::OK,
+    axum::Json(serde_json::json!({
+      "ok": true,
+      "group_by": group_by,
+      "days": days,
+      "groups": groups,
+      "total": total,
+    })),
+  )
+}
+
+async fn prometheus_metrics() -> impl IntoResponse {
+  (
+    [(
+      axum::http::header::CONTENT_TYPE,
+      "text/plain; version=0.0.4; charset=utf-8",
+    )],
+    metrics::render(),
+  )
 }
 
 async fn admin_put_config(
   State(state): State<AppState>,
-  axum::Json(next): axum::Json<Config>,
+  axum::Json(mut next): axum::Json<Config>,
 ) -> impl IntoResponse {
-  if let Err(err) = next.validate() {
+  let known = state.cfg.read().await.secret_refs.clone();
+  if let Err(err) = next.resolve_secrets_from_admin(&known) {
     return (
       StatusCode::BAD_REQUEST,
-      axum::Json(serde_json::json!({ "ok": false, "error": format!("{err}") })),
+      axum::Json(serde_json::json!({ "ok": false, "error": format!("{err:#}") })),
     );
   }
-  let current = state.cfg.read().await.clone();
-  if next.server.host != current.server.host || next.server.port != current.server.port {
+  if let Err(err) = next.validate() {
     return (
       StatusCode::BAD_REQUEST,
-      axum::Json(
-        serde_json::json!({ "ok": false, "error": "server.host/server.port 变更需要重启进程；本接口仅支持热更新上游相关配置" }),
-      ),
+      axum::Json(serde_json::json!({ "ok": false, "error": format!("{err}") })),
     );
   }
-  if next.logging.filter.trim() != current.logging.filter.trim() {
+  if let Err(err) = state.apply_config(next).await {
     return (
       StatusCode::BAD_REQUEST,
-      axum::Json(
-        serde_json::json!({ "ok": false, "error": "logging.filter 变更需要重启进程（暂不支持热更新）" }),
-      ),
+      axum::Json(serde_json::json!({ "ok": false, "error": format!("{err:#}") })),
     );
   }
-  *state.cfg.write().await = next;
   (
     StatusCode::OK,
     axum::Json(serde_json::json!({ "ok": true })),
@@ -249,16 +551,16 @@ async fn admin_put_config(
 
 async fn admin_save_config(State(state): State<AppState>) -> impl IntoResponse {
   let cfg = state.cfg.read().await.clone();
-  if let Err(err) = cfg.save(&state.config_path) {
-    return (
+  match cfg.save(&state.config_path) {
+    Ok(path) => (
+      StatusCode::OK,
+      axum::Json(serde_json::json!({ "ok": true, "path": path.display().to_string() })),
+    ),
+    Err(err) => (
       StatusCode::INTERNAL_SERVER_ERROR,
-      axum::Json(serde_json::json!({ "ok": false, "error": format!("{err}") })),
-    );
+      axum::Json(serde_json::json!({ "ok": false, "error": format!("{err:#}") })),
+    ),
   }
-  (
-    StatusCode::OK,
-    axum::Json(serde_json::json!({ "ok": true, "path": state.config_path.display().to_string() })),
-  )
 }
 
 async fn admin_delete_history_summary_cache(
@@ -325,8 +627,10 @@ async fn chat_stream(
   headers: HeaderMap,
   body: Bytes,
 ) -> Response<Body> {
+  let started = std::time::Instant::now();
   let cfg = state.cfg.read().await.clone();
-  let mode = read_byok_mode(&headers);
+  let route = resolve_byok_route(&cfg, "/chat-stream", &headers, &body);
+  let mode = route.mode;
   if !is_authorized(&headers, &cfg.proxy.auth_token) {
     let present = auth_present_headers(&headers);
     warn!(present=?present, "chat-stream 未授权（缺少或错误的鉴权 token）");
@@ -396,40 +700,38 @@ async fn chat_stream(
     .and_then(|v| v.to_str().ok())
     .or_else(|| headers.get("Model").and_then(|v| v.to_str().ok()));
 
-  let requested_model = query
-    .model
-    .as_deref()
-    .filter(|s| !s.trim().is_empty())
-    .or_else(|| header_model.filter(|s| !s.trim().is_empty()))
-    .or_else(|| augment.model.as_deref().filter(|s| !s.trim().is_empty()))
-    .unwrap_or("");
-
-  let (provider, raw_model) = match parse_byok_model_id(requested_model) {
-    Some((provider_id, model_id)) => match get_provider_by_id(&cfg, &provider_id) {
-      Ok(p) => (p, model_id),
-      Err(err) => return ndjson_response(error_response(format!("⚠️ 选择 provider 失败: {err}"))),
-    },
-    None => {
-      let p = match pick_active_provider(&cfg) {
-        Ok(v) => v,
-        Err(err) => return ndjson_response(error_response(format!("⚠️ 缺少默认 provider: {err}"))),
-      };
-      let model = if requested_model.trim().is_empty() {
-        p.default_model().to_string()
-      } else {
-        requested_model.to_string()
-      };
-      (p, model)
-    }
+  let header_model = header_model.filter(|s| !s.trim().is_empty());
+  let requested_model = match route.model.as_deref() {
+    Some(route_model) => header_model.unwrap_or(route_model),
+    None => query
+      .model
+      .as_deref()
+      .filter(|s| !s.trim().is_empty())
+      .or(header_model)
+      .or_else(|| augment.model.as_deref().filter(|s| !s.trim().is_empty()))
+      .unwrap_or(""),
   };
 
-  let model_for_trigger = match provider {
-    ProviderRef::Anthropic(_) => clean_model(&raw_model),
-    ProviderRef::OpenAICompatible(_) => raw_model.trim().to_string(),
+  let (provider, raw_model) = match resolve_chat_target(&cfg, requested_model) {
+    Ok(v) => v,
+    Err(msg) => return ndjson_response(error_response(msg)),
+  };
+  let (provider, raw_model) = match apply_budget(&state, &cfg, provider, raw_model) {
+    Ok(v) => v,
+    Err(msg) => return ndjson_response(error_response(msg)),
   };
 
+  let model_for_trigger = upstream_model_name(provider, &raw_model);
+  metrics::label_request_target(provider.id(), &model_for_trigger);
+
+  let summary_client = UpstreamClient {
+    http: &state.http,
+    health: &state.upstream,
+    policy: &UpstreamPolicy::from_config(&cfg),
+    usage: cfg.usage.enabled.then_some(&state.usage),
+  };
   if let Err(err) = maybe_summarize_and_compact(
-    &state.http,
+    summary_client,
     &cfg,
     &state.history_summary_cache,
     state.history_summary_cache_path.as_path(),
@@ -453,10 +755,164 @@ async fn chat_stream(
   let hard_timeout = match provider {
     ProviderRef::Anthropic(p) => Duration::from_secs(p.timeout_seconds),
     ProviderRef::OpenAICompatible(p) => Duration::from_secs(p.timeout_seconds),
+    ProviderRef::Gemini(p) => Duration::from_secs(p.timeout_seconds),
+    ProviderRef::OpenAIResponses(p) => Duration::from_secs(p.timeout_seconds),
   };
   maybe_inject_official_context(&state, &cfg, &mut augment, hard_timeout).await;
 
-  let tool_meta_by_name: std::collections::HashMap<String, (String, String)> = augment
+  let tool_meta_by_name = tool_meta_by_name(&augment);
+
+  let targets = resolve_fallback_chain(&cfg, provider, &raw_model);
+  let policy = UpstreamPolicy::from_config(&cfg)
+    .with_estimated_input_tokens(estimate_request_input_tokens(&augment));
+  let mut last_err = String::new();
+  let conversation_id = augment.conversation_id.clone().unwrap_or_default();
+  let trace = ChatStreamTrace {
+    dump_body,
+    transcript: Transcript::start(
+      &cfg,
+      &state.config_path,
+      provider.id(),
+      raw_model.trim(),
+      &conversation_id,
+      &body,
+    )
+    .map(Arc::new),
+  };
+  let record_error = |mut record: UsageRecord| {
+    if cfg.usage.enabled {
+      record.latency_ms = now_ms().saturating_sub(record.ts_ms);
+      record.status = "error".to_string();
+      state.usage.record(record);
+    }
+  };
+  for (attempt, (target, target_model)) in targets.iter().enumerate() {
+    if let Some(hit) = exceeded_budget(&cfg.usage, &state.usage, target.id(), target_model) {
+      warn!(provider=%target.id(), model=%target_model, attempt, "chat-stream 目标预算已用尽，跳过");
+      last_err = format!("💸 {}", hit.message());
+      continue;
+    }
+    let usage_record =
+      UsageRecord::new("/chat-stream", &conversation_id, target.id(), target_model);
+    if let Some(t) = &trace.transcript {
+      t.begin_attempt(target.id(), target_model);
+    }
+    let opened = open_chat_stream_upstream(
+      &state,
+      *target,
+      target_model,
+      &augment,
+      &tool_meta_by_name,
+      &policy,
+      &trace,
+    )
+    .await;
+    let (mut upstream, progress) = match opened {
+      Ok(v) => v,
+      Err(err) => {
+        warn!(provider=%target.id(), model=%target_model, attempt, error=%err, "chat-stream 上游失败，尝试下一个 fallback");
+        record_error(usage_record);
+        if let Some(t) = &trace.transcript {
+          t.on_attempt_error(&err);
+        }
+        last_err = err;
+        continue;
+      }
+    };
+    let first = match upstream.next().await {
+      Some(Err(err)) => {
+        warn!(provider=%target.id(), model=%target_model, attempt, error=%err, "chat-stream 上游失败，尝试下一个 fallback");
+        record_error(usage_record);
+        if let Some(t) = &trace.transcript {
+          t.on_attempt_error(&err);
+        }
+        last_err = err;
+        continue;
+      }
+      first => first,
+    };
+    metrics::label_request_target(target.id(), target_model);
+    metrics::record_chat_stream_first_token(target.id(), target_model, started.elapsed());
+
+    if attempt > 0 {
+      info!(provider=%target.id(), model=%target_model, attempt, requested_provider=%provider.id(), requested_model=%raw_model, "chat-stream 由 fallback 接管");
+    } else {
+      debug!(provider=%target.id(), model=%target_model, "chat-stream 上游已连接");
+    }
+
+    let mut turn = ChatStreamTurn::start(state.stats.clone(), progress, target.id(), target_model);
+    if cfg.usage.enabled {
+      turn = turn.with_usage_ledger(state.usage.clone(), usage_record);
+    }
+    let mut upstream = futures::stream::iter(first).chain(upstream);
+    let transcript = trace.transcript.clone();
+    let stream = stream! {
+      while let Some(item) = upstream.next().await {
+        let bytes = match item {
+          Ok(bytes) => bytes,
+          Err(msg) => ndjson_line(&error_response(msg)),
+        };
+        if let Some(t) = &transcript {
+          t.on_emitted(&bytes);
+        }
+        yield Ok::<Bytes, Infallible>(bytes);
+      }
+      turn.finish();
+    };
+    let mut response = Response::new(Body::from_stream(stream));
+    let headers = response.headers_mut();
+    headers.insert(
+      "content-type",
+      HeaderValue::from_static("application/x-ndjson; charset=utf-8"),
+    );
+    headers.insert("cache-control", HeaderValue::from_static("no-cache"));
+    headers.insert("connection", HeaderValue::from_static("keep-alive"));
+    headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
+    return response;
+  }
+  let chunk = error_response(last_err);
+  if let Some(t) = &trace.transcript {
+    t.on_emitted(&ndjson_line(&chunk));
+  }
+  ndjson_response(chunk)
+}
+
+// 请求的模型：byok:<providerId>:<modelId> 直接定位；否则用默认 provider（空则取其 default_model）
+fn resolve_chat_target<'a>(
+  cfg: &'a Config,
+  requested_model: &str,
+) -> Result<(ProviderRef<'a>, String), String> {
+  match parse_byok_model_id(requested_model) {
+    Some((provider_id, model_id)) => match get_provider_by_id(cfg, &provider_id) {
+      Ok(p) => Ok((p, model_id)),
+      Err(err) => Err(format!("⚠️ 选择 provider 失败: {err}")),
+    },
+    None => {
+      let p = pick_active_provider(cfg).map_err(|err| format!("⚠️ 缺少默认 provider: {err}"))?;
+      let model = if requested_model.trim().is_empty() {
+        p.default_model().to_string()
+      } else {
+        requested_model.to_string()
+      };
+      Ok((p, model))
+    }
+  }
+}
+
+// 与各 provider 分支里实际发给上游的模型名一致
+fn upstream_model_name(provider: ProviderRef<'_>, raw_model: &str) -> String {
+  match provider {
+    ProviderRef::Anthropic(_) => clean_model(raw_model),
+    ProviderRef::OpenAICompatible(_) | ProviderRef::OpenAIResponses(_) => {
+      raw_model.trim().to_string()
+    }
+    ProviderRef::Gemini(_) => raw_model.trim().trim_start_matches("models/").to_string(),
+  }
+}
+
+// tool name -> (mcp_server_name, mcp_tool_name)
+fn tool_meta_by_name(augment: &AugmentRequest) -> HashMap<String, (String, String)> {
+  augment
     .tool_definitions
     .iter()
     .filter_map(|d| {
@@ -474,29 +930,138 @@ async fn chat_stream(
         (mcp_server_name.to_string(), mcp_tool_name.to_string()),
       ))
     })
-    .collect();
+    .collect()
+}
+
+// 请求的模型预算已用尽时：on_exceeded=fallback 改用预算里配置的模型，否则拒绝
+fn apply_budget<'a>(
+  state: &AppState,
+  cfg: &'a Config,
+  provider: ProviderRef<'a>,
+  raw_model: String,
+) -> Result<(ProviderRef<'a>, String), String> {
+  let Some(hit) = exceeded_budget(&cfg.usage, &state.usage, provider.id(), &raw_model) else {
+    return Ok((provider, raw_model));
+  };
+  let (pid, model) = match (hit.budget.on_exceeded, hit.budget.fallback_target()) {
+    (BudgetAction::Fallback, Some(target)) => target,
+    _ => return Err(format!("💸 {}", hit.message())),
+  };
+  let fallback = get_provider_by_id(cfg, pid)
+    .map_err(|err| format!("💸 {}；fallback 不可用: {err}", hit.message()))?;
+  if let Some(fallback_hit) = exceeded_budget(&cfg.usage, &state.usage, pid, model) {
+    return Err(format!(
+      "💸 {}；fallback 也已超出预算：{}",
+      hit.message(),
+      fallback_hit.message()
+    ));
+  }
+  info!(provider=%provider.id(), model=%raw_model, fallback_provider=%pid, fallback_model=%model, "预算已用尽，改用 fallback 模型");
+  Ok((fallback, model.to_string()))
+}
+
+fn ndjson_line(chunk: &AugmentStreamChunk) -> Bytes {
+  let line = serde_json::to_string(chunk)
+    .unwrap_or_else(|_| "{\"text\":\"\",\"stop_reason\":1}".to_string());
+  Bytes::from(format!("{line}\n"))
+}
+
+// 主目标 + byok.fallbacks（模型级 key 优先于 provider 级 key）；不递归展开 fallback 自身的 fallback
+fn resolve_fallback_chain<'a>(
+  cfg: &'a Config,
+  provider: ProviderRef<'a>,
+  raw_model: &str,
+) -> Vec<(ProviderRef<'a>, String)> {
+  let mut out: Vec<(ProviderRef<'a>, String)> = vec![(provider, raw_model.to_string())];
+  let model_key = format!("byok:{}:{}", provider.id(), raw_model.trim());
+  let Some(targets) = cfg
+    .byok
+    .fallbacks
+    .get(&model_key)
+    .or_else(|| cfg.byok.fallbacks.get(provider.id()))
+  else {
+    return out;
+  };
+  for target in targets {
+    let Some((provider_id, model_id)) = parse_byok_model_id(target) else {
+      warn!(target=%target, "byok.fallbacks 目标格式无效（应为 byok:<providerId>:<modelId>），已跳过");
+      continue;
+    };
+    let p = match get_provider_by_id(cfg, &provider_id) {
+      Ok(p) => p,
+      Err(err) => {
+        warn!(target=%target, error=%err, "byok.fallbacks 目标 provider 不存在，已跳过");
+        continue;
+      }
+    };
+    if out
+      .iter()
+      .any(|(q, m)| q.id() == p.id() && m.trim() == model_id.trim())
+    {
+      continue;
+    }
+    out.push((p, model_id));
+  }
+  out
+}
 
+type UpstreamNdjsonStream =
+  std::pin::Pin<Box<dyn futures::Stream<Item = Result<Bytes, String>> + Send>>;
+
+// chat-stream 的调试输出：dump_body 打摘要日志，transcript 落盘完整记录
+struct ChatStreamTrace {
+  dump_body: bool,
+  transcript: Option<Arc<Transcript>>,
+}
+
+// 发起一次上游请求：在首个 NDJSON 行之前的失败以 Err 返回（或作为流的首项 Err），便于切换 fallback
+async fn open_chat_stream_upstream(
+  state: &AppState,
+  provider: ProviderRef<'_>,
+  raw_model: &str,
+  augment: &AugmentRequest,
+  tool_meta_by_name: &HashMap<String, (String, String)>,
+  policy: &UpstreamPolicy,
+  trace: &ChatStreamTrace,
+) -> Result<(UpstreamNdjsonStream, Arc<TurnProgress>), String> {
+  let dump_body = trace.dump_body;
+  let transcript = trace.transcript.clone();
+  let (max_concurrent, queue_timeout) = provider.concurrency_limit();
+  let permit = state
+    .upstream
+    .concurrency
+    .acquire(
+      provider.id(),
+      max_concurrent,
+      queue_timeout,
+      RequestPriority::Interactive,
+    )
+    .await
+    .map_err(|err| format!("❌ {err}"))?;
+  let mut timeouts = provider.stream_timeouts();
+  let policy = &policy.clone().with_header_timeout(timeouts.first_byte());
+  let progress = Arc::new(TurnProgress::default());
   match provider {
     ProviderRef::Anthropic(provider) => {
-      let model = clean_model(&raw_model);
-      let anthropic_req = match convert_augment_to_anthropic(provider, &augment, model) {
+      let model = clean_model(raw_model);
+      let anthropic_req = match convert_augment_to_anthropic(provider, augment, model) {
         Ok(v) => v,
-        Err(err) => return ndjson_response(error_response(format!("⚠️ 转换请求失败: {err}"))),
+        Err(err) => return Err(format!("⚠️ 转换请求失败: {err}")),
       };
+      if let Some(t) = &transcript {
+        t.on_upstream_request(&anthropic_req);
+      }
 
       let url = match join_url(&provider.base_url, "messages") {
         Ok(u) => u,
-        Err(err) => {
-          return ndjson_response(error_response(format!("⚠️ anthropic base_url 无效: {err}")))
-        }
+        Err(err) => return Err(format!("⚠️ anthropic base_url 无效: {err}")),
       };
 
-      let api_key = normalize_raw_token(&provider.api_key);
-      if api_key.is_empty() {
-        return ndjson_response(error_response(format!(
+      if provider.api_key.is_empty() {
+        return Err(format!(
           "⚠️ Provider({}) api_key 为空（请填写 byok.providers[].api_key；可用原始 token 或 KEY=VALUE 形式）",
           provider.id
-        )));
+        ));
       }
 
       let mut req = state
@@ -505,8 +1070,6 @@ async fn chat_stream(
         .header("content-type", "application/json")
         .header("accept", "text/event-stream")
         .header("anthropic-version", "2023-06-01")
-        .header("x-api-key", api_key)
-        .timeout(Duration::from_secs(provider.timeout_seconds))
         .json(&anthropic_req);
 
       for (k, v) in &provider.extra_headers {
@@ -515,17 +1078,27 @@ async fn chat_stream(
         }
       }
 
-      let resp = match req.send().await {
+      let resp = match send_upstream(
+        &state.upstream,
+        policy,
+        &provider.id,
+        &provider.api_key,
+        &provider.key_pool,
+        req,
+        |r, k| r.header("x-api-key", k),
+      )
+      .await
+      {
         Ok(r) => r,
-        Err(err) => return ndjson_response(error_response(format!("❌ 上游请求失败: {err}"))),
+        Err(err) => return Err(format!("❌ 上游请求失败: {err}")),
       };
+      timeouts.headers_received();
+      progress.attach_rate_limit_ticket(&resp);
 
       if !resp.status().is_success() {
         let status = resp.status();
         let body_text = resp.text().await.unwrap_or_default();
-        return ndjson_response(error_response(format!(
-          "❌ 上游返回错误: {status} {body_text}"
-        )));
+        return Err(format!("❌ 上游返回错误: {status} {body_text}"));
       }
 
       let content_type = resp
@@ -546,13 +1119,15 @@ async fn chat_stream(
       {
         let body_text = resp.text().await.unwrap_or_default();
         let preview = truncate_for_log(body_text, 1024);
-        return ndjson_response(error_response(format!(
+        return Err(format!(
           "❌ 上游响应不是 SSE（content-type={content_type}）；请确认 byok.providers[type=anthropic].base_url 指向 Anthropic Messages API 前缀（例如 https://api.anthropic.com/v1）；body: {preview}"
-        )));
+        ));
       }
 
       let tool_meta_by_name = tool_meta_by_name.clone();
+      let stream_progress = progress.clone();
       let stream = stream! {
+        let _permit = permit;
         let mut state_machine = AnthropicStreamState::default();
         state_machine.tool_meta_by_name = tool_meta_by_name;
         let mut data_lines: usize = 0;
@@ -563,7 +1138,22 @@ async fn chat_stream(
         let mut lines = tokio::io::BufReader::new(reader).lines();
         let mut sse_event_type: Option<String> = None;
 
-        while let Ok(Some(line)) = lines.next_line().await {
+        loop {
+          stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+          stream_progress.on_cache_usage(state_machine.usage_cache_read_input_tokens, state_machine.usage_cache_creation_input_tokens);
+          let line = match timeouts.next_line(&mut lines).await {
+            Ok(Some(line)) => {
+              if let Some(t) = &transcript {
+                t.on_upstream_line(&line);
+              }
+              line
+            }
+            Ok(None) => break,
+            Err(msg) => {
+              yield Err(msg);
+              return;
+            }
+          };
           if line.is_empty() {
             sse_event_type = None;
             continue;
@@ -586,11 +1176,21 @@ async fn chat_stream(
               event.event_type = t.clone();
             }
           }
+          if event.event_type == "error" {
+            let detail = event
+              .error
+              .as_ref()
+              .map(|e| format!("{} {}", e.error_type, e.message))
+              .unwrap_or_default();
+            yield Err(format!("❌ 上游返回 error event: {}", detail.trim()));
+            return;
+          }
 
           for chunk in convert_event_to_chunks(&mut state_machine, event) {
+            stream_progress.on_chunk(&chunk);
             if let Ok(line) = serde_json::to_string(&chunk) {
               emitted_chunks += 1;
-              yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
+              yield Ok(Bytes::from(format!("{line}\n")));
             }
           }
         }
@@ -602,51 +1202,43 @@ async fn chat_stream(
 
         if emitted_chunks == 0 && !has_usage {
           let msg = format!("❌ 未解析到任何上游 SSE 内容（data_lines={data_lines}, parsed_events={parsed_events}）；请检查 byok.providers[type=anthropic].base_url 是否真的是 Anthropic /messages SSE");
-          let error_chunk = error_response(msg);
-          if let Ok(line) = serde_json::to_string(&error_chunk) {
-            yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
-          }
+          yield Err(msg);
           return;
         }
 
+        stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+        stream_progress.on_cache_usage(state_machine.usage_cache_read_input_tokens, state_machine.usage_cache_creation_input_tokens);
+
         for chunk in state_machine.finalize() {
+          stream_progress.on_chunk(&chunk);
           if let Ok(line) = serde_json::to_string(&chunk) {
-            yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
+            yield Ok(Bytes::from(format!("{line}\n")));
           }
         }
       };
 
-      let mut response = Response::new(Body::from_stream(stream));
-      let headers = response.headers_mut();
-      headers.insert(
-        "content-type",
-        HeaderValue::from_static("application/x-ndjson; charset=utf-8"),
-      );
-      headers.insert("cache-control", HeaderValue::from_static("no-cache"));
-      headers.insert("connection", HeaderValue::from_static("keep-alive"));
-      headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
-      response
+      Ok((Box::pin(stream), progress))
     }
     ProviderRef::OpenAICompatible(provider) => {
       let model = raw_model.trim().to_string();
-      let openai_req = match convert_augment_to_openai_compatible(provider, &augment, model) {
+      let openai_req = match convert_augment_to_openai_compatible(provider, augment, model) {
         Ok(v) => v,
-        Err(err) => return ndjson_response(error_response(format!("⚠️ 转换请求失败: {err}"))),
+        Err(err) => return Err(format!("⚠️ 转换请求失败: {err}")),
       };
+      if let Some(t) = &transcript {
+        t.on_upstream_request(&openai_req);
+      }
 
       let url = match join_url(&provider.base_url, "chat/completions") {
         Ok(u) => u,
-        Err(err) => {
-          return ndjson_response(error_response(format!("⚠️ openai base_url 无效: {err}")))
-        }
+        Err(err) => return Err(format!("⚠️ openai base_url 无效: {err}")),
       };
 
-      let api_key = normalize_raw_token(&provider.api_key);
-      if api_key.is_empty() {
-        return ndjson_response(error_response(format!(
+      if provider.api_key.is_empty() {
+        return Err(format!(
           "⚠️ Provider({}) api_key 为空（请填写 byok.providers[].api_key；可用原始 token 或 KEY=VALUE 形式）",
           provider.id
-        )));
+        ));
       }
 
       let mut req = state
@@ -654,8 +1246,6 @@ async fn chat_stream(
         .post(url)
         .header("content-type", "application/json")
         .header("accept", "text/event-stream")
-        .header("authorization", format!("Bearer {api_key}"))
-        .timeout(Duration::from_secs(provider.timeout_seconds))
         .json(&openai_req);
 
       for (k, v) in &provider.extra_headers {
@@ -664,17 +1254,27 @@ async fn chat_stream(
         }
       }
 
-      let resp = match req.send().await {
+      let resp = match send_upstream(
+        &state.upstream,
+        policy,
+        &provider.id,
+        &provider.api_key,
+        &provider.key_pool,
+        req,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
+      {
         Ok(r) => r,
-        Err(err) => return ndjson_response(error_response(format!("❌ 上游请求失败: {err}"))),
+        Err(err) => return Err(format!("❌ 上游请求失败: {err}")),
       };
+      timeouts.headers_received();
+      progress.attach_rate_limit_ticket(&resp);
 
       if !resp.status().is_success() {
         let status = resp.status();
         let body_text = resp.text().await.unwrap_or_default();
-        return ndjson_response(error_response(format!(
-          "❌ 上游返回错误: {status} {body_text}"
-        )));
+        return Err(format!("❌ 上游返回错误: {status} {body_text}"));
       }
 
       let content_type = resp
@@ -695,13 +1295,15 @@ async fn chat_stream(
       {
         let body_text = resp.text().await.unwrap_or_default();
         let preview = truncate_for_log(body_text, 1024);
-        return ndjson_response(error_response(format!(
+        return Err(format!(
           "❌ 上游响应不是 SSE（content-type={content_type}）；请确认 byok.providers[type=openai_compatible].base_url 指向 OpenAI Chat Completions API 前缀（例如 https://api.openai.com/v1）；body: {preview}"
-        )));
+        ));
       }
 
       let tool_meta_by_name = tool_meta_by_name.clone();
+      let stream_progress = progress.clone();
       let stream = stream! {
+        let _permit = permit;
         let mut state_machine = OpenAIStreamState::default();
         state_machine.tool_meta_by_name = tool_meta_by_name;
         let mut data_lines: usize = 0;
@@ -711,7 +1313,21 @@ async fn chat_stream(
         let reader = StreamReader::new(bytes_stream);
         let mut lines = tokio::io::BufReader::new(reader).lines();
 
-        while let Ok(Some(line)) = lines.next_line().await {
+        loop {
+          stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+          let line = match timeouts.next_line(&mut lines).await {
+            Ok(Some(line)) => {
+              if let Some(t) = &transcript {
+                t.on_upstream_line(&line);
+              }
+              line
+            }
+            Ok(None) => break,
+            Err(msg) => {
+              yield Err(msg);
+              return;
+            }
+          };
           if line.is_empty() {
             continue;
           }
@@ -730,15 +1346,34 @@ async fn chat_stream(
 
           if let Some(u) = chunk.usage.as_ref() {
             state_machine.on_usage(u.prompt_tokens, u.completion_tokens);
+            stream_progress.on_cached_input_usage(u.cached_tokens());
           }
 
           for choice in &chunk.choices {
+            if let Some(reasoning) = choice.delta.reasoning_content.as_deref().or(choice.delta.reasoning.as_deref()) {
+              state_machine.on_reasoning_delta(reasoning);
+            }
+
+            let has_output = choice.delta.content.as_deref().is_some_and(|c| !c.is_empty())
+              || choice.delta.tool_calls.is_some()
+              || choice.delta.function_call.is_some();
+            if has_output {
+              if let Some(chunk) = state_machine.flush_thinking() {
+                stream_progress.on_chunk(&chunk);
+                if let Ok(line) = serde_json::to_string(&chunk) {
+                  emitted_chunks += 1;
+                  yield Ok(Bytes::from(format!("{line}\n")));
+                }
+              }
+            }
+
             if let Some(delta) = choice.delta.content.as_deref() {
               if !delta.is_empty() {
                 let chunk = state_machine.on_text_delta(delta);
+                stream_progress.on_chunk(&chunk);
                 if let Ok(line) = serde_json::to_string(&chunk) {
                   emitted_chunks += 1;
-                  yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
+                  yield Ok(Bytes::from(format!("{line}\n")));
                 }
               }
             }
@@ -750,9 +1385,10 @@ async fn chat_stream(
                   let name = c.function.as_ref().and_then(|f| f.name.as_deref());
                   let args = c.function.as_ref().and_then(|f| f.arguments.as_deref());
                   if let Some(chunk) = state_machine.on_tool_call_delta(idx, id, name, args) {
+                    stream_progress.on_chunk(&chunk);
                     if let Ok(line) = serde_json::to_string(&chunk) {
                       emitted_chunks += 1;
-                      yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
+                      yield Ok(Bytes::from(format!("{line}\n")));
                     }
                   }
                 }
@@ -762,9 +1398,10 @@ async fn chat_stream(
                 let name = fc.name.as_deref();
                 let args = fc.arguments.as_deref();
                 if let Some(chunk) = state_machine.on_tool_call_delta(0, None, name, args) {
+                  stream_progress.on_chunk(&chunk);
                   if let Ok(line) = serde_json::to_string(&chunk) {
                     emitted_chunks += 1;
-                    yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
+                    yield Ok(Bytes::from(format!("{line}\n")));
                   }
                 }
               }
@@ -777,32 +1414,356 @@ async fn chat_stream(
 
         let has_usage = state_machine.usage_input_tokens.is_some() || state_machine.usage_output_tokens.is_some();
         let has_tool_calls = !state_machine.tool_calls.is_empty();
-        if emitted_chunks == 0 && !has_usage && !has_tool_calls {
+        if emitted_chunks == 0 && !has_usage && !has_tool_calls && state_machine.thinking_buffer.is_empty() {
           let msg = format!("❌ 未解析到任何上游 SSE 内容（data_lines={data_lines}, parsed_chunks={parsed_chunks}）；请检查 byok.providers[type=openai_compatible].base_url 是否真的是 OpenAI /chat/completions SSE");
-          let error_chunk = error_response(msg);
-          if let Ok(line) = serde_json::to_string(&error_chunk) {
-            yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
-          }
+          yield Err(msg);
           return;
         }
 
+        stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+
         for chunk in state_machine.finalize() {
+          stream_progress.on_chunk(&chunk);
           if let Ok(line) = serde_json::to_string(&chunk) {
-            yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
+            yield Ok(Bytes::from(format!("{line}\n")));
           }
         }
       };
 
-      let mut response = Response::new(Body::from_stream(stream));
-      let headers = response.headers_mut();
-      headers.insert(
-        "content-type",
-        HeaderValue::from_static("application/x-ndjson; charset=utf-8"),
-      );
-      headers.insert("cache-control", HeaderValue::from_static("no-cache"));
-      headers.insert("connection", HeaderValue::from_static("keep-alive"));
-      headers.insert("transfer-encoding", HeaderValue::from_static("chunked"));
-      response
+      Ok((Box::pin(stream), progress))
+    }
+    ProviderRef::Gemini(provider) => {
+      let model = raw_model.trim().trim_start_matches("models/").to_string();
+      let gemini_req = match convert_augment_to_gemini(provider, augment) {
+        Ok(v) => v,
+        Err(err) => return Err(format!("⚠️ 转换请求失败: {err}")),
+      };
+      if let Some(t) = &transcript {
+        t.on_upstream_request(&gemini_req);
+      }
+
+      let url = match join_url(
+        &provider.base_url,
+        &format!("models/{model}:streamGenerateContent?alt=sse"),
+      ) {
+        Ok(u) => u,
+        Err(err) => return Err(format!("⚠️ gemini base_url 无效: {err}")),
+      };
+
+      if provider.api_key.is_empty() {
+        return Err(format!(
+          "⚠️ Provider({}) api_key 为空（请填写 byok.providers[].api_key；可用原始 token 或 KEY=VALUE 形式）",
+          provider.id
+        ));
+      }
+
+      let mut req = state
+        .http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "text/event-stream")
+        .json(&gemini_req);
+
+      for (k, v) in &provider.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          req = req.header(k, value);
+        }
+      }
+
+      let resp = match send_upstream(
+        &state.upstream,
+        policy,
+        &provider.id,
+        &provider.api_key,
+        &provider.key_pool,
+        req,
+        |r, k| r.header("x-goog-api-key", k),
+      )
+      .await
+      {
+        Ok(r) => r,
+        Err(err) => return Err(format!("❌ 上游请求失败: {err}")),
+      };
+      timeouts.headers_received();
+      progress.attach_rate_limit_ticket(&resp);
+
+      if !resp.status().is_success() {
+        let status = resp.status();
+        let body_text = resp.text().await.unwrap_or_default();
+        return Err(format!("❌ 上游返回错误: {status} {body_text}"));
+      }
+
+      let content_type = resp
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+      if dump_body {
+        info!(status=%resp.status(), content_type=%content_type, "上游响应");
+      } else {
+        debug!(status=%resp.status(), content_type=%content_type, "上游响应");
+      }
+      if !content_type
+        .trim()
+        .to_ascii_lowercase()
+        .contains("text/event-stream")
+      {
+        let body_text = resp.text().await.unwrap_or_default();
+        let preview = truncate_for_log(body_text, 1024);
+        return Err(format!(
+          "❌ 上游响应不是 SSE（content-type={content_type}）；请确认 byok.providers[type=gemini].base_url 指向 Gemini API 前缀（例如 https://generativelanguage.googleapis.com/v1beta）；body: {preview}"
+        ));
+      }
+
+      let tool_meta_by_name = tool_meta_by_name.clone();
+      let stream_progress = progress.clone();
+      let stream = stream! {
+        let _permit = permit;
+        let mut state_machine = GeminiStreamState {
+          tool_meta_by_name,
+          ..Default::default()
+        };
+        let mut data_lines: usize = 0;
+        let mut parsed_chunks: usize = 0;
+        let mut emitted_chunks: usize = 0;
+        let bytes_stream = resp.bytes_stream().map(|r| r.map_err(std::io::Error::other));
+        let reader = StreamReader::new(bytes_stream);
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+
+        loop {
+          stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+          stream_progress.on_cached_input_usage(state_machine.usage_cache_read_input_tokens);
+          let line = match timeouts.next_line(&mut lines).await {
+            Ok(Some(line)) => {
+              if let Some(t) = &transcript {
+                t.on_upstream_line(&line);
+              }
+              line
+            }
+            Ok(None) => break,
+            Err(msg) => {
+              yield Err(msg);
+              return;
+            }
+          };
+          if line.is_empty() {
+            continue;
+          }
+          let Some(data) = line.strip_prefix("data:") else { continue };
+          let data = data.trim_start();
+          data_lines += 1;
+
+          let chunk: GeminiStreamChunk = match serde_json::from_str(data) {
+            Ok(v) => v,
+            Err(_) => continue,
+          };
+          parsed_chunks += 1;
+
+          for chunk in state_machine.on_chunk(&chunk) {
+            stream_progress.on_chunk(&chunk);
+            if let Ok(line) = serde_json::to_string(&chunk) {
+              emitted_chunks += 1;
+              yield Ok(Bytes::from(format!("{line}\n")));
+            }
+          }
+        }
+
+        let has_usage = state_machine.usage_input_tokens.is_some() || state_machine.usage_output_tokens.is_some();
+        if emitted_chunks == 0 && !has_usage && state_machine.thinking_buffer.is_empty() {
+          let msg = format!("❌ 未解析到任何上游 SSE 内容（data_lines={data_lines}, parsed_chunks={parsed_chunks}）；请检查 byok.providers[type=gemini].base_url 是否真的是 Gemini :streamGenerateContent SSE");
+          yield Err(msg);
+          return;
+        }
+
+        stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+        stream_progress.on_cached_input_usage(state_machine.usage_cache_read_input_tokens);
+
+        for chunk in state_machine.finalize() {
+          stream_progress.on_chunk(&chunk);
+          if let Ok(line) = serde_json::to_string(&chunk) {
+            yield Ok(Bytes::from(format!("{line}\n")));
+          }
+        }
+      };
+
+      Ok((Box::pin(stream), progress))
+    }
+    ProviderRef::OpenAIResponses(provider) => {
+      let model = raw_model.trim().to_string();
+      let responses_req = match convert_augment_to_openai_responses(provider, augment, model) {
+        Ok(v) => v,
+        Err(err) => return Err(format!("⚠️ 转换请求失败: {err}")),
+      };
+      if let Some(t) = &transcript {
+        t.on_upstream_request(&responses_req);
+      }
+
+      let url = match join_url(&provider.base_url, "responses") {
+        Ok(u) => u,
+        Err(err) => return Err(format!("⚠️ openai base_url 无效: {err}")),
+      };
+
+      if provider.api_key.is_empty() {
+        return Err(format!(
+          "⚠️ Provider({}) api_key 为空（请填写 byok.providers[].api_key；可用原始 token 或 KEY=VALUE 形式）",
+          provider.id
+        ));
+      }
+
+      let mut req = state
+        .http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "text/event-stream")
+        .json(&responses_req);
+
+      for (k, v) in &provider.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          req = req.header(k, value);
+        }
+      }
+
+      let resp = match send_upstream(
+        &state.upstream,
+        policy,
+        &provider.id,
+        &provider.api_key,
+        &provider.key_pool,
+        req,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
+      {
+        Ok(r) => r,
+        Err(err) => return Err(format!("❌ 上游请求失败: {err}")),
+      };
+      timeouts.headers_received();
+      progress.attach_rate_limit_ticket(&resp);
+
+      if !resp.status().is_success() {
+        let status = resp.status();
+        let body_text = resp.text().await.unwrap_or_default();
+        return Err(format!("❌ 上游返回错误: {status} {body_text}"));
+      }
+
+      let content_type = resp
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+      if dump_body {
+        info!(status=%resp.status(), content_type=%content_type, "上游响应");
+      } else {
+        debug!(status=%resp.status(), content_type=%content_type, "上游响应");
+      }
+      if !content_type
+        .trim()
+        .to_ascii_lowercase()
+        .contains("text/event-stream")
+      {
+        let body_text = resp.text().await.unwrap_or_default();
+        let preview = truncate_for_log(body_text, 1024);
+        return Err(format!(
+          "❌ 上游响应不是 SSE（content-type={content_type}）；请确认 byok.providers[type=openai_responses].base_url 指向 OpenAI Responses API 前缀（例如 https://api.openai.com/v1）；body: {preview}"
+        ));
+      }
+
+      let tool_meta_by_name = tool_meta_by_name.clone();
+      let stream_progress = progress.clone();
+      let stream = stream! {
+        let _permit = permit;
+        let mut state_machine = OpenAIResponsesStreamState {
+          tool_meta_by_name,
+          ..Default::default()
+        };
+        let mut data_lines: usize = 0;
+        let mut parsed_events: usize = 0;
+        let mut emitted_chunks: usize = 0;
+        let bytes_stream = resp.bytes_stream().map(|r| r.map_err(std::io::Error::other));
+        let reader = StreamReader::new(bytes_stream);
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let mut sse_event_type: Option<String> = None;
+
+        loop {
+          stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+          stream_progress.on_cached_input_usage(state_machine.usage_cache_read_input_tokens);
+          let line = match timeouts.next_line(&mut lines).await {
+            Ok(Some(line)) => {
+              if let Some(t) = &transcript {
+                t.on_upstream_line(&line);
+              }
+              line
+            }
+            Ok(None) => break,
+            Err(msg) => {
+              yield Err(msg);
+              return;
+            }
+          };
+          if line.is_empty() {
+            sse_event_type = None;
+            continue;
+          }
+          if let Some(t) = line.strip_prefix("event:") {
+            sse_event_type = Some(t.trim().to_string());
+            continue;
+          }
+          let Some(data) = line.strip_prefix("data:") else { continue };
+          let data = data.trim_start();
+          data_lines += 1;
+          if data == "[DONE]" {
+            break;
+          }
+
+          let mut event: OpenAIResponsesStreamEvent = match serde_json::from_str(data) {
+            Ok(v) => v,
+            Err(_) => continue,
+          };
+          parsed_events += 1;
+          if event.event_type.is_empty() {
+            if let Some(t) = &sse_event_type {
+              event.event_type = t.clone();
+            }
+          }
+
+          for chunk in state_machine.on_event(event) {
+            stream_progress.on_chunk(&chunk);
+            if let Ok(line) = serde_json::to_string(&chunk) {
+              emitted_chunks += 1;
+              yield Ok(Bytes::from(format!("{line}\n")));
+            }
+          }
+          if state_machine.error_message.is_some() {
+            break;
+          }
+        }
+
+        if let Some(msg) = state_machine.error_message.take() {
+          yield Err(format!("❌ 上游返回错误: {msg}"));
+          return;
+        }
+
+        let has_usage = state_machine.usage_input_tokens.is_some() || state_machine.usage_output_tokens.is_some();
+        if emitted_chunks == 0 && !has_usage && state_machine.function_calls.is_empty() {
+          let msg = format!("❌ 未解析到任何上游 SSE 内容（data_lines={data_lines}, parsed_events={parsed_events}）；请检查 byok.providers[type=openai_responses].base_url 是否真的是 OpenAI /responses SSE");
+          yield Err(msg);
+          return;
+        }
+
+        stream_progress.on_usage(state_machine.usage_input_tokens, state_machine.usage_output_tokens);
+        stream_progress.on_cached_input_usage(state_machine.usage_cache_read_input_tokens);
+
+        for chunk in state_machine.finalize() {
+          stream_progress.on_chunk(&chunk);
+          if let Ok(line) = serde_json::to_string(&chunk) {
+            yield Ok(Bytes::from(format!("{line}\n")));
+          }
+        }
+      };
+
+      Ok((Box::pin(stream), progress))
     }
   }
 }
@@ -935,9 +1896,11 @@ fn read_model_from_body(body: &serde_json::Value) -> Option<String> {
 fn pick_provider_and_model_for_simple<'a>(
   cfg: &'a Config,
   headers: &HeaderMap,
+  route: &ByokRoute,
   body: &serde_json::Value,
 ) -> anyhow::Result<(ProviderRef<'a>, String)> {
   let requested_model = read_byok_model_override(headers)
+    .or_else(|| route.model.clone())
     .or_else(|| read_model_from_body(body))
     .unwrap_or_default();
 
@@ -957,25 +1920,57 @@ fn pick_provider_and_model_for_simple<'a>(
     }
   };
 
-  let model = match provider {
-    ProviderRef::Anthropic(_) => clean_model(&raw_model),
-    ProviderRef::OpenAICompatible(_) => raw_model.trim().to_string(),
-  };
+  let model = upstream_model_name(provider, &raw_model);
+  metrics::label_request_target(provider.id(), &model);
+  Ok((provider, model))
+}
+
+// 非 chat-stream 端点与 chat-stream 使用同一套预算规则（block / fallback）
+fn apply_simple_budget<'a>(
+  state: &AppState,
+  cfg: &'a Config,
+  provider: ProviderRef<'a>,
+  model: String,
+) -> Result<(ProviderRef<'a>, String), String> {
+  let (provider, raw_model) = apply_budget(state, cfg, provider, model)?;
+  let model = upstream_model_name(provider, &raw_model);
+  metrics::label_request_target(provider.id(), &model);
   Ok((provider, model))
 }
 
 async fn provider_complete_text(
   state: &AppState,
+  endpoint: &str,
   provider: ProviderRef<'_>,
   model: &str,
   system: &str,
   user: &str,
 ) -> anyhow::Result<String> {
+  let (max_concurrent, queue_timeout) = provider.concurrency_limit();
+  let _permit = state
+    .upstream
+    .concurrency
+    .acquire(
+      provider.id(),
+      max_concurrent,
+      queue_timeout,
+      RequestPriority::Interactive,
+    )
+    .await?;
+  let policy = state
+    .upstream_policy()
+    .await
+    .with_estimated_input_tokens(u64::from(approx_token_count_from_byte_len(
+      system.len() + user.len(),
+    )));
+  let mut call = UpstreamCall::start(
+    state.usage_ledger().await,
+    UsageRecord::new(endpoint, "", provider.id(), model),
+  );
   match provider {
     ProviderRef::Anthropic(p) => {
       let url = join_url(&p.base_url, "messages").context("构建 Anthropic messages URL 失败")?;
-      let key = normalize_raw_token(&p.api_key);
-      if key.is_empty() {
+      if p.api_key.is_empty() {
         anyhow::bail!("Provider({}) api_key 为空", p.id);
       }
 
@@ -1008,7 +2003,6 @@ async fn provider_complete_text(
         .header("content-type", "application/json")
         .header("accept", "application/json")
         .header("anthropic-version", "2023-06-01")
-        .header("x-api-key", key)
         .timeout(Duration::from_secs(p.timeout_seconds))
         .json(&payload);
 
@@ -1018,7 +2012,18 @@ async fn provider_complete_text(
         }
       }
 
-      let resp = req.send().await.context("请求 Anthropic /messages 失败")?;
+      let resp = send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("x-api-key", k),
+      )
+      .await
+      .context("请求 Anthropic /messages 失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
       let status = resp.status();
       let text = resp.text().await.unwrap_or_default();
       if !status.is_success() {
@@ -1026,6 +2031,9 @@ async fn provider_complete_text(
       }
       let json: serde_json::Value =
         serde_json::from_str(&text).context("Anthropic /messages 响应不是 JSON")?;
+      if let Ok(usage) = serde_json::from_value::<AnthropicUsage>(json["usage"].clone()) {
+        call.progress.on_anthropic_usage(&usage);
+      }
       let mut out = String::new();
       if let Some(arr) = json.get("content").and_then(|v| v.as_array()) {
         for b in arr {
@@ -1037,13 +2045,13 @@ async fn provider_complete_text(
           }
         }
       }
+      call.finish();
       Ok(out.trim().to_string())
     }
     ProviderRef::OpenAICompatible(p) => {
       let url = join_url(&p.base_url, "chat/completions")
         .context("构建 OpenAI chat/completions URL 失败")?;
-      let key = normalize_raw_token(&p.api_key);
-      if key.is_empty() {
+      if p.api_key.is_empty() {
         anyhow::bail!("Provider({}) api_key 为空", p.id);
       }
 
@@ -1065,7 +2073,6 @@ async fn provider_complete_text(
         .post(url)
         .header("content-type", "application/json")
         .header("accept", "application/json")
-        .header("authorization", format!("Bearer {key}"))
         .timeout(Duration::from_secs(p.timeout_seconds))
         .json(&payload);
 
@@ -1075,10 +2082,18 @@ async fn provider_complete_text(
         }
       }
 
-      let resp = req
-        .send()
-        .await
-        .context("请求 OpenAI /chat/completions 失败")?;
+      let resp = send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
+      .context("请求 OpenAI /chat/completions 失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
       let status = resp.status();
       let text = resp.text().await.unwrap_or_default();
       if !status.is_success() {
@@ -1086,6 +2101,9 @@ async fn provider_complete_text(
       }
       let json: serde_json::Value =
         serde_json::from_str(&text).context("OpenAI /chat/completions 响应不是 JSON")?;
+      if let Ok(usage) = serde_json::from_value::<OpenAIUsage>(json["usage"].clone()) {
+        call.progress.on_openai_usage(&usage);
+      }
       let content = json
         .get("choices")
         .and_then(|v| v.as_array())
@@ -1096,9 +2114,167 @@ async fn provider_complete_text(
         .unwrap_or("")
         .trim()
         .to_string();
+      call.finish();
       Ok(content)
     }
+    ProviderRef::Gemini(p) => {
+      let url = join_url(&p.base_url, &format!("models/{model}:generateContent"))
+        .context("构建 Gemini generateContent URL 失败")?;
+      if p.api_key.is_empty() {
+        anyhow::bail!("Provider({}) api_key 为空", p.id);
+      }
+
+      let payload = build_gemini_simple_payload(p, system, user);
+
+      let mut req = state
+        .http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "application/json")
+        .timeout(Duration::from_secs(p.timeout_seconds))
+        .json(&payload);
+
+      for (k, v) in &p.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          req = req.header(k, value);
+        }
+      }
+
+      let resp = send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("x-goog-api-key", k),
+      )
+      .await
+      .context("请求 Gemini :generateContent 失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
+      let status = resp.status();
+      let text = resp.text().await.unwrap_or_default();
+      if !status.is_success() {
+        anyhow::bail!("Gemini :generateContent 返回错误: {status} {text}");
+      }
+      let chunk: GeminiStreamChunk =
+        serde_json::from_str(&text).context("Gemini :generateContent 响应不是 JSON")?;
+      if let Some(usage) = &chunk.usage_metadata {
+        call.progress.on_gemini_usage(usage);
+      }
+      call.finish();
+      Ok(chunk.text().trim().to_string())
+    }
+    ProviderRef::OpenAIResponses(p) => {
+      let url = join_url(&p.base_url, "responses").context("构建 OpenAI responses URL 失败")?;
+      if p.api_key.is_empty() {
+        anyhow::bail!("Provider({}) api_key 为空", p.id);
+      }
+
+      let payload = build_openai_responses_simple_payload(p, model, system, user, false);
+
+      let mut req = state
+        .http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "application/json")
+        .timeout(Duration::from_secs(p.timeout_seconds))
+        .json(&payload);
+
+      for (k, v) in &p.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          req = req.header(k, value);
+        }
+      }
+
+      let resp = send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
+      .context("请求 OpenAI /responses 失败")?;
+      call.progress.attach_rate_limit_ticket(&resp);
+      let status = resp.status();
+      let text = resp.text().await.unwrap_or_default();
+      if !status.is_success() {
+        anyhow::bail!("OpenAI /responses 返回错误: {status} {text}");
+      }
+      let body: OpenAIResponsesResponse =
+        serde_json::from_str(&text).context("OpenAI /responses 响应不是 JSON")?;
+      if let Some(usage) = &body.usage {
+        call.progress.on_openai_responses_usage(usage);
+      }
+      call.finish();
+      Ok(body.output_text().trim().to_string())
+    }
+  }
+}
+
+fn build_gemini_simple_payload(
+  p: &GeminiProviderConfig,
+  system: &str,
+  user: &str,
+) -> serde_json::Value {
+  let mut payload = serde_json::json!({
+    "contents": [{ "role": "user", "parts": [{ "text": user }] }],
+    "generationConfig": { "maxOutputTokens": p.max_tokens }
+  });
+  if !system.trim().is_empty() {
+    if let Some(obj) = payload.as_object_mut() {
+      obj.insert(
+        "systemInstruction".to_string(),
+        serde_json::json!({ "parts": [{ "text": system.trim() }] }),
+      );
+    }
+  }
+  if p.thinking.enabled {
+    if let Some(gen) = payload
+      .get_mut("generationConfig")
+      .and_then(|v| v.as_object_mut())
+    {
+      gen.insert(
+        "thinkingConfig".to_string(),
+        serde_json::json!({ "thinkingBudget": p.thinking.budget_tokens }),
+      );
+    }
+  }
+  payload
+}
+
+fn build_openai_responses_simple_payload(
+  p: &OpenAIResponsesProviderConfig,
+  model: &str,
+  system: &str,
+  user: &str,
+  stream: bool,
+) -> serde_json::Value {
+  let mut payload = serde_json::json!({
+    "model": model,
+    "stream": stream,
+    "store": false,
+    "max_output_tokens": p.max_tokens,
+    "input": [{ "type": "message", "role": "user", "content": user }]
+  });
+  if let Some(obj) = payload.as_object_mut() {
+    if !system.trim().is_empty() {
+      obj.insert(
+        "instructions".to_string(),
+        serde_json::Value::String(system.trim().to_string()),
+      );
+    }
+    if p.reasoning.enabled && !p.reasoning.effort.trim().is_empty() {
+      obj.insert(
+        "reasoning".to_string(),
+        serde_json::json!({ "effort": p.reasoning.effort.trim() }),
+      );
+    }
   }
+  payload
 }
 
 async fn byok_text_stream_endpoint(
@@ -1114,7 +2290,8 @@ async fn byok_text_stream_endpoint(
     *resp.status_mut() = StatusCode::UNAUTHORIZED;
     return resp;
   }
-  let mode = read_byok_mode(&headers);
+  let route = resolve_byok_route(&cfg, endpoint_path, &headers, &body);
+  let mode = route.mode;
   if mode == ByokMode::Disabled {
     let mut resp = Response::new(Body::from("Disabled by routing rule"));
     *resp.status_mut() = StatusCode::NOT_FOUND;
@@ -1148,7 +2325,7 @@ async fn byok_text_stream_endpoint(
     return resp;
   }
 
-  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &value) {
+  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &route, &value) {
     Ok(v) => v,
     Err(err) => {
       let mut resp = Response::new(Body::from(format!("Bad request: {err}")));
@@ -1156,10 +2333,49 @@ async fn byok_text_stream_endpoint(
       return resp;
     }
   };
+  let (provider, model) = match apply_simple_budget(&state, &cfg, provider, model) {
+    Ok(v) => v,
+    Err(msg) => {
+      let mut resp = Response::new(Body::from(msg));
+      *resp.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+      return resp;
+    }
+  };
 
   let system = build_system_text(&value);
   let user = build_user_text(&value);
 
+  let (max_concurrent, queue_timeout) = provider.concurrency_limit();
+  let priority = match endpoint_path {
+    "/generate-conversation-title" | "/generate-commit-message-stream" => {
+      RequestPriority::Background
+    }
+    _ => RequestPriority::Interactive,
+  };
+  let permit = match state
+    .upstream
+    .concurrency
+    .acquire(provider.id(), max_concurrent, queue_timeout, priority)
+    .await
+  {
+    Ok(p) => p,
+    Err(err) => {
+      let mut resp = Response::new(Body::from(format!("{err}")));
+      *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+      return resp;
+    }
+  };
+
+  let mut timeouts = provider.stream_timeouts();
+  let policy = UpstreamPolicy::from_config(&cfg)
+    .with_estimated_input_tokens(u64::from(approx_token_count_from_byte_len(
+      system.len() + user.len(),
+    )))
+    .with_header_timeout(timeouts.first_byte());
+  let mut call = UpstreamCall::start(
+    cfg.usage.enabled.then(|| state.usage.clone()),
+    UsageRecord::new(endpoint_path, "", provider.id(), &model),
+  );
   let resp = match provider {
     ProviderRef::Anthropic(p) => {
       let url = match join_url(&p.base_url, "messages") {
@@ -1170,8 +2386,7 @@ async fn byok_text_stream_endpoint(
           return resp;
         }
       };
-      let key = normalize_raw_token(&p.api_key);
-      if key.is_empty() {
+      if p.api_key.is_empty() {
         let mut resp = Response::new(Body::from(format!("Provider({}) api_key 为空", p.id)));
         *resp.status_mut() = StatusCode::BAD_REQUEST;
         return resp;
@@ -1206,8 +2421,6 @@ async fn byok_text_stream_endpoint(
         .header("content-type", "application/json")
         .header("accept", "text/event-stream")
         .header("anthropic-version", "2023-06-01")
-        .header("x-api-key", key)
-        .timeout(Duration::from_secs(p.timeout_seconds))
         .json(&payload);
 
       for (k, v) in &p.extra_headers {
@@ -1215,7 +2428,16 @@ async fn byok_text_stream_endpoint(
           req = req.header(k, value);
         }
       }
-      req.send().await
+      send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("x-api-key", k),
+      )
+      .await
     }
     ProviderRef::OpenAICompatible(p) => {
       let url = match join_url(&p.base_url, "chat/completions") {
@@ -1226,8 +2448,7 @@ async fn byok_text_stream_endpoint(
           return resp;
         }
       };
-      let key = normalize_raw_token(&p.api_key);
-      if key.is_empty() {
+      if p.api_key.is_empty() {
         let mut resp = Response::new(Body::from(format!("Provider({}) api_key 为空", p.id)));
         *resp.status_mut() = StatusCode::BAD_REQUEST;
         return resp;
@@ -1242,6 +2463,7 @@ async fn byok_text_stream_endpoint(
       let payload = serde_json::json!({
         "model": model,
         "stream": true,
+        "stream_options": { "include_usage": true },
         "max_tokens": p.max_tokens,
         "messages": messages
       });
@@ -1251,8 +2473,6 @@ async fn byok_text_stream_endpoint(
         .post(url)
         .header("content-type", "application/json")
         .header("accept", "text/event-stream")
-        .header("authorization", format!("Bearer {key}"))
-        .timeout(Duration::from_secs(p.timeout_seconds))
         .json(&payload);
 
       for (k, v) in &p.extra_headers {
@@ -1260,7 +2480,99 @@ async fn byok_text_stream_endpoint(
           req = req.header(k, value);
         }
       }
-      req.send().await
+      send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
+    }
+    ProviderRef::Gemini(p) => {
+      let url = match join_url(
+        &p.base_url,
+        &format!("models/{model}:streamGenerateContent?alt=sse"),
+      ) {
+        Ok(u) => u,
+        Err(err) => {
+          let mut resp = Response::new(Body::from(format!("Bad request: {err}")));
+          *resp.status_mut() = StatusCode::BAD_REQUEST;
+          return resp;
+        }
+      };
+      if p.api_key.is_empty() {
+        let mut resp = Response::new(Body::from(format!("Provider({}) api_key 为空", p.id)));
+        *resp.status_mut() = StatusCode::BAD_REQUEST;
+        return resp;
+      }
+
+      let payload = build_gemini_simple_payload(p, &system, &user);
+
+      let mut req = state
+        .http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "text/event-stream")
+        .json(&payload);
+
+      for (k, v) in &p.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          req = req.header(k, value);
+        }
+      }
+      send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("x-goog-api-key", k),
+      )
+      .await
+    }
+    ProviderRef::OpenAIResponses(p) => {
+      let url = match join_url(&p.base_url, "responses") {
+        Ok(u) => u,
+        Err(err) => {
+          let mut resp = Response::new(Body::from(format!("Bad request: {err}")));
+          *resp.status_mut() = StatusCode::BAD_REQUEST;
+          return resp;
+        }
+      };
+      if p.api_key.is_empty() {
+        let mut resp = Response::new(Body::from(format!("Provider({}) api_key 为空", p.id)));
+        *resp.status_mut() = StatusCode::BAD_REQUEST;
+        return resp;
+      }
+
+      let payload = build_openai_responses_simple_payload(p, &model, &system, &user, true);
+
+      let mut req = state
+        .http
+        .post(url)
+        .header("content-type", "application/json")
+        .header("accept", "text/event-stream")
+        .json(&payload);
+
+      for (k, v) in &p.extra_headers {
+        if let Ok(value) = HeaderValue::from_str(v) {
+          req = req.header(k, value);
+        }
+      }
+      send_upstream(
+        &state.upstream,
+        &policy,
+        &p.id,
+        &p.api_key,
+        &p.key_pool,
+        req,
+        |r, k| r.header("authorization", format!("Bearer {k}")),
+      )
+      .await
     }
   };
 
@@ -1272,6 +2584,8 @@ async fn byok_text_stream_endpoint(
       return resp;
     }
   };
+  timeouts.headers_received();
+  call.progress.attach_rate_limit_ticket(&resp);
 
   if !resp.status().is_success() {
     let status = resp.status();
@@ -1281,13 +2595,28 @@ async fn byok_text_stream_endpoint(
     return out;
   }
 
+  call.streaming();
+  let kind = provider_kind(provider);
   let stream = stream! {
+    let _permit = permit;
+    // 流结束或客户端断开时随 call 一起释放：按已收到的 usage 校正限速令牌桶并写入账本
+    let mut call = call;
     let bytes_stream = resp.bytes_stream().map(|r| r.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)));
     let reader = StreamReader::new(bytes_stream);
     let mut lines = tokio::io::BufReader::new(reader).lines();
 
     let mut anthropic_event_type: Option<String> = None;
-    while let Ok(Some(line)) = lines.next_line().await {
+    loop {
+      let line = match timeouts.next_line(&mut lines).await {
+        Ok(Some(line)) => line,
+        Ok(None) => break,
+        Err(msg) => {
+          call.fail();
+          let raw = serde_json::json!({ "text": format!("\n{msg}") });
+          yield Ok::<Bytes, Infallible>(Bytes::from(format!("{raw}\n")));
+          return;
+        }
+      };
       if line.is_empty() {
         anthropic_event_type = None;
         continue;
@@ -1303,24 +2632,51 @@ async fn byok_text_stream_endpoint(
       }
 
       let mut text_delta: Option<String> = None;
-      if let Ok(mut ev) = serde_json::from_str::<AnthropicStreamEvent>(data) {
-        if ev.event_type.is_empty() {
-          if let Some(t) = &anthropic_event_type {
-            ev.event_type = t.clone();
+      if kind == "gemini" {
+        if let Ok(chunk) = serde_json::from_str::<GeminiStreamChunk>(data) {
+          if let Some(usage) = &chunk.usage_metadata {
+            call.progress.on_gemini_usage(usage);
+          }
+          let t = chunk.text();
+          if !t.is_empty() {
+            text_delta = Some(t);
+          }
+        }
+      } else if kind == "openai_responses" {
+        if let Ok(ev) = serde_json::from_str::<OpenAIResponsesStreamEvent>(data) {
+          if let Some(usage) = ev.response.as_ref().and_then(|r| r.usage.as_ref()) {
+            call.progress.on_openai_responses_usage(usage);
+          }
+          if ev.event_type == "response.output_text.delta" {
+            text_delta = ev.delta.filter(|t| !t.is_empty());
           }
         }
-        if ev.event_type == "content_block_delta" {
-          if let Some(delta) = ev.delta {
-            if delta.delta_type == "text_delta" {
-              if let Some(t) = delta.text {
-                if !t.is_empty() {
-                  text_delta = Some(t);
+      } else if kind == "anthropic" {
+        if let Ok(mut ev) = serde_json::from_str::<AnthropicStreamEvent>(data) {
+          if ev.event_type.is_empty() {
+            if let Some(t) = &anthropic_event_type {
+              ev.event_type = t.clone();
+            }
+          }
+          if let Some(usage) = ev.message.as_ref().map(|m| &m.usage).or(ev.usage.as_ref()) {
+            call.progress.on_anthropic_usage(usage);
+          }
+          if ev.event_type == "content_block_delta" {
+            if let Some(delta) = ev.delta {
+              if delta.delta_type == "text_delta" {
+                if let Some(t) = delta.text {
+                  if !t.is_empty() {
+                    text_delta = Some(t);
+                  }
                 }
               }
             }
           }
         }
       } else if let Ok(chunk) = serde_json::from_str::<OpenAIChatCompletionChunk>(data) {
+        if let Some(usage) = &chunk.usage {
+          call.progress.on_openai_usage(usage);
+        }
         for c in chunk.choices {
           if let Some(t) = c.delta.content {
             if !t.is_empty() {
@@ -1341,6 +2697,7 @@ async fn byok_text_stream_endpoint(
         yield Ok::<Bytes, Infallible>(Bytes::from(format!("{line}\n")));
       }
     }
+    call.finish();
   };
 
   let mut response = Response::new(Body::from_stream(stream));
@@ -1353,7 +2710,8 @@ async fn byok_text_stream_endpoint(
 
 async fn chat(State(state): State<AppState>, headers: HeaderMap, body: Bytes) -> Response<Body> {
   let cfg = state.cfg.read().await.clone();
-  let mode = read_byok_mode(&headers);
+  let route = resolve_byok_route(&cfg, "/chat", &headers, &body);
+  let mode = route.mode;
   if !is_authorized(&headers, &cfg.proxy.auth_token) {
     return (
       StatusCode::UNAUTHORIZED,
@@ -1399,7 +2757,7 @@ async fn chat(State(state): State<AppState>, headers: HeaderMap, body: Bytes) ->
     )
       .into_response();
   }
-  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &value) {
+  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &route, &value) {
     Ok(v) => v,
     Err(err) => {
       return (
@@ -1409,9 +2767,19 @@ async fn chat(State(state): State<AppState>, headers: HeaderMap, body: Bytes) ->
         .into_response()
     }
   };
+  let (provider, model) = match apply_simple_budget(&state, &cfg, provider, model) {
+    Ok(v) => v,
+    Err(msg) => {
+      return (
+        StatusCode::TOO_MANY_REQUESTS,
+        axum::Json(serde_json::json!({ "ok": false, "error": msg })),
+      )
+        .into_response()
+    }
+  };
   let system = build_system_text(&value);
   let user = build_user_text(&value);
-  let text = match provider_complete_text(&state, provider, &model, &system, &user).await {
+  let text = match provider_complete_text(&state, "/chat", provider, &model, &system, &user).await {
     Ok(v) => v,
     Err(err) => {
       return (
@@ -1430,7 +2798,8 @@ async fn completion(
   body: Bytes,
 ) -> Response<Body> {
   let cfg = state.cfg.read().await.clone();
-  let mode = read_byok_mode(&headers);
+  let route = resolve_byok_route(&cfg, "/completion", &headers, &body);
+  let mode = route.mode;
   if !is_authorized(&headers, &cfg.proxy.auth_token) {
     return (
       StatusCode::UNAUTHORIZED,
@@ -1476,7 +2845,7 @@ async fn completion(
     )
       .into_response();
   }
-  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &value) {
+  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &route, &value) {
     Ok(v) => v,
     Err(err) => {
       return (
@@ -1486,18 +2855,29 @@ async fn completion(
         .into_response()
     }
   };
-  let system = build_system_text(&value);
-  let user = build_user_text(&value);
-  let text = match provider_complete_text(&state, provider, &model, &system, &user).await {
+  let (provider, model) = match apply_simple_budget(&state, &cfg, provider, model) {
     Ok(v) => v,
-    Err(err) => {
+    Err(msg) => {
       return (
-        StatusCode::BAD_GATEWAY,
-        axum::Json(serde_json::json!({ "ok": false, "error": format!("{err}") })),
+        StatusCode::TOO_MANY_REQUESTS,
+        axum::Json(serde_json::json!({ "ok": false, "error": msg })),
       )
         .into_response()
     }
   };
+  let system = build_system_text(&value);
+  let user = build_user_text(&value);
+  let text =
+    match provider_complete_text(&state, "/completion", provider, &model, &system, &user).await {
+      Ok(v) => v,
+      Err(err) => {
+        return (
+          StatusCode::BAD_GATEWAY,
+          axum::Json(serde_json::json!({ "ok": false, "error": format!("{err}") })),
+        )
+          .into_response()
+      }
+    };
   let out = serde_json::json!({
     "completion_items": [{ "text": text, "suffix_replacement_text": "", "skipped_suffix": "" }],
     "unknown_blob_names": [],
@@ -1515,7 +2895,8 @@ async fn chat_input_completion(
   body: Bytes,
 ) -> Response<Body> {
   let cfg = state.cfg.read().await.clone();
-  let mode = read_byok_mode(&headers);
+  let route = resolve_byok_route(&cfg, "/chat-input-completion", &headers, &body);
+  let mode = route.mode;
   if !is_authorized(&headers, &cfg.proxy.auth_token) {
     return (
       StatusCode::UNAUTHORIZED,
@@ -1561,7 +2942,7 @@ async fn chat_input_completion(
     )
       .into_response();
   }
-  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &value) {
+  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &route, &value) {
     Ok(v) => v,
     Err(err) => {
       return (
@@ -1571,9 +2952,28 @@ async fn chat_input_completion(
         .into_response()
     }
   };
+  let (provider, model) = match apply_simple_budget(&state, &cfg, provider, model) {
+    Ok(v) => v,
+    Err(msg) => {
+      return (
+        StatusCode::TOO_MANY_REQUESTS,
+        axum::Json(serde_json::json!({ "ok": false, "error": msg })),
+      )
+        .into_response()
+    }
+  };
   let system = build_system_text(&value);
   let user = build_user_text(&value);
-  let text = match provider_complete_text(&state, provider, &model, &system, &user).await {
+  let text = match provider_complete_text(
+    &state,
+    "/chat-input-completion",
+    provider,
+    &model,
+    &system,
+    &user,
+  )
+  .await
+  {
     Ok(v) => v,
     Err(err) => {
       return (
@@ -1596,7 +2996,8 @@ async fn chat_input_completion(
 
 async fn edit(State(state): State<AppState>, headers: HeaderMap, body: Bytes) -> Response<Body> {
   let cfg = state.cfg.read().await.clone();
-  let mode = read_byok_mode(&headers);
+  let route = resolve_byok_route(&cfg, "/edit", &headers, &body);
+  let mode = route.mode;
   if !is_authorized(&headers, &cfg.proxy.auth_token) {
     return (
       StatusCode::UNAUTHORIZED,
@@ -1642,7 +3043,7 @@ async fn edit(State(state): State<AppState>, headers: HeaderMap, body: Bytes) ->
     )
       .into_response();
   }
-  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &value) {
+  let (provider, model) = match pick_provider_and_model_for_simple(&cfg, &headers, &route, &value) {
     Ok(v) => v,
     Err(err) => {
       return (
@@ -1652,9 +3053,19 @@ async fn edit(State(state): State<AppState>, headers: HeaderMap, body: Bytes) ->
         .into_response()
     }
   };
+  let (provider, model) = match apply_simple_budget(&state, &cfg, provider, model) {
+    Ok(v) => v,
+    Err(msg) => {
+      return (
+        StatusCode::TOO_MANY_REQUESTS,
+        axum::Json(serde_json::json!({ "ok": false, "error": msg })),
+      )
+        .into_response()
+    }
+  };
   let system = build_system_text(&value);
   let user = build_user_text(&value);
-  let text = match provider_complete_text(&state, provider, &model, &system, &user).await {
+  let text = match provider_complete_text(&state, "/edit", provider, &model, &system, &user).await {
     Ok(v) => v,
     Err(err) => {
       return (
@@ -1736,7 +3147,8 @@ async fn get_models(
   body: Bytes,
 ) -> Response<Body> {
   let cfg = state.cfg.read().await.clone();
-  let mode = read_byok_mode(&headers);
+  let route = resolve_byok_route(&cfg, "/get-models", &headers, &body);
+  let mode = route.mode;
   if !is_authorized(&headers, &cfg.proxy.auth_token) {
     let present = auth_present_headers(&headers);
     warn!(present=?present, "get-models 未授权（缺少或错误的鉴权 token）");
@@ -1885,6 +3297,8 @@ async fn get_models(
     let p = match p {
       ProviderConfig::Anthropic(p) => ProviderRef::Anthropic(p),
       ProviderConfig::OpenAICompatible(p) => ProviderRef::OpenAICompatible(p),
+      ProviderConfig::Gemini(p) => ProviderRef::Gemini(p),
+      ProviderConfig::OpenAIResponses(p) => ProviderRef::OpenAIResponses(p),
     };
     if p.id().trim() == active_id {
       continue;
@@ -1920,9 +3334,14 @@ async fn get_models(
         display_name.clone(),
         serde_json::Value::String(byok_id.clone()),
       );
+      let exhausted = exceeded_budget(&cfg.usage, &state.usage, p.id(), model_id);
+      let description = exhausted
+        .as_ref()
+        .map(|hit| hit.message())
+        .unwrap_or_default();
       info_registry.insert(
         byok_id.clone(),
-        serde_json::json!({ "description": "", "disabled": false, "displayName": display_name, "shortName": display_name }),
+        serde_json::json!({ "description": description, "disabled": exhausted.is_some(), "displayName": display_name, "shortName": display_name }),
       );
       models.push(serde_json::json!({ "name": byok_id, "suggested_prefix_char_count": 0, "suggested_suffix_char_count": 0 }));
     }
@@ -2037,6 +3456,7 @@ async fn forward_to_official(
   body_bytes: Bytes,
   timeout: Duration,
 ) -> Response<Body> {
+  metrics::label_request_mode("official");
   let url = match build_official_url(&cfg.official.base_url, uri) {
     Ok(u) => u,
     Err(err) => {
@@ -2095,7 +3515,6 @@ async fn forward_to_official(
 async fn proxy_fallback(State(state): State<AppState>, req: Request<Body>) -> Response<Body> {
   let (parts, body) = req.into_parts();
   let cfg = state.cfg.read().await.clone();
-  let mode = read_byok_mode(&parts.headers);
 
   if !is_authorized(&parts.headers, &cfg.proxy.auth_token) {
     let present = auth_present_headers(&parts.headers);
@@ -2105,6 +3524,16 @@ async fn proxy_fallback(State(state): State<AppState>, req: Request<Body>) -> Re
     return resp;
   }
 
+  let body_bytes = match to_bytes(body, 16 * 1024 * 1024).await {
+    Ok(b) => b,
+    Err(err) => {
+      let mut resp = Response::new(Body::from(format!("Bad request body: {err}")));
+      *resp.status_mut() = StatusCode::BAD_REQUEST;
+      return resp;
+    }
+  };
+  let mode = resolve_byok_route(&cfg, parts.uri.path(), &parts.headers, &body_bytes).mode;
+
   if mode == ByokMode::Disabled {
     let mut resp = Response::new(Body::from("Disabled by routing rule"));
     *resp.status_mut() = StatusCode::NOT_FOUND;
@@ -2116,14 +3545,6 @@ async fn proxy_fallback(State(state): State<AppState>, req: Request<Body>) -> Re
     return resp;
   }
 
-  let body_bytes = match to_bytes(body, 16 * 1024 * 1024).await {
-    Ok(b) => b,
-    Err(err) => {
-      let mut resp = Response::new(Body::from(format!("Bad request body: {err}")));
-      *resp.status_mut() = StatusCode::BAD_REQUEST;
-      return resp;
-    }
-  };
   maybe_delete_history_summary_cache_on_thread_delete(&state, &parts.uri, &body_bytes).await;
   forward_to_official(
     &state,
@@ -2218,6 +3639,14 @@ fn convert_event_to_chunks(
           state.on_tool_use_block_start(&id, &name);
         }
         "thinking" => state.on_thinking_block_start(),
+        "redacted_thinking" => {
+          return block
+            .data
+            .as_deref()
+            .and_then(|d| state.on_redacted_thinking_block(d))
+            .into_iter()
+            .collect();
+        }
         _ => {}
       }
       Vec::new()
@@ -2244,6 +3673,12 @@ fn convert_event_to_chunks(
           }
           Vec::new()
         }
+        "signature_delta" => {
+          if let Some(signature) = delta.signature.as_deref() {
+            state.on_signature_delta(signature);
+          }
+          Vec::new()
+        }
         _ => Vec::new(),
       }
     }
@@ -2272,9 +3707,7 @@ fn convert_event_to_chunks(
 }
 
 fn ndjson_response(chunk: AugmentStreamChunk) -> Response<Body> {
-  let line = serde_json::to_string(&chunk)
-    .unwrap_or_else(|_| "{\"text\":\"\",\"stop_reason\":1}".to_string());
-  let mut response = Response::new(Body::from(format!("{line}\n")));
+  let mut response = Response::new(Body::from(ndjson_line(&chunk)));
   let headers = response.headers_mut();
   headers.insert(
     "content-type",
@@ -2404,6 +3837,39 @@ fn is_authorized(headers: &HeaderMap, expected_token: &str) -> bool {
   false
 }
 
+struct ByokRoute {
+  mode: ByokMode,
+  model: Option<String>,
+}
+
+// x-byok-mode header 优先；header 缺省时才看 routing.rules（规则里的 byok:<providerId>:<modelId> 作为默认 model）
+fn resolve_byok_route(cfg: &Config, path: &str, headers: &HeaderMap, body: &[u8]) -> ByokRoute {
+  let rule = routing::match_rule(&cfg.routing, path, body);
+  let (rule_mode, rule_model) = match rule.and_then(RoutingRule::route_target) {
+    Some(RouteTarget::Byok(model)) => (ByokMode::Byok, model),
+    Some(RouteTarget::Official) => (ByokMode::Official, None),
+    Some(RouteTarget::Disabled) => (ByokMode::Disabled, None),
+    None => (ByokMode::Default, None),
+  };
+  if let Some(rule) = rule {
+    debug!(path=%path, rule_path=%rule.path, target=%rule.target, "命中 routing.rules");
+  }
+  let mode = match read_byok_mode(headers) {
+    ByokMode::Default => rule_mode,
+    header_mode => header_mode,
+  };
+  metrics::label_request_mode(match mode {
+    ByokMode::Byok => "byok",
+    ByokMode::Official => "official",
+    ByokMode::Disabled => "disabled",
+    ByokMode::Default => "default",
+  });
+  ByokRoute {
+    mode,
+    model: rule_model.filter(|_| mode == ByokMode::Byok),
+  }
+}
+
 fn read_byok_mode(headers: &HeaderMap) -> ByokMode {
   let raw = headers
     .get("x-byok-mode")
@@ -2458,6 +3924,8 @@ fn pick_active_provider(cfg: &Config) -> anyhow::Result<ProviderRef<'_>> {
   match cfg.byok.providers.first().expect("providers not empty") {
     ProviderConfig::Anthropic(p) => Ok(ProviderRef::Anthropic(p)),
     ProviderConfig::OpenAICompatible(p) => Ok(ProviderRef::OpenAICompatible(p)),
+    ProviderConfig::Gemini(p) => Ok(ProviderRef::Gemini(p)),
+    ProviderConfig::OpenAIResponses(p) => Ok(ProviderRef::OpenAIResponses(p)),
   }
 }
 
@@ -2472,6 +3940,10 @@ fn get_provider_by_id<'a>(cfg: &'a Config, provider_id: &str) -> anyhow::Result<
       ProviderConfig::OpenAICompatible(p) if p.id.trim() == pid => {
         return Ok(ProviderRef::OpenAICompatible(p))
       }
+      ProviderConfig::Gemini(p) if p.id.trim() == pid => return Ok(ProviderRef::Gemini(p)),
+      ProviderConfig::OpenAIResponses(p) if p.id.trim() == pid => {
+        return Ok(ProviderRef::OpenAIResponses(p))
+      }
       _ => {}
     }
   }
@@ -2535,6 +4007,8 @@ fn provider_kind(provider: ProviderRef<'_>) -> &'static str {
   match provider {
     ProviderRef::Anthropic(_) => "anthropic",
     ProviderRef::OpenAICompatible(_) => "openai_compatible",
+    ProviderRef::Gemini(_) => "gemini",
+    ProviderRef::OpenAIResponses(_) => "openai_responses",
   }
 }
 
@@ -2560,7 +4034,9 @@ async fn get_provider_models_cached(
     }
   }
 
-  let models = fetch_provider_models(state, provider).await?;
+  let fetched = fetch_provider_models(state, provider).await;
+  metrics::record_model_cache_refresh(provider.id(), fetched.is_ok());
+  let models = fetched?;
   {
     let mut cache = state.models_cache.write().await;
     cache.providers.insert(
@@ -2582,7 +4058,29 @@ async fn fetch_provider_models(
 ) -> anyhow::Result<Vec<String>> {
   match provider {
     ProviderRef::Anthropic(p) => fetch_anthropic_models(state, p).await,
-    ProviderRef::OpenAICompatible(p) => fetch_openai_models(state, p).await,
+    ProviderRef::OpenAICompatible(p) => {
+      fetch_openai_models(
+        state,
+        &p.id,
+        &p.base_url,
+        &p.api_key,
+        &p.key_pool,
+        &p.extra_headers,
+      )
+      .await
+    }
+    ProviderRef::Gemini(p) => fetch_gemini_models(state, p).await,
+    ProviderRef::OpenAIResponses(p) => {
+      fetch_openai_models(
+        state,
+        &p.id,
+        &p.base_url,
+        &p.api_key,
+        &p.key_pool,
+        &p.extra_headers,
+      )
+      .await
+    }
   }
 }
 
@@ -2591,17 +4089,14 @@ async fn fetch_anthropic_models(
   provider: &AnthropicProviderConfig,
 ) -> anyhow::Result<Vec<String>> {
   let url = join_url(&provider.base_url, "models").context("构建 Anthropic models URL 失败")?;
-  let key = normalize_raw_token(&provider.api_key);
-  if key.is_empty() {
+  if provider.api_key.is_empty() {
     anyhow::bail!("Provider({}) api_key 为空", provider.id);
   }
 
   let mut req = state
     .http
     .get(url)
-    .header("x-api-key", key.clone())
     .header("anthropic-version", "2023-06-01")
-    .header("authorization", format!("Bearer {key}"))
     .timeout(Duration::from_secs(12));
 
   for (k, v) in &provider.extra_headers {
@@ -2610,7 +4105,20 @@ async fn fetch_anthropic_models(
     }
   }
 
-  let resp = req.send().await.context("请求 Anthropic /models 失败")?;
+  let resp = send_upstream(
+    &state.upstream,
+    &state.upstream_policy().await,
+    &provider.id,
+    &provider.api_key,
+    &provider.key_pool,
+    req,
+    |r, k| {
+      r.header("x-api-key", k)
+        .header("authorization", format!("Bearer {k}"))
+    },
+  )
+  .await
+  .context("请求 Anthropic /models 失败")?;
   let status = resp.status();
   let text = resp.text().await.unwrap_or_default();
   if !status.is_success() {
@@ -2638,27 +4146,36 @@ async fn fetch_anthropic_models(
 
 async fn fetch_openai_models(
   state: &AppState,
-  provider: &OpenAICompatibleProviderConfig,
+  provider_id: &str,
+  base_url: &str,
+  api_key: &ApiKeys,
+  key_pool: &KeyPoolConfig,
+  extra_headers: &std::collections::BTreeMap<String, String>,
 ) -> anyhow::Result<Vec<String>> {
-  let url = join_url(&provider.base_url, "models").context("构建 OpenAI models URL 失败")?;
-  let key = normalize_raw_token(&provider.api_key);
-  if key.is_empty() {
-    anyhow::bail!("Provider({}) api_key 为空", provider.id);
+  let url = join_url(base_url, "models").context("构建 OpenAI models URL 失败")?;
+  if api_key.is_empty() {
+    anyhow::bail!("Provider({provider_id}) api_key 为空");
   }
 
-  let mut req = state
-    .http
-    .get(url)
-    .header("authorization", format!("Bearer {key}"))
-    .timeout(Duration::from_secs(12));
+  let mut req = state.http.get(url).timeout(Duration::from_secs(12));
 
-  for (k, v) in &provider.extra_headers {
+  for (k, v) in extra_headers {
     if let Ok(value) = HeaderValue::from_str(v) {
       req = req.header(k, value);
     }
   }
 
-  let resp = req.send().await.context("请求 OpenAI /models 失败")?;
+  let resp = send_upstream(
+    &state.upstream,
+    &state.upstream_policy().await,
+    provider_id,
+    api_key,
+    key_pool,
+    req,
+    |r, k| r.header("authorization", format!("Bearer {k}")),
+  )
+  .await
+  .context("请求 OpenAI /models 失败")?;
   let status = resp.status();
   let text = resp.text().await.unwrap_or_default();
   if !status.is_success() {
@@ -2685,6 +4202,92 @@ async fn fetch_openai_models(
   Ok(models)
 }
 
+async fn fetch_gemini_models(
+  state: &AppState,
+  provider: &GeminiProviderConfig,
+) -> anyhow::Result<Vec<String>> {
+  if provider.api_key.is_empty() {
+    anyhow::bail!("Provider({}) api_key 为空", provider.id);
+  }
+
+  let mut models: Vec<String> = Vec::new();
+  let mut page_token: Option<String> = None;
+  loop {
+    let endpoint = match page_token.as_deref() {
+      Some(t) => {
+        let mut ser = url::form_urlencoded::Serializer::new(String::new());
+        ser.append_pair("pageSize", "1000");
+        ser.append_pair("pageToken", t);
+        format!("models?{}", ser.finish())
+      }
+      None => "models?pageSize=1000".to_string(),
+    };
+    let url = join_url(&provider.base_url, &endpoint).context("构建 Gemini models URL 失败")?;
+
+    let mut req = state.http.get(url).timeout(Duration::from_secs(12));
+
+    for (k, v) in &provider.extra_headers {
+      if let Ok(value) = HeaderValue::from_str(v) {
+        req = req.header(k, value);
+      }
+    }
+
+    let resp = send_upstream(
+      &state.upstream,
+      &state.upstream_policy().await,
+      &provider.id,
+      &provider.api_key,
+      &provider.key_pool,
+      req,
+      |r, k| r.header("x-goog-api-key", k),
+    )
+    .await
+    .context("请求 Gemini /models 失败")?;
+    let status = resp.status();
+    let text = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+      anyhow::bail!("Gemini /models 返回错误: {status} {text}");
+    }
+
+    let json: serde_json::Value =
+      serde_json::from_str(&text).context("Gemini /models 响应不是 JSON")?;
+    let data = json
+      .get("models")
+      .and_then(|v| v.as_array())
+      .context("Gemini /models 缺少 models[]")?;
+    for m in data {
+      let supports_generate = m
+        .get("supportedGenerationMethods")
+        .and_then(|v| v.as_array())
+        .map(|arr| arr.iter().any(|v| v.as_str() == Some("generateContent")))
+        .unwrap_or(true);
+      if !supports_generate {
+        continue;
+      }
+      let Some(name) = m.get("name").and_then(|v| v.as_str()) else {
+        continue;
+      };
+      let name = name.trim().trim_start_matches("models/");
+      if !name.is_empty() {
+        models.push(name.to_string());
+      }
+    }
+
+    page_token = json
+      .get("nextPageToken")
+      .and_then(|v| v.as_str())
+      .map(str::trim)
+      .filter(|s| !s.is_empty())
+      .map(str::to_string);
+    if page_token.is_none() {
+      break;
+    }
+  }
+  models.sort();
+  models.dedup();
+  Ok(models)
+}
+
 fn parse_augment_request(body: &[u8]) -> anyhow::Result<AugmentRequest> {
   let value: serde_json::Value = serde_json::from_slice(body).context("解析 JSON 失败")?;
   let Some(obj) = value.as_object() else {
@@ -2796,3 +4399,113 @@ mod parse_tests {
     assert!(err.to_string().contains("encrypted_data"));
   }
 }
+
+#[cfg(test)]
+mod fallback_tests {
+  use super::{get_provider_by_id, resolve_fallback_chain};
+  use crate::config::Config;
+
+  fn config_with_fallbacks(fallbacks: &str) -> Config {
+    let yaml = format!(
+      r#"
+server: {{ host: "127.0.0.1", port: 8317 }}
+proxy: {{ auth_token: "t" }}
+official: {{ base_url: "https://api.augmentcode.com/", api_token: "o" }}
+byok:
+  providers:
+    - {{ type: anthropic, id: anthropic, base_url: "https://api.anthropic.com/v1", api_key: "k", default_model: "claude-sonnet-4" }}
+    - {{ type: openai_compatible, id: openrouter, base_url: "https://openrouter.ai/api/v1", api_key: "k", default_model: "anthropic/claude-sonnet-4" }}
+    - {{ type: openai_compatible, id: openai, base_url: "https://api.openai.com/v1", api_key: "k", default_model: "gpt-4.1" }}
+  fallbacks:
+{fallbacks}
+"#
+    );
+    serde_yaml::from_str(&yaml).unwrap()
+  }
+
+  #[test]
+  fn model_level_fallbacks_win_over_provider_level() {
+    let cfg = config_with_fallbacks(
+      r#"    anthropic: ["byok:openai:gpt-4.1"]
+    "byok:anthropic:claude-sonnet-4":
+      - "byok:openrouter:anthropic/claude-sonnet-4"
+      - "byok:anthropic:claude-sonnet-4"
+      - "byok:openai:gpt-4.1""#,
+    );
+    cfg.byok.validate().unwrap();
+    let primary = get_provider_by_id(&cfg, "anthropic").unwrap();
+
+    let chain: Vec<(String, String)> = resolve_fallback_chain(&cfg, primary, "claude-sonnet-4")
+      .into_iter()
+      .map(|(p, m)| (p.id().to_string(), m))
+      .collect();
+    assert_eq!(
+      chain,
+      vec![
+        ("anthropic".to_string(), "claude-sonnet-4".to_string()),
+        (
+          "openrouter".to_string(),
+          "anthropic/claude-sonnet-4".to_string()
+        ),
+        ("openai".to_string(), "gpt-4.1".to_string()),
+      ]
+    );
+
+    let chain = resolve_fallback_chain(&cfg, primary, "claude-opus-4");
+    assert_eq!(chain.len(), 2);
+    assert_eq!(chain[1].0.id(), "openai");
+  }
+
+  #[test]
+  fn fallbacks_to_unknown_provider_fail_validation() {
+    let cfg = config_with_fallbacks(r#"    anthropic: ["byok:nope:m"]"#);
+    assert!(cfg.byok.validate().is_err());
+    let cfg = config_with_fallbacks(r#"    anthropic: ["openai"]"#);
+    assert!(cfg.byok.validate().is_err());
+  }
+}
+
+#[cfg(test)]
+mod routing_tests {
+  use axum::http::{HeaderMap, HeaderValue};
+
+  use super::{resolve_byok_route, ByokMode};
+  use crate::config::Config;
+
+  #[test]
+  fn headers_override_routing_rules() {
+    let cfg: Config = serde_yaml::from_str(
+      r#"
+server: { host: "127.0.0.1", port: 8317 }
+proxy: { auth_token: "t" }
+official: { base_url: "https://api.augmentcode.com/", api_token: "o" }
+byok:
+  providers:
+    - { type: anthropic, id: anthropic, base_url: "https://api.anthropic.com/v1", api_key: "k", default_model: "claude-sonnet-4" }
+routing:
+  rules:
+    - { path: "/chat-stream", mode: "AGENT", target: "byok:anthropic:claude-opus-4" }
+    - { path: "/chat-stream", target: "official" }
+"#,
+    )
+    .unwrap();
+    cfg.validate().unwrap();
+    let agent = br#"{"mode":"AGENT"}"#;
+
+    let route = resolve_byok_route(&cfg, "/chat-stream", &HeaderMap::new(), agent);
+    assert_eq!(route.mode, ByokMode::Byok);
+    assert_eq!(route.model.as_deref(), Some("byok:anthropic:claude-opus-4"));
+
+    let route = resolve_byok_route(&cfg, "/chat-stream", &HeaderMap::new(), b"{}");
+    assert_eq!(route.mode, ByokMode::Official);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-byok-mode", HeaderValue::from_static("official"));
+    let route = resolve_byok_route(&cfg, "/chat-stream", &headers, agent);
+    assert_eq!(route.mode, ByokMode::Official);
+    assert_eq!(route.model, None);
+
+    let route = resolve_byok_route(&cfg, "/edit", &HeaderMap::new(), agent);
+    assert_eq!(route.mode, ByokMode::Default);
+  }
+}
@@ -0,0 +1,386 @@
+use std::{
+  cell::RefCell,
+  collections::BTreeMap,
+  fmt::Write,
+  pin::Pin,
+  sync::{LazyLock, Mutex},
+  task::{Context, Poll},
+  time::{Duration, Instant},
+};
+
+use axum::{
+  body::{Body, Bytes, HttpBody},
+  extract::{MatchedPath, Request},
+  middleware::Next,
+  response::Response,
+};
+use http_body::{Frame, SizeHint};
+
+// Prometheus 文本格式的进程内指标（重启清零）；不引入 prometheus crate，只实现 counter / histogram
+static METRICS: LazyLock<Mutex<Registry>> = LazyLock::new(Mutex::default);
+
+const LATENCY_BUCKETS: [f64; 12] = [
+  0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+const FAMILIES: &[(&str, &str)] = &[
+  (
+    "byok_proxy_http_requests_total",
+    "HTTP requests handled, by endpoint / routing mode / provider / model / status",
+  ),
+  (
+    "byok_proxy_http_request_duration_seconds",
+    "HTTP request duration until the response body ends (or the client disconnects)",
+  ),
+  (
+    "byok_proxy_chat_stream_time_to_first_token_seconds",
+    "chat-stream time from request to the first upstream chunk",
+  ),
+  (
+    "byok_proxy_chat_streams_total",
+    "chat-stream turns by outcome (completed / cancelled)",
+  ),
+  (
+    "byok_proxy_upstream_responses_total",
+    "Upstream provider responses by status code (error = network error)",
+  ),
+  (
+    "byok_proxy_tokens_total",
+    "Tokens reported by upstream usage, by type (input / output / cache_read / cache_creation)",
+  ),
+  (
+    "byok_proxy_history_summary_triggers_total",
+    "history_summary triggers",
+  ),
+  (
+    "byok_proxy_history_summary_cache_hits_total",
+    "history_summary cache hits",
+  ),
+  (
+    "byok_proxy_history_summary_duration_seconds",
+    "history_summary summarizer model call duration",
+  ),
+  (
+    "byok_proxy_official_injection_requests_total",
+    "Official context injection calls by endpoint and result",
+  ),
+  (
+    "byok_proxy_model_cache_refreshes_total",
+    "Provider model list refreshes by result",
+  ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Debug, Default)]
+struct Registry {
+  counters: BTreeMap<&'static str, BTreeMap<Labels, f64>>,
+  histograms: BTreeMap<&'static str, BTreeMap<Labels, Histogram>>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+  buckets: [u64; LATENCY_BUCKETS.len()],
+  sum: f64,
+  count: u64,
+}
+
+fn labels(pairs: &[(&'static str, &str)]) -> Labels {
+  pairs.iter().map(|(k, v)| (*k, v.to_string())).collect()
+}
+
+fn inc(name: &'static str, pairs: &[(&'static str, &str)], by: f64) {
+  if let Ok(mut reg) = METRICS.lock() {
+    *reg
+      .counters
+      .entry(name)
+      .or_default()
+      .entry(labels(pairs))
+      .or_default() += by;
+  }
+}
+
+fn observe(name: &'static str, pairs: &[(&'static str, &str)], d: Duration) {
+  let secs = d.as_secs_f64();
+  if let Ok(mut reg) = METRICS.lock() {
+    let h = reg
+      .histograms
+      .entry(name)
+      .or_default()
+      .entry(labels(pairs))
+      .or_default();
+    for (i, le) in LATENCY_BUCKETS.iter().enumerate() {
+      if secs <= *le {
+        h.buckets[i] += 1;
+      }
+    }
+    h.sum += secs;
+    h.count += 1;
+  }
+}
+
+fn escape_label(v: &str) -> String {
+  v.replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &Labels, extra: Option<(&str, &str)>) -> String {
+  let parts: Vec<String> = labels
+    .iter()
+    .map(|(k, v)| (*k, v.as_str()))
+    .chain(extra)
+    .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+    .collect();
+  if parts.is_empty() {
+    String::new()
+  } else {
+    format!("{{{}}}", parts.join(","))
+  }
+}
+
+pub(crate) fn render() -> String {
+  let Ok(reg) = METRICS.lock() else {
+    return String::new();
+  };
+  let mut out = String::new();
+  for (name, help) in FAMILIES {
+    if let Some(series) = reg.counters.get(name) {
+      let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+      for (l, v) in series {
+        let _ = writeln!(out, "{name}{} {v}", format_labels(l, None));
+      }
+    }
+    if let Some(series) = reg.histograms.get(name) {
+      let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+      for (l, h) in series {
+        for (le, n) in LATENCY_BUCKETS.iter().zip(h.buckets) {
+          let le = le.to_string();
+          let _ = writeln!(
+            out,
+            "{name}_bucket{} {n}",
+            format_labels(l, Some(("le", &le)))
+          );
+        }
+        let inf = format_labels(l, Some(("le", "+Inf")));
+        let _ = writeln!(out, "{name}_bucket{inf} {}", h.count);
+        let _ = writeln!(out, "{name}_sum{} {}", format_labels(l, None), h.sum);
+        let _ = writeln!(out, "{name}_count{} {}", format_labels(l, None), h.count);
+      }
+    }
+  }
+  out
+}
+
+pub(crate) fn record_chat_stream_first_token(provider: &str, model: &str, elapsed: Duration) {
+  observe(
+    "byok_proxy_chat_stream_time_to_first_token_seconds",
+    &[("provider", provider), ("model", model)],
+    elapsed,
+  );
+}
+
+pub(crate) fn record_chat_stream(outcome: &str) {
+  inc(
+    "byok_proxy_chat_streams_total",
+    &[("outcome", outcome)],
+    1.0,
+  );
+}
+
+// status 为 None 表示网络错误
+pub(crate) fn record_upstream_response(provider: &str, status: Option<u16>) {
+  let status = status.map_or_else(|| "error".to_string(), |s| s.to_string());
+  inc(
+    "byok_proxy_upstream_responses_total",
+    &[("provider", provider), ("status", &status)],
+    1.0,
+  );
+}
+
+pub(crate) fn record_tokens(provider: &str, model: &str, token_type: &str, tokens: Option<i64>) {
+  if let Some(n) = tokens.filter(|n| *n > 0) {
+    inc(
+      "byok_proxy_tokens_total",
+      &[
+        ("provider", provider),
+        ("model", model),
+        ("type", token_type),
+      ],
+      n as f64,
+    );
+  }
+}
+
+pub(crate) fn record_history_summary_trigger() {
+  inc("byok_proxy_history_summary_triggers_total", &[], 1.0);
+}
+
+pub(crate) fn record_history_summary_cache_hit() {
+  inc("byok_proxy_history_summary_cache_hits_total", &[], 1.0);
+}
+
+pub(crate) fn record_history_summary_duration(elapsed: Duration, ok: bool) {
+  observe(
+    "byok_proxy_history_summary_duration_seconds",
+    &[("result", if ok { "ok" } else { "error" })],
+    elapsed,
+  );
+}
+
+pub(crate) fn record_official_injection(endpoint: &str, ok: bool) {
+  inc(
+    "byok_proxy_official_injection_requests_total",
+    &[
+      ("endpoint", endpoint),
+      ("result", if ok { "ok" } else { "error" }),
+    ],
+    1.0,
+  );
+}
+
+pub(crate) fn record_model_cache_refresh(provider: &str, ok: bool) {
+  inc(
+    "byok_proxy_model_cache_refreshes_total",
+    &[
+      ("provider", provider),
+      ("result", if ok { "ok" } else { "error" }),
+    ],
+    1.0,
+  );
+}
+
+// handler 在处理过程中补充的请求维度；由 track_requests 中间件在请求结束时读取
+#[derive(Debug, Clone, Default)]
+struct RequestLabels {
+  mode: &'static str,
+  provider: String,
+  model: String,
+}
+
+tokio::task_local! {
+  static REQUEST_LABELS: RefCell<RequestLabels>;
+}
+
+pub(crate) fn label_request_mode(mode: &'static str) {
+  let _ = REQUEST_LABELS.try_with(|l| l.borrow_mut().mode = mode);
+}
+
+pub(crate) fn label_request_target(provider: &str, model: &str) {
+  let _ = REQUEST_LABELS.try_with(|l| {
+    let mut l = l.borrow_mut();
+    l.mode = "byok";
+    l.provider = provider.to_string();
+    l.model = model.to_string();
+  });
+}
+
+struct RequestTimer {
+  endpoint: String,
+  labels: RequestLabels,
+  status: String,
+  started: Instant,
+}
+
+impl Drop for RequestTimer {
+  fn drop(&mut self) {
+    let l = &self.labels;
+    let dims = [
+      ("endpoint", self.endpoint.as_str()),
+      ("mode", l.mode),
+      ("provider", l.provider.as_str()),
+      ("model", l.model.as_str()),
+    ];
+    let mut with_status = dims.to_vec();
+    with_status.push(("status", &self.status));
+    inc("byok_proxy_http_requests_total", &with_status, 1.0);
+    observe(
+      "byok_proxy_http_request_duration_seconds",
+      &dims,
+      self.started.elapsed(),
+    );
+  }
+}
+
+// 包一层响应体：流式响应要等 body 结束（或被 drop）才算请求结束
+struct TimedBody {
+  inner: Body,
+  timer: Option<RequestTimer>,
+}
+
+impl HttpBody for TimedBody {
+  type Data = Bytes;
+  type Error = axum::Error;
+
+  fn poll_frame(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+    let polled = Pin::new(&mut self.inner).poll_frame(cx);
+    if let Poll::Ready(None) = polled {
+      self.timer.take();
+    }
+    polled
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+pub(crate) async fn track_requests(req: Request, next: Next) -> Response {
+  // 未命中路由（原样反代官方）的请求统一记为 fallback，避免 path 维度无限膨胀
+  let endpoint = req
+    .extensions()
+    .get::<MatchedPath>()
+    .map_or("fallback", MatchedPath::as_str)
+    .to_string();
+  let started = Instant::now();
+  let (resp, labels) = REQUEST_LABELS
+    .scope(RefCell::new(RequestLabels::default()), async move {
+      let resp = next.run(req).await;
+      let labels = REQUEST_LABELS.with(|l| l.borrow().clone());
+      (resp, labels)
+    })
+    .await;
+  let timer = RequestTimer {
+    endpoint,
+    labels,
+    status: resp.status().as_u16().to_string(),
+    started,
+  };
+  resp.map(|inner| {
+    Body::new(TimedBody {
+      inner,
+      timer: Some(timer),
+    })
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_counters_and_histograms_in_text_format() {
+    record_model_cache_refresh("test-\"p\"", true);
+    record_model_cache_refresh("test-\"p\"", true);
+    record_history_summary_duration(Duration::from_millis(700), true);
+
+    let text = render();
+    assert!(text.contains("# TYPE byok_proxy_model_cache_refreshes_total counter"));
+    assert!(text.contains(
+      "byok_proxy_model_cache_refreshes_total{provider=\"test-\\\"p\\\"\",result=\"ok\"} 2"
+    ));
+    assert!(text
+      .contains("byok_proxy_history_summary_duration_seconds_bucket{result=\"ok\",le=\"0.5\"} 0"));
+    assert!(
+      text.contains("byok_proxy_history_summary_duration_seconds_bucket{result=\"ok\",le=\"1\"} 1")
+    );
+    assert!(text.contains("byok_proxy_history_summary_duration_seconds_count{result=\"ok\"} 1"));
+  }
+}
@@ -5,9 +5,12 @@ use serde_json::Value;
 use tracing::{debug, warn};
 
 use crate::config::Config;
+use crate::metrics;
 use crate::protocol::{
   AugmentBlobs, AugmentRequest, NodeIn, TextNode, REQUEST_NODE_TEXT,
 };
+use crate::request_id;
+use crate::retry::send_with_retry;
 use crate::util::{join_url, normalize_raw_token, now_ms};
 use crate::AppState;
 
@@ -320,12 +323,27 @@ async fn fetch_official_codebase_retrieval(
   payload_base: Value,
   timeout: Duration,
 ) -> anyhow::Result<String> {
-  let url = join_url(completion_url, "agents/codebase-retrieval")?;
-  let mut resp = post_official_json(state, &url, api_token, &payload, timeout).await?;
+  let mut resp = post_official_json(
+    state,
+    completion_url,
+    "agents/codebase-retrieval",
+    api_token,
+    &payload,
+    timeout,
+  )
+  .await?;
   if !resp.status().is_success() {
     let status = resp.status().as_u16();
     if status == 400 || status == 422 {
-      resp = post_official_json(state, &url, api_token, &payload_base, timeout).await?;
+      resp = post_official_json(
+        state,
+        completion_url,
+        "agents/codebase-retrieval",
+        api_token,
+        &payload_base,
+        timeout,
+      )
+      .await?;
     }
   }
 
@@ -352,21 +370,27 @@ async fn fetch_official_codebase_retrieval(
 
 async fn post_official_json(
   state: &AppState,
-  url: &str,
+  completion_url: &str,
+  endpoint: &str,
   api_token: &str,
   payload: &Value,
   timeout: Duration,
 ) -> anyhow::Result<reqwest::Response> {
-  let resp = state
+  let url = join_url(completion_url, endpoint)?;
+  let mut req = state
     .http
     .post(url)
     .timeout(timeout)
     .header("content-type", "application/json")
     .bearer_auth(api_token)
-    .json(payload)
-    .send()
-    .await?;
-  Ok(resp)
+    .json(payload);
+  if let Some(id) = request_id::current_request_id() {
+    req = req.header(request_id::REQUEST_ID_HEADER, id);
+  }
+  let resp = send_with_retry(&state.retry_policy().await, None, req).await;
+  let ok = matches!(&resp, Ok(r) if r.status().is_success());
+  metrics::record_official_injection(endpoint, ok);
+  resp
 }
 
 async fn maybe_inject_official_context_canvas(
@@ -447,21 +471,20 @@ async fn fetch_official_context_canvas_list(
   page_token: &str,
   timeout: Duration,
 ) -> anyhow::Result<Value> {
-  let url = join_url(completion_url, "context-canvas/list")?;
   let payload = serde_json::json!({
     "page_size": page_size,
     "page_token": page_token,
   });
 
-  let resp = state
-    .http
-    .post(url)
-    .timeout(timeout)
-    .header("content-type", "application/json")
-    .bearer_auth(api_token)
-    .json(&payload)
-    .send()
-    .await?;
+  let resp = post_official_json(
+    state,
+    completion_url,
+    "context-canvas/list",
+    api_token,
+    &payload,
+    timeout,
+  )
+  .await?;
 
   if !resp.status().is_success() {
     let status = resp.status();
@@ -753,17 +776,16 @@ async fn fetch_official_implicit_external_sources(
   message: &str,
   timeout: Duration,
 ) -> anyhow::Result<Value> {
-  let url = join_url(completion_url, "get-implicit-external-sources")?;
   let payload = serde_json::json!({ "message": message });
-  let resp = state
-    .http
-    .post(url)
-    .timeout(timeout)
-    .header("content-type", "application/json")
-    .bearer_auth(api_token)
-    .json(&payload)
-    .send()
-    .await?;
+  let resp = post_official_json(
+    state,
+    completion_url,
+    "get-implicit-external-sources",
+    api_token,
+    &payload,
+    timeout,
+  )
+  .await?;
 
   if !resp.status().is_success() {
     let status = resp.status();
@@ -837,20 +859,19 @@ async fn fetch_official_search_external_sources(
   query: &str,
   timeout: Duration,
 ) -> anyhow::Result<Value> {
-  let url = join_url(completion_url, "search-external-sources")?;
   let payload = serde_json::json!({
     "query": query,
     "source_types": [],
   });
-  let resp = state
-    .http
-    .post(url)
-    .timeout(timeout)
-    .header("content-type", "application/json")
-    .bearer_auth(api_token)
-    .json(&payload)
-    .send()
-    .await?;
+  let resp = post_official_json(
+    state,
+    completion_url,
+    "search-external-sources",
+    api_token,
+    &payload,
+    timeout,
+  )
+  .await?;
 
   if !resp.status().is_success() {
     let status = resp.status();
@@ -35,6 +35,8 @@ pub struct OpenAIChatMessage {
   pub tool_calls: Option<Vec<OpenAIToolCall>>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub tool_call_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reasoning_content: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -89,6 +91,10 @@ pub struct OpenAIDelta {
   #[serde(default)]
   pub content: Option<String>,
   #[serde(default)]
+  pub reasoning_content: Option<String>,
+  #[serde(default)]
+  pub reasoning: Option<String>,
+  #[serde(default)]
   pub tool_calls: Option<Vec<OpenAIDeltaToolCall>>,
   #[serde(default)]
   pub function_call: Option<OpenAIDeltaFunctionCall>,
@@ -130,4 +136,26 @@ pub struct OpenAIUsage {
   pub completion_tokens: Option<i64>,
   #[serde(default)]
   pub total_tokens: Option<i64>,
+  #[serde(default)]
+  pub prompt_tokens_details: Option<OpenAIPromptTokensDetails>,
+  // DeepSeek 风格：命中缓存的 prompt token 单独给出
+  #[serde(default)]
+  pub prompt_cache_hit_tokens: Option<i64>,
+}
+
+impl OpenAIUsage {
+  // prompt_tokens 已包含命中缓存的部分
+  pub fn cached_tokens(&self) -> Option<i64> {
+    self
+      .prompt_tokens_details
+      .as_ref()
+      .and_then(|d| d.cached_tokens)
+      .or(self.prompt_cache_hit_tokens)
+  }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIPromptTokensDetails {
+  #[serde(default)]
+  pub cached_tokens: Option<i64>,
 }
@@ -0,0 +1,186 @@
+#![allow(dead_code)]
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct OpenAIResponsesRequest {
+  pub model: String,
+  pub input: Vec<OpenAIResponsesInputItem>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub instructions: Option<String>,
+  pub stream: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub store: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_output_tokens: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tools: Option<Vec<OpenAIResponsesTool>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tool_choice: Option<serde_json::Value>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reasoning: Option<OpenAIResponsesReasoning>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub include: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OpenAIResponsesInputItem {
+  Message {
+    role: String,
+    content: serde_json::Value,
+  },
+  FunctionCall {
+    call_id: String,
+    name: String,
+    arguments: String,
+  },
+  FunctionCallOutput {
+    call_id: String,
+    output: String,
+  },
+  Reasoning {
+    summary: Vec<OpenAIResponsesSummaryText>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encrypted_content: Option<String>,
+  },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenAIResponsesSummaryText {
+  #[serde(default, rename = "type")]
+  pub summary_type: String,
+  #[serde(default)]
+  pub text: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenAIResponsesTool {
+  #[serde(rename = "type")]
+  pub tool_type: String,
+  pub name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpenAIResponsesReasoning {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub effort: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub summary: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesStreamEvent {
+  #[serde(default, rename = "type")]
+  pub event_type: String,
+  #[serde(default)]
+  pub delta: Option<String>,
+  #[serde(default)]
+  pub item_id: Option<String>,
+  #[serde(default)]
+  pub output_index: Option<usize>,
+  #[serde(default)]
+  pub summary_index: Option<usize>,
+  #[serde(default)]
+  pub item: Option<OpenAIResponsesOutputItem>,
+  #[serde(default)]
+  pub response: Option<OpenAIResponsesResponse>,
+  #[serde(default)]
+  pub message: Option<String>,
+  #[serde(default)]
+  pub code: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesResponse {
+  #[serde(default)]
+  pub id: String,
+  #[serde(default)]
+  pub status: Option<String>,
+  #[serde(default)]
+  pub output: Vec<OpenAIResponsesOutputItem>,
+  #[serde(default)]
+  pub usage: Option<OpenAIResponsesUsage>,
+  #[serde(default)]
+  pub incomplete_details: Option<OpenAIResponsesIncompleteDetails>,
+  #[serde(default)]
+  pub error: Option<OpenAIResponsesError>,
+}
+
+impl OpenAIResponsesResponse {
+  pub fn output_text(&self) -> String {
+    let mut out = String::new();
+    for item in &self.output {
+      if item.item_type != "message" {
+        continue;
+      }
+      for c in &item.content {
+        if c.content_type == "output_text" {
+          out.push_str(&c.text);
+        }
+      }
+    }
+    out
+  }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesOutputItem {
+  #[serde(default, rename = "type")]
+  pub item_type: String,
+  #[serde(default)]
+  pub id: Option<String>,
+  #[serde(default)]
+  pub call_id: Option<String>,
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(default)]
+  pub arguments: Option<String>,
+  #[serde(default)]
+  pub summary: Vec<OpenAIResponsesSummaryText>,
+  #[serde(default)]
+  pub encrypted_content: Option<String>,
+  #[serde(default)]
+  pub content: Vec<OpenAIResponsesOutputContent>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesOutputContent {
+  #[serde(default, rename = "type")]
+  pub content_type: String,
+  #[serde(default)]
+  pub text: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesUsage {
+  #[serde(default)]
+  pub input_tokens: Option<i64>,
+  #[serde(default)]
+  pub output_tokens: Option<i64>,
+  #[serde(default)]
+  pub input_tokens_details: Option<OpenAIResponsesInputTokensDetails>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesInputTokensDetails {
+  #[serde(default)]
+  pub cached_tokens: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesIncompleteDetails {
+  #[serde(default)]
+  pub reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct OpenAIResponsesError {
+  #[serde(default)]
+  pub code: Option<String>,
+  #[serde(default)]
+  pub message: String,
+}
@@ -41,6 +41,12 @@ pub const STOP_REASON_SAFETY: i32 = 4;
 pub const STOP_REASON_RECITATION: i32 = 5;
 pub const STOP_REASON_MALFORMED_FUNCTION_CALL: i32 = 6;
 
+// ThinkingNode.provider：Anthropic signature 与 Gemini thoughtSignature 共用 signature，
+// Anthropic redacted_thinking 与 Responses encrypted_content 共用 encrypted_content，回放时只交给写出它的那种上游
+pub const THINKING_PROVIDER_ANTHROPIC: &str = "anthropic";
+pub const THINKING_PROVIDER_GEMINI: &str = "gemini";
+pub const THINKING_PROVIDER_OPENAI_RESPONSES: &str = "openai_responses";
+
 pub fn is_false(v: &bool) -> bool {
   !*v
 }
@@ -424,6 +430,31 @@ pub struct ToolUse {
 pub struct ThinkingNode {
   #[serde(default, deserialize_with = "de_null_as_default")]
   pub summary: String,
+  #[serde(
+    default,
+    deserialize_with = "de_null_as_default",
+    skip_serializing_if = "String::is_empty"
+  )]
+  pub signature: String,
+  #[serde(
+    default,
+    deserialize_with = "de_null_as_default",
+    skip_serializing_if = "String::is_empty"
+  )]
+  pub encrypted_content: String,
+  #[serde(
+    default,
+    deserialize_with = "de_null_as_default",
+    skip_serializing_if = "String::is_empty"
+  )]
+  pub provider: String,
+}
+
+impl ThinkingNode {
+  // 没有 provider 标记的旧节点无法判断来源，一律不回放
+  pub fn written_by(&self, provider: &str) -> bool {
+    self.provider == provider
+  }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -480,7 +511,7 @@ pub fn probe_response() -> AugmentStreamChunk {
 
 pub fn error_response(message: impl Into<String>) -> AugmentStreamChunk {
   AugmentStreamChunk {
-    text: message.into(),
+    text: crate::request_id::annotate_error(message.into()),
     unknown_blob_names: Vec::new(),
     checkpoint_not_found: false,
     workspace_file_chunks: Vec::new(),
@@ -0,0 +1,210 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use tracing::info;
+
+use crate::{config::RateLimitConfig, key_pool::mask_key, util::now_ms};
+
+pub(crate) type SharedRateLimits = Arc<Mutex<RateLimitRegistry>>;
+
+#[derive(Debug, Default)]
+pub(crate) struct RateLimitRegistry {
+  by_key: HashMap<(String, String), KeyBuckets>,
+}
+
+#[derive(Debug, Default)]
+struct KeyBuckets {
+  requests: TokenBucket,
+  input_tokens: TokenBucket,
+  output_tokens: TokenBucket,
+}
+
+// available 允许为负（实际用量超过估算、或输出 token 只能事后扣），欠账还清之前不再放行
+#[derive(Debug, Default)]
+struct TokenBucket {
+  available: f64,
+  updated_at_ms: Option<u64>,
+}
+
+impl TokenBucket {
+  fn refill(&mut self, per_minute: u64, now: u64) {
+    let capacity = per_minute as f64;
+    self.available = match self.updated_at_ms {
+      None => capacity,
+      Some(at) => {
+        let elapsed_ms = now.saturating_sub(at) as f64;
+        (self.available + elapsed_ms * capacity / 60_000.0).min(capacity)
+      }
+    };
+    self.updated_at_ms = Some(now);
+  }
+
+  fn wait_ms(&self, per_minute: u64, needed: f64) -> u64 {
+    if per_minute == 0 || self.available >= needed {
+      return 0;
+    }
+    ((needed - self.available) * 60_000.0 / per_minute as f64).ceil() as u64
+  }
+}
+
+impl RateLimitRegistry {
+  // Ok 表示已扣除本次请求的 1 次 RPM 与估算输入 token；Err 为还需等待的毫秒数
+  fn try_reserve(
+    &mut self,
+    provider_id: &str,
+    key: &str,
+    cfg: &RateLimitConfig,
+    estimated_input_tokens: u64,
+    now: u64,
+  ) -> Result<(), u64> {
+    let b = self
+      .by_key
+      .entry((provider_id.to_string(), key.to_string()))
+      .or_default();
+    b.requests.refill(cfg.requests_per_minute, now);
+    b.input_tokens.refill(cfg.input_tokens_per_minute, now);
+    b.output_tokens.refill(cfg.output_tokens_per_minute, now);
+
+    // 单个请求的估算超过每分钟上限时，等桶满即可放行，避免永远等不到
+    let input_needed = estimated_input_tokens.min(cfg.input_tokens_per_minute) as f64;
+    let wait = [
+      b.requests.wait_ms(cfg.requests_per_minute, 1.0),
+      b.input_tokens
+        .wait_ms(cfg.input_tokens_per_minute, input_needed),
+      b.output_tokens.wait_ms(cfg.output_tokens_per_minute, 0.0),
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or(0);
+    if wait > 0 {
+      return Err(wait);
+    }
+    if cfg.requests_per_minute > 0 {
+      b.requests.available -= 1.0;
+    }
+    if cfg.input_tokens_per_minute > 0 {
+      b.input_tokens.available -= estimated_input_tokens as f64;
+    }
+    Ok(())
+  }
+
+  fn reconcile(
+    &mut self,
+    provider_id: &str,
+    key: &str,
+    estimated_input_tokens: u64,
+    actual_input_tokens: Option<i64>,
+    actual_output_tokens: Option<i64>,
+  ) {
+    let Some(b) = self
+      .by_key
+      .get_mut(&(provider_id.to_string(), key.to_string()))
+    else {
+      return;
+    };
+    if let Some(actual) = actual_input_tokens {
+      b.input_tokens.available += estimated_input_tokens as f64 - actual.max(0) as f64;
+    }
+    if let Some(actual) = actual_output_tokens {
+      b.output_tokens.available -= actual.max(0) as f64;
+    }
+  }
+}
+
+// 放行后附在上游响应的 extensions 上，调用方拿到实际 usage 后用它校正令牌桶
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimitTicket {
+  registry: SharedRateLimits,
+  provider_id: String,
+  key: String,
+  estimated_input_tokens: u64,
+}
+
+impl RateLimitTicket {
+  pub(crate) fn reconcile(self, input_tokens: Option<i64>, output_tokens: Option<i64>) {
+    if let Ok(mut reg) = self.registry.lock() {
+      reg.reconcile(
+        &self.provider_id,
+        &self.key,
+        self.estimated_input_tokens,
+        input_tokens,
+        output_tokens,
+      );
+    }
+  }
+}
+
+pub(crate) async fn wait_for_capacity(
+  registry: &SharedRateLimits,
+  provider_id: &str,
+  key: &str,
+  cfg: &RateLimitConfig,
+  estimated_input_tokens: u64,
+) -> RateLimitTicket {
+  loop {
+    let reserved = match registry.lock() {
+      Ok(mut reg) => reg.try_reserve(provider_id, key, cfg, estimated_input_tokens, now_ms()),
+      Err(_) => Ok(()),
+    };
+    match reserved {
+      Ok(()) => {
+        return RateLimitTicket {
+          registry: registry.clone(),
+          provider_id: provider_id.to_string(),
+          key: key.to_string(),
+          estimated_input_tokens,
+        }
+      }
+      Err(wait_ms) => {
+        info!(provider_id=%provider_id, key=%mask_key(key), wait_ms, estimated_input_tokens, "本地限流：等待令牌桶放行");
+        tokio::time::sleep(Duration::from_millis(wait_ms)).await;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn buckets_pace_requests_and_reconcile_actual_usage() {
+    let cfg = RateLimitConfig {
+      requests_per_minute: 2,
+      input_tokens_per_minute: 6_000,
+      output_tokens_per_minute: 600,
+    };
+    let mut reg = RateLimitRegistry::default();
+
+    assert_eq!(reg.try_reserve("p", "k", &cfg, 1_000, 0), Ok(()));
+    assert_eq!(reg.try_reserve("p", "k", &cfg, 1_000, 0), Ok(()));
+    // RPM 用完：补回 1 个请求需要 30s
+    assert_eq!(reg.try_reserve("p", "k", &cfg, 1_000, 0), Err(30_000));
+    // 其它 key 独立计数
+    assert_eq!(reg.try_reserve("p", "other", &cfg, 1_000, 0), Ok(()));
+
+    // 实际输入比估算多 3000，输出 1200（欠 600）
+    reg.reconcile("p", "k", 1_000, Some(4_000), Some(1_200));
+    let b = &reg.by_key[&("p".to_string(), "k".to_string())];
+    assert_eq!(b.input_tokens.available, 1_000.0);
+    assert_eq!(b.output_tokens.available, -600.0);
+    // 输出欠 600，按每分钟 600 回补，60s 时才还清
+    assert_eq!(reg.try_reserve("p", "k", &cfg, 100, 30_000), Err(30_000));
+    assert_eq!(reg.try_reserve("p", "k", &cfg, 100, 60_000), Ok(()));
+  }
+
+  #[test]
+  fn oversized_request_waits_for_full_bucket_only() {
+    let cfg = RateLimitConfig {
+      requests_per_minute: 0,
+      input_tokens_per_minute: 1_000,
+      output_tokens_per_minute: 0,
+    };
+    let mut reg = RateLimitRegistry::default();
+    assert_eq!(reg.try_reserve("p", "k", &cfg, 5_000, 0), Ok(()));
+    assert_eq!(reg.try_reserve("p", "k", &cfg, 5_000, 0), Err(300_000));
+  }
+}
@@ -0,0 +1,489 @@
+use std::{
+  collections::HashMap,
+  fmt::Write as _,
+  path::{Path, PathBuf},
+  time::Instant,
+};
+
+use anyhow::Context;
+use futures::StreamExt;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+  config::{self, Config},
+  convert::{
+    convert_augment_to_anthropic, convert_augment_to_gemini, convert_augment_to_openai_compatible,
+    convert_augment_to_openai_responses,
+  },
+  history_summary::compact_chat_history,
+  history_summary_auto::estimate_request_input_tokens,
+  open_chat_stream_upstream, parse_augment_request,
+  protocol::{AugmentRequest, RESPONSE_NODE_TOOL_USE},
+  resolve_chat_target,
+  stats::TurnUsage,
+  tool_meta_by_name,
+  upstream::UpstreamPolicy,
+  upstream_model_name, AppState, ChatStreamTrace, ProviderRef,
+};
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct ReplayArgs {
+  #[arg(
+    required = true,
+    value_name = "PATH",
+    help = "录制的 Augment chat-stream 请求体（JSON 文件或目录下的 *.json）；transcript 文件取其 augment_request"
+  )]
+  inputs: Vec<PathBuf>,
+  #[arg(
+    short,
+    long = "target",
+    value_name = "byok:PROVIDER:MODEL",
+    help = "重放目标，可重复；缺省按请求体里的 model 走默认 provider"
+  )]
+  targets: Vec<String>,
+  #[arg(long, help = "只做转换并输出上游请求体，不发送")]
+  dry_run: bool,
+  #[arg(
+    long,
+    value_name = "DIR",
+    help = "dry-run 时把上游请求体写入该目录（缺省打印到 stdout）"
+  )]
+  out: Option<PathBuf>,
+}
+
+// 不走 history_summary 自动摘要与官方上下文注入（两者都会产生额外的上游调用），也不计入 usage 账本与预算
+pub(crate) async fn run(config_path: PathBuf, cfg: Config, args: ReplayArgs) -> anyhow::Result<()> {
+  config::init_cli_tracing()?;
+
+  for t in &args.targets {
+    resolve_chat_target(&cfg, t).map_err(|msg| anyhow::anyhow!("--target {t}: {msg}"))?;
+  }
+  let inputs = load_inputs(&args.inputs)?;
+  if inputs.is_empty() {
+    anyhow::bail!("没有找到可重放的请求体（*.json）");
+  }
+  if let Some(dir) = &args.out {
+    std::fs::create_dir_all(dir).with_context(|| format!("创建输出目录失败: {}", dir.display()))?;
+  }
+
+  let state = if args.dry_run {
+    None
+  } else {
+    Some(
+      AppState::new(
+        config_path,
+        cfg.clone(),
+        reqwest::Client::builder().build()?,
+      )
+      .await,
+    )
+  };
+  let mut totals: Vec<TargetTotals> = Vec::new();
+  for (path, body) in &inputs {
+    let mut augment = match parse_augment_request(body) {
+      Ok(v) => v,
+      Err(err) => {
+        println!("== {}\n  ⚠️ 请求解析失败: {err:#}", path.display());
+        continue;
+      }
+    };
+    compact_chat_history(&mut augment.chat_history);
+    let requested: Vec<String> = if args.targets.is_empty() {
+      vec![augment.model.clone().unwrap_or_default()]
+    } else {
+      args.targets.clone()
+    };
+    let mut outcomes = Vec::new();
+    for requested_model in &requested {
+      let (provider, raw_model) = match resolve_chat_target(&cfg, requested_model) {
+        Ok(v) => v,
+        Err(msg) => {
+          outcomes.push(ReplayOutcome::failed(requested_model, msg));
+          continue;
+        }
+      };
+      let model = upstream_model_name(provider, &raw_model);
+      let label = format!("byok:{}:{model}", provider.id());
+      if args.dry_run {
+        dump_upstream_request(path, provider, &model, &augment, args.out.as_deref())?;
+        continue;
+      }
+      if let Some(state) = &state {
+        outcomes.push(replay_once(state, &cfg, provider, &raw_model, label, &augment).await);
+      }
+    }
+    if args.dry_run {
+      continue;
+    }
+    print!("{}", render_report(path, &augment, &outcomes));
+    for (i, o) in outcomes.iter().enumerate() {
+      let t = match totals.iter_mut().find(|t| t.target == o.target) {
+        Some(t) => t,
+        None => {
+          totals.push(TargetTotals {
+            target: o.target.clone(),
+            ..Default::default()
+          });
+          totals.last_mut().unwrap()
+        }
+      };
+      t.add(o, i > 0 && !diff_against(&outcomes[0], o).is_empty());
+    }
+  }
+  if !args.dry_run {
+    print!("{}", render_totals(&totals));
+  }
+  Ok(())
+}
+
+// 目录按文件名排序展开；transcript 文件（logging.transcripts）取其中的 augment_request
+fn load_inputs(paths: &[PathBuf]) -> anyhow::Result<Vec<(PathBuf, Vec<u8>)>> {
+  let mut files = Vec::new();
+  for p in paths {
+    if p.is_dir() {
+      let mut entries: Vec<PathBuf> = std::fs::read_dir(p)
+        .with_context(|| format!("读取目录失败: {}", p.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|f| f.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+      entries.sort();
+      files.extend(entries);
+    } else {
+      files.push(p.clone());
+    }
+  }
+  files
+    .into_iter()
+    .map(|f| {
+      let raw = std::fs::read(&f).with_context(|| format!("读取文件失败: {}", f.display()))?;
+      let body = match serde_json::from_slice::<Value>(&raw) {
+        Ok(Value::Object(mut obj)) if obj.contains_key("augment_request") => {
+          serde_json::to_vec(&obj.remove("augment_request").unwrap_or_default())?
+        }
+        _ => raw,
+      };
+      Ok((f, body))
+    })
+    .collect()
+}
+
+fn dump_upstream_request(
+  input: &Path,
+  provider: ProviderRef<'_>,
+  model: &str,
+  augment: &AugmentRequest,
+  out: Option<&Path>,
+) -> anyhow::Result<()> {
+  let request = match provider {
+    ProviderRef::Anthropic(p) => {
+      to_json(convert_augment_to_anthropic(p, augment, model.to_string()))
+    }
+    ProviderRef::OpenAICompatible(p) => to_json(convert_augment_to_openai_compatible(
+      p,
+      augment,
+      model.to_string(),
+    )),
+    ProviderRef::Gemini(p) => to_json(convert_augment_to_gemini(p, augment)),
+    ProviderRef::OpenAIResponses(p) => to_json(convert_augment_to_openai_responses(
+      p,
+      augment,
+      model.to_string(),
+    )),
+  };
+  let request = match request {
+    Ok(v) => v,
+    Err(err) => {
+      println!(
+        "== {} -> byok:{}:{model}\n  ⚠️ 转换请求失败: {err:#}",
+        input.display(),
+        provider.id()
+      );
+      return Ok(());
+    }
+  };
+  let dump = serde_json::json!({
+    "input": input.display().to_string(),
+    "provider": provider.id(),
+    "model": model,
+    "request": request,
+  });
+  let text = serde_json::to_string_pretty(&dump)?;
+  match out {
+    Some(dir) => {
+      let stem = input
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("request");
+      let name: String = format!("{stem}.{}.{model}.json", provider.id())
+        .chars()
+        .map(|c| {
+          if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+            c
+          } else {
+            '_'
+          }
+        })
+        .collect();
+      let path = dir.join(name);
+      std::fs::write(&path, text).with_context(|| format!("写入失败: {}", path.display()))?;
+      println!("{}", path.display());
+    }
+    None => println!("{text}"),
+  }
+  Ok(())
+}
+
+fn to_json(req: anyhow::Result<impl Serialize>) -> anyhow::Result<Value> {
+  Ok(serde_json::to_value(req?)?)
+}
+
+#[derive(Debug, Default)]
+struct ReplayOutcome {
+  target: String,
+  text: String,
+  tool_calls: Vec<String>,
+  stop_reason: Option<i64>,
+  usage: TurnUsage,
+  elapsed_ms: u128,
+  error: Option<String>,
+}
+
+impl ReplayOutcome {
+  fn failed(target: &str, error: String) -> Self {
+    Self {
+      target: target.to_string(),
+      error: Some(error),
+      ..Default::default()
+    }
+  }
+
+  fn on_ndjson_line(&mut self, line: &str) {
+    let Ok(chunk) = serde_json::from_str::<Value>(line) else {
+      return;
+    };
+    if let Some(text) = chunk["text"].as_str() {
+      self.text.push_str(text);
+    }
+    for node in chunk["nodes"].as_array().into_iter().flatten() {
+      if node["type"].as_i64() == Some(RESPONSE_NODE_TOOL_USE as i64) {
+        let name = node["tool_use"]["tool_name"].as_str().unwrap_or("?");
+        self.tool_calls.push(name.to_string());
+      }
+    }
+    if let Some(r) = chunk["stop_reason"].as_i64() {
+      self.stop_reason = Some(r);
+    }
+  }
+}
+
+async fn replay_once(
+  state: &AppState,
+  cfg: &Config,
+  provider: ProviderRef<'_>,
+  raw_model: &str,
+  target: String,
+  augment: &AugmentRequest,
+) -> ReplayOutcome {
+  let mut outcome = ReplayOutcome {
+    target,
+    ..Default::default()
+  };
+  let started = Instant::now();
+  let policy = UpstreamPolicy::from_config(cfg)
+    .with_estimated_input_tokens(estimate_request_input_tokens(augment));
+  let trace = ChatStreamTrace {
+    dump_body: false,
+    transcript: None,
+  };
+  let tool_meta: HashMap<String, (String, String)> = tool_meta_by_name(augment);
+  match open_chat_stream_upstream(
+    state, provider, raw_model, augment, &tool_meta, &policy, &trace,
+  )
+  .await
+  {
+    Ok((mut upstream, progress)) => {
+      while let Some(item) = upstream.next().await {
+        match item {
+          Ok(bytes) => String::from_utf8_lossy(&bytes)
+            .lines()
+            .for_each(|line| outcome.on_ndjson_line(line)),
+          Err(err) => {
+            outcome.error = Some(err);
+            break;
+          }
+        }
+      }
+      outcome.usage = progress.snapshot();
+    }
+    Err(err) => outcome.error = Some(err),
+  }
+  outcome.elapsed_ms = started.elapsed().as_millis();
+  outcome
+}
+
+fn stop_reason_name(r: Option<i64>) -> &'static str {
+  match r {
+    None => "-",
+    Some(1) => "end_turn",
+    Some(2) => "max_tokens",
+    Some(3) => "tool_use",
+    Some(4) => "safety",
+    Some(5) => "recitation",
+    Some(6) => "malformed_function_call",
+    Some(_) => "unspecified",
+  }
+}
+
+fn fmt_tokens(v: Option<i64>) -> String {
+  v.map_or_else(|| "-".to_string(), |n| n.to_string())
+}
+
+// 与基准（第一个目标）相比的差异；为空表示一致
+fn diff_against(base: &ReplayOutcome, other: &ReplayOutcome) -> Vec<String> {
+  let mut out = Vec::new();
+  if base.error.is_some() != other.error.is_some() {
+    out.push("一方失败".to_string());
+    return out;
+  }
+  if base.text != other.text {
+    let first_diff = base
+      .text
+      .chars()
+      .zip(other.text.chars())
+      .take_while(|(a, b)| a == b)
+      .count();
+    out.push(format!(
+      "text 不同（{} vs {} 字符，第 {first_diff} 字符起分叉）",
+      base.text.chars().count(),
+      other.text.chars().count()
+    ));
+  }
+  if base.tool_calls != other.tool_calls {
+    out.push(format!(
+      "tool calls 不同（{:?} vs {:?}）",
+      base.tool_calls, other.tool_calls
+    ));
+  }
+  if base.stop_reason != other.stop_reason {
+    out.push(format!(
+      "stop_reason 不同（{} vs {}）",
+      stop_reason_name(base.stop_reason),
+      stop_reason_name(other.stop_reason)
+    ));
+  }
+  let (bu, ou) = (&base.usage, &other.usage);
+  if (bu.input_tokens, bu.output_tokens) != (ou.input_tokens, ou.output_tokens) {
+    out.push(format!(
+      "tokens 不同（in {} vs {}，out {} vs {}）",
+      fmt_tokens(bu.input_tokens),
+      fmt_tokens(ou.input_tokens),
+      fmt_tokens(bu.output_tokens),
+      fmt_tokens(ou.output_tokens)
+    ));
+  }
+  out
+}
+
+fn render_report(input: &Path, augment: &AugmentRequest, outcomes: &[ReplayOutcome]) -> String {
+  let message: String = augment.message.chars().take(60).collect();
+  let mut out = format!("== {}  message={message:?}\n", input.display());
+  for (i, o) in outcomes.iter().enumerate() {
+    let _ = write!(out, "  [{}] {}  {}ms", i + 1, o.target, o.elapsed_ms);
+    if let Some(err) = &o.error {
+      let _ = writeln!(out, "  ❌ {err}");
+      continue;
+    }
+    let preview: String = o.text.chars().take(80).collect();
+    let _ = writeln!(
+      out,
+      "  stop={}  tools={:?}  tokens in={} out={}  text={} 字符\n      {preview:?}",
+      stop_reason_name(o.stop_reason),
+      o.tool_calls,
+      fmt_tokens(o.usage.input_tokens),
+      fmt_tokens(o.usage.output_tokens),
+      o.text.chars().count()
+    );
+    if i > 0 {
+      let diffs = diff_against(&outcomes[0], o);
+      if diffs.is_empty() {
+        let _ = writeln!(out, "      与 [1] 一致");
+      } else {
+        let _ = writeln!(out, "      与 [1] 相比: {}", diffs.join("；"));
+      }
+    }
+  }
+  out
+}
+
+#[derive(Debug, Default)]
+struct TargetTotals {
+  target: String,
+  runs: u64,
+  errors: u64,
+  differs: u64,
+  input_tokens: i64,
+  output_tokens: i64,
+  elapsed_ms: u128,
+}
+
+impl TargetTotals {
+  fn add(&mut self, o: &ReplayOutcome, differs: bool) {
+    self.runs += 1;
+    self.errors += u64::from(o.error.is_some());
+    self.differs += u64::from(differs);
+    self.input_tokens += o.usage.input_tokens.unwrap_or(0);
+    self.output_tokens += o.usage.output_tokens.unwrap_or(0);
+    self.elapsed_ms += o.elapsed_ms;
+  }
+}
+
+fn render_totals(totals: &[TargetTotals]) -> String {
+  let mut out = String::from("== 汇总\n");
+  for t in totals {
+    let _ = writeln!(
+      out,
+      "  {}  runs={} errors={} 与基准不同={}  tokens in={} out={}  平均 {}ms",
+      t.target,
+      t.runs,
+      t.errors,
+      t.differs,
+      t.input_tokens,
+      t.output_tokens,
+      t.elapsed_ms / u128::from(t.runs.max(1))
+    );
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn collects_ndjson_and_reports_differences() {
+    let mut a = ReplayOutcome::default();
+    for line in [
+      r#"{"text":"Hello"}"#,
+      r#"{"text":" world","nodes":[{"id":1,"type":5,"content":"","tool_use":{"tool_use_id":"t1","tool_name":"view","input_json":"{}"}}]}"#,
+      r#"{"text":"","stop_reason":3}"#,
+      "not json",
+    ] {
+      a.on_ndjson_line(line);
+    }
+    a.usage.output_tokens = Some(7);
+    assert_eq!(
+      (a.text.as_str(), a.tool_calls.clone(), a.stop_reason),
+      ("Hello world", vec!["view".to_string()], Some(3))
+    );
+
+    let mut b = ReplayOutcome::default();
+    b.on_ndjson_line(r#"{"text":"Hello there","stop_reason":1}"#);
+    b.usage.output_tokens = Some(7);
+    let diffs = diff_against(&a, &b);
+    assert_eq!(diffs.len(), 3, "{diffs:?}");
+    assert!(diffs[0].contains("第 6 字符起分叉"), "{diffs:?}");
+    assert!(diffs[2].contains("tool_use vs end_turn"), "{diffs:?}");
+    assert!(diff_against(&a, &a).is_empty());
+  }
+}
@@ -0,0 +1,191 @@
+use std::{
+  hash::{BuildHasher, RandomState},
+  pin::Pin,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  task::{Context, Poll},
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+  body::{Body, Bytes, HttpBody},
+  extract::Request,
+  http::HeaderValue,
+  middleware::Next,
+  response::Response,
+};
+use http_body::{Frame, SizeHint};
+use tracing::{field, Instrument, Span};
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+// 一个入站请求的关联 id：沿用客户端的 x-request-id（没有则生成），并记下最近一次上游响应的 request-id
+#[derive(Debug)]
+struct RequestIds {
+  request_id: String,
+  upstream_request_id: Mutex<Option<String>>,
+  span: Span,
+}
+
+tokio::task_local! {
+  static CURRENT: Arc<RequestIds>;
+}
+
+fn is_valid_request_id(v: &str) -> bool {
+  !v.is_empty()
+    && v.len() <= 128
+    && v
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+}
+
+// UUID v4 形式；不追求密码学随机，只需进程内外都不易撞
+fn generate_request_id() -> String {
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_nanos())
+    .unwrap_or_default();
+  let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+  let s = RandomState::new();
+  let hi = s.hash_one((nanos, seq, 0u8));
+  let lo = s.hash_one((nanos, seq, 1u8));
+  format!(
+    "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+    hi >> 32,
+    (hi >> 16) & 0xffff,
+    hi & 0x0fff,
+    (lo >> 48) & 0x3fff | 0x8000,
+    lo & 0xffff_ffff_ffff
+  )
+}
+
+pub(crate) fn current_request_id() -> Option<String> {
+  CURRENT.try_with(|ids| ids.request_id.clone()).ok()
+}
+
+// 上游（Anthropic 为 request-id，OpenAI 等为 x-request-id）返回的 id；同时写入请求 span，之后的日志都会带上
+pub(crate) fn record_upstream_request_id(resp: &reqwest::Response) {
+  let Some(id) = ["request-id", REQUEST_ID_HEADER]
+    .iter()
+    .find_map(|h| resp.headers().get(*h)?.to_str().ok())
+    .map(str::trim)
+    .filter(|v| !v.is_empty())
+  else {
+    return;
+  };
+  let _ = CURRENT.try_with(|ids| {
+    ids.span.record("upstream_request_id", id);
+    if let Ok(mut u) = ids.upstream_request_id.lock() {
+      *u = Some(id.to_string());
+    }
+  });
+}
+
+// 给返回给 VS Code 的错误文本附上关联 id，便于对照代理日志与上游控制台
+pub(crate) fn annotate_error(message: String) -> String {
+  let Ok((request_id, upstream)) = CURRENT.try_with(|ids| {
+    let upstream = ids.upstream_request_id.lock().ok().and_then(|u| u.clone());
+    (ids.request_id.clone(), upstream)
+  }) else {
+    return message;
+  };
+  match upstream {
+    Some(u) => format!("{message}\n（request_id={request_id}，upstream_request_id={u}）"),
+    None => format!("{message}\n（request_id={request_id}）"),
+  }
+}
+
+// 流式响应体在 handler 返回后才被读取：读取时重新进入请求 span 与 id 上下文
+struct ScopedBody {
+  inner: Body,
+  ids: Arc<RequestIds>,
+}
+
+impl HttpBody for ScopedBody {
+  type Data = Bytes;
+  type Error = axum::Error;
+
+  fn poll_frame(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+    let ids = self.ids.clone();
+    let _entered = ids.span.enter();
+    CURRENT.sync_scope(ids.clone(), || Pin::new(&mut self.inner).poll_frame(cx))
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+pub(crate) async fn assign_request_id(mut req: Request, next: Next) -> Response {
+  let request_id = req
+    .headers()
+    .get(REQUEST_ID_HEADER)
+    .and_then(|v| v.to_str().ok())
+    .map(str::trim)
+    .filter(|v| is_valid_request_id(v))
+    .map(str::to_string)
+    .unwrap_or_else(generate_request_id);
+  // 写回请求头：透传到官方的请求因此总是带着同一个 id
+  if let Ok(v) = HeaderValue::from_str(&request_id) {
+    req.headers_mut().insert(REQUEST_ID_HEADER, v);
+  }
+  let span = tracing::info_span!(
+    "request",
+    request_id = %request_id,
+    upstream_request_id = field::Empty
+  );
+  let ids = Arc::new(RequestIds {
+    request_id,
+    upstream_request_id: Mutex::default(),
+    span: span.clone(),
+  });
+  let mut resp = CURRENT
+    .scope(ids.clone(), next.run(req).instrument(span))
+    .await;
+  if let Ok(v) = HeaderValue::from_str(&ids.request_id) {
+    resp.headers_mut().insert(REQUEST_ID_HEADER, v);
+  }
+  resp.map(|inner| Body::new(ScopedBody { inner, ids }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn annotates_errors_only_inside_a_request_scope() {
+    assert_eq!(annotate_error("boom".to_string()), "boom");
+
+    let id = generate_request_id();
+    assert!(is_valid_request_id(&id) && id.len() == 36, "{id}");
+    assert_ne!(id, generate_request_id());
+    assert!(!is_valid_request_id("a b") && !is_valid_request_id(""));
+
+    let ids = Arc::new(RequestIds {
+      request_id: "req-1".to_string(),
+      upstream_request_id: Mutex::new(Some("req_up_9".to_string())),
+      span: Span::none(),
+    });
+    let text = CURRENT
+      .scope(ids, async {
+        assert_eq!(current_request_id().as_deref(), Some("req-1"));
+        annotate_error("❌ 上游返回错误".to_string())
+      })
+      .await;
+    assert_eq!(
+      text,
+      "❌ 上游返回错误\n（request_id=req-1，upstream_request_id=req_up_9）"
+    );
+  }
+}
@@ -0,0 +1,284 @@
+use std::{
+  collections::hash_map::RandomState,
+  fmt,
+  hash::{BuildHasher, Hasher},
+  time::Duration,
+};
+
+use reqwest::{header::HeaderMap, RequestBuilder, Response};
+use tracing::warn;
+
+use crate::{config::RetryConfig, util::now_ms};
+
+const ANTHROPIC_RATELIMIT_KINDS: [&str; 4] =
+  ["requests", "tokens", "input-tokens", "output-tokens"];
+
+// 单次尝试在 header_timeout 内没有拿到响应头；与网络超时一样可重试，并计入 key 池/熔断失败
+#[derive(Debug)]
+pub struct HeaderTimeout(pub Duration);
+
+impl fmt::Display for HeaderTimeout {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "⏱️ 上游 {}s 内没有返回响应头（first_byte_timeout_seconds）",
+      self.0.as_secs()
+    )
+  }
+}
+
+impl std::error::Error for HeaderTimeout {}
+
+// 只在拿到响应头之前重试（此时还没有向客户端写出任何字节），流式请求同样适用；
+// header_timeout 按每次尝试计时，不包含熔断检查、限速等待与退避
+pub async fn send_with_retry(
+  policy: &RetryConfig,
+  header_timeout: Option<Duration>,
+  req: RequestBuilder,
+) -> anyhow::Result<Response> {
+  let max_attempts = policy.max_attempts.max(1);
+  let mut attempt: u32 = 1;
+  while attempt < max_attempts {
+    // body 为流时无法 clone，只能发一次
+    let Some(r) = req.try_clone() else {
+      break;
+    };
+    let delay = match send_once(r, header_timeout).await {
+      Ok(resp) => {
+        let status = resp.status();
+        if !policy.retryable_status_codes.contains(&status.as_u16()) {
+          return Ok(resp);
+        }
+        let delay = match retry_delay_from_headers(resp.headers(), now_ms()) {
+          Some(d) if d > Duration::from_millis(policy.max_backoff_ms) => {
+            warn!(url=%resp.url(), status=%status, retry_after_ms=d.as_millis() as u64, "上游要求的等待时间超过 retry.max_backoff_ms，不再重试");
+            return Ok(resp);
+          }
+          Some(d) => d,
+          None => backoff_delay(policy, attempt),
+        };
+        warn!(url=%resp.url(), status=%status, attempt, max_attempts, delay_ms=delay.as_millis() as u64, "上游返回可重试状态码，稍后重试");
+        delay
+      }
+      Err(err) => {
+        if !policy.retry_on_network_error || !is_retryable_error(&err) {
+          return Err(err);
+        }
+        let delay = backoff_delay(policy, attempt);
+        warn!(error=%err, attempt, max_attempts, delay_ms=delay.as_millis() as u64, "上游请求失败，稍后重试");
+        delay
+      }
+    };
+    tokio::time::sleep(delay).await;
+    attempt += 1;
+  }
+  send_once(req, header_timeout).await
+}
+
+async fn send_once(
+  req: RequestBuilder,
+  header_timeout: Option<Duration>,
+) -> anyhow::Result<Response> {
+  let Some(limit) = header_timeout else {
+    return Ok(req.send().await?);
+  };
+  match tokio::time::timeout(limit, req.send()).await {
+    Ok(result) => Ok(result?),
+    Err(_) => Err(HeaderTimeout(limit).into()),
+  }
+}
+
+fn is_retryable_error(err: &anyhow::Error) -> bool {
+  match err.downcast_ref::<reqwest::Error>() {
+    Some(err) => err.is_connect() || err.is_timeout(),
+    None => err.is::<HeaderTimeout>(),
+  }
+}
+
+fn backoff_delay(policy: &RetryConfig, attempt: u32) -> Duration {
+  let exp = policy
+    .initial_backoff_ms
+    .saturating_mul(1u64 << attempt.saturating_sub(1).min(20));
+  let capped = exp.min(policy.max_backoff_ms);
+  let half = capped / 2;
+  Duration::from_millis(half + random_u64() % (capped - half + 1))
+}
+
+fn random_u64() -> u64 {
+  let mut h = RandomState::new().build_hasher();
+  h.write_u64(now_ms());
+  h.finish()
+}
+
+fn retry_delay_from_headers(headers: &HeaderMap, now: u64) -> Option<Duration> {
+  let header = |name: &str| {
+    headers
+      .get(name)
+      .and_then(|v| v.to_str().ok())
+      .map(str::trim)
+  };
+
+  if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+    return Some(Duration::from_millis(ms.max(0.0) as u64));
+  }
+  if let Some(secs) = header("retry-after").and_then(|v| v.parse::<f64>().ok()) {
+    return Some(Duration::from_millis((secs.max(0.0) * 1000.0) as u64));
+  }
+
+  // 只看已耗尽（remaining=0）的那一类限额，取最晚的 reset
+  ANTHROPIC_RATELIMIT_KINDS
+    .iter()
+    .filter(|kind| header(&format!("anthropic-ratelimit-{kind}-remaining")) == Some("0"))
+    .filter_map(|kind| header(&format!("anthropic-ratelimit-{kind}-reset")))
+    .filter_map(parse_rfc3339_ms)
+    .map(|reset| Duration::from_millis(reset.saturating_sub(now)))
+    .max()
+}
+
+fn parse_rfc3339_ms(s: &str) -> Option<u64> {
+  let b = s.as_bytes();
+  if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+    return None;
+  }
+  let num = |from: usize, to: usize| s.get(from..to)?.parse::<i64>().ok();
+  let (year, month, day) = (num(0, 4)?, num(5, 7)?, num(8, 10)?);
+  let (hour, minute, second) = (num(11, 13)?, num(14, 16)?, num(17, 19)?);
+
+  let mut rest = &s[19..];
+  let mut millis: i64 = 0;
+  if let Some(frac) = rest.strip_prefix('.') {
+    let digits = frac.bytes().take_while(u8::is_ascii_digit).count();
+    let padded = format!("{:0<3}", &frac[..digits.min(3)]);
+    millis = padded.parse().ok()?;
+    rest = &frac[digits..];
+  }
+  let offset_secs = match rest {
+    "Z" | "z" => 0,
+    _ => {
+      let sign = match rest.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+      };
+      let (h, m) = rest.get(1..)?.split_once(':')?;
+      sign * (h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60)
+    }
+  };
+
+  // days_from_civil（proleptic Gregorian）
+  let y = if month <= 2 { year - 1 } else { year };
+  let era = y.div_euclid(400);
+  let yoe = y - era * 400;
+  let mp = (month + 9) % 12;
+  let doy = (153 * mp + 2) / 5 + day - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  let days = era * 146_097 + doe - 719_468;
+
+  let secs = days * 86_400 + hour * 3600 + minute * 60 + second - offset_secs;
+  u64::try_from(secs * 1000 + millis).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use reqwest::header::HeaderValue;
+
+  #[test]
+  fn parse_rfc3339_handles_fraction_and_offset() {
+    assert_eq!(parse_rfc3339_ms("1970-01-01T00:00:00Z"), Some(0));
+    assert_eq!(
+      parse_rfc3339_ms("2024-06-01T12:00:30.5Z"),
+      Some(1_717_243_230_500)
+    );
+    assert_eq!(
+      parse_rfc3339_ms("2024-06-01T20:00:30+08:00"),
+      Some(1_717_243_230_000)
+    );
+    assert_eq!(parse_rfc3339_ms("not a date"), None);
+  }
+
+  #[test]
+  fn retry_delay_prefers_retry_after_then_exhausted_anthropic_limits() {
+    let now = parse_rfc3339_ms("2024-06-01T12:00:00Z").unwrap();
+
+    let mut h = HeaderMap::new();
+    h.insert("retry-after", HeaderValue::from_static("2"));
+    h.insert(
+      "anthropic-ratelimit-tokens-reset",
+      HeaderValue::from_static("2024-06-01T12:00:30Z"),
+    );
+    assert_eq!(
+      retry_delay_from_headers(&h, now),
+      Some(Duration::from_secs(2))
+    );
+
+    let mut h = HeaderMap::new();
+    h.insert(
+      "anthropic-ratelimit-requests-remaining",
+      HeaderValue::from_static("10"),
+    );
+    h.insert(
+      "anthropic-ratelimit-requests-reset",
+      HeaderValue::from_static("2024-06-01T12:01:00Z"),
+    );
+    h.insert(
+      "anthropic-ratelimit-tokens-remaining",
+      HeaderValue::from_static("0"),
+    );
+    h.insert(
+      "anthropic-ratelimit-tokens-reset",
+      HeaderValue::from_static("2024-06-01T12:00:05Z"),
+    );
+    assert_eq!(
+      retry_delay_from_headers(&h, now),
+      Some(Duration::from_secs(5))
+    );
+
+    assert_eq!(retry_delay_from_headers(&HeaderMap::new(), now), None);
+  }
+
+  #[test]
+  fn backoff_is_jittered_and_capped() {
+    let policy = RetryConfig {
+      initial_backoff_ms: 400,
+      max_backoff_ms: 1000,
+      ..Default::default()
+    };
+    for _ in 0..50 {
+      let first = backoff_delay(&policy, 1).as_millis() as u64;
+      assert!((200..=400).contains(&first));
+      let late = backoff_delay(&policy, 10).as_millis() as u64;
+      assert!((500..=1000).contains(&late));
+    }
+  }
+
+  #[tokio::test]
+  async fn header_timeout_applies_per_attempt_and_is_retried() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // 只接受连接、从不返回响应头
+    let accepted = tokio::spawn(async move {
+      let mut conns = Vec::new();
+      while let Ok((conn, _)) = listener.accept().await {
+        conns.push(conn);
+      }
+    });
+
+    let policy = RetryConfig {
+      max_attempts: 3,
+      initial_backoff_ms: 1,
+      max_backoff_ms: 1,
+      ..Default::default()
+    };
+    let req = reqwest::Client::new().get(format!("http://{addr}/"));
+    let started = std::time::Instant::now();
+    let err = send_with_retry(&policy, Some(Duration::from_millis(100)), req)
+      .await
+      .unwrap_err();
+    assert!(err.is::<HeaderTimeout>(), "{err}");
+    assert!(err.to_string().contains("first_byte_timeout_seconds"));
+    // 三次尝试各自计时，而不是共用一个 100ms 的总预算
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    accepted.abort();
+  }
+}
@@ -0,0 +1,105 @@
+use serde_json::Value;
+
+use crate::config::{RoutingConfig, RoutingRule};
+
+fn path_matches(pattern: &str, path: &str) -> bool {
+  let pattern = pattern.trim();
+  match pattern.strip_suffix('*') {
+    Some(prefix) => path.starts_with(prefix),
+    None => pattern == path,
+  }
+}
+
+fn body_string<'a>(body: &'a Value, keys: &[&str]) -> &'a str {
+  keys
+    .iter()
+    .find_map(|k| body.get(*k).and_then(Value::as_str))
+    .map(str::trim)
+    .unwrap_or("")
+}
+
+// 只有规则用到 mode / message_source 时才解析 body；body 不是 JSON 时这类规则不命中
+pub(crate) fn match_rule<'a>(
+  routing: &'a RoutingConfig,
+  path: &str,
+  body: &[u8],
+) -> Option<&'a RoutingRule> {
+  let mut parsed: Option<Value> = None;
+  routing.rules.iter().find(|rule| {
+    if !path_matches(&rule.path, path) {
+      return false;
+    }
+    if rule.min_body_bytes.is_some_and(|min| body.len() < min)
+      || rule.max_body_bytes.is_some_and(|max| body.len() > max)
+    {
+      return false;
+    }
+    let (mode, source) = (rule.mode.trim(), rule.message_source.trim());
+    if mode.is_empty() && source.is_empty() {
+      return true;
+    }
+    let json = parsed.get_or_insert_with(|| serde_json::from_slice(body).unwrap_or(Value::Null));
+    (mode.is_empty()
+      || body_string(json, &["mode", "chatModeOverride", "chat_mode_override"])
+        .eq_ignore_ascii_case(mode))
+      && (source.is_empty() || body_string(json, &["message_source", "messageSource"]) == source)
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn rule(path: &str, mode: &str, target: &str) -> RoutingRule {
+    RoutingRule {
+      path: path.to_string(),
+      mode: mode.to_string(),
+      message_source: String::new(),
+      min_body_bytes: None,
+      max_body_bytes: None,
+      target: target.to_string(),
+    }
+  }
+
+  #[test]
+  fn first_matching_rule_wins() {
+    let mut big = rule("/chat-stream", "", "byok:big:model");
+    big.min_body_bytes = Some(100);
+    let routing = RoutingConfig {
+      rules: vec![
+        rule("/chat-stream", "agent", "byok:anthropic:claude"),
+        big,
+        rule("/chat*", "", "official"),
+        rule("*", "", "disabled"),
+      ],
+    };
+
+    let agent = br#"{"mode":"AGENT","message":"hi"}"#;
+    assert_eq!(
+      match_rule(&routing, "/chat-stream", agent).unwrap().target,
+      "byok:anthropic:claude"
+    );
+
+    let chat = br#"{"mode":"CHAT"}"#;
+    assert_eq!(
+      match_rule(&routing, "/chat-stream", chat).unwrap().target,
+      "official"
+    );
+
+    let large = format!(r#"{{"mode":"CHAT","message":"{}"}}"#, "x".repeat(200));
+    assert_eq!(
+      match_rule(&routing, "/chat-stream", large.as_bytes())
+        .unwrap()
+        .target,
+      "byok:big:model"
+    );
+
+    assert_eq!(
+      match_rule(&routing, "/completion", b"not json")
+        .unwrap()
+        .target,
+      "disabled"
+    );
+    assert!(match_rule(&RoutingConfig::default(), "/chat", b"{}").is_none());
+  }
+}
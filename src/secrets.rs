@@ -0,0 +1,255 @@
+use std::{collections::BTreeMap, process::Command};
+
+use anyhow::Context;
+use serde_json::Value;
+
+// config.yaml 字符串字段里的引用：${VAR} / ${VAR:-default}（可嵌在字符串中，$${ 转义为字面量 ${）、
+// 整串 file:<path>（读文件）、整串 cmd:<command>（取本地命令 stdout，如 `cmd:pass show llm/anthropic`）。
+// 记录 JSON pointer -> (原始引用, 解析值)，写回配置时还原为引用，避免把 secret 落盘或暴露给管理台。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecretRefs(BTreeMap<String, SecretRef>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SecretRef {
+  reference: String,
+  resolved: String,
+}
+
+impl SecretRefs {
+  pub fn resolve(root: &mut Value) -> anyhow::Result<Self> {
+    Self::resolve_with(root, |_, s| resolve_reference(s))
+  }
+
+  // 管理台提交的配置：${VAR} 照常解析；file: / cmd: 只接受 known（当前配置）里已有的引用，
+  // 沿用已解析的值，不重新读文件、执行命令——新的 file: / cmd: 只能写在配置文件里。
+  // 按引用原文匹配而不是按字段，删除/调整 provider 顺序后数组下标变化也能沿用
+  pub fn resolve_known(root: &mut Value, known: &SecretRefs) -> anyhow::Result<Self> {
+    Self::resolve_with(root, |_, s| {
+      if !(s.starts_with("file:") || s.starts_with("cmd:")) {
+        return resolve_reference(s);
+      }
+      match known.0.values().find(|r| r.reference == s) {
+        Some(r) => Ok(Some(r.resolved.clone())),
+        None => anyhow::bail!("管理台不能新增或改动 file: / cmd: 引用，请在配置文件中修改"),
+      }
+    })
+  }
+
+  fn resolve_with(
+    root: &mut Value,
+    mut resolve: impl FnMut(&str, &str) -> anyhow::Result<Option<String>>,
+  ) -> anyhow::Result<Self> {
+    let mut refs = BTreeMap::new();
+    walk_strings(root, &mut String::new(), &mut |pointer, s| {
+      let Some(resolved) =
+        resolve(pointer, s).with_context(|| format!("解析配置引用失败 ({pointer})"))?
+      else {
+        return Ok(());
+      };
+      let reference = std::mem::replace(s, resolved.clone());
+      refs.insert(
+        pointer.to_string(),
+        SecretRef {
+          reference,
+          resolved,
+        },
+      );
+      Ok(())
+    })?;
+    Ok(Self(refs))
+  }
+
+  // 只还原仍等于解析值的字段；已被改成字面量的字段保持新值
+  pub fn restore(&self, root: &mut Value) {
+    for (pointer, r) in &self.0 {
+      if let Some(v) = root.pointer_mut(pointer) {
+        if v.as_str() == Some(r.resolved.as_str()) {
+          *v = Value::String(r.reference.clone());
+        }
+      }
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+}
+
+fn walk_strings(
+  v: &mut Value,
+  pointer: &mut String,
+  f: &mut impl FnMut(&str, &mut String) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+  let len = pointer.len();
+  match v {
+    Value::String(s) => f(pointer, s)?,
+    Value::Array(items) => {
+      for (i, item) in items.iter_mut().enumerate() {
+        pointer.push_str(&format!("/{i}"));
+        walk_strings(item, pointer, f)?;
+        pointer.truncate(len);
+      }
+    }
+    Value::Object(map) => {
+      for (k, item) in map.iter_mut() {
+        pointer.push('/');
+        pointer.push_str(&k.replace('~', "~0").replace('/', "~1"));
+        walk_strings(item, pointer, f)?;
+        pointer.truncate(len);
+      }
+    }
+    _ => {}
+  }
+  Ok(())
+}
+
+// 不含引用时返回 None
+fn resolve_reference(s: &str) -> anyhow::Result<Option<String>> {
+  if let Some(path) = s.strip_prefix("file:") {
+    let path = path.trim();
+    let text =
+      std::fs::read_to_string(path).with_context(|| format!("读取 secret 文件失败: {path}"))?;
+    return Ok(Some(text.trim_end_matches(['\r', '\n']).to_string()));
+  }
+  if let Some(cmd) = s.strip_prefix("cmd:") {
+    return run_command(cmd.trim()).map(Some);
+  }
+  if s.contains("${") {
+    return interpolate_env(s).map(Some);
+  }
+  Ok(None)
+}
+
+fn run_command(cmd: &str) -> anyhow::Result<String> {
+  if cmd.is_empty() {
+    anyhow::bail!("cmd: 后的命令为空");
+  }
+  let output = if cfg!(windows) {
+    Command::new("cmd").args(["/C", cmd]).output()
+  } else {
+    Command::new("sh").args(["-c", cmd]).output()
+  }
+  .with_context(|| format!("执行命令失败: {cmd}"))?;
+  if !output.status.success() {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    anyhow::bail!(
+      "命令退出码 {}: {cmd}; stderr: {}",
+      output.status,
+      stderr.trim()
+    );
+  }
+  let stdout = String::from_utf8(output.stdout).context("命令输出不是 UTF-8")?;
+  Ok(stdout.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn interpolate_env(s: &str) -> anyhow::Result<String> {
+  let mut out = String::with_capacity(s.len());
+  let mut rest = s;
+  while let Some(i) = rest.find("${") {
+    if rest[..i].ends_with('$') {
+      out.push_str(&rest[..i - 1]);
+      out.push_str("${");
+      rest = &rest[i + 2..];
+      continue;
+    }
+    out.push_str(&rest[..i]);
+    let after = &rest[i + 2..];
+    let end = after.find('}').context("${ 缺少对应的 }")?;
+    let expr = &after[..end];
+    let (name, default) = match expr.split_once(":-") {
+      Some((name, default)) => (name, Some(default)),
+      None => (expr, None),
+    };
+    let valid_name = name
+      .chars()
+      .next()
+      .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+      && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+      anyhow::bail!("环境变量名不合法: {name:?}");
+    }
+    // 与 shell 一致：:- 在变量未设置或为空时取默认值
+    let value = match (std::env::var(name), default) {
+      (Ok(v), Some(d)) if v.is_empty() => d.to_string(),
+      (Ok(v), _) => v,
+      (Err(_), Some(d)) => d.to_string(),
+      (Err(_), None) => anyhow::bail!("环境变量 {name} 未设置"),
+    };
+    out.push_str(&value);
+    rest = &after[end + 1..];
+  }
+  out.push_str(rest);
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn resolves_references_and_restores_them_for_writing() {
+    std::env::set_var("BYOK_SECRETS_TEST_KEY", "sk-env");
+    std::env::remove_var("BYOK_SECRETS_TEST_MISSING");
+    let file = std::env::temp_dir().join(format!("byok_secret_test_{}", std::process::id()));
+    std::fs::write(&file, "sk-file\n").unwrap();
+
+    let mut v = serde_json::json!({
+      "official": { "api_token": "Bearer ${BYOK_SECRETS_TEST_KEY}", "base_url": "https://x/" },
+      "byok": { "providers": [
+        { "api_key": format!("file:{}", file.display()) },
+        { "api_key": ["cmd:echo sk-cmd", "${BYOK_SECRETS_TEST_MISSING:-fallback}", "$${LITERAL}"] },
+      ] },
+      "prices": { "a/b": "${BYOK_SECRETS_TEST_KEY}" },
+    });
+    let refs = SecretRefs::resolve(&mut v).unwrap();
+    assert_eq!(v["official"]["api_token"], "Bearer sk-env");
+    assert_eq!(v["byok"]["providers"][0]["api_key"], "sk-file");
+    assert_eq!(
+      v["byok"]["providers"][1]["api_key"],
+      serde_json::json!(["sk-cmd", "fallback", "${LITERAL}"])
+    );
+    assert_eq!(v["prices"]["a/b"], "sk-env");
+
+    // 被改成字面量的字段不还原
+    v["byok"]["providers"][0]["api_key"] = "sk-new".into();
+    refs.restore(&mut v);
+    assert_eq!(
+      v["official"]["api_token"],
+      "Bearer ${BYOK_SECRETS_TEST_KEY}"
+    );
+    assert_eq!(v["byok"]["providers"][0]["api_key"], "sk-new");
+    assert_eq!(v["byok"]["providers"][1]["api_key"][0], "cmd:echo sk-cmd");
+    assert_eq!(v["prices"]["a/b"], "${BYOK_SECRETS_TEST_KEY}");
+
+    let err = SecretRefs::resolve(&mut serde_json::json!({ "k": "${BYOK_SECRETS_TEST_MISSING}" }))
+      .unwrap_err();
+    assert!(format!("{err:#}").contains("/k"), "{err:#}");
+    let _ = std::fs::remove_file(&file);
+  }
+
+  #[test]
+  fn admin_updates_only_reuse_known_file_and_cmd_references() {
+    std::env::set_var("BYOK_SECRETS_ADMIN_TEST_KEY", "sk-env");
+    let mut current = serde_json::json!({ "a": "cmd:echo sk-cmd", "b": "plain" });
+    let known = SecretRefs::resolve(&mut current).unwrap();
+
+    // 已有的引用（换了字段也一样）：沿用已解析的值；${ENV} 照常解析
+    let mut v =
+      serde_json::json!({ "a": "${BYOK_SECRETS_ADMIN_TEST_KEY}", "b": "cmd:echo sk-cmd" });
+    let refs = SecretRefs::resolve_known(&mut v, &known).unwrap();
+    assert_eq!(v, serde_json::json!({ "a": "sk-env", "b": "sk-cmd" }));
+    refs.restore(&mut v);
+    assert_eq!(v["b"], "cmd:echo sk-cmd");
+
+    let marker = std::env::temp_dir().join(format!("byok_admin_cmd_test_{}", std::process::id()));
+    for v in [
+      serde_json::json!({ "a": format!("cmd:touch {}", marker.display()) }),
+      serde_json::json!({ "b": "cmd:echo sk-cmd; id" }),
+      serde_json::json!({ "b": "file:/etc/passwd" }),
+    ] {
+      let err = SecretRefs::resolve_known(&mut v.clone(), &known).unwrap_err();
+      assert!(format!("{err:#}").contains("管理台不能新增"), "{err:#}");
+    }
+    assert!(!marker.exists());
+  }
+}
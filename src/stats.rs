@@ -0,0 +1,507 @@
+use std::{
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+  },
+  time::Instant,
+};
+
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+  anthropic::AnthropicUsage,
+  gemini::GeminiUsageMetadata,
+  metrics,
+  openai::OpenAIUsage,
+  openai_responses::OpenAIResponsesUsage,
+  protocol::AugmentStreamChunk,
+  rate_limit::RateLimitTicket,
+  usage::{UsageLedger, UsageRecord},
+  util::now_ms,
+};
+
+// 进程级统计（重启清零）
+#[derive(Debug, Default)]
+pub(crate) struct ProxyStats {
+  chat_streams_started: AtomicU64,
+  chat_streams_completed: AtomicU64,
+  chat_streams_cancelled: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ProxyStatsSnapshot {
+  chat_streams_started: u64,
+  chat_streams_completed: u64,
+  chat_streams_cancelled: u64,
+}
+
+impl ProxyStats {
+  pub(crate) fn snapshot(&self) -> ProxyStatsSnapshot {
+    ProxyStatsSnapshot {
+      chat_streams_started: self.chat_streams_started.load(Ordering::Relaxed),
+      chat_streams_completed: self.chat_streams_completed.load(Ordering::Relaxed),
+      chat_streams_cancelled: self.chat_streams_cancelled.load(Ordering::Relaxed),
+    }
+  }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TurnUsage {
+  pub(crate) text_chars: usize,
+  pub(crate) input_tokens: Option<i64>,
+  pub(crate) output_tokens: Option<i64>,
+  pub(crate) cache_read_tokens: Option<i64>,
+  pub(crate) cache_creation_tokens: Option<i64>,
+  // Gemini / OpenAI Responses 的输入 token 已包含缓存命中部分（Anthropic 的 input_tokens 不含）
+  pub(crate) input_includes_cache_read: bool,
+}
+
+impl TurnUsage {
+  // 不含缓存命中的输入 token，账本与指标里 input 与 cache_read 分开计，避免缓存部分被重复计费
+  pub(crate) fn uncached_input_tokens(&self) -> Option<i64> {
+    match (self.input_includes_cache_read, self.input_tokens) {
+      (true, Some(input)) => Some((input - self.cache_read_tokens.unwrap_or(0)).max(0)),
+      (_, input) => input,
+    }
+  }
+}
+
+// 上游流在生成过程中持续更新，客户端断开时据此记录已产生的内容与用量
+#[derive(Debug, Default)]
+pub(crate) struct TurnProgress {
+  usage: Mutex<TurnUsage>,
+  rate_limit_ticket: Mutex<Option<RateLimitTicket>>,
+}
+
+impl TurnProgress {
+  pub(crate) fn attach_rate_limit_ticket(&self, resp: &reqwest::Response) {
+    if let Ok(mut t) = self.rate_limit_ticket.lock() {
+      *t = resp.extensions().get::<RateLimitTicket>().cloned();
+    }
+  }
+
+  pub(crate) fn on_chunk(&self, chunk: &AugmentStreamChunk) {
+    if let Ok(mut u) = self.usage.lock() {
+      u.text_chars += chunk.text.chars().count();
+    }
+  }
+
+  pub(crate) fn on_usage(&self, input_tokens: Option<i64>, output_tokens: Option<i64>) {
+    if let Ok(mut u) = self.usage.lock() {
+      u.input_tokens = input_tokens.or(u.input_tokens);
+      u.output_tokens = output_tokens.or(u.output_tokens);
+    }
+  }
+
+  pub(crate) fn on_cache_usage(&self, read_tokens: Option<i64>, creation_tokens: Option<i64>) {
+    if let Ok(mut u) = self.usage.lock() {
+      u.cache_read_tokens = read_tokens.or(u.cache_read_tokens);
+      u.cache_creation_tokens = creation_tokens.or(u.cache_creation_tokens);
+    }
+  }
+
+  pub(crate) fn on_cached_input_usage(&self, read_tokens: Option<i64>) {
+    if let Ok(mut u) = self.usage.lock() {
+      u.cache_read_tokens = read_tokens.or(u.cache_read_tokens);
+      u.input_includes_cache_read = true;
+    }
+  }
+
+  pub(crate) fn on_anthropic_usage(&self, usage: &AnthropicUsage) {
+    self.on_usage(usage.input_tokens, usage.output_tokens);
+    self.on_cache_usage(
+      usage.cache_read_input_tokens,
+      usage.cache_creation_input_tokens,
+    );
+  }
+
+  pub(crate) fn on_openai_usage(&self, usage: &OpenAIUsage) {
+    self.on_usage(usage.prompt_tokens, usage.completion_tokens);
+    self.on_cached_input_usage(usage.cached_tokens());
+  }
+
+  // 与 GeminiStreamState::on_usage 一致：思考 token 计入输出
+  pub(crate) fn on_gemini_usage(&self, usage: &GeminiUsageMetadata) {
+    let output = (usage.candidates_token_count.is_some() || usage.thoughts_token_count.is_some())
+      .then(|| usage.candidates_token_count.unwrap_or(0) + usage.thoughts_token_count.unwrap_or(0));
+    self.on_usage(usage.prompt_token_count, output);
+    self.on_cached_input_usage(usage.cached_content_token_count);
+  }
+
+  pub(crate) fn on_openai_responses_usage(&self, usage: &OpenAIResponsesUsage) {
+    self.on_usage(usage.input_tokens, usage.output_tokens);
+    let cached = usage
+      .input_tokens_details
+      .as_ref()
+      .and_then(|d| d.cached_tokens);
+    self.on_cached_input_usage(cached);
+  }
+
+  pub(crate) fn snapshot(&self) -> TurnUsage {
+    self.usage.lock().map(|u| *u).unwrap_or_default()
+  }
+
+  // 用已记录的 usage 校正发送时预扣的令牌桶（输入按实际修正，输出事后扣）；只生效一次
+  pub(crate) fn reconcile_rate_limit(&self) {
+    let ticket = self
+      .rate_limit_ticket
+      .lock()
+      .ok()
+      .and_then(|mut t| t.take());
+    if let Some(ticket) = ticket {
+      let usage = self.snapshot();
+      ticket.reconcile(usage.input_tokens, usage.output_tokens);
+    }
+  }
+}
+
+// 非 chat-stream 的调用（文本端点、provider_complete_text、历史摘要）不经过 ChatStreamTurn，释放时在这里校正
+impl Drop for TurnProgress {
+  fn drop(&mut self) {
+    self.reconcile_rate_limit();
+  }
+}
+
+// 一次 chat-stream 响应的生命周期：流被读完之前就被 drop（客户端断开/点了停止）即视为取消。
+// 上游响应体随流一起 drop，连接随即关闭，不会继续在后台消耗 token。
+pub(crate) struct ChatStreamTurn {
+  stats: Arc<ProxyStats>,
+  progress: Arc<TurnProgress>,
+  provider_id: String,
+  model: String,
+  started: Instant,
+  finished: bool,
+  usage_ledger: Option<(Arc<UsageLedger>, UsageRecord)>,
+}
+
+impl ChatStreamTurn {
+  pub(crate) fn start(
+    stats: Arc<ProxyStats>,
+    progress: Arc<TurnProgress>,
+    provider_id: &str,
+    model: &str,
+  ) -> Self {
+    stats.chat_streams_started.fetch_add(1, Ordering::Relaxed);
+    Self {
+      stats,
+      progress,
+      provider_id: provider_id.to_string(),
+      model: model.to_string(),
+      started: Instant::now(),
+      finished: false,
+      usage_ledger: None,
+    }
+  }
+
+  // record 在发起上游请求时创建（ts_ms 即起点），结束时补上用量/耗时/状态后写入账本
+  pub(crate) fn with_usage_ledger(mut self, ledger: Arc<UsageLedger>, record: UsageRecord) -> Self {
+    self.usage_ledger = Some((ledger, record));
+    self
+  }
+
+  pub(crate) fn finish(&mut self) {
+    self.finished = true;
+  }
+}
+
+impl Drop for ChatStreamTurn {
+  fn drop(&mut self) {
+    self.progress.reconcile_rate_limit();
+    let usage = self.progress.snapshot();
+    record_token_metrics(&self.provider_id, &self.model, &usage);
+    if let Some((ledger, record)) = self.usage_ledger.take() {
+      let status = if self.finished {
+        "completed"
+      } else {
+        "cancelled"
+      };
+      ledger.record(finish_usage_record(record, &usage, status));
+    }
+    let elapsed_ms = self.started.elapsed().as_millis() as u64;
+    if self.finished {
+      self
+        .stats
+        .chat_streams_completed
+        .fetch_add(1, Ordering::Relaxed);
+      metrics::record_chat_stream("completed");
+      debug!(provider=%self.provider_id, model=%self.model, elapsed_ms, text_chars=usage.text_chars, input_tokens=?usage.input_tokens, output_tokens=?usage.output_tokens, "chat-stream 完成");
+    } else {
+      self
+        .stats
+        .chat_streams_cancelled
+        .fetch_add(1, Ordering::Relaxed);
+      metrics::record_chat_stream("cancelled");
+      info!(provider=%self.provider_id, model=%self.model, elapsed_ms, text_chars=usage.text_chars, input_tokens=?usage.input_tokens, output_tokens=?usage.output_tokens, "chat-stream 客户端已断开，上游请求已中止");
+    }
+  }
+}
+
+// 非 chat-stream 的一次上游调用（文本端点、provider_complete_text、历史摘要），释放时把用量写入账本。
+// 没有 finish 时记为 error；流式响应开始输出后调用 streaming，此后中途断开记为 cancelled
+pub(crate) struct UpstreamCall {
+  pub(crate) progress: TurnProgress,
+  usage_ledger: Option<Arc<UsageLedger>>,
+  record: UsageRecord,
+  status: &'static str,
+}
+
+impl UpstreamCall {
+  // usage_ledger 为 None（usage.enabled=false）时只校正限速、记录指标，不写账本
+  pub(crate) fn start(usage_ledger: Option<Arc<UsageLedger>>, record: UsageRecord) -> Self {
+    Self {
+      progress: TurnProgress::default(),
+      usage_ledger,
+      record,
+      status: "error",
+    }
+  }
+
+  pub(crate) fn streaming(&mut self) {
+    self.status = "cancelled";
+  }
+
+  pub(crate) fn fail(&mut self) {
+    self.status = "error";
+  }
+
+  pub(crate) fn finish(&mut self) {
+    self.status = "completed";
+  }
+}
+
+impl Drop for UpstreamCall {
+  fn drop(&mut self) {
+    self.progress.reconcile_rate_limit();
+    let usage = self.progress.snapshot();
+    record_token_metrics(&self.record.provider, &self.record.model, &usage);
+    if let Some(ledger) = self.usage_ledger.take() {
+      let record = std::mem::take(&mut self.record);
+      ledger.record(finish_usage_record(record, &usage, self.status));
+    }
+  }
+}
+
+fn record_token_metrics(provider_id: &str, model: &str, usage: &TurnUsage) {
+  for (kind, tokens) in [
+    ("input", usage.uncached_input_tokens()),
+    ("output", usage.output_tokens),
+    ("cache_read", usage.cache_read_tokens),
+    ("cache_creation", usage.cache_creation_tokens),
+  ] {
+    metrics::record_tokens(provider_id, model, kind, tokens);
+  }
+}
+
+// 补上用量、耗时（自 record.ts_ms 起）与状态
+fn finish_usage_record(mut record: UsageRecord, usage: &TurnUsage, status: &str) -> UsageRecord {
+  record.input_tokens = usage.uncached_input_tokens();
+  record.output_tokens = usage.output_tokens;
+  record.cache_read_tokens = usage.cache_read_tokens;
+  record.cache_creation_tokens = usage.cache_creation_tokens;
+  record.latency_ms = now_ms().saturating_sub(record.ts_ms);
+  record.status = status.to_string();
+  record
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dropped_turn_counts_as_cancelled() {
+    let stats = Arc::new(ProxyStats::default());
+    let progress = Arc::new(TurnProgress::default());
+    progress.on_usage(Some(120), None);
+    progress.on_chunk(&AugmentStreamChunk {
+      text: "你好".to_string(),
+      unknown_blob_names: Vec::new(),
+      checkpoint_not_found: false,
+      workspace_file_chunks: Vec::new(),
+      nodes: Vec::new(),
+      stop_reason: None,
+    });
+    progress.on_usage(None, Some(3));
+    assert_eq!(
+      progress.snapshot(),
+      TurnUsage {
+        text_chars: 2,
+        input_tokens: Some(120),
+        output_tokens: Some(3),
+        ..Default::default()
+      }
+    );
+
+    drop(ChatStreamTurn::start(
+      stats.clone(),
+      progress.clone(),
+      "p",
+      "m",
+    ));
+    let mut done = ChatStreamTurn::start(stats.clone(), progress, "p", "m");
+    done.finish();
+    drop(done);
+
+    let s = stats.snapshot();
+    assert_eq!(
+      (
+        s.chat_streams_started,
+        s.chat_streams_completed,
+        s.chat_streams_cancelled
+      ),
+      (2, 1, 1)
+    );
+  }
+
+  #[tokio::test]
+  async fn dropped_progress_reconciles_rate_limit_ticket() {
+    use crate::{
+      config::RateLimitConfig,
+      rate_limit::{wait_for_capacity, SharedRateLimits},
+    };
+    use std::time::Duration;
+
+    let registry = SharedRateLimits::default();
+    let cfg = RateLimitConfig {
+      output_tokens_per_minute: 600,
+      ..Default::default()
+    };
+    let mut resp = reqwest::Response::from(axum::http::Response::new(""));
+    resp
+      .extensions_mut()
+      .insert(wait_for_capacity(&registry, "p", "k", &cfg, 0).await);
+
+    // 非 chat-stream 调用：只有 TurnProgress，没有 ChatStreamTurn
+    let progress = TurnProgress::default();
+    progress.attach_rate_limit_ticket(&resp);
+    progress.on_openai_usage(&OpenAIUsage {
+      prompt_tokens: Some(10),
+      completion_tokens: Some(1_200),
+      ..Default::default()
+    });
+    drop(progress);
+
+    // 输出欠 600，令牌桶 60s 后才放行下一个请求
+    let next = tokio::time::timeout(
+      Duration::from_millis(50),
+      wait_for_capacity(&registry, "p", "k", &cfg, 0),
+    )
+    .await;
+    assert!(next.is_err());
+  }
+
+  #[tokio::test]
+  async fn upstream_call_writes_ledger_with_status() {
+    let path = std::env::temp_dir().join(format!("usage_ledger_call_test_{}.jsonl", now_ms()));
+    let ledger = Arc::new(UsageLedger::open(path.clone()).await);
+
+    let mut ok = UpstreamCall::start(
+      Some(ledger.clone()),
+      UsageRecord::new("/generate-conversation-title", "", "p", "m"),
+    );
+    ok.progress.on_openai_usage(&OpenAIUsage {
+      prompt_tokens: Some(40),
+      completion_tokens: Some(5),
+      ..Default::default()
+    });
+    ok.finish();
+    drop(ok);
+    drop(UpstreamCall::start(
+      Some(ledger.clone()),
+      UsageRecord::new("history_summary", "c", "p", "m"),
+    ));
+    let mut cut = UpstreamCall::start(
+      Some(ledger.clone()),
+      UsageRecord::new("/instruction-stream", "", "p", "m"),
+    );
+    cut.streaming();
+    drop(cut);
+    drop(UpstreamCall::start(
+      None,
+      UsageRecord::new("/edit", "", "p", "m"),
+    ));
+
+    let records = loop {
+      let records = ledger.load(0).await.unwrap();
+      if records.len() >= 3 {
+        break records;
+      }
+      tokio::task::yield_now().await;
+    };
+    let summary: Vec<_> = records
+      .iter()
+      .map(|r| {
+        (
+          r.endpoint.as_str(),
+          r.status.as_str(),
+          r.input_tokens,
+          r.output_tokens,
+        )
+      })
+      .collect();
+    assert_eq!(
+      summary,
+      [
+        (
+          "/generate-conversation-title",
+          "completed",
+          Some(40),
+          Some(5)
+        ),
+        ("history_summary", "error", None, None),
+        ("/instruction-stream", "cancelled", None, None),
+      ]
+    );
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[test]
+  fn cached_input_is_billed_once() {
+    let gemini = TurnProgress::default();
+    gemini.on_gemini_usage(&GeminiUsageMetadata {
+      prompt_token_count: Some(100),
+      candidates_token_count: Some(5),
+      cached_content_token_count: Some(40),
+      ..Default::default()
+    });
+    let record = finish_usage_record(
+      UsageRecord::new("/chat-stream", "c", "g", "m"),
+      &gemini.snapshot(),
+      "completed",
+    );
+    assert_eq!(
+      (record.input_tokens, record.cache_read_tokens),
+      (Some(60), Some(40))
+    );
+
+    // Anthropic 的 input_tokens 本就不含 cache_read
+    let anthropic = TurnProgress::default();
+    anthropic.on_anthropic_usage(&AnthropicUsage {
+      input_tokens: Some(100),
+      cache_read_input_tokens: Some(40),
+      ..Default::default()
+    });
+    assert_eq!(anthropic.snapshot().uncached_input_tokens(), Some(100));
+  }
+
+  #[test]
+  fn openai_cached_prompt_tokens_are_billed_once() {
+    for body in [
+      r#"{"prompt_tokens":100,"completion_tokens":5,"prompt_tokens_details":{"cached_tokens":30}}"#,
+      r#"{"prompt_tokens":100,"completion_tokens":5,"prompt_cache_hit_tokens":30,"prompt_cache_miss_tokens":70}"#,
+    ] {
+      let usage: OpenAIUsage = serde_json::from_str(body).unwrap();
+      let progress = TurnProgress::default();
+      progress.on_openai_usage(&usage);
+      let record = finish_usage_record(
+        UsageRecord::new("/chat", "c", "p", "m"),
+        &progress.snapshot(),
+        "completed",
+      );
+      assert_eq!(
+        (record.input_tokens, record.cache_read_tokens),
+        (Some(70), Some(30))
+      );
+    }
+  }
+}
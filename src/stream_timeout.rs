@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncBufRead, Lines};
+
+// 流式请求的两段超时：收到响应头后到首个数据（等待响应头的每次尝试同样受 first_byte 限制），以及之后相邻两行数据之间的间隔
+pub(crate) struct StreamTimeouts {
+  first_byte: Duration,
+  idle: Duration,
+  started: Instant,
+  received_any: bool,
+}
+
+impl StreamTimeouts {
+  pub(crate) fn start(first_byte: Duration, idle: Duration) -> Self {
+    Self {
+      first_byte,
+      idle,
+      started: Instant::now(),
+      received_any: false,
+    }
+  }
+
+  fn first_byte_error(&self) -> String {
+    format!(
+      "⏱️ 上游 {}s 内没有返回任何数据（first_byte_timeout_seconds），已中断",
+      self.first_byte.as_secs()
+    )
+  }
+
+  pub(crate) fn first_byte(&self) -> Duration {
+    self.first_byte
+  }
+
+  // 响应头按每次尝试单独计时（UpstreamPolicy::header_timeout），拿到响应头后首个数据重新计时
+  pub(crate) fn headers_received(&mut self) {
+    self.started = Instant::now();
+  }
+
+  // 读取错误按流结束处理（与原先 while let Ok(Some(line)) 的行为一致）；超时返回 Err
+  pub(crate) async fn next_line<R: AsyncBufRead + Unpin>(
+    &mut self,
+    lines: &mut Lines<R>,
+  ) -> Result<Option<String>, String> {
+    let wait = if self.received_any {
+      self.idle
+    } else {
+      self.first_byte.saturating_sub(self.started.elapsed())
+    };
+    match tokio::time::timeout(wait, lines.next_line()).await {
+      Ok(Ok(Some(line))) => {
+        self.received_any = true;
+        Ok(Some(line))
+      }
+      Ok(Ok(None)) | Ok(Err(_)) => Ok(None),
+      Err(_) if self.received_any => Err(format!(
+        "⏱️ 上游流已 {}s 没有新数据（idle_timeout_seconds），已中断",
+        self.idle.as_secs()
+      )),
+      Err(_) => Err(self.first_byte_error()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+  #[tokio::test]
+  async fn first_byte_then_idle_timeouts() {
+    let (mut tx, rx) = tokio::io::duplex(64);
+    let mut lines = BufReader::new(rx).lines();
+    let mut t = StreamTimeouts::start(Duration::from_millis(200), Duration::from_millis(30));
+
+    tx.write_all(b"data: a\n").await.unwrap();
+    assert_eq!(
+      t.next_line(&mut lines).await,
+      Ok(Some("data: a".to_string()))
+    );
+
+    let err = t.next_line(&mut lines).await.unwrap_err();
+    assert!(err.contains("idle_timeout_seconds"), "{err}");
+
+    let (_tx, rx) = tokio::io::duplex(64);
+    let mut lines = BufReader::new(rx).lines();
+    let mut t = StreamTimeouts::start(Duration::from_millis(200), Duration::from_millis(30));
+    let err = t.next_line(&mut lines).await.unwrap_err();
+    assert!(err.contains("first_byte_timeout_seconds"), "{err}");
+  }
+}
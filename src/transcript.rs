@@ -0,0 +1,297 @@
+use std::{
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+  },
+  time::{Duration, SystemTime},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::{
+  config::{Config, TranscriptConfig},
+  util::now_ms,
+};
+
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+// 一次 chat-stream 请求的完整记录；由上游生成器与输出流共同持有，全部 drop 后写入文件
+pub(crate) struct Transcript {
+  path: PathBuf,
+  cfg: TranscriptConfig,
+  secrets: Vec<String>,
+  data: Mutex<TranscriptData>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct TranscriptData {
+  started_at_ms: u64,
+  endpoint: String,
+  conversation_id: String,
+  provider: String,
+  model: String,
+  augment_request: Value,
+  attempts: Vec<TranscriptAttempt>,
+  emitted_ndjson: Vec<String>,
+  truncated: bool,
+  #[serde(skip)]
+  bytes: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct TranscriptAttempt {
+  provider: String,
+  model: String,
+  upstream_request: Value,
+  upstream_sse: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  error: Option<String>,
+}
+
+impl Transcript {
+  // 未开启或不满足 logging.transcripts 过滤条件时返回 None
+  pub(crate) fn start(
+    cfg: &Config,
+    config_path: &Path,
+    provider_id: &str,
+    model: &str,
+    conversation_id: &str,
+    augment_body: &[u8],
+  ) -> Option<Self> {
+    let tc = &cfg.logging.transcripts;
+    if !tc.matches(provider_id, model, conversation_id) {
+      return None;
+    }
+    let started_at_ms = now_ms();
+    let conv: String = conversation_id
+      .chars()
+      .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+      .take(40)
+      .collect();
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+    let file_name = format!(
+      "{started_at_ms:013}-{seq:06}-{}.json",
+      if conv.is_empty() { "none" } else { &conv }
+    );
+    let augment_request = serde_json::from_slice(augment_body)
+      .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(augment_body).into_owned()));
+    Some(Self {
+      path: config_path.with_file_name(tc.dir.trim()).join(file_name),
+      cfg: tc.clone(),
+      secrets: collect_secrets(cfg),
+      data: Mutex::new(TranscriptData {
+        started_at_ms,
+        endpoint: "/chat-stream".to_string(),
+        conversation_id: conversation_id.to_string(),
+        provider: provider_id.to_string(),
+        model: model.to_string(),
+        augment_request,
+        bytes: augment_body.len(),
+        ..Default::default()
+      }),
+    })
+  }
+
+  pub(crate) fn begin_attempt(&self, provider_id: &str, model: &str) {
+    if let Ok(mut d) = self.data.lock() {
+      d.attempts.push(TranscriptAttempt {
+        provider: provider_id.to_string(),
+        model: model.to_string(),
+        ..Default::default()
+      });
+    }
+  }
+
+  pub(crate) fn on_upstream_request(&self, req: &impl Serialize) {
+    let v = serde_json::to_value(req).unwrap_or(Value::Null);
+    if let Ok(mut d) = self.data.lock() {
+      d.bytes += v.to_string().len();
+      if let Some(a) = d.attempts.last_mut() {
+        a.upstream_request = v;
+      }
+    }
+  }
+
+  pub(crate) fn on_upstream_line(&self, line: &str) {
+    self.push_line(line, |d| d.attempts.last_mut().map(|a| &mut a.upstream_sse));
+  }
+
+  pub(crate) fn on_attempt_error(&self, err: &str) {
+    if let Ok(mut d) = self.data.lock() {
+      if let Some(a) = d.attempts.last_mut() {
+        a.error = Some(err.to_string());
+      }
+    }
+  }
+
+  pub(crate) fn on_emitted(&self, bytes: &[u8]) {
+    for line in String::from_utf8_lossy(bytes).lines() {
+      self.push_line(line, |d| Some(&mut d.emitted_ndjson));
+    }
+  }
+
+  fn push_line(
+    &self,
+    line: &str,
+    target: impl FnOnce(&mut TranscriptData) -> Option<&mut Vec<String>>,
+  ) {
+    let Ok(mut d) = self.data.lock() else { return };
+    if d.truncated {
+      return;
+    }
+    if d.bytes + line.len() > self.cfg.max_file_bytes {
+      d.truncated = true;
+      return;
+    }
+    d.bytes += line.len();
+    if let Some(lines) = target(&mut d) {
+      lines.push(line.to_string());
+    }
+  }
+}
+
+impl Drop for Transcript {
+  fn drop(&mut self) {
+    let data = self
+      .data
+      .lock()
+      .map(|mut d| std::mem::take(&mut *d))
+      .unwrap_or_default();
+    let path = std::mem::take(&mut self.path);
+    let cfg = self.cfg.clone();
+    let secrets = std::mem::take(&mut self.secrets);
+    let write = move || match write_file(&path, &cfg, &secrets, &data) {
+      Ok(()) => debug!(path=%path.display(), "chat-stream transcript 已写入"),
+      Err(err) => warn!(error=%err, path=%path.display(), "chat-stream transcript 写入失败"),
+    };
+    match tokio::runtime::Handle::try_current() {
+      Ok(h) => drop(h.spawn_blocking(write)),
+      Err(_) => write(),
+    }
+  }
+}
+
+fn write_file(
+  path: &Path,
+  cfg: &TranscriptConfig,
+  secrets: &[String],
+  data: &TranscriptData,
+) -> anyhow::Result<()> {
+  let json = redact(serde_json::to_string_pretty(data)?, secrets);
+  let dir = path.parent().unwrap_or(Path::new("."));
+  std::fs::create_dir_all(dir)?;
+  std::fs::write(path, json)?;
+  prune(dir, cfg);
+  Ok(())
+}
+
+// 所有已配置的 key / token；只替换足够长的值，避免误伤普通文本
+fn collect_secrets(cfg: &Config) -> Vec<String> {
+  let mut out: Vec<String> = cfg
+    .byok
+    .providers
+    .iter()
+    .flat_map(|p| p.api_key().keys())
+    .chain([cfg.official.api_token.clone(), cfg.proxy.auth_token.clone()])
+    .map(|s| s.trim().to_string())
+    .filter(|s| s.len() >= 8)
+    .collect();
+  // 长的先替换，避免某个 key 是另一个 key 的前缀时残留
+  out.sort_by_key(|s| std::cmp::Reverse(s.len()));
+  out.dedup();
+  out
+}
+
+fn redact(mut text: String, secrets: &[String]) -> String {
+  for s in secrets {
+    if text.contains(s.as_str()) {
+      text = text.replace(s.as_str(), "[REDACTED]");
+    }
+  }
+  text
+}
+
+fn prune(dir: &Path, cfg: &TranscriptConfig) {
+  let Ok(entries) = std::fs::read_dir(dir) else {
+    return;
+  };
+  let mut files: Vec<(PathBuf, SystemTime)> = entries
+    .filter_map(|e| e.ok())
+    .map(|e| e.path())
+    .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+    .filter_map(|p| Some((p.clone(), std::fs::metadata(&p).ok()?.modified().ok()?)))
+    .collect();
+  // 文件名以毫秒时间戳开头，按名字倒序即新的在前
+  files.sort_by(|a, b| b.0.cmp(&a.0));
+  let max_age = Duration::from_secs(cfg.max_age_hours.saturating_mul(3600));
+  for (i, (path, modified)) in files.iter().enumerate() {
+    let too_many = cfg.max_files > 0 && i >= cfg.max_files;
+    let too_old = cfg.max_age_hours > 0 && modified.elapsed().is_ok_and(|age| age > max_age);
+    if too_many || too_old {
+      let _ = std::fs::remove_file(path);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn writes_redacted_transcripts_and_keeps_newest_files() {
+    let root = std::env::temp_dir().join(format!("byok_transcripts_test_{}", now_ms()));
+    let mut cfg: Config = serde_yaml::from_str(
+      r#"
+server: { host: "127.0.0.1", port: 8317 }
+proxy: { auth_token: "proxy-token-123" }
+official: { base_url: "https://example.com/", api_token: "official-token-456" }
+byok:
+  providers:
+    - { type: anthropic, id: a, base_url: "https://api.anthropic.com/v1", api_key: "sk-ant-secret-789", default_model: m }
+logging:
+  transcripts: { enabled: true, max_files: 2, max_file_bytes: 400, providers: [a] }
+"#,
+    )
+    .unwrap();
+    cfg.logging.transcripts.dir = root.join("t").display().to_string();
+    let config_path = root.join("config.yaml");
+
+    assert!(Transcript::start(&cfg, &config_path, "b", "m", "c", b"{}").is_none());
+    for i in 0..3 {
+      let t = Transcript::start(
+        &cfg,
+        &config_path,
+        "a",
+        "m",
+        "conv/1",
+        b"{\"message\":\"hi\"}",
+      )
+      .unwrap();
+      t.begin_attempt("a", "m");
+      t.on_upstream_request(&serde_json::json!({ "echo": "sk-ant-secret-789" }));
+      t.on_upstream_line(&format!("data: {i}"));
+      t.on_emitted(&"x".repeat(500).into_bytes());
+    }
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(root.join("t"))
+      .unwrap()
+      .map(|e| e.unwrap().path())
+      .collect();
+    files.sort();
+    assert_eq!(files.len(), 2);
+    let text = std::fs::read_to_string(&files[1]).unwrap();
+    assert!(files[1].to_string_lossy().ends_with("-conv1.json"));
+    assert!(
+      !text.contains("sk-ant-secret-789") && text.contains("[REDACTED]"),
+      "{text}"
+    );
+    assert!(
+      text.contains("data: 2") && text.contains("\"truncated\": true"),
+      "{text}"
+    );
+    let _ = std::fs::remove_dir_all(&root);
+  }
+}
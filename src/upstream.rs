@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use reqwest::{RequestBuilder, Response};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::{
+  circuit_breaker::CircuitBreakerRegistry,
+  concurrency::ConcurrencyLimiter,
+  config::{ApiKeys, CircuitBreakerConfig, Config, KeyPoolConfig, RateLimitConfig, RetryConfig},
+  key_pool::{send_with_key_pool, KeyPoolRegistry},
+  metrics,
+  rate_limit::SharedRateLimits,
+  request_id,
+  usage::UsageLedger,
+  util::now_ms,
+};
+
+// 所有上游 provider 调用共享的运行时状态（key 池健康度、熔断器、并发名额、限速令牌桶）
+#[derive(Debug, Default)]
+pub(crate) struct UpstreamHealth {
+  pub(crate) key_pools: RwLock<KeyPoolRegistry>,
+  pub(crate) breakers: RwLock<CircuitBreakerRegistry>,
+  pub(crate) concurrency: ConcurrencyLimiter,
+  pub(crate) rate_limits: SharedRateLimits,
+}
+
+// 不持有 AppState 的调用方（history_summary）发起上游请求所需的共享状态与策略
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct UpstreamClient<'a> {
+  pub(crate) http: &'a reqwest::Client,
+  pub(crate) health: &'a UpstreamHealth,
+  pub(crate) policy: &'a UpstreamPolicy,
+  // None 时不写 usage 账本（usage.enabled=false）
+  pub(crate) usage: Option<&'a Arc<UsageLedger>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct UpstreamPolicy {
+  pub(crate) retry: RetryConfig,
+  pub(crate) circuit_breaker: CircuitBreakerConfig,
+  rate_limits: HashMap<String, RateLimitConfig>,
+  // 本次请求的输入 token 估算，用于 rate_limit 预扣
+  pub(crate) estimated_input_tokens: u64,
+  // 流式请求每次尝试等待响应头的上限（first_byte_timeout_seconds）；None 时只受 reqwest 自身超时约束
+  pub(crate) header_timeout: Option<Duration>,
+}
+
+impl UpstreamPolicy {
+  pub(crate) fn from_config(cfg: &Config) -> Self {
+    Self {
+      retry: cfg.retry.clone(),
+      circuit_breaker: cfg.circuit_breaker.clone(),
+      rate_limits: cfg
+        .byok
+        .providers
+        .iter()
+        .filter(|p| p.rate_limit().is_enabled())
+        .map(|p| (p.id().to_string(), p.rate_limit().clone()))
+        .collect(),
+      estimated_input_tokens: 0,
+      header_timeout: None,
+    }
+  }
+
+  pub(crate) fn with_estimated_input_tokens(mut self, tokens: u64) -> Self {
+    self.estimated_input_tokens = tokens;
+    self
+  }
+
+  pub(crate) fn with_header_timeout(mut self, timeout: Duration) -> Self {
+    self.header_timeout = Some(timeout);
+    self
+  }
+
+  pub(crate) fn rate_limit(&self, provider_id: &str) -> Option<&RateLimitConfig> {
+    self.rate_limits.get(provider_id)
+  }
+}
+
+// 熔断检查 -> key 池选 key -> 重试；网络错误与 5xx 计入熔断失败
+pub(crate) async fn send_upstream(
+  health: &UpstreamHealth,
+  policy: &UpstreamPolicy,
+  provider_id: &str,
+  api_key: &ApiKeys,
+  key_pool: &KeyPoolConfig,
+  req: RequestBuilder,
+  auth: impl Fn(RequestBuilder, &str) -> RequestBuilder,
+) -> anyhow::Result<Response> {
+  let acquired =
+    health
+      .breakers
+      .write()
+      .await
+      .try_acquire(provider_id, &policy.circuit_breaker, now_ms());
+  if let Err(remaining_ms) = acquired {
+    anyhow::bail!(
+      "Provider({provider_id}) 已熔断（连续失败过多），约 {}s 后再探测",
+      remaining_ms.div_ceil(1000)
+    );
+  }
+
+  let req = match request_id::current_request_id() {
+    Some(id) => req.header(request_id::REQUEST_ID_HEADER, id),
+    None => req,
+  };
+  let result = send_with_key_pool(health, policy, provider_id, api_key, key_pool, req, auth).await;
+  if let Ok(resp) = &result {
+    request_id::record_upstream_request_id(resp);
+  }
+  metrics::record_upstream_response(
+    provider_id,
+    result.as_ref().ok().map(|r| r.status().as_u16()),
+  );
+
+  let failure = match &result {
+    Ok(resp) if resp.status().is_server_error() => Some(format!("上游返回 {}", resp.status())),
+    Ok(_) => None,
+    Err(err) => Some(format!("上游请求失败: {err}")),
+  };
+  let mut breakers = health.breakers.write().await;
+  match failure {
+    None => breakers.record_success(provider_id),
+    Some(error) => {
+      if breakers.record_failure(provider_id, &policy.circuit_breaker, error, now_ms()) {
+        warn!(provider_id=%provider_id, open_seconds=policy.circuit_breaker.open_seconds, "provider 连续失败，熔断器打开");
+      }
+    }
+  }
+  drop(breakers);
+  result
+}
@@ -0,0 +1,325 @@
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::warn;
+
+use crate::{
+  config::{ModelPrice, UsageConfig},
+  util::now_ms,
+};
+
+// usage_ledger.jsonl 的一行；费用不落盘，查询时按当前 usage.prices 计算
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub(crate) struct UsageRecord {
+  pub(crate) ts_ms: u64,
+  pub(crate) endpoint: String,
+  #[serde(default)]
+  pub(crate) conversation_id: String,
+  pub(crate) provider: String,
+  pub(crate) model: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) input_tokens: Option<i64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) output_tokens: Option<i64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) cache_read_tokens: Option<i64>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) cache_creation_tokens: Option<i64>,
+  pub(crate) latency_ms: u64,
+  // completed | cancelled | error
+  pub(crate) status: String,
+}
+
+impl UsageRecord {
+  pub(crate) fn new(endpoint: &str, conversation_id: &str, provider: &str, model: &str) -> Self {
+    Self {
+      ts_ms: now_ms(),
+      endpoint: endpoint.to_string(),
+      conversation_id: conversation_id.to_string(),
+      provider: provider.to_string(),
+      model: model.to_string(),
+      ..Default::default()
+    }
+  }
+
+  fn cost_usd(&self, price: &ModelPrice) -> f64 {
+    price.cost_usd(
+      self.input_tokens.unwrap_or(0),
+      self.output_tokens.unwrap_or(0),
+      self.cache_read_tokens.unwrap_or(0),
+      self.cache_creation_tokens.unwrap_or(0),
+    )
+  }
+}
+
+// (provider, model, YYYY-MM-DD) -> 当日合计；只保留本月，供预算检查
+type DailyTotals = BTreeMap<(String, String, String), UsageSummary>;
+
+// 追加写由后台任务串行完成，record 可在 Drop 等同步上下文里调用
+#[derive(Debug)]
+pub(crate) struct UsageLedger {
+  path: PathBuf,
+  tx: mpsc::UnboundedSender<UsageRecord>,
+  daily: Mutex<DailyTotals>,
+}
+
+impl UsageLedger {
+  // 启动时从账本文件重建本月的日合计，预算状态因此跨重启保留
+  pub(crate) async fn open(path: PathBuf) -> Self {
+    let (tx, mut rx) = mpsc::unbounded_channel::<UsageRecord>();
+    let write_path = path.clone();
+    tokio::spawn(async move {
+      while let Some(record) = rx.recv().await {
+        if let Err(err) = append_line(&write_path, &record).await {
+          warn!(error=%err, path=%write_path.display(), "usage ledger 写入失败（本条记录已丢弃）");
+        }
+      }
+    });
+    let ledger = Self {
+      path,
+      tx,
+      daily: Mutex::default(),
+    };
+    match ledger.load(month_start_ms(now_ms())).await {
+      Ok(records) => records.iter().for_each(|r| ledger.add_daily(r)),
+      Err(err) => {
+        warn!(error=%err, path=%ledger.path.display(), "usage ledger 读取失败（本月预算用量从 0 开始计）")
+      }
+    }
+    ledger
+  }
+
+  pub(crate) fn record(&self, record: UsageRecord) {
+    self.add_daily(&record);
+    let _ = self.tx.send(record);
+  }
+
+  fn add_daily(&self, r: &UsageRecord) {
+    let Ok(mut daily) = self.daily.lock() else {
+      return;
+    };
+    let day = utc_date(r.ts_ms);
+    let month = &day[..7];
+    daily.retain(|(_, _, d), _| d.as_str() >= month);
+    daily
+      .entry((r.provider.clone(), r.model.clone(), day))
+      .or_default()
+      .add(r, None);
+  }
+
+  // 某 provider 自 since_day（含）起按模型合计的用量
+  pub(crate) fn provider_totals_since(
+    &self,
+    provider_id: &str,
+    since_day: &str,
+  ) -> BTreeMap<String, UsageSummary> {
+    let mut out: BTreeMap<String, UsageSummary> = BTreeMap::new();
+    let Ok(daily) = self.daily.lock() else {
+      return out;
+    };
+    for ((provider, model, day), t) in daily.iter() {
+      if provider != provider_id || day.as_str() < since_day {
+        continue;
+      }
+      let s = out.entry(model.clone()).or_default();
+      s.requests += t.requests;
+      s.input_tokens += t.input_tokens;
+      s.output_tokens += t.output_tokens;
+      s.cache_read_tokens += t.cache_read_tokens;
+      s.cache_creation_tokens += t.cache_creation_tokens;
+    }
+    out
+  }
+
+  pub(crate) async fn load(&self, since_ms: u64) -> anyhow::Result<Vec<UsageRecord>> {
+    let raw = match tokio::fs::read_to_string(&self.path).await {
+      Ok(v) => v,
+      Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(err) => return Err(err.into()),
+    };
+    Ok(
+      raw
+        .lines()
+        .filter_map(|line| serde_json::from_str::<UsageRecord>(line).ok())
+        .filter(|r| r.ts_ms >= since_ms)
+        .collect(),
+    )
+  }
+}
+
+async fn append_line(path: &PathBuf, record: &UsageRecord) -> anyhow::Result<()> {
+  let mut line = serde_json::to_string(record)?;
+  line.push('\n');
+  let mut f = tokio::fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .await?;
+  f.write_all(line.as_bytes()).await?;
+  Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UsageGroupBy {
+  Day,
+  Model,
+  Conversation,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct UsageSummary {
+  pub(crate) key: String,
+  pub(crate) requests: u64,
+  pub(crate) errors: u64,
+  pub(crate) cancelled: u64,
+  pub(crate) input_tokens: i64,
+  pub(crate) output_tokens: i64,
+  pub(crate) cache_read_tokens: i64,
+  pub(crate) cache_creation_tokens: i64,
+  pub(crate) latency_ms_total: u64,
+  pub(crate) cost_usd: f64,
+  // 没有匹配到 usage.prices 的记录数（未计入 cost_usd）
+  pub(crate) unpriced_requests: u64,
+}
+
+impl UsageSummary {
+  fn add(&mut self, r: &UsageRecord, price: Option<&ModelPrice>) {
+    self.requests += 1;
+    match r.status.as_str() {
+      "error" => self.errors += 1,
+      "cancelled" => self.cancelled += 1,
+      _ => {}
+    }
+    self.input_tokens += r.input_tokens.unwrap_or(0).max(0);
+    self.output_tokens += r.output_tokens.unwrap_or(0).max(0);
+    self.cache_read_tokens += r.cache_read_tokens.unwrap_or(0).max(0);
+    self.cache_creation_tokens += r.cache_creation_tokens.unwrap_or(0).max(0);
+    self.latency_ms_total += r.latency_ms;
+    match price {
+      Some(p) => self.cost_usd += r.cost_usd(p),
+      None => self.unpriced_requests += 1,
+    }
+  }
+
+  pub(crate) fn total_tokens(&self) -> i64 {
+    self.input_tokens + self.output_tokens + self.cache_read_tokens + self.cache_creation_tokens
+  }
+
+  pub(crate) fn cost_with(&self, price: &ModelPrice) -> f64 {
+    price.cost_usd(
+      self.input_tokens,
+      self.output_tokens,
+      self.cache_read_tokens,
+      self.cache_creation_tokens,
+    )
+  }
+}
+
+// 返回 (分组明细, 合计)；分组按 key 升序（day 即时间顺序）
+pub(crate) fn summarize(
+  records: &[UsageRecord],
+  cfg: &UsageConfig,
+  group_by: UsageGroupBy,
+) -> (Vec<UsageSummary>, UsageSummary) {
+  let mut groups: BTreeMap<String, UsageSummary> = BTreeMap::new();
+  let mut total = UsageSummary {
+    key: "total".to_string(),
+    ..Default::default()
+  };
+  for r in records {
+    let price = cfg.price_for(&r.model);
+    let key = match group_by {
+      UsageGroupBy::Day => utc_date(r.ts_ms),
+      UsageGroupBy::Model => format!("{}:{}", r.provider, r.model),
+      UsageGroupBy::Conversation => r.conversation_id.clone(),
+    };
+    groups
+      .entry(key.clone())
+      .or_insert_with(|| UsageSummary {
+        key,
+        ..Default::default()
+      })
+      .add(r, price);
+    total.add(r, price);
+  }
+  (groups.into_values().collect(), total)
+}
+
+fn month_start_ms(ts_ms: u64) -> u64 {
+  let day_of_month: u64 = utc_date(ts_ms)[8..].parse().unwrap_or(1);
+  (ts_ms / 86_400_000 - (day_of_month - 1)) * 86_400_000
+}
+
+// YYYY-MM-DD（UTC）
+pub(crate) fn utc_date(ts_ms: u64) -> String {
+  let days = (ts_ms / 86_400_000) as i64;
+  // Howard Hinnant 的 civil_from_days
+  let z = days + 719_468;
+  let era = z.div_euclid(146_097);
+  let doe = z.rem_euclid(146_097);
+  let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let d = doy - (153 * mp + 2) / 5 + 1;
+  let m = if mp < 10 { mp + 3 } else { mp - 9 };
+  let y = yoe + era * 400 + i64::from(m <= 2);
+  format!("{y:04}-{m:02}-{d:02}")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn summarizes_by_day_and_model_with_prefix_prices() {
+    let cfg: UsageConfig = serde_yaml::from_str(
+      r#"
+prices:
+  "claude-*": { input: 3, output: 15, cache_read: 0.3 }
+  "claude-haiku*": { input: 1, output: 5 }
+"#,
+    )
+    .unwrap();
+    let rec = |ts_ms, model: &str, input, output, status: &str| UsageRecord {
+      ts_ms,
+      provider: "p".to_string(),
+      model: model.to_string(),
+      input_tokens: Some(input),
+      output_tokens: Some(output),
+      status: status.to_string(),
+      ..Default::default()
+    };
+    let mut sonnet = rec(0, "claude-sonnet-4", 1_000_000, 100_000, "completed");
+    sonnet.cache_read_tokens = Some(1_000_000);
+    let records = vec![
+      sonnet,
+      rec(86_399_999, "claude-haiku-4", 1_000_000, 0, "cancelled"),
+      rec(86_400_000, "gpt-5", 10, 10, "error"),
+    ];
+
+    let (days, total) = summarize(&records, &cfg, UsageGroupBy::Day);
+    assert_eq!(
+      days
+        .iter()
+        .map(|g| (g.key.as_str(), g.requests))
+        .collect::<Vec<_>>(),
+      [("1970-01-01", 2), ("1970-01-02", 1)]
+    );
+    // sonnet: 3 + 1.5 + 0.3；haiku 走更长的前缀：1
+    assert!(
+      (days[0].cost_usd - 5.8).abs() < 1e-9,
+      "{}",
+      days[0].cost_usd
+    );
+    assert_eq!(
+      (total.errors, total.cancelled, total.unpriced_requests),
+      (1, 1, 1)
+    );
+
+    let (models, _) = summarize(&records, &cfg, UsageGroupBy::Model);
+    assert_eq!(models[2].key, "p:gpt-5");
+    assert_eq!(utc_date(1_760_745_600_000), "2025-10-18");
+  }
+}